@@ -65,6 +65,14 @@ pub struct CacheConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub public_key: Option<String>,
 
+    /// All public keys that clients should trust, including keys
+    /// staged for an upcoming rotation.
+    ///
+    /// The public key the cache signs with first comes first.
+    /// This is read-only and may not be available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_keys: Option<Vec<String>>,
+
     /// Whether the cache is public or not.
     ///
     /// Anonymous clients are implicitly granted the "pull"
@@ -92,9 +100,43 @@ pub struct CacheConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub upstream_cache_key_names: Option<Vec<String>>,
 
+    /// A list of upstream substituters to fetch missing paths from.
+    ///
+    /// When a store path isn't in the cache, the upstreams are tried
+    /// in order and the path is ingested into the cache if found.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_substituters: Option<Vec<UpstreamSubstituter>>,
+
     /// The retention period of the cache.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub retention_period: Option<RetentionPeriodConfig>,
+
+    /// The storage quota of the cache.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_quota: Option<StorageQuotaConfig>,
+
+    /// Public keys that signatures of uploaded paths are verified with,
+    /// in the format used by Nix.
+    ///
+    /// If not empty, only valid signatures by these keys are kept when
+    /// a path is uploaded. If empty, signatures are kept as uploaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_trusted_keys: Option<Vec<String>>,
+
+    /// Whether uploaded paths must be signed by one of the trusted keys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_trusted_signature: Option<bool>,
+}
+
+/// An upstream substituter of a cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpstreamSubstituter {
+    /// The HTTP(S) endpoint of the binary cache.
+    pub url: String,
+
+    /// Public keys of which at least one must have signed a path
+    /// for it to be fetched, in the format used by Nix.
+    pub trusted_keys: Vec<String>,
 }
 
 /// Configuaration of a keypair.
@@ -107,6 +149,61 @@ pub enum KeypairConfig {
     Keypair(NixKeypair),
 }
 
+/// A step of a key rotation.
+///
+/// A rotation stages a new key, which clients can trust ahead of
+/// time, activates it once they do, and retires the old key once
+/// nothing relies on it any more. While both keys are active, paths
+/// are signed with both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyRotationStep {
+    /// Generate the next key of the cache.
+    Stage,
+
+    /// Start signing with the next key, in addition to the current one.
+    Activate,
+
+    /// Stop signing with all keys but the newest active one.
+    Retire,
+}
+
+/// The state of a signing key of a cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CacheKeyState {
+    /// The key will be used after the next rotation.
+    ///
+    /// Clients should already trust it.
+    Next,
+
+    /// Paths are signed with the key.
+    Active,
+
+    /// The key isn't used any more.
+    Retired,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotateKeyRequest {
+    /// The step of the rotation to perform.
+    pub step: KeyRotationStep,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotateKeyResponse {
+    /// The signing keys of the cache after the step.
+    pub keys: Vec<CacheKey>,
+}
+
+/// A signing key of a cache.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheKey {
+    /// The public key, in the canonical format used by Nix.
+    pub public_key: String,
+
+    /// The state of the key.
+    pub state: CacheKeyState,
+}
+
 /// Configuration of retention period.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum RetentionPeriodConfig {
@@ -119,6 +216,20 @@ pub enum RetentionPeriodConfig {
     Period(u32),
 }
 
+/// Configuration of storage quota.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StorageQuotaConfig {
+    /// Use the global default.
+    Global,
+
+    /// Specify a storage quota in bytes.
+    ///
+    /// When the cache grows beyond the quota, the least recently
+    /// accessed paths are garbage-collected. If 0, then quota-based
+    /// garbage collection is disabled.
+    Quota(u64),
+}
+
 impl CacheConfig {
     pub fn blank() -> Self {
         Self {
@@ -126,11 +237,16 @@ impl CacheConfig {
             substituter_endpoint: None,
             api_endpoint: None,
             public_key: None,
+            public_keys: None,
             is_public: None,
             store_dir: None,
             priority: None,
             upstream_cache_key_names: None,
+            upstream_substituters: None,
             retention_period: None,
+            storage_quota: None,
+            upload_trusted_keys: None,
+            require_trusted_signature: None,
         }
     }
 }
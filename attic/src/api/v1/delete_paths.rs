@@ -0,0 +1,47 @@
+//! delete-paths v1
+//!
+//! `DELETE /_api/v1/objects`
+//!
+//! Requires "delete" permission.
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::CacheName;
+use crate::nix_store::StorePathHash;
+
+/// Request to delete store paths from a cache.
+///
+/// A path is deleted if it matches any of the selectors. The
+/// underlying NARs and chunks are not deleted immediately; they
+/// are reaped by the garbage collector once no other object
+/// references them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeletePathsRequest {
+    /// The name of the cache.
+    pub cache: CacheName,
+
+    /// A list of store path hashes to delete.
+    #[serde(default)]
+    pub store_path_hashes: Vec<StorePathHash>,
+
+    /// A list of full store paths to delete.
+    ///
+    /// Example: `/nix/store/p4pclmv1gyja5kzc26npqpia1qqxrf0l-ruby-2.7.3`
+    #[serde(default)]
+    pub store_paths: Vec<String>,
+
+    /// A list of globs matched against the name portion of store paths.
+    ///
+    /// The name is the part after the hash and the dash. `*` matches
+    /// any sequence of characters, `?` matches a single character.
+    ///
+    /// Example: `ruby-2.7.*`
+    #[serde(default)]
+    pub name_globs: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeletePathsResponse {
+    /// The full store paths that were deleted.
+    pub deleted_paths: Vec<String>,
+}
@@ -22,4 +22,11 @@ pub struct GetMissingPathsRequest {
 pub struct GetMissingPathsResponse {
     /// A list of paths that are not in the cache.
     pub missing_paths: Vec<StorePathHash>,
+
+    /// Paths in `missing_paths` that are in the cache but whose NARs
+    /// are incomplete.
+    ///
+    /// Uploading them again repairs the cache.
+    #[serde(default)]
+    pub incomplete_paths: Vec<StorePathHash>,
 }
@@ -0,0 +1,28 @@
+//! incomplete-paths v1
+//!
+//! `GET /_api/v1/incomplete-paths/{cache}`
+//!
+//! Requires "push" permission.
+
+use serde::{Deserialize, Serialize};
+
+/// A path whose NAR is missing chunks in the cache.
+///
+/// It can't be substituted until it's pushed again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IncompletePath {
+    /// The full store path.
+    pub store_path: String,
+
+    /// How many times the NAR was requested while incomplete.
+    pub hits: u64,
+
+    /// When the NAR was last requested, in RFC 3339 format.
+    pub last_hit_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListIncompletePathsResponse {
+    /// The incomplete paths of the cache, most recently requested first.
+    pub paths: Vec<IncompletePath>,
+}
@@ -1,3 +1,6 @@
 pub mod cache_config;
+pub mod delete_paths;
 pub mod get_missing_paths;
+pub mod incomplete_paths;
+pub mod pin;
 pub mod upload_path;
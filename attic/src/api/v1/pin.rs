@@ -0,0 +1,44 @@
+//! pin v1
+//!
+//! `GET /_api/v1/pins/{cache}` lists the pins of a cache and
+//! requires "pull" permission.
+//!
+//! `PUT /_api/v1/pins/{cache}/{name}` and `DELETE /_api/v1/pins/{cache}/{name}`
+//! create or remove a pin and require "push" permission.
+//!
+//! `GET /{cache}/pins/{name}` resolves a pin to its store path
+//! as plain text and requires "pull" permission.
+
+use serde::{Deserialize, Serialize};
+
+/// Request to pin a store path in a cache.
+///
+/// If a pin of the same name exists, it's moved to the new path.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatePinRequest {
+    /// The full store path to pin.
+    ///
+    /// The path must be in the cache.
+    pub store_path: String,
+}
+
+/// A pin of a store path in a cache.
+///
+/// Pinned paths and their closures are never garbage-collected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pin {
+    /// The name of the pin.
+    pub name: String,
+
+    /// The full store path that is pinned.
+    pub store_path: String,
+
+    /// When the pin was last set, in RFC 3339 format.
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListPinsResponse {
+    /// The pins of the cache, ordered by name.
+    pub pins: Vec<Pin>,
+}
@@ -226,6 +226,18 @@ mod ffi {
             sender: Box<AsyncWriteSender>,
         ) -> Result<()>;
 
+        /// Returns the build log of a derivation, or null if there is none.
+        fn build_log(self: Pin<&mut CNixStore>, base_name: &[u8]) -> Result<UniquePtr<CxxString>>;
+
+        /// Returns the realisations of a derivation that produced a store path.
+        ///
+        /// Each realisation is returned as JSON.
+        fn query_realisations(
+            self: Pin<&mut CNixStore>,
+            base_name: &[u8],
+            drv_base_name: &[u8],
+        ) -> Result<UniquePtr<CxxVector<CxxString>>>;
+
         /// Obtains a handle to the Nix store.
         fn open_nix_store() -> Result<UniquePtr<CNixStore>>;
 
@@ -250,5 +262,8 @@ mod ffi {
 
         /// Returns the CA field of the store path.
         fn ca(self: Pin<&mut CPathInfo>) -> String;
+
+        /// Returns the base name of the deriver of the store path, or an empty string.
+        fn deriver(self: Pin<&mut CPathInfo>) -> String;
     }
 }
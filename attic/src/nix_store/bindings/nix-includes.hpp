@@ -4,6 +4,10 @@
 		#include <nix/store/local-store.hh>
 		#include <nix/store/remote-store.hh>
 		#include <nix/store/uds-remote-store.hh>
+		#include <nix/store/log-store.hh>
+		#include <nix/store/store-cast.hh>
+		#include <nix/store/derivations.hh>
+		#include <nix/store/realisation.hh>
 		#include <nix/store/path.hh>
 		#include <nix/util/hash.hh>
 		#include <nix/util/serialise.hh>
@@ -20,6 +24,10 @@
 		#include <nix/local-store.hh>
 		#include <nix/remote-store.hh>
 		#include <nix/uds-remote-store.hh>
+		#include <nix/log-store.hh>
+		#include <nix/store-cast.hh>
+		#include <nix/derivations.hh>
+		#include <nix/realisation.hh>
 		#include <nix/hash.hh>
 		#include <nix/path.hh>
 		#include <nix/serialise.hh>
@@ -79,6 +79,14 @@ RString CPathInfo::ca() {
 	}
 }
 
+RString CPathInfo::deriver() {
+	if (this->pi->deriver) {
+		return RString(this->pi->deriver->to_string());
+	} else {
+		return RString("");
+	}
+}
+
 // =========
 // CNixStore
 // =========
@@ -149,6 +157,36 @@ void CNixStore::nar_from_path(RVec<unsigned char> base_name, RBox<AsyncWriteSend
 	sink.eof();
 }
 
+std::unique_ptr<std::string> CNixStore::build_log(RBasePathSlice base_name) {
+	auto &log_store = nix::require<nix::LogStore>(*this->store);
+	auto log = log_store.getBuildLog(store_path_from_rust(base_name));
+
+	if (log) {
+		return std::make_unique<std::string>(std::move(*log));
+	} else {
+		return nullptr;
+	}
+}
+
+std::unique_ptr<std::vector<std::string>> CNixStore::query_realisations(RBasePathSlice base_name, RBasePathSlice drv_base_name) {
+	auto store_path = store_path_from_rust(base_name);
+	auto drv_path = store_path_from_rust(drv_base_name);
+
+	auto drv = this->store->readDerivation(drv_path);
+	auto output_hashes = nix::staticOutputHashes(*this->store, drv);
+
+	auto results = std::make_unique<std::vector<std::string>>();
+	for (auto & [output_name, hash] : output_hashes) {
+		auto realisation = this->store->queryRealisation(nix::DrvOutput { hash, output_name });
+
+		if (realisation && realisation->outPath == store_path) {
+			results->push_back(realisation->toJSON().dump());
+		}
+	}
+
+	return results;
+}
+
 std::unique_ptr<CNixStore> open_nix_store() {
 	return std::make_unique<CNixStore>();
 }
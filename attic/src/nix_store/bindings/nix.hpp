@@ -49,6 +49,7 @@ public:
 	std::unique_ptr<std::vector<std::string>> sigs();
 	std::unique_ptr<std::vector<std::string>> references();
 	RString ca();
+	RString deriver();
 };
 
 class CNixStore {
@@ -69,6 +70,8 @@ public:
 		bool include_outputs,
 		bool include_derivers);
 	void nar_from_path(RVec<unsigned char> base_name, RBox<AsyncWriteSender> sender);
+	std::unique_ptr<std::string> build_log(RBasePathSlice base_name);
+	std::unique_ptr<std::vector<std::string>> query_realisations(RBasePathSlice base_name, RBasePathSlice drv_base_name);
 };
 
 std::unique_ptr<CNixStore> open_nix_store();
@@ -148,6 +148,11 @@ pub struct ValidPathInfo {
 
     /// Content Address.
     pub ca: Option<String>,
+
+    /// The derivation that produced the path.
+    ///
+    /// This only contains the base name of the path.
+    pub deriver: Option<PathBuf>,
 }
 
 #[cfg_attr(not(feature = "nix_store"), allow(dead_code))]
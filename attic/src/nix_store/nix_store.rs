@@ -191,6 +191,51 @@ impl NixStore {
         .unwrap()
     }
 
+    /// Returns the build log of a derivation if it's available.
+    ///
+    /// This is akin to `nix log`, but only looks at the local store.
+    pub async fn build_log(&self, drv_path: StorePath) -> AtticResult<Option<Vec<u8>>> {
+        let inner = self.inner.clone();
+
+        spawn_blocking(move || {
+            let base_name = drv_path.as_base_name_bytes();
+            let log = inner.store().build_log(base_name)?;
+
+            if log.is_null() {
+                Ok(None)
+            } else {
+                Ok(Some(log.as_bytes().to_vec()))
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Returns the realisations of a derivation that produced a path.
+    ///
+    /// The realisations are returned as JSON as they are in the local store.
+    /// This is empty unless the derivation is content-addressed.
+    pub async fn query_realisations(
+        &self,
+        store_path: StorePath,
+        drv_path: StorePath,
+    ) -> AtticResult<Vec<String>> {
+        let inner = self.inner.clone();
+
+        spawn_blocking(move || {
+            let base_name = store_path.as_base_name_bytes();
+            let drv_base_name = drv_path.as_base_name_bytes();
+            let realisations = inner.store().query_realisations(base_name, drv_base_name)?;
+
+            Ok(realisations
+                .iter()
+                .map(|s| s.to_string_lossy().into_owned())
+                .collect())
+        })
+        .await
+        .unwrap()
+    }
+
     /// Returns detailed information on a path.
     pub async fn query_path_info(&self, store_path: StorePath) -> AtticResult<ValidPathInfo> {
         let inner = self.inner.clone();
@@ -222,6 +267,7 @@ impl NixStore {
                 })
                 .collect();
             let ca = c_path_info.pin_mut().ca();
+            let deriver = c_path_info.pin_mut().deriver();
 
             Ok(ValidPathInfo {
                 path: store_path,
@@ -230,6 +276,11 @@ impl NixStore {
                 references,
                 sigs,
                 ca: if ca.is_empty() { None } else { Some(ca) },
+                deriver: if deriver.is_empty() {
+                    None
+                } else {
+                    Some(PathBuf::from(deriver))
+                },
             })
         })
         .await
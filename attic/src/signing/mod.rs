@@ -118,6 +118,11 @@ impl NixKeypair {
         })
     }
 
+    /// Returns the name of the keypair.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     /// Returns the canonical representation of the keypair.
     ///
     /// This results in a 64-byte base64 payload that contains both the private
@@ -187,6 +192,28 @@ impl Serialize for NixKeypair {
     }
 }
 
+impl<'de> Deserialize<'de> for NixPublicKey {
+    /// Deserializes a potentially-invalid Nix public key from its canonical representation.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        use de::Error;
+        String::deserialize(deserializer)
+            .and_then(|s| Self::from_str(&s).map_err(|e| Error::custom(e.to_string())))
+    }
+}
+
+impl Serialize for NixPublicKey {
+    /// Serializes a Nix public key to its canonical representation.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.export())
+    }
+}
+
 impl NixPublicKey {
     /// Imports an existing public key from its canonical representation.
     pub fn from_str(public_key: &str) -> AtticResult<Self> {
@@ -51,6 +51,18 @@ fn test_import_public_key() {
     assert_eq!(cache_nixos_org, import.export());
 }
 
+#[test]
+fn test_public_key_serde() {
+    let json = "\"cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=\"";
+
+    let public_key: NixPublicKey =
+        serde_json::from_str(json).expect("Could not deserialize public key");
+
+    let export = serde_json::to_string(&public_key).expect("Could not serialize public key");
+
+    assert_eq!(json, &export);
+}
+
 #[test]
 fn test_signing() {
     let keypair = NixKeypair::generate("attic-test").expect("Could not generate key");
@@ -10,19 +10,25 @@ use futures::{
     stream::{self, StreamExt, TryStream, TryStreamExt},
 };
 use reqwest::{
-    header::{HeaderMap, HeaderValue, AUTHORIZATION, USER_AGENT},
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
     Body, Client as HttpClient, Response, StatusCode, Url,
 };
 use serde::Deserialize;
 
 use crate::config::ServerConfig;
 use crate::version::ATTIC_DISTRIBUTOR;
-use attic::api::v1::cache_config::{CacheConfig, CreateCacheRequest};
+use attic::api::v1::cache_config::{
+    CacheConfig, CreateCacheRequest, KeyRotationStep, RotateKeyRequest, RotateKeyResponse,
+};
+use attic::api::v1::delete_paths::{DeletePathsRequest, DeletePathsResponse};
 use attic::api::v1::get_missing_paths::{GetMissingPathsRequest, GetMissingPathsResponse};
+use attic::api::v1::incomplete_paths::ListIncompletePathsResponse;
+use attic::api::v1::pin::{CreatePinRequest, ListPinsResponse, Pin};
 use attic::api::v1::upload_path::{
     UploadPathNarInfo, UploadPathResult, ATTIC_NAR_INFO, ATTIC_NAR_INFO_PREAMBLE_SIZE,
 };
 use attic::cache::CacheName;
+use attic::mime;
 use attic::nix_store::StorePathHash;
 
 /// The User-Agent string of Attic.
@@ -145,6 +151,29 @@ impl ApiClient {
         }
     }
 
+    /// Performs a step of a key rotation of a cache.
+    pub async fn rotate_cache_key(
+        &self,
+        cache: &CacheName,
+        step: KeyRotationStep,
+    ) -> Result<RotateKeyResponse> {
+        let endpoint = self
+            .endpoint
+            .join(&format!("_api/v1/cache-config/{}/", cache.as_str()))?
+            .join("rotate-key")?;
+        let payload = RotateKeyRequest { step };
+
+        let res = self.client.post(endpoint).json(&payload).send().await?;
+
+        if res.status().is_success() {
+            let response = res.json().await?;
+            Ok(response)
+        } else {
+            let api_error = ApiError::try_from_response(res).await?;
+            Err(api_error.into())
+        }
+    }
+
     /// Returns paths missing from a cache.
     pub async fn get_missing_paths(
         &self,
@@ -168,6 +197,149 @@ impl ApiClient {
         }
     }
 
+    /// Returns paths of a cache whose NARs are incomplete.
+    pub async fn list_incomplete_paths(
+        &self,
+        cache: &CacheName,
+    ) -> Result<ListIncompletePathsResponse> {
+        let endpoint = self
+            .endpoint
+            .join("_api/v1/incomplete-paths/")?
+            .join(cache.as_str())?;
+
+        let res = self.client.get(endpoint).send().await?;
+
+        if res.status().is_success() {
+            let response = res.json().await?;
+            Ok(response)
+        } else {
+            let api_error = ApiError::try_from_response(res).await?;
+            Err(api_error.into())
+        }
+    }
+
+    /// Deletes paths from a cache.
+    pub async fn delete_paths(&self, request: &DeletePathsRequest) -> Result<DeletePathsResponse> {
+        let endpoint = self.endpoint.join("_api/v1/objects")?;
+
+        let res = self.client.delete(endpoint).json(request).send().await?;
+
+        if res.status().is_success() {
+            let response = res.json().await?;
+            Ok(response)
+        } else {
+            let api_error = ApiError::try_from_response(res).await?;
+            Err(api_error.into())
+        }
+    }
+
+    /// Lists the pins of a cache.
+    pub async fn list_pins(&self, cache: &CacheName) -> Result<ListPinsResponse> {
+        let endpoint = self.endpoint.join("_api/v1/pins/")?.join(cache.as_str())?;
+
+        let res = self.client.get(endpoint).send().await?;
+
+        if res.status().is_success() {
+            let response = res.json().await?;
+            Ok(response)
+        } else {
+            let api_error = ApiError::try_from_response(res).await?;
+            Err(api_error.into())
+        }
+    }
+
+    /// Pins a store path in a cache.
+    pub async fn create_pin(
+        &self,
+        cache: &CacheName,
+        name: &str,
+        request: &CreatePinRequest,
+    ) -> Result<Pin> {
+        let endpoint = self
+            .endpoint
+            .join(&format!("_api/v1/pins/{}/", cache.as_str()))?
+            .join(name)?;
+
+        let res = self.client.put(endpoint).json(request).send().await?;
+
+        if res.status().is_success() {
+            let pin = res.json().await?;
+            Ok(pin)
+        } else {
+            let api_error = ApiError::try_from_response(res).await?;
+            Err(api_error.into())
+        }
+    }
+
+    /// Removes a pin from a cache.
+    pub async fn delete_pin(&self, cache: &CacheName, name: &str) -> Result<()> {
+        let endpoint = self
+            .endpoint
+            .join(&format!("_api/v1/pins/{}/", cache.as_str()))?
+            .join(name)?;
+
+        let res = self.client.delete(endpoint).send().await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            let api_error = ApiError::try_from_response(res).await?;
+            Err(api_error.into())
+        }
+    }
+
+    /// Uploads the build log of a derivation.
+    pub async fn upload_build_log(
+        &self,
+        cache: &CacheName,
+        drv_name: &str,
+        log: Vec<u8>,
+    ) -> Result<()> {
+        let endpoint = self
+            .endpoint
+            .join(&format!("{}/log/", cache.as_str()))?
+            .join(drv_name)?;
+
+        let res = self.client.put(endpoint).body(log).send().await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            let api_error = ApiError::try_from_response(res).await?;
+            Err(api_error.into())
+        }
+    }
+
+    /// Uploads a realisation of a content-addressed derivation output.
+    pub async fn upload_realisation(
+        &self,
+        cache: &CacheName,
+        output_id: &str,
+        realisation: String,
+    ) -> Result<()> {
+        // The output ID contains a colon, so it must not be joined on its own
+        let endpoint = self.endpoint.join(&format!(
+            "{}/realisations/{}.doi",
+            cache.as_str(),
+            output_id
+        ))?;
+
+        let res = self
+            .client
+            .put(endpoint)
+            .header(CONTENT_TYPE, mime::REALISATION)
+            .body(realisation)
+            .send()
+            .await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            let api_error = ApiError::try_from_response(res).await?;
+            Err(api_error.into())
+        }
+    }
+
     /// Uploads a path.
     pub async fn upload_path<S>(
         &self,
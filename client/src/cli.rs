@@ -10,6 +10,7 @@ use enum_as_inner::EnumAsInner;
 use crate::command::cache::{self, Cache};
 use crate::command::get_closure::{self, GetClosure};
 use crate::command::login::{self, Login};
+use crate::command::pin::{self, Pin};
 use crate::command::push::{self, Push};
 use crate::command::r#use::{self, Use};
 use crate::command::watch_store::{self, WatchStore};
@@ -29,6 +30,7 @@ pub enum Command {
     Use(Use),
     Push(Push),
     Cache(Cache),
+    Pin(Pin),
     WatchStore(WatchStore),
 
     #[clap(hide = true)]
@@ -55,6 +57,7 @@ pub async fn run() -> Result<()> {
         Command::Use(_) => r#use::run(opts).await,
         Command::Push(_) => push::run(opts).await,
         Command::Cache(_) => cache::run(opts).await,
+        Command::Pin(_) => pin::run(opts).await,
         Command::WatchStore(_) => watch_store::run(opts).await,
         Command::GetClosure(_) => get_closure::run(opts).await,
     }
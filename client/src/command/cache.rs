@@ -8,8 +8,11 @@ use crate::cache::CacheRef;
 use crate::cli::Opts;
 use crate::config::Config;
 use attic::api::v1::cache_config::{
-    CacheConfig, CreateCacheRequest, KeypairConfig, RetentionPeriodConfig,
+    CacheConfig, CacheKeyState, CreateCacheRequest, KeyRotationStep, KeypairConfig,
+    RetentionPeriodConfig, StorageQuotaConfig, UpstreamSubstituter,
 };
+use attic::api::v1::delete_paths::DeletePathsRequest;
+use attic::nix_store::StorePathHash;
 
 /// Manage caches on an Attic server.
 #[derive(Debug, Parser)]
@@ -24,6 +27,8 @@ enum Command {
     Configure(Configure),
     Destroy(Destroy),
     Info(Info),
+    DeletePaths(DeletePaths),
+    RotateKey(RotateKey),
 }
 
 /// Create a cache.
@@ -87,7 +92,8 @@ struct Configure {
     ///
     /// The server-side signing key will be regenerated and
     /// all users will need to configure the new signing key
-    /// in `nix.conf`.
+    /// in `nix.conf`. Use `attic cache rotate-key` to replace
+    /// the key without breaking existing users.
     #[clap(long)]
     regenerate_keypair: bool,
 
@@ -125,6 +131,20 @@ struct Configure {
     #[clap(value_name = "NAME", long = "upstream-cache-key-name")]
     upstream_cache_key_names: Option<Vec<String>>,
 
+    /// An upstream substituter to fetch missing paths from.
+    ///
+    /// The value is the URL of the binary cache followed by the
+    /// public keys trusted to sign its paths, separated by spaces,
+    /// for example "https://cache.nixos.org cache.nixos.org-1:...".
+    /// Specify this flag multiple times to add multiple upstreams,
+    /// which are tried in order. The existing upstreams are replaced.
+    #[clap(value_name = "URL KEY...", long = "upstream")]
+    upstreams: Option<Vec<String>>,
+
+    /// Remove all upstream substituters.
+    #[clap(long)]
+    no_upstreams: bool,
+
     /// Set the retention period of the cache.
     ///
     /// You can use expressions like "2 years", "3 months"
@@ -135,6 +155,38 @@ struct Configure {
     /// Reset the retention period of the cache to global default.
     #[clap(long)]
     reset_retention_period: bool,
+
+    /// Set the storage quota of the cache.
+    ///
+    /// When the cache grows beyond the quota, the least recently
+    /// accessed paths are garbage-collected. You can use sizes
+    /// like "500M", "100G" and "1TiB". 0 disables the quota.
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    storage_quota: Option<u64>,
+
+    /// Reset the storage quota of the cache to global default.
+    #[clap(long)]
+    reset_storage_quota: bool,
+
+    /// A public key that signatures of uploaded paths are verified with.
+    ///
+    /// Once set, only valid signatures by the trusted keys are kept
+    /// when a path is uploaded. Specify this flag multiple times to
+    /// trust multiple keys. The existing keys are replaced.
+    #[clap(value_name = "KEY", long = "trusted-upload-key")]
+    trusted_upload_keys: Option<Vec<String>>,
+
+    /// Keep signatures of uploaded paths as they are.
+    #[clap(long)]
+    no_trusted_upload_keys: bool,
+
+    /// Reject uploaded paths that aren't signed by a trusted key.
+    #[clap(long)]
+    require_trusted_signature: bool,
+
+    /// Accept uploaded paths that aren't signed by a trusted key.
+    #[clap(long)]
+    allow_untrusted_signature: bool,
 }
 
 /// Destroy a cache.
@@ -163,6 +215,56 @@ struct Info {
     cache: CacheRef,
 }
 
+/// Delete store paths from a cache.
+///
+/// The paths become unavailable immediately. The underlying
+/// data is deleted by garbage collection once no other path
+/// in any cache shares it.
+///
+/// You need the `delete` permission on the cache.
+#[derive(Debug, Clone, Parser)]
+struct DeletePaths {
+    /// Name of the cache to delete paths from.
+    cache: CacheRef,
+
+    /// The store paths to delete.
+    ///
+    /// Each can be either a full store path or the hash
+    /// portion of one.
+    paths: Vec<String>,
+
+    /// A glob matched against the names of the store paths.
+    ///
+    /// The name is the part after the hash, for example
+    /// `hello-*`. Specify this flag multiple times to add
+    /// multiple globs.
+    #[clap(value_name = "GLOB", long = "name")]
+    name_globs: Vec<String>,
+}
+
+/// Rotate the signing key of a cache.
+///
+/// A rotation takes three steps. Without flags, the next key
+/// is generated, and `attic use` configures Nix to trust it.
+/// Once users trust it, `--activate` signs paths with both the
+/// next and the current key. Once nothing relies on the old
+/// key any more, `--retire` stops signing with it.
+///
+/// You need the `configure_cache` permission on the cache.
+#[derive(Debug, Clone, Parser)]
+struct RotateKey {
+    /// Name of the cache to rotate the key of.
+    cache: CacheRef,
+
+    /// Start signing with the next key.
+    #[clap(long, conflicts_with = "retire")]
+    activate: bool,
+
+    /// Stop signing with all keys but the newest one.
+    #[clap(long)]
+    retire: bool,
+}
+
 pub async fn run(opts: Opts) -> Result<()> {
     let sub = opts.command.as_cache().unwrap();
     match &sub.command {
@@ -170,6 +272,8 @@ pub async fn run(opts: Opts) -> Result<()> {
         Command::Configure(sub) => configure_cache(sub.to_owned()).await,
         Command::Destroy(sub) => destroy_cache(sub.to_owned()).await,
         Command::Info(sub) => show_cache_config(sub.to_owned()).await,
+        Command::DeletePaths(sub) => delete_paths(sub.to_owned()).await,
+        Command::RotateKey(sub) => rotate_key(sub.to_owned()).await,
     }
 }
 
@@ -216,6 +320,30 @@ async fn configure_cache(sub: Configure) -> Result<()> {
         ));
     }
 
+    if sub.storage_quota.is_some() && sub.reset_storage_quota {
+        return Err(anyhow!(
+            "`--storage-quota` and `--reset-storage-quota` cannot be set at the same time."
+        ));
+    }
+
+    if sub.upstreams.is_some() && sub.no_upstreams {
+        return Err(anyhow!(
+            "`--upstream` and `--no-upstreams` cannot be set at the same time."
+        ));
+    }
+
+    if sub.trusted_upload_keys.is_some() && sub.no_trusted_upload_keys {
+        return Err(anyhow!(
+            "`--trusted-upload-key` and `--no-trusted-upload-keys` cannot be set at the same time."
+        ));
+    }
+
+    if sub.require_trusted_signature && sub.allow_untrusted_signature {
+        return Err(anyhow!(
+            "`--require-trusted-signature` and `--allow-untrusted-signature` cannot be set at the same time."
+        ));
+    }
+
     if sub.public {
         patch.is_public = Some(true);
     } else if sub.private {
@@ -228,6 +356,12 @@ async fn configure_cache(sub: Configure) -> Result<()> {
         patch.retention_period = Some(RetentionPeriodConfig::Global);
     }
 
+    if let Some(quota) = sub.storage_quota {
+        patch.storage_quota = Some(StorageQuotaConfig::Quota(quota));
+    } else if sub.reset_storage_quota {
+        patch.storage_quota = Some(StorageQuotaConfig::Global);
+    }
+
     if sub.regenerate_keypair {
         patch.keypair = Some(KeypairConfig::Generate);
     }
@@ -236,6 +370,29 @@ async fn configure_cache(sub: Configure) -> Result<()> {
     patch.priority = sub.priority;
     patch.upstream_cache_key_names = sub.upstream_cache_key_names;
 
+    if let Some(upstreams) = sub.upstreams {
+        patch.upstream_substituters = Some(
+            upstreams
+                .iter()
+                .map(|upstream| parse_upstream(upstream))
+                .collect::<Result<_>>()?,
+        );
+    } else if sub.no_upstreams {
+        patch.upstream_substituters = Some(Vec::new());
+    }
+
+    if let Some(keys) = sub.trusted_upload_keys {
+        patch.upload_trusted_keys = Some(keys);
+    } else if sub.no_trusted_upload_keys {
+        patch.upload_trusted_keys = Some(Vec::new());
+    }
+
+    if sub.require_trusted_signature {
+        patch.require_trusted_signature = Some(true);
+    } else if sub.allow_untrusted_signature {
+        patch.require_trusted_signature = Some(false);
+    }
+
     let api = ApiClient::from_server_config(server.clone())?;
     api.configure_cache(cache, &patch).await?;
 
@@ -283,6 +440,93 @@ async fn destroy_cache(sub: Destroy) -> Result<()> {
     Ok(())
 }
 
+async fn delete_paths(sub: DeletePaths) -> Result<()> {
+    let config = Config::load()?;
+
+    let (server_name, server, cache) = config.resolve_cache(&sub.cache)?;
+
+    if sub.paths.is_empty() && sub.name_globs.is_empty() {
+        return Err(anyhow!("No paths or `--name` globs were specified."));
+    }
+
+    let mut request = DeletePathsRequest {
+        cache: cache.to_owned(),
+        store_path_hashes: Vec::new(),
+        store_paths: Vec::new(),
+        name_globs: sub.name_globs,
+    };
+
+    for path in sub.paths {
+        if path.starts_with('/') {
+            request.store_paths.push(path);
+        } else {
+            request.store_path_hashes.push(StorePathHash::new(path)?);
+        }
+    }
+
+    let api = ApiClient::from_server_config(server.clone())?;
+    let response = api.delete_paths(&request).await?;
+
+    for path in &response.deleted_paths {
+        eprintln!("🗑️ {}", path);
+    }
+
+    eprintln!(
+        "Deleted {} paths from \"{}\" on \"{}\"",
+        response.deleted_paths.len(),
+        cache.as_str(),
+        server_name.as_str()
+    );
+
+    Ok(())
+}
+
+async fn rotate_key(sub: RotateKey) -> Result<()> {
+    let config = Config::load()?;
+
+    let (server_name, server, cache) = config.resolve_cache(&sub.cache)?;
+
+    let step = if sub.activate {
+        KeyRotationStep::Activate
+    } else if sub.retire {
+        KeyRotationStep::Retire
+    } else {
+        KeyRotationStep::Stage
+    };
+
+    let api = ApiClient::from_server_config(server.clone())?;
+    let response = api.rotate_cache_key(cache, step).await?;
+
+    for key in &response.keys {
+        let state = match key.state {
+            CacheKeyState::Next => "next",
+            CacheKeyState::Active => "active",
+            CacheKeyState::Retired => "retired",
+        };
+        eprintln!("{:>8}: {}", state, key.public_key);
+    }
+
+    match step {
+        KeyRotationStep::Stage => eprintln!(
+            "🔑 Staged the next key of \"{}\" on \"{}\". Run `attic use` to trust it.",
+            cache.as_str(),
+            server_name.as_str()
+        ),
+        KeyRotationStep::Activate => eprintln!(
+            "🔑 \"{}\" on \"{}\" is now signed with the next key.",
+            cache.as_str(),
+            server_name.as_str()
+        ),
+        KeyRotationStep::Retire => eprintln!(
+            "🔑 Retired the old keys of \"{}\" on \"{}\".",
+            cache.as_str(),
+            server_name.as_str()
+        ),
+    }
+
+    Ok(())
+}
+
 async fn show_cache_config(sub: Info) -> Result<()> {
     let config = Config::load()?;
 
@@ -294,7 +538,15 @@ async fn show_cache_config(sub: Info) -> Result<()> {
         eprintln!("               Public: {}", is_public);
     }
 
-    if let Some(public_key) = cache_config.public_key {
+    if let Some(public_keys) = cache_config.public_keys {
+        for (i, public_key) in public_keys.iter().enumerate() {
+            if i == 0 {
+                eprintln!("           Public Key: {}", public_key);
+            } else {
+                eprintln!("                       {}", public_key);
+            }
+        }
+    } else if let Some(public_key) = cache_config.public_key {
         eprintln!("           Public Key: {}", public_key);
     }
 
@@ -318,6 +570,15 @@ async fn show_cache_config(sub: Info) -> Result<()> {
         eprintln!("  Upstream Cache Keys: {:?}", upstream_cache_key_names);
     }
 
+    if let Some(upstream_substituters) = cache_config.upstream_substituters {
+        for upstream in upstream_substituters {
+            eprintln!("             Upstream: {}", upstream.url);
+            for key in upstream.trusted_keys {
+                eprintln!("                       {}", key);
+            }
+        }
+    }
+
     if let Some(retention_period) = cache_config.retention_period {
         match retention_period {
             RetentionPeriodConfig::Period(period) => {
@@ -329,5 +590,76 @@ async fn show_cache_config(sub: Info) -> Result<()> {
         }
     }
 
+    if let Some(storage_quota) = cache_config.storage_quota {
+        match storage_quota {
+            StorageQuotaConfig::Quota(quota) => {
+                eprintln!("        Storage Quota: {} bytes", quota);
+            }
+            StorageQuotaConfig::Global => {
+                eprintln!("        Storage Quota: Global Default");
+            }
+        }
+    }
+
+    if let Some(keys) = cache_config.upload_trusted_keys {
+        for (i, key) in keys.iter().enumerate() {
+            if i == 0 {
+                eprintln!("  Trusted Upload Keys: {}", key);
+            } else {
+                eprintln!("                       {}", key);
+            }
+        }
+    }
+
+    if let Some(require) = cache_config.require_trusted_signature {
+        eprintln!("    Require Signature: {}", require);
+    }
+
     Ok(())
 }
+
+/// Parses an upstream substituter in the form of `URL KEY...`.
+fn parse_upstream(s: &str) -> Result<UpstreamSubstituter> {
+    let mut parts = s.split_whitespace();
+
+    let url = parts
+        .next()
+        .ok_or_else(|| anyhow!("The upstream must not be empty"))?
+        .to_string();
+    let trusted_keys: Vec<String> = parts.map(str::to_string).collect();
+
+    if trusted_keys.is_empty() {
+        return Err(anyhow!(
+            "The upstream \"{}\" must have at least one trusted key",
+            url
+        ));
+    }
+
+    Ok(UpstreamSubstituter { url, trusted_keys })
+}
+
+/// Parses a size like `100G` or `1TiB` into bytes.
+///
+/// Suffixes are binary, so `1K` is 1024 bytes.
+fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid size \"{}\"", s))?;
+
+    let shift = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => return Err(anyhow!("Invalid size suffix \"{}\"", suffix)),
+    };
+
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| anyhow!("Size \"{}\" is too large", s))
+}
@@ -1,6 +1,7 @@
 pub mod cache;
 pub mod get_closure;
 pub mod login;
+pub mod pin;
 pub mod push;
 pub mod r#use;
 pub mod watch_store;
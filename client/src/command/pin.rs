@@ -0,0 +1,155 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+
+use crate::api::ApiClient;
+use crate::cache::CacheRef;
+use crate::cli::Opts;
+use crate::config::Config;
+use attic::api::v1::pin::CreatePinRequest;
+use attic::nix_store::NixStore;
+
+/// Manage pinned store paths in a cache.
+///
+/// Pinned paths and their closures are never garbage-collected.
+/// Pins can be resolved over HTTP at `{cache endpoint}/pins/{name}`.
+#[derive(Debug, Parser)]
+pub struct Pin {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    Add(Add),
+    List(List),
+    Remove(Remove),
+}
+
+/// Pin a store path.
+///
+/// The path must already be in the cache. If a pin of the same
+/// name exists, it's moved to the new path.
+///
+/// You need the `push` permission on the cache.
+#[derive(Debug, Clone, Parser)]
+struct Add {
+    /// The cache to pin the path in.
+    ///
+    /// This can be either `servername:cachename` or `cachename`
+    /// when using the default server.
+    cache: CacheRef,
+
+    /// Name of the pin, for example `release-24.05`.
+    name: String,
+
+    /// The store path to pin.
+    ///
+    /// Symlinks to store paths like `./result` are followed.
+    store_path: PathBuf,
+}
+
+/// List the pins of a cache.
+#[derive(Debug, Clone, Parser)]
+struct List {
+    /// The cache to list the pins of.
+    cache: CacheRef,
+}
+
+/// Remove a pin.
+///
+/// The pinned path stays in the cache, but becomes subject to
+/// garbage collection again.
+///
+/// You need the `push` permission on the cache.
+#[derive(Debug, Clone, Parser)]
+struct Remove {
+    /// The cache to remove the pin from.
+    cache: CacheRef,
+
+    /// Name of the pin.
+    name: String,
+}
+
+pub async fn run(opts: Opts) -> Result<()> {
+    let sub = opts.command.as_pin().unwrap();
+    match &sub.command {
+        Command::Add(sub) => add_pin(sub.to_owned()).await,
+        Command::List(sub) => list_pins(sub.to_owned()).await,
+        Command::Remove(sub) => remove_pin(sub.to_owned()).await,
+    }
+}
+
+async fn add_pin(sub: Add) -> Result<()> {
+    let config = Config::load()?;
+
+    let (server_name, server, cache) = config.resolve_cache(&sub.cache)?;
+
+    let store = NixStore::connect()?;
+    let store_path = store.follow_store_path(&sub.store_path)?;
+    let store_path = store
+        .get_full_path(&store_path)
+        .to_str()
+        .ok_or_else(|| anyhow!("The store path is not valid UTF-8"))?
+        .to_string();
+
+    let api = ApiClient::from_server_config(server.clone())?;
+    let pin = api
+        .create_pin(cache, &sub.name, &CreatePinRequest { store_path })
+        .await?;
+
+    eprintln!(
+        "📌 Pinned {} as \"{}\" in \"{}\" on \"{}\"",
+        pin.store_path,
+        pin.name,
+        cache.as_str(),
+        server_name.as_str()
+    );
+
+    Ok(())
+}
+
+async fn list_pins(sub: List) -> Result<()> {
+    let config = Config::load()?;
+
+    let (_, server, cache) = config.resolve_cache(&sub.cache)?;
+    let api = ApiClient::from_server_config(server.clone())?;
+    let response = api.list_pins(cache).await?;
+
+    let width = response
+        .pins
+        .iter()
+        .map(|pin| pin.name.len())
+        .max()
+        .unwrap_or(0);
+
+    for pin in response.pins {
+        println!(
+            "{:width$}  {}  {}",
+            pin.name,
+            pin.store_path,
+            pin.created_at,
+            width = width
+        );
+    }
+
+    Ok(())
+}
+
+async fn remove_pin(sub: Remove) -> Result<()> {
+    let config = Config::load()?;
+
+    let (server_name, server, cache) = config.resolve_cache(&sub.cache)?;
+    let api = ApiClient::from_server_config(server.clone())?;
+    api.delete_pin(cache, &sub.name).await?;
+
+    eprintln!(
+        "🗑️ Removed pin \"{}\" from \"{}\" on \"{}\"",
+        sub.name,
+        cache.as_str(),
+        server_name.as_str()
+    );
+
+    Ok(())
+}
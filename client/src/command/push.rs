@@ -12,7 +12,7 @@ use crate::cache::{CacheName, CacheRef, ServerName};
 use crate::cli::Opts;
 use crate::config::Config;
 use crate::push::{PushConfig, PushSessionConfig, Pusher};
-use attic::nix_store::NixStore;
+use attic::nix_store::{NixStore, StorePath};
 
 /// Push closures to a binary cache.
 #[derive(Debug, Parser)]
@@ -38,6 +38,22 @@ pub struct Push {
     #[clap(long)]
     ignore_upstream_cache_filter: bool,
 
+    /// Also push paths whose NARs are incomplete in the cache.
+    ///
+    /// The server keeps track of paths that are missing chunks, for
+    /// example after a scrub found corrupt chunks. Those that are
+    /// still in the local store are pushed again, along with any
+    /// paths specified.
+    #[clap(long)]
+    repair: bool,
+
+    /// Also upload the build logs of the pushed paths.
+    ///
+    /// Logs are looked up by the derivers of the paths and are
+    /// skipped if they aren't available in the local store.
+    #[clap(long)]
+    logs: bool,
+
     /// The maximum number of parallel upload processes.
     #[clap(short = 'j', long, default_value = "5")]
     jobs: usize,
@@ -54,11 +70,14 @@ struct PushContext {
     pusher: Pusher,
     no_closure: bool,
     ignore_upstream_cache_filter: bool,
+
+    /// Paths with incomplete NARs in the cache to push again.
+    repair_paths: Vec<StorePath>,
 }
 
 impl PushContext {
     async fn push_static(self, paths: Vec<PathBuf>) -> Result<()> {
-        if paths.is_empty() {
+        if paths.is_empty() && self.repair_paths.is_empty() {
             eprintln!("🤷 Nothing specified.");
             if !std::io::stdin().is_terminal() {
                 eprintln!(
@@ -68,10 +87,11 @@ impl PushContext {
             return Ok(());
         }
 
-        let roots = paths
+        let mut roots = paths
             .into_iter()
             .map(|p| self.store.follow_store_path(p))
             .collect::<std::result::Result<Vec<_>, _>>()?;
+        roots.extend(self.repair_paths.iter().cloned());
 
         let plan = self
             .pusher
@@ -100,6 +120,13 @@ impl PushContext {
             );
         }
 
+        if plan.num_incomplete != 0 {
+            eprintln!(
+                "🩹 {num_incomplete} of them have incomplete NARs in the cache and will be repaired",
+                num_incomplete = plan.num_incomplete,
+            );
+        }
+
         for (_, path_info) in plan.store_path_map {
             self.pusher.queue(path_info).await?;
         }
@@ -116,6 +143,10 @@ impl PushContext {
             ignore_upstream_cache_filter: self.ignore_upstream_cache_filter,
         });
 
+        if !self.repair_paths.is_empty() {
+            session.queue_many(self.repair_paths)?;
+        }
+
         let stdin = BufReader::new(io::stdin());
         let mut lines = stdin.lines();
         while let Some(line) = lines.next_line().await? {
@@ -156,9 +187,16 @@ pub async fn run(opts: Opts) -> Result<()> {
         api.set_endpoint(api_endpoint)?;
     }
 
+    let repair_paths = if sub.repair {
+        find_repair_paths(&store, &api, cache_name).await?
+    } else {
+        Vec::new()
+    };
+
     let push_config = PushConfig {
         num_workers: sub.jobs,
         force_preamble: sub.force_preamble,
+        upload_logs: sub.logs,
     };
 
     let mp = MultiProgress::new();
@@ -179,6 +217,7 @@ pub async fn run(opts: Opts) -> Result<()> {
         pusher,
         no_closure: sub.no_closure,
         ignore_upstream_cache_filter: sub.ignore_upstream_cache_filter,
+        repair_paths,
     };
 
     if sub.stdin {
@@ -195,3 +234,32 @@ pub async fn run(opts: Opts) -> Result<()> {
 
     Ok(())
 }
+
+/// Finds paths with incomplete NARs in the cache that are in the local store.
+async fn find_repair_paths(
+    store: &NixStore,
+    api: &ApiClient,
+    cache_name: &CacheName,
+) -> Result<Vec<StorePath>> {
+    let incomplete = api.list_incomplete_paths(cache_name).await?.paths;
+
+    let mut repair_paths = Vec::new();
+    for path in incomplete.iter() {
+        // The path may be from another store or no longer exist locally
+        let Ok(store_path) = store.parse_store_path(&path.store_path) else {
+            continue;
+        };
+        if store.query_path_info(store_path.clone()).await.is_ok() {
+            repair_paths.push(store_path);
+        }
+    }
+
+    eprintln!(
+        "🩹 {num_incomplete} paths have incomplete NARs in \"{cache}\", {num_local} of them are available locally",
+        num_incomplete = incomplete.len(),
+        num_local = repair_paths.len(),
+        cache = cache_name.as_str(),
+    );
+
+    Ok(repair_paths)
+}
@@ -31,8 +31,12 @@ pub async fn run(opts: Opts) -> Result<()> {
     let substituter = cache_config
         .substituter_endpoint
         .ok_or_else(|| anyhow!("The server did not tell us where the binary cache endpoint is."))?;
-    let public_key = cache_config.public_key
-        .ok_or_else(|| anyhow!("The server did not tell us which public key it uses. Is signing managed by the client?"))?;
+    // Includes the next key of a rotation, so it's trusted ahead of time
+    let public_keys = match (cache_config.public_keys, cache_config.public_key) {
+        (Some(public_keys), _) if !public_keys.is_empty() => public_keys,
+        (_, Some(public_key)) => vec![public_key],
+        _ => return Err(anyhow!("The server did not tell us which public key it uses. Is signing managed by the client?")),
+    };
 
     eprintln!(
         "Configuring Nix to use \"{cache}\" on \"{server_name}\":",
@@ -42,11 +46,15 @@ pub async fn run(opts: Opts) -> Result<()> {
 
     // Modify nix.conf
     eprintln!("+ Substituter: {}", substituter);
-    eprintln!("+ Trusted Public Key: {}", public_key);
+    for public_key in public_keys.iter() {
+        eprintln!("+ Trusted Public Key: {}", public_key);
+    }
 
     let mut nix_config = NixConfig::load().await?;
     nix_config.add_substituter(&substituter);
-    nix_config.add_trusted_public_key(&public_key);
+    for public_key in public_keys.iter() {
+        nix_config.add_trusted_public_key(public_key);
+    }
 
     // Modify netrc
     if let Some(token) = server.token()? {
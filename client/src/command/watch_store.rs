@@ -65,6 +65,7 @@ pub async fn run(opts: Opts) -> Result<()> {
     let push_config = PushConfig {
         num_workers: sub.jobs,
         force_preamble: sub.force_preamble,
+        upload_logs: false,
     };
 
     let push_session_config = PushSessionConfig {
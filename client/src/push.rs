@@ -17,6 +17,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
+use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
@@ -50,6 +51,9 @@ pub struct PushConfig {
 
     /// Whether to always include the upload info in the PUT payload.
     pub force_preamble: bool,
+
+    /// Whether to upload the build logs of the derivers of pushed paths.
+    pub upload_logs: bool,
 }
 
 /// Configuration for a push session.
@@ -133,6 +137,9 @@ pub struct PushPlan {
 
     /// Number of paths that have been filtered out because they are signed by an upstream cache.
     pub num_upstream: usize,
+
+    /// Number of paths to push that are in the cache but have incomplete NARs.
+    pub num_incomplete: usize,
 }
 
 /// Wrapper to update a progress bar as a NAR is streamed.
@@ -245,6 +252,8 @@ impl Pusher {
             };
 
             let store_path = path_info.path.clone();
+            let deriver = path_info.deriver.clone();
+            let is_ca = path_info.ca.is_some();
 
             let r = upload_path(
                 path_info,
@@ -256,6 +265,18 @@ impl Pusher {
             )
             .await;
 
+            if r.is_ok() {
+                if let Some(deriver) = deriver {
+                    if is_ca {
+                        upload_realisations(&store_path, &deriver, &store, &api, &cache, &mp).await;
+                    }
+
+                    if config.upload_logs {
+                        upload_build_log(&deriver, &store, &api, &cache, &mp).await;
+                    }
+                }
+            }
+
             results.insert(store_path, r);
         }
 
@@ -445,6 +466,7 @@ impl PushPlan {
                 num_all_paths,
                 num_already_cached: 0,
                 num_upstream: 0,
+                num_incomplete: 0,
             });
         }
 
@@ -474,23 +496,31 @@ impl PushPlan {
                 num_all_paths,
                 num_already_cached: 0,
                 num_upstream: num_all_paths - num_filtered_paths,
+                num_incomplete: 0,
             });
         }
 
         // Query missing paths
-        let missing_path_hashes: HashSet<StorePathHash> = {
-            let store_path_hashes = store_path_map.keys().map(|sph| sph.to_owned()).collect();
-            let res = api.get_missing_paths(cache, store_path_hashes).await?;
-            res.missing_paths.into_iter().collect()
-        };
+        //
+        // Paths with incomplete NARs are reported as missing, so
+        // pushing them again repairs the cache.
+        let store_path_hashes = store_path_map.keys().map(|sph| sph.to_owned()).collect();
+        let res = api.get_missing_paths(cache, store_path_hashes).await?;
+        let missing_path_hashes: HashSet<StorePathHash> = res.missing_paths.into_iter().collect();
         store_path_map.retain(|sph, _| missing_path_hashes.contains(sph));
         let num_missing_paths = store_path_map.len();
+        let num_incomplete = res
+            .incomplete_paths
+            .iter()
+            .filter(|sph| store_path_map.contains_key(sph))
+            .count();
 
         Ok(Self {
             store_path_map,
             num_all_paths,
             num_already_cached: num_filtered_paths - num_missing_paths,
             num_upstream: num_all_paths - num_filtered_paths,
+            num_incomplete,
         })
     }
 }
@@ -527,8 +557,11 @@ pub async fn upload_path(
             store_path_hash: path.to_hash(),
             store_path: full_path,
             references,
-            system: None,  // TODO
-            deriver: None, // TODO
+            system: None, // TODO
+            deriver: path_info
+                .deriver
+                .as_ref()
+                .map(|d| d.to_string_lossy().into_owned()),
             sigs: path_info.sigs,
             ca: path_info.ca,
             nar_hash: path_info.nar_hash.to_owned(),
@@ -615,6 +648,92 @@ pub async fn upload_path(
     }
 }
 
+/// Uploads the realisations of a derivation that produced a path.
+///
+/// Realisations only exist for content-addressed derivations. They are
+/// best-effort like build logs.
+async fn upload_realisations(
+    store_path: &StorePath,
+    deriver: &Path,
+    store: &NixStore,
+    api: &ApiClient,
+    cache: &CacheName,
+    mp: &MultiProgress,
+) {
+    let result = async {
+        let drv_path = store.parse_store_path(store.store_dir().join(deriver))?;
+        let realisations = store
+            .query_realisations(store_path.clone(), drv_path)
+            .await?;
+
+        let mut ids = Vec::new();
+        for realisation in realisations {
+            let value: serde_json::Value = serde_json::from_str(&realisation)?;
+            let id = value["id"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Realisation has no ID"))?
+                .to_string();
+
+            api.upload_realisation(cache, &id, realisation).await?;
+            ids.push(id);
+        }
+
+        Ok::<Vec<String>, anyhow::Error>(ids)
+    }
+    .await;
+
+    match result {
+        Ok(ids) => mp.suspend(|| {
+            for id in ids {
+                eprintln!("🔗 {} (realisation)", id);
+            }
+        }),
+        Err(e) => mp.suspend(|| {
+            eprintln!(
+                "⚠️ {}: Failed to upload realisations: {}",
+                store_path.as_os_str().to_string_lossy(),
+                e
+            );
+        }),
+    }
+}
+
+/// Uploads the build log of a derivation if it's available locally.
+///
+/// Build logs are best-effort, so failures are only reported.
+async fn upload_build_log(
+    deriver: &Path,
+    store: &NixStore,
+    api: &ApiClient,
+    cache: &CacheName,
+    mp: &MultiProgress,
+) {
+    let drv_name = deriver.to_string_lossy();
+
+    let result = async {
+        let drv_path = store.parse_store_path(store.store_dir().join(deriver))?;
+
+        match store.build_log(drv_path).await? {
+            Some(log) => {
+                api.upload_build_log(cache, &drv_name, log).await?;
+                Ok(true)
+            }
+            None => Ok::<bool, anyhow::Error>(false),
+        }
+    }
+    .await;
+
+    match result {
+        Ok(true) => mp.suspend(|| {
+            eprintln!("📜 {} (build log)", drv_name);
+        }),
+        Ok(false) => {}
+        Err(e) => mp.suspend(|| {
+            eprintln!("⚠️ {}: Failed to upload build log: {}", drv_name, e);
+        }),
+    }
+}
+
 impl<S: Stream<Item = AtticResult<Vec<u8>>>> NarStreamProgress<S> {
     fn new(stream: S, bar: ProgressBar) -> Self {
         Self { stream, bar }
@@ -140,6 +140,8 @@ in
 
           'garbage-collector' only runs the garbage collector periodically.
 
+          'substituter' only serves the binary cache, read-only, and is suitable for pull-only nodes with an embedded replica of the database.
+
           A simple NixOS-based Attic deployment will typically have one 'monolithic' and any number of 'api-server' nodes.
 
           There are several other supported modes that perform one-off operations, but these are the only ones that make sense to run via the NixOS module.
@@ -148,6 +150,7 @@ in
           "monolithic"
           "api-server"
           "garbage-collector"
+          "substituter"
         ];
         default = "monolithic";
       };
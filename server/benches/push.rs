@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::future::try_join_all;
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+
+use attic_server::database::connection::{TursoConfig, TursoConnection};
+use attic_server::database::models::{ChunkState, NarState};
+use attic_server::database::{migrations, queries};
+
+/// The number of paths pushed in each iteration.
+const PATHS: u64 = 200;
+
+struct Database {
+    _dir: TempDir,
+    conn: Arc<TursoConnection>,
+    cache_id: i64,
+}
+
+async fn setup(pool_size: usize) -> Database {
+    let dir = TempDir::new().unwrap();
+    let config = TursoConfig {
+        url: dir.path().join("server.db").to_string_lossy().to_string(),
+        auth_token: None,
+        local_replica_path: None,
+        sync_interval: Duration::from_secs(60),
+        pool_size,
+        busy_timeout: Duration::from_secs(30),
+    };
+
+    let conn = TursoConnection::connect(config).await.unwrap();
+    migrations::run_migrations(&conn).await.unwrap();
+
+    let cache = queries::create_cache(&conn, "bench", "", false, "/nix/store", 41, &[])
+        .await
+        .unwrap();
+
+    Database {
+        _dir: dir,
+        conn,
+        cache_id: cache.id,
+    }
+}
+
+/// Records an unchunked path the way an upload does.
+async fn push_path(conn: &TursoConnection, cache_id: i64, n: u64) -> Result<()> {
+    let hash = format!("sha256:{:064x}", n);
+    let store_path_hash = format!("{:032x}", n);
+    let store_path = format!("/nix/store/{}-bench", store_path_hash);
+    let remote_file = format!(r#"{{"Local":{{"name":"{}.chunk"}}}}"#, n);
+
+    let chunk = queries::insert_chunk(
+        conn,
+        ChunkState::PendingUpload,
+        &hash,
+        1024,
+        "zstd",
+        &remote_file,
+        &hash,
+    )
+    .await?;
+
+    let txn = conn.begin_transaction().await?;
+    let tconn = txn.connection();
+
+    queries::update_chunk(tconn, chunk.id, Some(ChunkState::Valid), None, None, None).await?;
+    let nar = queries::insert_nar(tconn, NarState::Valid, &hash, 1024, "zstd", 1).await?;
+    queries::update_nar_completeness_hint(tconn, nar.id, true).await?;
+    queries::insert_chunkref(tconn, nar.id, 0, Some(chunk.id), &hash, "zstd").await?;
+    queries::insert_object_upsert(
+        tconn,
+        cache_id,
+        nar.id,
+        &store_path_hash,
+        &store_path,
+        "[]",
+        None,
+        None,
+        "[]",
+        None,
+        None,
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(())
+}
+
+pub fn bench_push(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let counter = Arc::new(AtomicU64::new(0));
+
+    let mut group = c.benchmark_group("push");
+    group.throughput(Throughput::Elements(PATHS));
+    group.sample_size(10);
+
+    for concurrency in [1, 8, 40] {
+        let db = rt.block_on(setup(concurrency));
+
+        group.bench_with_input(
+            BenchmarkId::new("concurrency", concurrency),
+            &concurrency,
+            |b, &concurrency| {
+                b.to_async(&rt).iter(|| {
+                    let conn = db.conn.clone();
+                    let cache_id = db.cache_id;
+                    let counter = counter.clone();
+
+                    async move {
+                        let workers = (0..concurrency).map(|_| {
+                            let conn = conn.clone();
+                            let counter = counter.clone();
+
+                            tokio::spawn(async move {
+                                let per_worker = PATHS / concurrency as u64;
+                                for _ in 0..per_worker {
+                                    let n = counter.fetch_add(1, Ordering::Relaxed);
+                                    push_path(&conn, cache_id, n).await?;
+                                }
+                                anyhow::Ok(())
+                            })
+                        });
+
+                        for result in try_join_all(workers).await.unwrap() {
+                            result.unwrap();
+                        }
+                    }
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_push);
+criterion_main!(benches);
@@ -11,7 +11,7 @@ use crate::access::{CachePermission, Token};
 use crate::database::connection::TursoConnection;
 use crate::database::models::CacheModel;
 use crate::database::AtticDatabase;
-use crate::error::ServerResult;
+use crate::error::{ErrorKind, ServerResult};
 use crate::{RequestState, State};
 
 /// Auth state.
@@ -36,6 +36,13 @@ impl AuthState {
         self.token.get().and_then(|token| token.sub())
     }
 
+    /// Returns the token, failing if the request isn't authenticated.
+    pub fn require_token(&self) -> ServerResult<&Token> {
+        self.token
+            .get()
+            .ok_or_else(|| ErrorKind::Unauthorized.into())
+    }
+
     /// Finds and performs authorization for a cache.
     pub async fn auth_cache<F, T>(
         &self,
@@ -0,0 +1,117 @@
+//! Access time tracking.
+//!
+//! Garbage collection keeps objects that were accessed recently, so
+//! every download updates the access time of its object. Writing it
+//! right away would put a database write, and with a remote database a
+//! round trip, in front of the first byte of every download.
+//!
+//! Instead, accesses are collected in memory, coalesced per object, and
+//! written in a single statement every `flush-interval`. Accesses that
+//! haven't been written when the server crashes are lost, so at most one
+//! interval of access times is lost. They are written on shutdown.
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::time;
+
+use crate::config::AccessTrackingConfig;
+use crate::database::connection::TursoConnection;
+use crate::database::queries;
+use crate::error::ServerResult;
+
+/// Collects the access times of objects and writes them in batches.
+#[derive(Debug)]
+pub struct AccessTracker {
+    /// How often to write the access times, or zero to write them
+    /// right away.
+    flush_interval: Duration,
+
+    /// The latest access time of each object, waiting to be written.
+    pending: Mutex<HashMap<i64, DateTime<Utc>>>,
+}
+
+impl AccessTracker {
+    pub fn new(config: &AccessTrackingConfig) -> Self {
+        Self {
+            flush_interval: config.flush_interval,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records an access to an object.
+    ///
+    /// The access time is only written right away if buffering is
+    /// disabled.
+    pub async fn bump(&self, db: &TursoConnection, object_id: i64) -> ServerResult<()> {
+        if self.flush_interval.is_zero() {
+            return queries::bump_object_last_accessed(db, object_id).await;
+        }
+
+        self.pending.lock().unwrap().insert(object_id, Utc::now());
+
+        Ok(())
+    }
+
+    /// Returns the number of objects with access times waiting to be
+    /// written.
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Returns whether no access times are waiting to be written.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the buffered access times, returning the number of objects
+    /// updated.
+    ///
+    /// If the write fails, the access times are kept for the next
+    /// attempt.
+    pub async fn flush(&self, db: &TursoConnection) -> ServerResult<u64> {
+        let pending = mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let accesses: Vec<(i64, DateTime<Utc>)> = pending.into_iter().collect();
+
+        match queries::bump_objects_last_accessed(db, &accesses).await {
+            Ok(updated) => Ok(updated),
+            Err(e) => {
+                // Newer accesses may have come in meanwhile
+                let mut pending = self.pending.lock().unwrap();
+                for (object_id, accessed_at) in accesses {
+                    let latest = pending.entry(object_id).or_insert(accessed_at);
+                    *latest = (*latest).max(accessed_at);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes the buffered access times periodically.
+    pub async fn run(&self, db: &TursoConnection) {
+        if self.flush_interval.is_zero() {
+            return;
+        }
+
+        loop {
+            time::sleep(self.flush_interval).await;
+
+            if self.is_empty() {
+                continue;
+            }
+
+            match self.flush(db).await {
+                Ok(n) => tracing::debug!("Wrote the access times of {} objects", n),
+                Err(e) => tracing::warn!("Failed to write access times: {}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::path::PathBuf;
+use std::time::Duration as StdDuration;
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use humantime::Duration;
+
+use crate::Opts;
+use attic_server::config;
+use attic_server::database::check::{self, CheckOptions};
+use attic_server::database::connection::{TursoConfig, TursoConnection};
+
+/// Manage the database.
+#[derive(Debug, Parser)]
+pub struct Db {
+    #[clap(subcommand)]
+    command: DbCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum DbCommand {
+    Check(Check),
+}
+
+/// Check the consistency of the database.
+///
+/// Looks for rows pointing at rows that don't exist, NARs whose chunks
+/// don't add up, uploads that were abandoned and holder counts that went
+/// wrong. Nothing is changed unless requested. Exits with an error if
+/// any problem is left unfixed.
+#[derive(Debug, Parser)]
+struct Check {
+    /// Repair the problems found.
+    ///
+    /// Broken NARs are deleted along with their objects, and the
+    /// damaged store paths are reported so they can be pushed again.
+    #[clap(long)]
+    fix: bool,
+
+    /// Reset the holder counts of all NARs and chunks to zero.
+    ///
+    /// Only use this while no server is using the database, as holders
+    /// are taken by requests in progress.
+    #[clap(long)]
+    reset_holders: bool,
+
+    /// Consider NARs and chunks pending for longer than this abandoned.
+    #[clap(long, default_value = "1 day")]
+    pending_age: Duration,
+
+    /// Check this SQLite database file instead of the configured database.
+    ///
+    /// No configuration file is needed in this case.
+    #[clap(long)]
+    database: Option<PathBuf>,
+
+    /// Print the report as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+pub async fn run(opts: Opts) -> Result<()> {
+    let sub = opts.command.as_db().unwrap();
+
+    match &sub.command {
+        DbCommand::Check(check) => run_check(&opts, check).await,
+    }
+}
+
+async fn run_check(opts: &Opts, args: &Check) -> Result<()> {
+    let turso_config = match &args.database {
+        Some(path) => {
+            if !path.exists() {
+                return Err(anyhow!("{} does not exist", path.display()));
+            }
+
+            TursoConfig {
+                url: path.to_string_lossy().to_string(),
+                auth_token: None,
+                local_replica_path: None,
+                sync_interval: StdDuration::from_secs(60),
+                pool_size: 1,
+                busy_timeout: StdDuration::from_secs(5),
+            }
+        }
+        None => {
+            let config = config::load_config(opts.config.as_deref(), false).await?;
+            TursoConfig::from_database_config(&config.database)
+        }
+    };
+    let db = TursoConnection::connect(turso_config).await?;
+
+    let options = CheckOptions {
+        pending_age: args.pending_age.into(),
+        fix: args.fix,
+        reset_holders: args.reset_holders,
+    };
+
+    let report = check::check_database(&db, &options).await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report);
+    }
+
+    let unfixed = report.unfixed();
+    if unfixed != 0 {
+        return Err(anyhow!("Found {} problems that were not fixed", unfixed));
+    }
+
+    Ok(())
+}
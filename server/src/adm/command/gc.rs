@@ -0,0 +1,79 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+
+use crate::Opts;
+use attic_server::config::Config;
+use attic_server::gc::{self, format_bytes};
+
+/// Manage garbage collection.
+#[derive(Debug, Parser)]
+pub struct Gc {
+    #[clap(subcommand)]
+    command: GcCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum GcCommand {
+    RetryDeletions(RetryDeletions),
+}
+
+/// Retry deleting chunks whose deletion from the storage failed.
+///
+/// Garbage collection retries failed deletions by itself with an
+/// increasing backoff. This reports the chunks waiting to be deleted,
+/// then retries all of them right away.
+#[derive(Debug, Parser)]
+struct RetryDeletions {
+    /// Only report the chunks waiting to be deleted.
+    #[clap(long)]
+    report_only: bool,
+}
+
+pub async fn run(config: Config, opts: Opts) -> Result<()> {
+    let sub = opts.command.as_gc().unwrap();
+
+    match &sub.command {
+        GcCommand::RetryDeletions(retry) => retry_deletions(config, retry).await,
+    }
+}
+
+async fn retry_deletions(config: Config, retry: &RetryDeletions) -> Result<()> {
+    let backlog = gc::find_deletion_backlog(config.clone()).await?;
+
+    if backlog.chunks == 0 {
+        println!("No chunks are waiting to be deleted");
+        return Ok(());
+    }
+
+    println!(
+        "{} chunks ({}) are waiting to be deleted, {} of them due for a retry",
+        backlog.chunks,
+        format_bytes(backlog.stored_size as u64),
+        backlog.due
+    );
+    println!("Most failed attempts of a chunk: {}", backlog.max_attempts);
+
+    if !backlog.errors.is_empty() {
+        println!();
+        println!("Most common errors:");
+        for (error, count) in backlog.errors.iter() {
+            println!("{:>8}  {}", count, error);
+        }
+    }
+
+    if retry.report_only {
+        return Ok(());
+    }
+
+    println!();
+    let deletions = gc::retry_chunk_deletions(config).await?;
+
+    println!(
+        "Deleted {} chunks ({}), {} still failing",
+        deletions.deleted,
+        format_bytes(deletions.bytes_freed),
+        deletions.failed
+    );
+
+    Ok(())
+}
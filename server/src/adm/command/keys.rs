@@ -0,0 +1,58 @@
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+
+use crate::Opts;
+use attic_server::config::Config;
+use attic_server::signing;
+
+/// Manage the encryption of cache signing keys.
+#[derive(Debug, Parser)]
+pub struct Keys {
+    #[clap(subcommand)]
+    command: KeysCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum KeysCommand {
+    Rewrap(Rewrap),
+}
+
+/// Encrypt all signing keys with the current master key.
+///
+/// Signing keys stored in plaintext or encrypted with a previous master
+/// key are encrypted with the current one. Afterwards, the previous master
+/// keys can be removed from the configuration. Exits with an error if any
+/// signing key can't be decrypted.
+#[derive(Debug, Parser)]
+struct Rewrap {
+    /// Print the report as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+pub async fn run(config: Config, opts: Opts) -> Result<()> {
+    let sub = opts.command.as_keys().unwrap();
+
+    match &sub.command {
+        KeysCommand::Rewrap(rewrap) => run_rewrap(config, rewrap).await,
+    }
+}
+
+async fn run_rewrap(config: Config, args: &Rewrap) -> Result<()> {
+    let report = signing::run_rewrap(config).await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("   Encrypted: {}", report.sealed);
+        println!("   Rewrapped: {}", report.rewrapped);
+        println!("   Unchanged: {}", report.unchanged);
+        println!("      Failed: {}", report.failed);
+    }
+
+    if report.failed != 0 {
+        return Err(anyhow!("Failed to decrypt {} signing keys", report.failed));
+    }
+
+    Ok(())
+}
@@ -1 +1,6 @@
+pub mod db;
+pub mod gc;
+pub mod keys;
 pub mod make_token;
+pub mod scrub;
+pub mod storage;
@@ -0,0 +1,40 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+
+use crate::Opts;
+use attic_server::config::Config;
+use attic_server::scrub;
+
+/// Verify the chunks in the storage against their recorded hashes.
+///
+/// Every chunk is read back from the storage. Corrupt chunks are taken
+/// out of service and the store paths using them are reported, so they
+/// can be pushed again. Exits with an error if any corrupt chunk is found.
+#[derive(Debug, Parser)]
+pub struct Scrub {
+    /// Only report corrupt chunks without marking them.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Print the report as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+pub async fn run(config: Config, opts: Opts) -> Result<()> {
+    let sub = opts.command.as_scrub().unwrap();
+
+    let report = scrub::run_scrub_once(config, sub.dry_run).await?;
+
+    if sub.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report);
+    }
+
+    if !report.corrupt.is_empty() {
+        return Err(anyhow!("Found {} corrupt chunks", report.corrupt.len()));
+    }
+
+    Ok(())
+}
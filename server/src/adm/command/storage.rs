@@ -0,0 +1,74 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use humantime::Duration;
+
+use crate::Opts;
+use attic_server::config::Config;
+use attic_server::reconcile::{self, ReconcileOptions};
+
+/// Manage the storage.
+#[derive(Debug, Parser)]
+pub struct Storage {
+    #[clap(subcommand)]
+    command: StorageCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum StorageCommand {
+    Reconcile(Reconcile),
+}
+
+/// Compare the files in the storage with the chunks in the database.
+///
+/// Reports files that nothing refers to, such as leftovers of failed
+/// uploads, and chunks whose files are missing, such as after restoring
+/// the database from a backup. Nothing is changed unless requested.
+#[derive(Debug, Parser)]
+struct Reconcile {
+    /// Delete files that nothing refers to.
+    #[clap(long)]
+    delete: bool,
+
+    /// Mark valid chunks whose files are missing as corrupt.
+    ///
+    /// The store paths using them are then reported as missing to
+    /// clients, so pushing them again repairs them.
+    #[clap(long)]
+    mark_missing: bool,
+
+    /// Ignore files and chunks more recent than this.
+    ///
+    /// Newer ones may belong to uploads in progress.
+    #[clap(long, default_value = "1 day")]
+    grace_period: Duration,
+
+    /// Print the report as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+pub async fn run(config: Config, opts: Opts) -> Result<()> {
+    let sub = opts.command.as_storage().unwrap();
+
+    match &sub.command {
+        StorageCommand::Reconcile(reconcile) => run_reconcile(config, reconcile).await,
+    }
+}
+
+async fn run_reconcile(config: Config, args: &Reconcile) -> Result<()> {
+    let options = ReconcileOptions {
+        grace_period: args.grace_period.into(),
+        delete_unreferenced: args.delete,
+        mark_missing: args.mark_missing,
+    };
+
+    let report = reconcile::run_reconcile(config, options).await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report);
+    }
+
+    Ok(())
+}
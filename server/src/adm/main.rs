@@ -7,7 +7,12 @@ use clap::{Parser, Subcommand};
 use enum_as_inner::EnumAsInner;
 
 use attic_server::config;
+use command::db::{self, Db};
+use command::gc::{self, Gc};
+use command::keys::{self, Keys};
 use command::make_token::{self, MakeToken};
+use command::scrub::{self, Scrub};
+use command::storage::{self, Storage};
 
 /// Attic server administration utilities.
 #[derive(Debug, Parser)]
@@ -23,18 +28,36 @@ pub struct Opts {
     pub command: Command,
 }
 
+// Only one command is ever constructed
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Subcommand, EnumAsInner)]
 pub enum Command {
     MakeToken(MakeToken),
+    Gc(Gc),
+    Scrub(Scrub),
+    Storage(Storage),
+    Keys(Keys),
+    Db(Db),
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Opts::parse();
+
+    // Database checks can run against a SQLite file without a config
+    if opts.command.is_db() {
+        return db::run(opts).await;
+    }
+
     let config = config::load_config(opts.config.as_deref(), false).await?;
 
     match opts.command {
         Command::MakeToken(_) => make_token::run(config, opts).await?,
+        Command::Gc(_) => gc::run(config, opts).await?,
+        Command::Scrub(_) => scrub::run(config, opts).await?,
+        Command::Storage(_) => storage::run(config, opts).await?,
+        Command::Keys(_) => keys::run(config, opts).await?,
+        Command::Db(_) => unreachable!(),
     }
 
     Ok(())
@@ -3,36 +3,80 @@
 //! This module implements the Nix Binary Cache API.
 //!
 //! The implementation is based on the specifications at <https://github.com/fzakaria/nix-http-binary-cache-api-spec>.
+//!
+//! ## Write protocol
+//!
+//! Clients like `nix copy --to` upload a path with two requests. The
+//! (possibly compressed) NAR is first uploaded to the URL that the
+//! `.narinfo` will refer to, then the `.narinfo` itself is uploaded.
+//!
+//! Since we can't know which store path a NAR belongs to until its
+//! `.narinfo` arrives, the decompressed NAR is staged as a single file
+//! on the storage backend. Once the `.narinfo` is uploaded, the staged
+//! NAR goes through the same ingestion path as `/_api/v1/upload-path`,
+//! including chunking and deduplication.
+//!
+//! ## Build logs
+//!
+//! Build logs uploaded with `nix store copy-log --to` or `attic push --logs`
+//! are served under `/:cache/log/{drvName}`, which is where `nix log` looks
+//! for them on substituters. They are not chunked or deduplicated and are
+//! stored Brotli-compressed as single files on the storage backend.
+//!
+//! ## Realisations
+//!
+//! Realisations of content-addressed derivations are uploaded and served
+//! under `/:cache/realisations/{drvOutputId}.doi`. When served, they are
+//! signed with the key of the cache in addition to any signatures they
+//! were uploaded with.
+//!
+//! ## Upstream substituters
+//!
+//! If a `.narinfo`, `.ls` or NAR is requested for a path that isn't in
+//! the cache, the path is fetched from the upstream substituters of the
+//! cache, if any, before giving up. See [`crate::upstream`].
 
 use std::collections::VecDeque;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use anyhow::anyhow;
+use async_compression::tokio::bufread::BrotliEncoder;
 use axum::http;
 use axum::{
     body::Body,
     extract::{Extension, Path},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Redirect, Response},
     routing::get,
     Router,
 };
 use futures::stream::BoxStream;
-use futures::TryStreamExt as _;
+use futures::{StreamExt as _, TryStreamExt as _};
 use serde::Serialize;
-use tokio_util::io::ReaderStream;
+use sha2::{Digest, Sha256};
+use tokio::io::BufReader;
+use tokio_util::io::{ReaderStream, StreamReader};
 use tracing::instrument;
-
-use crate::database::models::ChunkModel;
-use crate::database::AtticDatabase;
-use crate::error::{ErrorKind, ServerResult};
-use crate::narinfo::NarInfo;
+use uuid::Uuid;
+
+use super::v1::upload_path::upload_path_with_info;
+use crate::compression::get_decompressor;
+use crate::database::models::{CacheModel, ChunkModel, NarModel, ObjectModel};
+use crate::database::{queries, AtticDatabase};
+use crate::error::{ErrorKind, ServerError, ServerResult};
+use crate::narinfo::cache::CachedNarInfo;
+use crate::narinfo::{Compression, NarInfo};
 use crate::nix_manifest;
+use crate::realisation::{self, Realisation};
+use crate::signing::CacheKeys;
 use crate::storage::{Download, StorageBackend};
 use crate::{RequestState, State};
+use attic::api::v1::upload_path::UploadPathNarInfo;
 use attic::cache::CacheName;
-use attic::io::merge_chunks;
+use attic::hash::Hash;
+use attic::io::{merge_chunks, HashReader};
 use attic::mime;
 use attic::nix_store::StorePathHash;
 
@@ -109,38 +153,96 @@ async fn get_nix_cache_info(
 /// `/:cache/:path`, which may be one of
 /// - GET `/:cache/{storePathHash}.narinfo`
 /// - HEAD `/:cache/{storePathHash}.narinfo`
-/// - GET `/:cache/{storePathHash}.ls` (not implemented)
+/// - GET `/:cache/{storePathHash}.ls`
 #[instrument(skip_all, fields(cache_name, path))]
 #[axum_macros::debug_handler]
 async fn get_store_path_info(
     Extension(state): Extension<State>,
     Extension(req_state): Extension<RequestState>,
     Path((cache_name, path)): Path<(CacheName, String)>,
-) -> ServerResult<NarInfo> {
+) -> ServerResult<Response> {
     let components: Vec<&str> = path.splitn(2, '.').collect();
 
     if components.len() != 2 {
         return Err(ErrorKind::NotFound.into());
     }
 
-    // TODO: Other endpoints
-    if components[1] != "narinfo" {
+    if !matches!(components[1], "narinfo" | "ls") {
         return Err(ErrorKind::NotFound.into());
     }
 
     let store_path_hash = StorePathHash::new(components[0].to_string())?;
 
+    if components[1] == "ls" {
+        get_nar_listing(state, req_state, cache_name, store_path_hash).await
+    } else {
+        get_nar_info(state, req_state, cache_name, store_path_hash).await
+    }
+}
+
+/// Gets the `.narinfo` of a store path.
+///
+/// Rendered `.narinfo`s are cached in memory, see [`crate::narinfo::cache`].
+async fn get_nar_info(
+    state: State,
+    req_state: RequestState,
+    cache_name: CacheName,
+    store_path_hash: StorePathHash,
+) -> ServerResult<Response> {
     tracing::debug!(
         "Received request for {}.narinfo in {:?}",
         store_path_hash.as_str(),
         cache_name
     );
 
-    let (object, cache, nar, _) = state
-        .database()
-        .await?
-        .find_object_and_chunks_by_store_path_hash(&cache_name, &store_path_hash, false)
-        .await?;
+    let ticket = match state.narinfo_cache.get(&cache_name, &store_path_hash) {
+        Ok(CachedNarInfo::Found { narinfo, is_public }) => {
+            let permission = req_state
+                .auth
+                .get_permission_for_cache(&cache_name, is_public);
+            permission.require_pull()?;
+
+            req_state.set_public_cache(is_public);
+
+            return Ok(narinfo_response(narinfo));
+        }
+        Ok(CachedNarInfo::Missing) => return Err(ErrorKind::NoSuchObject.into()),
+        Err(ticket) => ticket,
+    };
+
+    let (object, cache, nar, _) = match find_object_or_fetch(
+        &state,
+        &req_state,
+        &cache_name,
+        &store_path_hash,
+        false,
+    )
+    .await
+    {
+        Ok(found) => found,
+        Err(e) => {
+            // Only remember the path as missing if the upstream
+            // substituters were given a chance to provide it
+            if matches!(e.kind(), ErrorKind::NoSuchObject) {
+                let database = state.database().await?;
+                if let Ok(cache) = database.find_cache(&cache_name).await {
+                    let permission = req_state
+                        .auth
+                        .get_permission_for_cache(&cache_name, cache.is_public);
+                    if permission.require_pull().is_ok() {
+                        state.narinfo_cache.insert(
+                            ticket,
+                            &cache_name,
+                            &store_path_hash,
+                            CachedNarInfo::Missing,
+                        );
+                    }
+                }
+            }
+
+            return Err(e);
+        }
+    };
 
     let permission = req_state
         .auth
@@ -151,12 +253,84 @@ async fn get_store_path_info(
 
     let mut narinfo = object.to_nar_info(&nar)?;
 
-    if narinfo.signature().is_none() {
-        let keypair = cache.keypair()?;
-        narinfo.sign(&keypair);
+    let keys = CacheKeys::load(state.database().await?, &state, &cache).await?;
+    for signer in keys.signers() {
+        narinfo.sign_with(signer).await?;
     }
 
-    Ok(narinfo)
+    let narinfo = narinfo.to_string()?;
+
+    state.narinfo_cache.insert(
+        ticket,
+        &cache_name,
+        &store_path_hash,
+        CachedNarInfo::Found {
+            narinfo: narinfo.clone(),
+            is_public: cache.is_public,
+        },
+    );
+
+    Ok(narinfo_response(narinfo))
+}
+
+fn narinfo_response(narinfo: String) -> Response {
+    (
+        [(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static(mime::NARINFO),
+        )],
+        narinfo,
+    )
+        .into_response()
+}
+
+/// Gets the listing of the files in a store path.
+///
+/// The listing is stored Brotli-compressed and served as-is
+/// with `Content-Encoding: br`. NARs uploaded before listings
+/// were introduced don't have one.
+async fn get_nar_listing(
+    state: State,
+    req_state: RequestState,
+    cache_name: CacheName,
+    store_path_hash: StorePathHash,
+) -> ServerResult<Response> {
+    tracing::debug!(
+        "Received request for {}.ls in {:?}",
+        store_path_hash.as_str(),
+        cache_name
+    );
+
+    let (_, cache, nar, _) =
+        find_object_or_fetch(&state, &req_state, &cache_name, &store_path_hash, false).await?;
+
+    let database = state.database().await?;
+
+    let permission = req_state
+        .auth
+        .get_permission_for_cache(&cache_name, cache.is_public);
+    permission.require_pull()?;
+
+    req_state.set_public_cache(cache.is_public);
+
+    let listing = queries::find_nar_listing(database, nar.id)
+        .await?
+        .ok_or(ErrorKind::NotFound)?;
+
+    Ok((
+        [
+            (
+                http::header::CONTENT_TYPE,
+                http::HeaderValue::from_static(mime::NAR_LISTING),
+            ),
+            (
+                http::header::CONTENT_ENCODING,
+                http::HeaderValue::from_static("br"),
+            ),
+        ],
+        listing,
+    )
+        .into_response())
 }
 
 /// Gets a NAR.
@@ -190,11 +364,10 @@ async fn get_nar(
         cache_name
     );
 
-    let database = state.database().await?;
+    let (object, cache, nar, chunks) =
+        find_object_or_fetch(&state, &req_state, &cache_name, &store_path_hash, true).await?;
 
-    let (object, cache, _nar, chunks) = database
-        .find_object_and_chunks_by_store_path_hash(&cache_name, &store_path_hash, true)
-        .await?;
+    let database = state.database().await?;
 
     let permission = req_state
         .auth
@@ -205,10 +378,23 @@ async fn get_nar(
 
     if chunks.iter().any(Option::is_none) {
         // at least one of the chunks is missing :(
+        tracing::warn!(
+            "{} in {:?} has an incomplete NAR",
+            object.store_path,
+            cache_name
+        );
+
+        // Only a hint for repairs, which mustn't change the response
+        if !state.read_only {
+            if let Err(e) = queries::record_incomplete_nar_hit(database, nar.id).await {
+                tracing::warn!("Failed to record the incomplete NAR hit: {}", e);
+            }
+        }
+
         return Err(ErrorKind::IncompleteNar.into());
     }
 
-    database.bump_object_last_accessed(object.id).await?;
+    state.access_tracker.bump(database, object.id).await?;
 
     if chunks.len() == 1 {
         // single chunk
@@ -279,9 +465,531 @@ async fn get_nar(
     }
 }
 
+/// Finds an object, fetching it from the upstreams of the cache if it's missing.
+///
+/// Upstreams are only consulted for clients with "pull" permission.
+async fn find_object_or_fetch(
+    state: &State,
+    req_state: &RequestState,
+    cache_name: &CacheName,
+    store_path_hash: &StorePathHash,
+    include_chunks: bool,
+) -> ServerResult<(ObjectModel, CacheModel, NarModel, Vec<Option<ChunkModel>>)> {
+    let database = state.database().await?;
+
+    let miss = match database
+        .find_object_and_chunks_by_store_path_hash(cache_name, store_path_hash, include_chunks)
+        .await
+    {
+        Err(e) if matches!(e.kind(), ErrorKind::NoSuchObject) => e,
+        result => return result,
+    };
+
+    let Ok(cache) = database.find_cache(cache_name).await else {
+        return Err(miss);
+    };
+
+    let permission = req_state
+        .auth
+        .get_permission_for_cache(cache_name, cache.is_public);
+    if permission.require_pull().is_err() {
+        return Err(miss);
+    }
+
+    // A read-only server has nowhere to store what it fetches
+    if state.read_only {
+        return Err(miss);
+    }
+
+    if !state
+        .upstream
+        .fetch_path(state, cache, cache_name, store_path_hash)
+        .await?
+    {
+        return Err(miss);
+    }
+
+    database
+        .find_object_and_chunks_by_store_path_hash(cache_name, store_path_hash, include_chunks)
+        .await
+}
+
+/// Uploads a `.narinfo`, binding it to a previously-staged NAR.
+///
+/// - PUT `/:cache/{storePathHash}.narinfo`
+///
+/// Requires "push" permission.
+#[instrument(skip_all, fields(cache_name, path))]
+async fn put_store_path_info(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path((cache_name, path)): Path<(CacheName, String)>,
+    body: String,
+) -> ServerResult<()> {
+    let components: Vec<&str> = path.splitn(2, '.').collect();
+
+    if components.len() != 2 || components[1] != "narinfo" {
+        return Err(ErrorKind::NotFound.into());
+    }
+
+    let store_path_hash = StorePathHash::new(components[0].to_string())?;
+
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_push()?;
+            Ok(cache)
+        })
+        .await?;
+
+    if body.len() > state.config.max_nar_info_size {
+        return Err(ErrorKind::RequestError(anyhow!("Upload info is too large")).into());
+    }
+
+    let narinfo = NarInfo::from_str(&body)?;
+
+    let store_path = narinfo
+        .store_path
+        .to_str()
+        .ok_or_else(|| ErrorKind::RequestError(anyhow!("Store path contains non-UTF-8")))?
+        .to_string();
+
+    let base_name = narinfo
+        .store_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+
+    if !base_name.starts_with(&format!("{}-", store_path_hash.as_str())) {
+        return Err(ErrorKind::RequestError(anyhow!(
+            "Store path doesn't match the store path hash"
+        ))
+        .into());
+    }
+
+    if narinfo.store_dir() != std::path::Path::new(&cache.store_dir) {
+        return Err(ErrorKind::RequestError(anyhow!(
+            "Store path isn't in the store directory of the cache"
+        ))
+        .into());
+    }
+
+    let file_name = narinfo
+        .url
+        .strip_prefix("nar/")
+        .ok_or_else(|| ErrorKind::RequestError(anyhow!("The NAR URL must be relative to nar/")))?;
+
+    let staged_nar = queries::find_staged_nar(database, cache.id, file_name)
+        .await?
+        .ok_or_else(|| {
+            ErrorKind::RequestError(anyhow!("The NAR {} hasn't been uploaded", narinfo.url))
+        })?;
+
+    // Confirm that the NAR Hash and Size match what we received
+    let staged_nar_hash = Hash::from_typed(&staged_nar.nar_hash)?;
+    if staged_nar_hash != narinfo.nar_hash || staged_nar.nar_size as usize != narinfo.nar_size {
+        return Err(ErrorKind::RequestError(anyhow!("Bad NAR Hash or Size")).into());
+    }
+
+    let upload_info = UploadPathNarInfo {
+        cache: cache_name,
+        store_path_hash,
+        store_path,
+        references: narinfo.references,
+        system: narinfo.system,
+        deriver: narinfo.deriver,
+        sigs: narinfo.signatures,
+        ca: narinfo.ca,
+        nar_hash: narinfo.nar_hash,
+        nar_size: narinfo.nar_size,
+    };
+
+    let storage = state.storage().await?;
+    let stream = match storage
+        .download_file_db(&staged_nar.remote_file.0, true)
+        .await?
+    {
+        Download::AsyncRead(stream) => BufReader::new(stream),
+        Download::Url(_) => {
+            return Err(
+                ErrorKind::StorageError(anyhow!("URLs not supported for staged NARs")).into(),
+            );
+        }
+    };
+
+    let username = req_state.auth.username().map(str::to_string);
+    let result =
+        upload_path_with_info(username, cache, upload_info, stream, database, &state).await?;
+    tracing::debug!(
+        "Ingested staged NAR {}: {:?}",
+        staged_nar.file_name,
+        result.kind
+    );
+
+    // The NAR is now in the global cache
+    queries::delete_staged_nar(database, staged_nar.id).await?;
+    if let Err(e) = storage.delete_file_db(&staged_nar.remote_file.0).await {
+        tracing::warn!("Failed to delete staged NAR: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Stages a NAR uploaded through the Nix binary cache protocol.
+///
+/// - PUT `/:cache/nar/{fileHash}.nar`
+/// - PUT `/:cache/nar/{fileHash}.nar.{xz,zst,br}`
+///
+/// The file name is arbitrary as far as we are concerned. It only
+/// needs to match the `URL` of the `.narinfo` uploaded afterwards.
+///
+/// Requires "push" permission.
+#[instrument(skip_all, fields(cache_name, path))]
+async fn put_nar(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path((cache_name, path)): Path<(CacheName, String)>,
+    body: Body,
+) -> ServerResult<()> {
+    let components: Vec<&str> = path.splitn(2, '.').collect();
+
+    if components.len() != 2 {
+        return Err(ErrorKind::NotFound.into());
+    }
+
+    let compression = match components[1] {
+        "nar" => Compression::None,
+        "nar.xz" => Compression::Xz,
+        "nar.zst" => Compression::Zstd,
+        "nar.br" => Compression::Brotli,
+        "nar.bz2" => Compression::Bzip2,
+        _ => return Err(ErrorKind::NotFound.into()),
+    };
+
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_push()?;
+            Ok(cache)
+        })
+        .await?;
+
+    let stream = StreamReader::new(
+        body.into_data_stream()
+            .map(|r| r.map_err(|e| IoError::other(e.to_string()))),
+    );
+    let stream = get_decompressor(compression, stream)?;
+    let (mut stream, nar_compute) = HashReader::new(stream, Sha256::new());
+
+    let key = format!("{}.staged-nar", Uuid::new_v4());
+    let storage = state.storage().await?;
+    let remote_file = storage.upload_file(key.clone(), &mut stream).await?;
+
+    let (nar_hash, nar_size) = match nar_compute.get() {
+        Some((nar_hash, nar_size)) => (
+            Hash::Sha256(nar_hash.as_slice().try_into().unwrap()),
+            *nar_size,
+        ),
+        None => {
+            if let Err(e) = storage.delete_file(key).await {
+                tracing::warn!("Failed to clean up incomplete staged NAR: {}", e);
+            }
+            return Err(ErrorKind::RequestError(anyhow!("Incomplete NAR upload")).into());
+        }
+    };
+
+    let nar_size_db = i64::try_from(nar_size).map_err(ServerError::request_error)?;
+    let remote_file_json =
+        serde_json::to_string(&remote_file).map_err(ServerError::request_error)?;
+
+    let previous = queries::find_staged_nar(database, cache.id, &path).await?;
+
+    queries::insert_staged_nar(
+        database,
+        cache.id,
+        &path,
+        &nar_hash.to_typed_base16(),
+        nar_size_db,
+        &remote_file_json,
+    )
+    .await?;
+
+    if let Some(previous) = previous {
+        if let Err(e) = storage.delete_file_db(&previous.remote_file.0).await {
+            tracing::warn!("Failed to delete replaced staged NAR: {}", e);
+        }
+    }
+
+    tracing::debug!(
+        "Staged {} ({} bytes) in {:?}",
+        nar_hash.to_typed_base32(),
+        nar_size,
+        cache_name
+    );
+
+    Ok(())
+}
+
+/// Uploads a build log.
+///
+/// - PUT `/:cache/log/{drvName}`
+///
+/// The log may be compressed, in which case the compression
+/// is indicated in the `Content-Encoding` header.
+///
+/// Requires "push" permission.
+#[instrument(skip_all, fields(cache_name, drv_name))]
+async fn put_build_log(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path((cache_name, drv_name)): Path<(CacheName, String)>,
+    headers: HeaderMap,
+    body: Body,
+) -> ServerResult<()> {
+    validate_drv_name(&drv_name)?;
+
+    let compression = match headers.get(http::header::CONTENT_ENCODING) {
+        None => Compression::None,
+        Some(encoding) => match encoding.to_str().map_err(ServerError::request_error)? {
+            "identity" => Compression::None,
+            encoding => encoding.parse()?,
+        },
+    };
+
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_push()?;
+            Ok(cache)
+        })
+        .await?;
+
+    let stream = StreamReader::new(
+        body.into_data_stream()
+            .map(|r| r.map_err(|e| IoError::other(e.to_string()))),
+    );
+    let stream = BufReader::new(get_decompressor(compression, stream)?);
+    let mut stream = BrotliEncoder::new(stream);
+
+    let key = format!("{}.log.br", Uuid::new_v4());
+    let storage = state.storage().await?;
+    let remote_file = storage.upload_file(key, &mut stream).await?;
+    let remote_file_json =
+        serde_json::to_string(&remote_file).map_err(ServerError::request_error)?;
+
+    let previous = queries::find_build_log(database, cache.id, &drv_name).await?;
+
+    queries::insert_build_log(
+        database,
+        cache.id,
+        &drv_name,
+        &remote_file_json,
+        req_state.auth.username(),
+    )
+    .await?;
+
+    if let Some(previous) = previous {
+        if let Err(e) = storage.delete_file_db(&previous.remote_file.0).await {
+            tracing::warn!("Failed to delete replaced build log: {}", e);
+        }
+    }
+
+    tracing::debug!("Stored build log of {} in {:?}", drv_name, cache_name);
+
+    Ok(())
+}
+
+/// Gets a build log.
+///
+/// - GET `/:cache/log/{drvName}`
+///
+/// The log is served with `Content-Encoding: br`.
+///
+/// Requires "pull" permission.
+#[instrument(skip_all, fields(cache_name, drv_name))]
+async fn get_build_log(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path((cache_name, drv_name)): Path<(CacheName, String)>,
+) -> ServerResult<Response> {
+    validate_drv_name(&drv_name)?;
+
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_pull()?;
+            Ok(cache)
+        })
+        .await?;
+
+    req_state.set_public_cache(cache.is_public);
+
+    let build_log = queries::find_build_log(database, cache.id, &drv_name)
+        .await?
+        .ok_or(ErrorKind::NotFound)?;
+
+    // We can't redirect since the Content-Encoding would be lost
+    let storage = state.storage().await?;
+    let stream = match storage
+        .download_file_db(&build_log.remote_file.0, true)
+        .await?
+    {
+        Download::AsyncRead(stream) => stream,
+        Download::Url(_) => {
+            return Err(ErrorKind::StorageError(anyhow!(
+                "Storage backend returned a URL for a build log"
+            ))
+            .into());
+        }
+    };
+
+    let stream = ReaderStream::new(stream).map_err(|e| {
+        tracing::error!(%e, "Stream error");
+        e
+    });
+
+    Ok((
+        [
+            (
+                http::header::CONTENT_TYPE,
+                http::HeaderValue::from_static(mime::BUILD_LOG),
+            ),
+            (
+                http::header::CONTENT_ENCODING,
+                http::HeaderValue::from_static("br"),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+/// Gets the realisation of a derivation output.
+///
+/// - GET `/:cache/realisations/{drvOutputId}.doi`
+///
+/// Requires "pull" permission.
+#[instrument(skip_all, fields(cache_name, path))]
+async fn get_realisation(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path((cache_name, path)): Path<(CacheName, String)>,
+) -> ServerResult<Realisation> {
+    let output_id = path.strip_suffix(".doi").ok_or(ErrorKind::NotFound)?;
+    realisation::validate_id(output_id)?;
+
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_pull()?;
+            Ok(cache)
+        })
+        .await?;
+
+    req_state.set_public_cache(cache.is_public);
+
+    let mut realisation = queries::find_realisation(database, cache.id, output_id)
+        .await?
+        .ok_or(ErrorKind::NotFound)?
+        .to_realisation();
+
+    let keys = CacheKeys::load(database, &state, &cache).await?;
+    for signer in keys.signers() {
+        realisation.sign_with(signer).await?;
+    }
+
+    Ok(realisation)
+}
+
+/// Uploads the realisation of a derivation output.
+///
+/// - PUT `/:cache/realisations/{drvOutputId}.doi`
+///
+/// Requires "push" permission.
+#[instrument(skip_all, fields(cache_name, path))]
+async fn put_realisation(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path((cache_name, path)): Path<(CacheName, String)>,
+    body: String,
+) -> ServerResult<()> {
+    let output_id = path.strip_suffix(".doi").ok_or(ErrorKind::NotFound)?;
+
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_push()?;
+            Ok(cache)
+        })
+        .await?;
+
+    let realisation = Realisation::from_json(&body)?;
+
+    if realisation.id != output_id {
+        return Err(ErrorKind::RequestError(anyhow!(
+            "Realisation ID does not match the path it was uploaded to"
+        ))
+        .into());
+    }
+
+    let signatures_json =
+        serde_json::to_string(&realisation.signatures).map_err(ServerError::request_error)?;
+    let dependent_realisations_json = serde_json::to_string(&realisation.dependent_realisations)
+        .map_err(ServerError::request_error)?;
+
+    queries::insert_realisation(
+        database,
+        cache.id,
+        &realisation.id,
+        &realisation.out_path,
+        &signatures_json,
+        &dependent_realisations_json,
+        req_state.auth.username(),
+    )
+    .await?;
+
+    tracing::debug!(
+        "Stored realisation {} -> {} in {:?}",
+        realisation.id,
+        realisation.out_path,
+        cache_name
+    );
+
+    Ok(())
+}
+
+/// Checks that a build log is requested by the base name of a derivation.
+fn validate_drv_name(drv_name: &str) -> ServerResult<()> {
+    let valid = drv_name.len() > 33
+        && drv_name.ends_with(".drv")
+        && drv_name.as_bytes()[32] == b'-'
+        && !drv_name.contains('/')
+        && StorePathHash::new(drv_name[..32].to_string()).is_ok();
+
+    if !valid {
+        return Err(ErrorKind::RequestError(anyhow!("Invalid derivation name")).into());
+    }
+
+    Ok(())
+}
+
 pub fn get_router() -> Router {
     Router::new()
         .route("/:cache/nix-cache-info", get(get_nix_cache_info))
-        .route("/:cache/:path", get(get_store_path_info))
-        .route("/:cache/nar/:path", get(get_nar))
+        .route(
+            "/:cache/:path",
+            get(get_store_path_info).put(put_store_path_info),
+        )
+        .route("/:cache/nar/:path", get(get_nar).put(put_nar))
+        .route("/:cache/log/:drv", get(get_build_log).put(put_build_log))
+        .route(
+            "/:cache/realisations/:path",
+            get(get_realisation).put(put_realisation),
+        )
 }
@@ -1,7 +1,7 @@
 //! HTTP API.
 
 mod binary_cache;
-mod v1;
+pub(crate) mod v1;
 pub mod web_ui;
 
 use axum::{response::Html, routing::get, Router};
@@ -27,3 +27,12 @@ pub(crate) fn get_router_with_web_ui(web_ui_config: &WebUiConfig, app_state: Sta
 
     router
 }
+
+/// Returns the router of a read-only substituter.
+///
+/// Only the binary cache is served.
+pub(crate) fn get_substituter_router() -> Router {
+    Router::new()
+        .route("/", get(placeholder))
+        .merge(binary_cache::get_router())
+}
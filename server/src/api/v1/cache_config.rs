@@ -4,14 +4,20 @@ use anyhow::anyhow;
 use axum::extract::{Extension, Json, Path};
 use tracing::instrument;
 
-use crate::database::queries;
-use crate::error::{ErrorKind, ServerResult};
+use crate::database::connection::TursoConnection;
+use crate::database::models::CacheKeyState;
+use crate::database::{queries, TursoDbError};
+use crate::error::{ErrorKind, ServerError, ServerResult};
+use crate::signing::envelope::{self, KeypairSlot};
+use crate::signing::{self, CacheKeys};
+use crate::upstream;
 use crate::{RequestState, State};
 use attic::api::v1::cache_config::{
-    CacheConfig, CreateCacheRequest, KeypairConfig, RetentionPeriodConfig,
+    CacheConfig, CreateCacheRequest, KeyRotationStep, KeypairConfig, RetentionPeriodConfig,
+    RotateKeyRequest, RotateKeyResponse, StorageQuotaConfig, UpstreamSubstituter,
 };
 use attic::cache::CacheName;
-use attic::signing::NixKeypair;
+use attic::signing::{NixKeypair, NixPublicKey};
 
 #[instrument(skip_all, fields(cache_name))]
 pub(crate) async fn get_cache_config(
@@ -28,7 +34,18 @@ pub(crate) async fn get_cache_config(
         })
         .await?;
 
-    let public_key = cache.keypair()?.export_public_key();
+    let public_keys = CacheKeys::load(database, &state, &cache)
+        .await?
+        .trusted_public_keys();
+
+    let upstream_substituters = queries::find_cache_upstreams(database, cache.id)
+        .await?
+        .into_iter()
+        .map(|upstream| UpstreamSubstituter {
+            url: upstream.url,
+            trusted_keys: upstream.trusted_keys.0,
+        })
+        .collect();
 
     let retention_period_config = if let Some(period) = cache.retention_period {
         RetentionPeriodConfig::Period(period as u32)
@@ -36,16 +53,27 @@ pub(crate) async fn get_cache_config(
         RetentionPeriodConfig::Global
     };
 
+    let storage_quota_config = if let Some(quota) = cache.storage_quota {
+        StorageQuotaConfig::Quota(quota as u64)
+    } else {
+        StorageQuotaConfig::Global
+    };
+
     Ok(Json(CacheConfig {
         substituter_endpoint: Some(req_state.substituter_endpoint(cache_name)?),
         api_endpoint: Some(req_state.api_endpoint()?),
         keypair: None,
-        public_key: Some(public_key),
+        public_key: public_keys.first().cloned(),
+        public_keys: Some(public_keys),
         is_public: Some(cache.is_public),
         store_dir: Some(cache.store_dir),
         priority: Some(cache.priority),
         upstream_cache_key_names: Some(cache.upstream_cache_key_names.0),
+        upstream_substituters: Some(upstream_substituters),
         retention_period: Some(retention_period_config),
+        storage_quota: Some(storage_quota_config),
+        upload_trusted_keys: Some(cache.upload_trusted_keys.0),
+        require_trusted_signature: Some(cache.require_trusted_signature),
     }))
 }
 
@@ -71,6 +99,8 @@ pub(crate) async fn configure_cache(
     let mut priority_val = None;
     let mut upstream_json = None;
     let mut retention_period_val: Option<Option<i32>> = None;
+    let mut storage_quota_val: Option<Option<i64>> = None;
+    let mut upload_trusted_keys_json = None;
 
     let mut modified = false;
 
@@ -79,7 +109,11 @@ pub(crate) async fn configure_cache(
             KeypairConfig::Generate => NixKeypair::generate(cache_name.as_str())?,
             KeypairConfig::Keypair(k) => k,
         };
-        keypair_str = Some(keypair.export_keypair());
+        keypair_str = Some(envelope::seal(
+            &state.master_keys,
+            &keypair,
+            KeypairSlot::Cache(cache.id),
+        )?);
         modified = true;
     }
 
@@ -106,6 +140,13 @@ pub(crate) async fn configure_cache(
         modified = true;
     }
 
+    if let Some(upstream_substituters) = &payload.upstream_substituters {
+        for upstream in upstream_substituters.iter() {
+            upstream::validate_substituter(upstream)?;
+        }
+        modified = true;
+    }
+
     if let Some(retention_period_config) = payload.retention_period {
         permission.require_configure_cache_retention()?;
 
@@ -124,18 +165,106 @@ pub(crate) async fn configure_cache(
         modified = true;
     }
 
+    if let Some(storage_quota_config) = payload.storage_quota {
+        permission.require_configure_cache_retention()?;
+
+        match storage_quota_config {
+            StorageQuotaConfig::Global => {
+                storage_quota_val = Some(None);
+            }
+            StorageQuotaConfig::Quota(quota) => {
+                storage_quota_val =
+                    Some(Some(quota.try_into().map_err(|_| {
+                        ErrorKind::RequestError(anyhow!("Invalid storage quota"))
+                    })?));
+            }
+        }
+
+        modified = true;
+    }
+
+    if let Some(keys) = &payload.upload_trusted_keys {
+        for key in keys.iter() {
+            NixPublicKey::from_str(key).map_err(|e| {
+                ErrorKind::RequestError(anyhow!("Invalid trusted upload key \"{}\": {}", key, e))
+            })?;
+        }
+
+        upload_trusted_keys_json =
+            Some(serde_json::to_string(keys).map_err(|e| ErrorKind::RequestError(e.into()))?);
+        modified = true;
+    }
+
+    if payload.require_trusted_signature.is_some() {
+        modified = true;
+    }
+
+    // Requiring a signature without trusting any key would reject everything
+    let require_trusted_signature = payload
+        .require_trusted_signature
+        .unwrap_or(cache.require_trusted_signature);
+    let has_trusted_keys = payload
+        .upload_trusted_keys
+        .as_ref()
+        .map_or(!cache.upload_trusted_keys.0.is_empty(), |keys| {
+            !keys.is_empty()
+        });
+    if require_trusted_signature && !has_trusted_keys {
+        return Err(ErrorKind::RequestError(anyhow!(
+            "Trusted upload keys must be set to require a trusted signature"
+        ))
+        .into());
+    }
+
     if modified {
-        queries::update_cache(
-            database,
-            cache.id,
-            keypair_str.as_deref(),
-            is_public_val,
-            store_dir_str.as_deref(),
-            priority_val,
-            upstream_json.as_deref(),
-            retention_period_val,
-        )
-        .await?;
+        let txn = database
+            .begin_transaction()
+            .await
+            .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+        let conn = txn.connection();
+
+        let result = async {
+            queries::update_cache(
+                conn,
+                cache.id,
+                keypair_str.as_deref(),
+                is_public_val,
+                store_dir_str.as_deref(),
+                priority_val,
+                upstream_json.as_deref(),
+                retention_period_val,
+                storage_quota_val,
+                upload_trusted_keys_json.as_deref(),
+                payload.require_trusted_signature,
+            )
+            .await?;
+
+            if let Some(upstream_substituters) = payload.upstream_substituters {
+                replace_upstreams(conn, cache.id, upstream_substituters).await?;
+            }
+
+            // A replaced keypair ends any rotation in progress
+            if keypair_str.is_some() {
+                queries::retire_cache_keys(conn, cache.id).await?;
+            }
+
+            Ok::<(), ServerError>(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => txn
+                .commit()
+                .await
+                .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?,
+            Err(e) => {
+                let _ = txn.rollback().await;
+                return Err(e);
+            }
+        }
+
+        // The keypair, visibility or upstreams may have changed
+        state.narinfo_cache.invalidate_cache(&cache_name);
 
         Ok(())
     } else {
@@ -143,6 +272,162 @@ pub(crate) async fn configure_cache(
     }
 }
 
+/// Performs a step of a key rotation.
+#[instrument(skip_all, fields(cache_name, payload))]
+pub(crate) async fn rotate_cache_key(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path(cache_name): Path<CacheName>,
+    Json(payload): Json<RotateKeyRequest>,
+) -> ServerResult<Json<RotateKeyResponse>> {
+    let database = state.database().await?;
+    req_state
+        .auth
+        .auth_cache(database, &cache_name, |_, permission| {
+            permission.require_configure_cache()?;
+            Ok(())
+        })
+        .await?;
+
+    let txn = database
+        .begin_transaction()
+        .await
+        .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+    let conn = txn.connection();
+
+    let result = async {
+        // Read within the transaction, in case of a concurrent rotation
+        let cache = queries::find_cache(conn, &cache_name).await?;
+        let keys = CacheKeys::load(conn, &state, &cache).await?;
+
+        if keys.is_external() {
+            return Err(ErrorKind::RequestError(anyhow!(
+                "The cache is signed by a signing agent, whose keys are managed outside Attic"
+            ))
+            .into());
+        }
+
+        match payload.step {
+            KeyRotationStep::Stage => {
+                if keys.next().is_some() {
+                    return Err(
+                        ErrorKind::RequestError(anyhow!("A next key is already staged")).into(),
+                    );
+                }
+
+                let keypair = NixKeypair::generate(&keys.next_key_name(cache_name.as_str()))?;
+                queries::insert_cache_key(
+                    conn,
+                    cache.id,
+                    &envelope::seal(
+                        &state.master_keys,
+                        &keypair,
+                        KeypairSlot::CacheKey(cache.id),
+                    )?,
+                    CacheKeyState::Next,
+                )
+                .await?;
+            }
+            KeyRotationStep::Activate => {
+                let next = keys
+                    .next()
+                    .ok_or_else(|| ErrorKind::RequestError(anyhow!("No next key is staged")))?;
+
+                // Keypairs are sealed for where they're stored
+                if keys.primary_row().is_none() {
+                    queries::insert_cache_key(
+                        conn,
+                        cache.id,
+                        &envelope::seal(
+                            &state.master_keys,
+                            &cache.keypair(&state.master_keys)?,
+                            KeypairSlot::CacheKey(cache.id),
+                        )?,
+                        CacheKeyState::Active,
+                    )
+                    .await?;
+                }
+
+                let next_keypair = envelope::seal(
+                    &state.master_keys,
+                    &next.keypair(&state.master_keys)?,
+                    KeypairSlot::Cache(cache.id),
+                )?;
+
+                queries::update_cache_key_state(conn, next.id, CacheKeyState::Active).await?;
+                queries::update_cache(
+                    conn,
+                    cache.id,
+                    Some(&next_keypair),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+                .await?;
+            }
+            KeyRotationStep::Retire => {
+                let secondary: Vec<_> = keys.secondary().collect();
+                if secondary.is_empty() {
+                    return Err(ErrorKind::RequestError(anyhow!(
+                        "No keys other than the newest one are active"
+                    ))
+                    .into());
+                }
+
+                for key in secondary {
+                    queries::update_cache_key_state(conn, key.id, CacheKeyState::Retired).await?;
+                }
+            }
+        }
+
+        let cache = queries::find_cache(conn, &cache_name).await?;
+        Ok(CacheKeys::load(conn, &state, &cache).await?.to_api())
+    }
+    .await;
+
+    let keys = match result {
+        Ok(keys) => {
+            txn.commit()
+                .await
+                .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+            keys
+        }
+        Err(e) => {
+            let _ = txn.rollback().await;
+            return Err(e);
+        }
+    };
+
+    // The paths are signed with a different set of keys now
+    if payload.step != KeyRotationStep::Stage {
+        state.narinfo_cache.invalidate_cache(&cache_name);
+    }
+
+    Ok(Json(RotateKeyResponse { keys }))
+}
+
+/// Replaces the upstream substituters of a cache.
+async fn replace_upstreams(
+    conn: &TursoConnection,
+    cache_id: i64,
+    upstreams: Vec<UpstreamSubstituter>,
+) -> ServerResult<()> {
+    queries::delete_cache_upstreams(conn, cache_id).await?;
+
+    for upstream in upstreams {
+        let trusted_keys_json =
+            serde_json::to_string(&upstream.trusted_keys).map_err(ServerError::request_error)?;
+        queries::insert_cache_upstream(conn, cache_id, &upstream.url, &trusted_keys_json).await?;
+    }
+
+    Ok(())
+}
+
 #[instrument(skip_all, fields(cache_name))]
 pub(crate) async fn destroy_cache(
     Extension(state): Extension<State>,
@@ -158,21 +443,21 @@ pub(crate) async fn destroy_cache(
         })
         .await?;
 
-    if state.config.soft_delete_caches {
+    let deleted = if state.config.soft_delete_caches {
         // Perform soft deletion
-        let deleted = queries::soft_delete_cache(database, cache.id).await;
-        match deleted {
-            Ok(()) => Ok(()),
-            Err(_) => Err(ErrorKind::NoSuchCache.into()),
-        }
+        queries::soft_delete_cache(database, cache.id).await
     } else {
         // Perform hard deletion
-        let deleted = queries::hard_delete_cache(database, cache.id).await;
-        match deleted {
-            Ok(()) => Ok(()),
-            Err(_) => Err(ErrorKind::NoSuchCache.into()),
-        }
+        queries::hard_delete_cache(database, cache.id).await
+    };
+
+    if deleted.is_err() {
+        return Err(ErrorKind::NoSuchCache.into());
     }
+
+    state.narinfo_cache.invalidate_cache(&cache_name);
+
+    Ok(())
 }
 
 #[instrument(skip_all, fields(cache_name, payload))]
@@ -195,21 +480,42 @@ pub(crate) async fn create_cache(
     let upstream_json = serde_json::to_string(&payload.upstream_cache_key_names)
         .map_err(|e| ErrorKind::RequestError(e.into()))?;
 
-    let num_inserted = queries::insert_cache(
-        database,
-        cache_name.as_str(),
-        &keypair.export_keypair(),
-        payload.is_public,
-        &payload.store_dir,
-        payload.priority,
-        &upstream_json,
-    )
-    .await?;
-
-    if num_inserted == 0 {
-        // The cache already exists
-        Err(ErrorKind::CacheAlreadyExists.into())
-    } else {
-        Ok(())
+    let txn = database
+        .begin_transaction()
+        .await
+        .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+    let conn = txn.connection();
+
+    let result = async {
+        let num_inserted = queries::insert_cache(
+            conn,
+            cache_name.as_str(),
+            "",
+            payload.is_public,
+            &payload.store_dir,
+            payload.priority,
+            &upstream_json,
+        )
+        .await?;
+
+        if num_inserted == 0 {
+            // The cache already exists
+            return Err(ErrorKind::CacheAlreadyExists.into());
+        }
+
+        let cache = queries::find_cache(conn, &cache_name).await?;
+        signing::store_new_cache_keypair(conn, &state.master_keys, cache.id, &keypair).await
+    }
+    .await;
+
+    match result {
+        Ok(()) => txn
+            .commit()
+            .await
+            .map_err(|e| ServerError::database_error(TursoDbError(e.to_string()))),
+        Err(e) => {
+            let _ = txn.rollback().await;
+            Err(e)
+        }
     }
 }
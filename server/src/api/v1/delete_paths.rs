@@ -0,0 +1,98 @@
+//! Store path deletion endpoint.
+
+use anyhow::anyhow;
+use axum::extract::{Extension, Json};
+use tracing::instrument;
+
+use crate::database::{queries, TursoDbError};
+use crate::error::{ErrorKind, ServerError, ServerResult};
+use crate::{RequestState, State};
+use attic::api::v1::delete_paths::{DeletePathsRequest, DeletePathsResponse};
+
+/// Deletes store paths from a cache.
+///
+/// Only the `object` rows and the pins of the deleted paths are removed
+/// here. NARs and chunks that are no longer referenced are reaped by the
+/// garbage collector.
+#[instrument(skip_all, fields(payload))]
+pub(crate) async fn delete_paths(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Json(payload): Json<DeletePathsRequest>,
+) -> ServerResult<Json<DeletePathsResponse>> {
+    if payload.store_path_hashes.is_empty()
+        && payload.store_paths.is_empty()
+        && payload.name_globs.is_empty()
+    {
+        return Err(ErrorKind::RequestError(anyhow!("No paths were specified")).into());
+    }
+
+    if payload
+        .name_globs
+        .iter()
+        .any(|glob| glob.is_empty() || glob.contains('/'))
+    {
+        return Err(ErrorKind::RequestError(anyhow!("Invalid name glob")).into());
+    }
+
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &payload.cache, |cache, permission| {
+            permission.require_delete()?;
+            Ok(cache)
+        })
+        .await?;
+
+    let store_path_hashes: Vec<String> = payload
+        .store_path_hashes
+        .iter()
+        .map(|h| h.as_str().to_owned())
+        .collect();
+
+    let txn = database
+        .begin_transaction()
+        .await
+        .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+    let conn = txn.connection();
+
+    let result = async {
+        let deleted_paths = queries::delete_objects_by_selectors(
+            conn,
+            cache.id,
+            &store_path_hashes,
+            &payload.store_paths,
+            &payload.name_globs,
+        )
+        .await?;
+
+        // Pins must not outlive the paths they point to
+        queries::delete_pins_by_store_paths(conn, cache.id, &deleted_paths).await?;
+
+        Ok::<_, ServerError>(deleted_paths)
+    }
+    .await;
+
+    let deleted_paths = match result {
+        Ok(deleted_paths) => {
+            txn.commit()
+                .await
+                .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+            deleted_paths
+        }
+        Err(e) => {
+            let _ = txn.rollback().await;
+            return Err(e);
+        }
+    };
+
+    state.narinfo_cache.invalidate_cache(&payload.cache);
+
+    tracing::info!(
+        "Deleted {} paths from cache {}",
+        deleted_paths.len(),
+        cache.name
+    );
+
+    Ok(Json(DeletePathsResponse { deleted_paths }))
+}
@@ -45,13 +45,34 @@ pub(crate) async fn get_missing_paths(
     .into_iter()
     .collect();
 
+    let missing_hashes: Vec<String> = requested_hashes
+        .difference(&found_hashes)
+        .cloned()
+        .collect();
+
+    let incomplete_hashes = queries::find_incomplete_objects_by_store_path_hashes(
+        database,
+        payload.cache.as_str(),
+        &missing_hashes,
+    )
+    .await?;
+
     // Safety: All requested_hashes are validated `StorePathHash`es.
     // No need to pay the cost of checking again
     #[allow(unsafe_code)]
-    let missing_paths = requested_hashes
-        .difference(&found_hashes)
-        .map(|h| unsafe { StorePathHash::new_unchecked(h.to_string()) })
+    let missing_paths = missing_hashes
+        .into_iter()
+        .map(|h| unsafe { StorePathHash::new_unchecked(h) })
+        .collect();
+
+    #[allow(unsafe_code)]
+    let incomplete_paths = incomplete_hashes
+        .into_iter()
+        .map(|h| unsafe { StorePathHash::new_unchecked(h) })
         .collect();
 
-    Ok(Json(GetMissingPathsResponse { missing_paths }))
+    Ok(Json(GetMissingPathsResponse {
+        missing_paths,
+        incomplete_paths,
+    }))
 }
@@ -0,0 +1,39 @@
+use axum::extract::{Extension, Json, Path};
+use tracing::instrument;
+
+use crate::database::queries;
+use crate::error::ServerResult;
+use crate::{RequestState, State};
+use attic::api::v1::incomplete_paths::{IncompletePath, ListIncompletePathsResponse};
+use attic::cache::CacheName;
+
+/// Lists the paths of a cache whose NARs are incomplete.
+///
+/// Requires "push" permission as only pushing can repair them.
+#[instrument(skip_all, fields(cache_name))]
+pub(crate) async fn list_incomplete_paths(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path(cache_name): Path<CacheName>,
+) -> ServerResult<Json<ListIncompletePathsResponse>> {
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_push()?;
+            Ok(cache)
+        })
+        .await?;
+
+    let paths = queries::find_incomplete_objects(database, cache.id)
+        .await?
+        .into_iter()
+        .map(|object| IncompletePath {
+            store_path: object.store_path,
+            hits: object.hits as u64,
+            last_hit_at: object.last_hit_at.map(|t| t.to_rfc3339()),
+        })
+        .collect();
+
+    Ok(Json(ListIncompletePathsResponse { paths }))
+}
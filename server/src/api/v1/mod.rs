@@ -1,6 +1,10 @@
 mod cache_config;
+mod delete_paths;
 mod get_missing_paths;
-mod upload_path;
+mod incomplete_paths;
+mod pin;
+mod stats;
+pub(crate) mod upload_path;
 
 use axum::{
     routing::{delete, get, patch, post, put},
@@ -14,6 +18,11 @@ pub(crate) fn get_router() -> Router {
             post(get_missing_paths::get_missing_paths),
         )
         .route("/_api/v1/upload-path", put(upload_path::upload_path))
+        .route(
+            "/_api/v1/incomplete-paths/:cache",
+            get(incomplete_paths::list_incomplete_paths),
+        )
+        .route("/_api/v1/objects", delete(delete_paths::delete_paths))
         .route(
             "/:cache/attic-cache-info",
             get(cache_config::get_cache_config),
@@ -34,4 +43,15 @@ pub(crate) fn get_router() -> Router {
             "/_api/v1/cache-config/:cache",
             delete(cache_config::destroy_cache),
         )
+        .route(
+            "/_api/v1/cache-config/:cache/rotate-key",
+            post(cache_config::rotate_cache_key),
+        )
+        .route("/_api/v1/pins/:cache", get(pin::list_pins))
+        .route(
+            "/_api/v1/pins/:cache/:name",
+            put(pin::create_pin).delete(pin::delete_pin),
+        )
+        .route("/:cache/pins/:name", get(pin::resolve_pin))
+        .route("/_api/v1/stats", get(stats::get_stats))
 }
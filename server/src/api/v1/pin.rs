@@ -0,0 +1,179 @@
+//! Pin endpoints.
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Extension, Json, Path},
+    http::header,
+    response::IntoResponse,
+};
+use tracing::instrument;
+
+use crate::database::{queries, AtticDatabase};
+use crate::error::{ErrorKind, ServerResult};
+use crate::{RequestState, State};
+use attic::api::v1::pin::{CreatePinRequest, ListPinsResponse, Pin};
+use attic::cache::CacheName;
+use attic::nix_store::StorePathHash;
+
+/// The maximum length of a pin name.
+const MAX_PIN_NAME_LENGTH: usize = 128;
+
+/// Lists the pins of a cache.
+#[instrument(skip_all, fields(cache_name))]
+pub(crate) async fn list_pins(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path(cache_name): Path<CacheName>,
+) -> ServerResult<Json<ListPinsResponse>> {
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_pull()?;
+            Ok(cache)
+        })
+        .await?;
+
+    let pins = queries::find_pins_by_cache(database, cache.id)
+        .await?
+        .into_iter()
+        .map(|pin| pin.to_pin())
+        .collect();
+
+    Ok(Json(ListPinsResponse { pins }))
+}
+
+/// Pins a store path in a cache.
+///
+/// The path must already be in the cache.
+#[instrument(skip_all, fields(cache_name, name, payload))]
+pub(crate) async fn create_pin(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path((cache_name, name)): Path<(CacheName, String)>,
+    Json(payload): Json<CreatePinRequest>,
+) -> ServerResult<Json<Pin>> {
+    validate_pin_name(&name)?;
+
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_push()?;
+            Ok(cache)
+        })
+        .await?;
+
+    let store_path_hash = payload
+        .store_path
+        .strip_prefix(&format!("{}/", cache.store_dir))
+        .and_then(|base_name| base_name.get(..32))
+        .and_then(|hash| StorePathHash::new(hash.to_string()).ok())
+        .ok_or_else(|| ErrorKind::RequestError(anyhow!("Invalid store path")))?;
+
+    let (object, _, _, _) = database
+        .find_object_and_chunks_by_store_path_hash(&cache_name, &store_path_hash, false)
+        .await?;
+
+    if object.store_path != payload.store_path {
+        return Err(ErrorKind::NoSuchObject.into());
+    }
+
+    let pin = queries::insert_pin(
+        database,
+        cache.id,
+        &name,
+        store_path_hash.as_str(),
+        &object.store_path,
+        req_state.auth.username(),
+    )
+    .await?;
+
+    tracing::info!("Pinned {} as {} in {}", pin.store_path, name, cache.name);
+
+    Ok(Json(pin.to_pin()))
+}
+
+/// Removes a pin from a cache.
+///
+/// The pinned path stays in the cache.
+#[instrument(skip_all, fields(cache_name, name))]
+pub(crate) async fn delete_pin(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path((cache_name, name)): Path<(CacheName, String)>,
+) -> ServerResult<()> {
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_push()?;
+            Ok(cache)
+        })
+        .await?;
+
+    if queries::delete_pin(database, cache.id, &name).await? == 0 {
+        return Err(ErrorKind::NoSuchPin.into());
+    }
+
+    Ok(())
+}
+
+/// Resolves a pin to its store path.
+///
+/// The store path is returned as plain text so that scripts
+/// can easily look up what a pin points to. Pins of paths that
+/// are no longer in the cache don't resolve.
+#[instrument(skip_all, fields(cache_name, name))]
+pub(crate) async fn resolve_pin(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+    Path((cache_name, name)): Path<(CacheName, String)>,
+) -> ServerResult<impl IntoResponse> {
+    let database = state.database().await?;
+    let cache = req_state
+        .auth
+        .auth_cache(database, &cache_name, |cache, permission| {
+            permission.require_pull()?;
+            Ok(cache)
+        })
+        .await?;
+
+    req_state.set_public_cache(cache.is_public);
+
+    let pin = queries::find_pin(database, cache.id, &name)
+        .await?
+        .ok_or(ErrorKind::NoSuchPin)?;
+
+    // Don't hand out paths that are gone from the cache
+    let store_path_hash =
+        StorePathHash::new(pin.store_path_hash.clone()).map_err(|_| ErrorKind::NoSuchObject)?;
+    let (object, _, _, _) = database
+        .find_object_and_chunks_by_store_path_hash(&cache_name, &store_path_hash, false)
+        .await?;
+
+    if object.store_path != pin.store_path {
+        return Err(ErrorKind::NoSuchObject.into());
+    }
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain")],
+        format!("{}\n", pin.store_path),
+    ))
+}
+
+/// Checks that a pin name is well-formed.
+fn validate_pin_name(name: &str) -> ServerResult<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_PIN_NAME_LENGTH
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-._".contains(c));
+
+    if !valid {
+        return Err(ErrorKind::RequestError(anyhow!("Invalid pin name")).into());
+    }
+
+    Ok(())
+}
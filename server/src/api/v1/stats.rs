@@ -0,0 +1,32 @@
+//! Server statistics endpoint.
+
+use axum::extract::{Extension, Json};
+use serde::Serialize;
+
+use crate::error::ServerResult;
+use crate::narinfo::cache::NarInfoCacheStats;
+use crate::{RequestState, State};
+
+/// Statistics of this server process.
+#[derive(Debug, Serialize)]
+pub(crate) struct ServerStats {
+    /// Hit rate of the `.narinfo` cache.
+    narinfo_cache: NarInfoCacheStats,
+}
+
+/// Reports statistics of this server process.
+///
+/// - GET `/_api/v1/stats`
+///
+/// The statistics are kept in memory and reset when the server restarts.
+/// They aren't tied to a cache, but still require a token.
+pub(crate) async fn get_stats(
+    Extension(state): Extension<State>,
+    Extension(req_state): Extension<RequestState>,
+) -> ServerResult<Json<ServerStats>> {
+    req_state.auth.require_token()?;
+
+    Ok(Json(ServerStats {
+        narinfo_cache: state.narinfo_cache.stats(),
+    }))
+}
@@ -2,6 +2,7 @@ use std::io;
 
 use std::io::Cursor;
 use std::marker::Unpin;
+use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::anyhow;
@@ -17,7 +18,7 @@ use futures::future::join_all;
 use futures::StreamExt;
 use sha2::{Digest, Sha256};
 use tokio::io::{AsyncBufRead, AsyncReadExt};
-use tokio::sync::Semaphore;
+use tokio::sync::{OnceCell, Semaphore};
 use tokio::task::spawn;
 use tokio_util::io::StreamReader;
 use tracing::instrument;
@@ -26,15 +27,18 @@ use uuid::Uuid;
 use crate::compression::{CompressionStream, CompressorFn};
 use crate::config::CompressionType;
 use crate::error::{ErrorKind, ServerError, ServerResult};
-use crate::narinfo::Compression;
+use crate::nar_listing::{NarListing, NarListingReader};
+use crate::narinfo::{self, Compression};
 use crate::{RequestState, State};
 use attic::api::v1::upload_path::{
     UploadPathNarInfo, UploadPathResult, UploadPathResultKind, ATTIC_NAR_INFO,
     ATTIC_NAR_INFO_PREAMBLE_SIZE,
 };
+use attic::cache::CacheName;
 use attic::chunking::chunk_stream;
 use attic::hash::Hash;
 use attic::io::{read_chunk_async, HashReader};
+use attic::signing::NixPublicKey;
 use attic::util::Finally;
 
 use crate::database::connection::TursoConnection;
@@ -137,6 +141,102 @@ pub(crate) async fn upload_path(
 
     let username = req_state.auth.username().map(str::to_string);
 
+    upload_path_with_info(username, cache, upload_info, stream, database, &state).await
+}
+
+/// Uploads an object whose upload info has already been received and authorized.
+///
+/// This is shared with the Nix binary cache write protocol, where the
+/// upload info comes from the `.narinfo` and the NAR from a staged file.
+pub(crate) async fn upload_path_with_info(
+    username: Option<String>,
+    cache: CacheModel,
+    mut upload_info: UploadPathNarInfo,
+    stream: impl AsyncBufRead + Send + Unpin + 'static,
+    database: &Arc<TursoConnection>,
+    state: &State,
+) -> ServerResult<Json<UploadPathResult>> {
+    if let Some(system) = &upload_info.system {
+        if system.is_empty() || system.contains(char::is_whitespace) {
+            return Err(ErrorKind::RequestError(anyhow!("Invalid system \"{}\"", system)).into());
+        }
+    }
+
+    verify_signatures(&cache, &mut upload_info)?;
+
+    let cache_name = CacheName::new(cache.name.clone())?;
+    let store_path_hash = upload_info.store_path_hash.clone();
+
+    let result =
+        upload_path_with_info_uncached(username, cache, upload_info, stream, database, state).await;
+
+    // Even a failed upload may have replaced the object
+    state
+        .narinfo_cache
+        .invalidate(&cache_name, &store_path_hash);
+
+    result
+}
+
+/// Verifies the uploaded signatures against the trusted upload keys of the cache.
+///
+/// If the cache trusts any key, only valid signatures by trusted keys
+/// are kept, and paths left without one are rejected if the cache
+/// requires it. The signatures are made over the claimed NAR hash and
+/// size, which the upload checks against the NAR afterwards.
+fn verify_signatures(cache: &CacheModel, upload_info: &mut UploadPathNarInfo) -> ServerResult<()> {
+    if cache.upload_trusted_keys.0.is_empty() {
+        return Ok(());
+    }
+
+    let trusted_keys: Vec<NixPublicKey> = cache
+        .upload_trusted_keys
+        .0
+        .iter()
+        .filter_map(|key| NixPublicKey::from_str(key).ok())
+        .collect();
+
+    let fingerprint = narinfo::fingerprint(
+        Path::new(&upload_info.store_path),
+        &upload_info.nar_hash,
+        upload_info.nar_size,
+        &upload_info.references,
+    );
+
+    let uploaded = upload_info.sigs.len();
+    upload_info.sigs.retain(|sig| {
+        trusted_keys
+            .iter()
+            .any(|key| key.verify(&fingerprint, sig).is_ok())
+    });
+
+    if upload_info.sigs.len() != uploaded {
+        tracing::debug!(
+            "Removed {} untrusted signatures from {}",
+            uploaded - upload_info.sigs.len(),
+            upload_info.store_path
+        );
+    }
+
+    if upload_info.sigs.is_empty() && cache.require_trusted_signature {
+        return Err(ErrorKind::RequestError(anyhow!(
+            "{} isn't signed by a key trusted by the cache",
+            upload_info.store_path
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+async fn upload_path_with_info_uncached(
+    username: Option<String>,
+    cache: CacheModel,
+    upload_info: UploadPathNarInfo,
+    stream: impl AsyncBufRead + Send + Unpin + 'static,
+    database: &Arc<TursoConnection>,
+    state: &State,
+) -> ServerResult<Json<UploadPathResult>> {
     // Try to acquire a lock on an existing NAR
     if let Some(existing_nar) = database.find_and_lock_nar(&upload_info.nar_hash).await? {
         // Deduplicate?
@@ -150,7 +250,7 @@ pub(crate) async fn upload_path(
                 upload_info,
                 stream,
                 database,
-                &state,
+                state,
                 existing_nar,
             )
             .await;
@@ -158,7 +258,7 @@ pub(crate) async fn upload_path(
     }
 
     // New NAR or need to repair
-    upload_path_new(username, cache, upload_info, stream, database, &state).await
+    upload_path_new(username, cache, upload_info, stream, database, state).await
 }
 
 /// Uploads a path when there is already a matching NAR in the global cache.
@@ -195,6 +295,7 @@ async fn upload_path_dedup(
         .begin_transaction()
         .await
         .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+    let conn = txn.connection();
 
     let result = async {
         // Create a mapping granting the local cache access to the NAR
@@ -204,13 +305,13 @@ async fn upload_path_dedup(
             serde_json::to_string(&upload_info.sigs).map_err(ServerError::request_error)?;
 
         queries::insert_object_upsert(
-            database,
+            conn,
             cache.id,
             existing_nar.id,
             &upload_info.store_path_hash.to_string(),
             &upload_info.store_path,
             &references_json,
-            None, // system
+            upload_info.system.as_deref(),
             upload_info.deriver.as_deref(),
             &sigs_json,
             upload_info.ca.as_deref(),
@@ -219,7 +320,7 @@ async fn upload_path_dedup(
         .await?;
 
         // Also mark the NAR as complete again
-        queries::update_nar_completeness_hint(database, existing_nar.id, true).await?;
+        queries::update_nar_completeness_hint(conn, existing_nar.id, true).await?;
 
         Ok::<(), ServerError>(())
     }
@@ -250,8 +351,8 @@ async fn upload_path_dedup(
 /// Uploads a path when there is no matching NAR in the global cache.
 ///
 /// It's okay if some other client races to upload the same NAR before
-/// us. The `nar` table can hold duplicate NARs which can be deduplicated
-/// in a background process.
+/// us. The `nar` table can hold duplicate NARs which are merged by
+/// garbage collection.
 async fn upload_path_new(
     username: Option<String>,
     cache: CacheModel,
@@ -261,11 +362,30 @@ async fn upload_path_new(
     state: &State,
 ) -> ServerResult<Json<UploadPathResult>> {
     let nar_size_threshold = state.config.chunking.nar_size_threshold;
+    let (stream, listing) = NarListingReader::new(stream);
 
     if nar_size_threshold == 0 || upload_info.nar_size < nar_size_threshold {
-        upload_path_new_unchunked(username, cache, upload_info, stream, database, state).await
+        upload_path_new_unchunked(
+            username,
+            cache,
+            upload_info,
+            stream,
+            listing,
+            database,
+            state,
+        )
+        .await
     } else {
-        upload_path_new_chunked(username, cache, upload_info, stream, database, state).await
+        upload_path_new_chunked(
+            username,
+            cache,
+            upload_info,
+            stream,
+            listing,
+            database,
+            state,
+        )
+        .await
     }
 }
 
@@ -275,6 +395,7 @@ async fn upload_path_new_chunked(
     cache: CacheModel,
     upload_info: UploadPathNarInfo,
     stream: impl AsyncBufRead + Send + Unpin + 'static,
+    listing: Arc<OnceCell<NarListing>>,
     database: &Arc<TursoConnection>,
     state: &State,
 ) -> ServerResult<Json<UploadPathResult>> {
@@ -397,23 +518,38 @@ async fn upload_path_new_chunked(
                 )
             });
 
+    let listing = compress_nar_listing(&listing).await;
+
     // Begin transaction for final updates
     let txn = database
         .begin_transaction()
         .await
         .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+    let conn = txn.connection();
 
     let result = async {
-        // Set num_chunks and mark the NAR as Valid
+        // Set num_chunks and mark the NAR as Valid and complete
         queries::update_nar(
-            database,
+            conn,
             nar_id,
             Some(NarState::Valid),
             Some(chunks.len() as i32),
-            None,
+            Some(true),
         )
         .await?;
 
+        // The chunks are served one after another
+        let file_hash = match chunks.as_slice() {
+            [chunk] => chunk.guard.file_hash.as_deref(),
+            _ => None,
+        };
+        let file_size_db = i64::try_from(file_size).map_err(ServerError::request_error)?;
+        queries::update_nar_file(conn, nar_id, file_hash, Some(file_size_db)).await?;
+
+        if let Some(listing) = &listing {
+            queries::insert_nar_listing(conn, nar_id, listing).await?;
+        }
+
         // Create a mapping granting the local cache access to the NAR
         let references_json =
             serde_json::to_string(&upload_info.references).map_err(ServerError::request_error)?;
@@ -421,13 +557,13 @@ async fn upload_path_new_chunked(
             serde_json::to_string(&upload_info.sigs).map_err(ServerError::request_error)?;
 
         queries::insert_object_upsert(
-            database,
+            conn,
             cache.id,
             nar_id,
             &upload_info.store_path_hash.to_string(),
             &upload_info.store_path,
             &references_json,
-            None, // system
+            upload_info.system.as_deref(),
             upload_info.deriver.as_deref(),
             &sigs_json,
             upload_info.ca.as_deref(),
@@ -470,6 +606,7 @@ async fn upload_path_new_unchunked(
     cache: CacheModel,
     upload_info: UploadPathNarInfo,
     stream: impl AsyncBufRead + Send + Unpin + 'static,
+    listing: Arc<OnceCell<NarListing>>,
     database: &Arc<TursoConnection>,
     state: &State,
 ) -> ServerResult<Json<UploadPathResult>> {
@@ -494,17 +631,19 @@ async fn upload_path_new_unchunked(
     )
     .await?;
     let file_size = chunk.guard.file_size.unwrap() as usize;
+    let listing = compress_nar_listing(&listing).await;
 
     // Begin transaction
     let txn = database
         .begin_transaction()
         .await
         .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+    let conn = txn.connection();
 
     let result = async {
         // Create a NAR entry
         let nar = queries::insert_nar(
-            database,
+            conn,
             NarState::Valid,
             &upload_info.nar_hash.to_typed_base16(),
             chunk.guard.chunk_size,
@@ -514,9 +653,23 @@ async fn upload_path_new_unchunked(
         .await?;
         let nar_id = nar.id;
 
+        // All of its data is already in the chunk
+        queries::update_nar_completeness_hint(conn, nar_id, true).await?;
+        queries::update_nar_file(
+            conn,
+            nar_id,
+            chunk.guard.file_hash.as_deref(),
+            chunk.guard.file_size,
+        )
+        .await?;
+
+        if let Some(listing) = &listing {
+            queries::insert_nar_listing(conn, nar_id, listing).await?;
+        }
+
         // Create a mapping from the NAR to the chunk
         queries::insert_chunkref(
-            database,
+            conn,
             nar_id,
             0,
             Some(chunk.guard.id),
@@ -532,13 +685,13 @@ async fn upload_path_new_unchunked(
             serde_json::to_string(&upload_info.sigs).map_err(ServerError::request_error)?;
 
         queries::insert_object_upsert(
-            database,
+            conn,
             cache.id,
             nar_id,
             &upload_info.store_path_hash.to_string(),
             &upload_info.store_path,
             &references_json,
-            None, // system
+            upload_info.system.as_deref(),
             upload_info.deriver.as_deref(),
             &sigs_json,
             upload_info.ca.as_deref(),
@@ -682,12 +835,13 @@ async fn upload_chunk(
         .begin_transaction()
         .await
         .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+    let conn = txn.connection();
 
     let result = async {
         // Update the file hash and size, and set the chunk to valid
         let file_size_db = i64::try_from(*file_size).map_err(ServerError::request_error)?;
         let updated_chunk = queries::update_chunk(
-            &database,
+            conn,
             chunk_id,
             Some(ChunkState::Valid),
             Some(&file_hash.to_typed_base16()),
@@ -698,7 +852,7 @@ async fn upload_chunk(
 
         // Also repair broken chunk references pointing at the same chunk
         let repaired = queries::update_many_chunkrefs_by_hash(
-            &database,
+            conn,
             chunk_id,
             &chunk_hash.to_typed_base16(),
             compression.as_str(),
@@ -733,6 +887,21 @@ async fn upload_chunk(
     }
 }
 
+/// Returns the compressed NAR listing if one was built.
+///
+/// The listing is missing if the NAR couldn't be parsed or wasn't read
+/// entirely, for example when an identical chunk already exists. Uploads
+/// should still succeed without it.
+async fn compress_nar_listing(listing: &OnceCell<NarListing>) -> Option<Vec<u8>> {
+    match listing.get()?.to_compressed_json().await {
+        Ok(compressed) => Some(compressed),
+        Err(e) => {
+            tracing::warn!("Failed to compress NAR listing: {}", e);
+            None
+        }
+    }
+}
+
 /// Returns a compressor function that takes some stream as input.
 fn get_compressor_fn<C: AsyncBufRead + Unpin + Send + 'static>(
     ctype: CompressionType,
@@ -15,7 +15,10 @@ use super::dashboard::CacheWithStats;
 use super::permissions::get_effective_permissions;
 use super::WebUiState;
 use crate::database::models::UserCachePermissionModel;
-use crate::database::queries;
+use crate::database::{queries, TursoDbError};
+use crate::error::{ServerError, ServerResult};
+use crate::signing;
+use attic::cache::CacheName;
 use attic::signing::NixKeypair;
 
 /// Cache list template (unified for admin and regular users).
@@ -28,6 +31,22 @@ struct CachesTemplate {
     is_admin: bool,
 }
 
+/// Incomplete paths of a cache.
+#[derive(Template)]
+#[template(path = "cache_incomplete.html")]
+struct IncompletePathsTemplate {
+    user: crate::database::models::UserModel,
+    cache_name: String,
+    paths: Vec<IncompletePath>,
+}
+
+/// A path with an incomplete NAR formatted for display.
+struct IncompletePath {
+    store_path: String,
+    hits: i64,
+    last_hit_at: String,
+}
+
 /// Deserialize an empty string as None for Option<i32>.
 fn empty_string_as_none<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
 where
@@ -136,9 +155,14 @@ pub async fn list_caches(
             .await
             .unwrap_or(0);
 
+        let incomplete_count = queries::count_incomplete_objects(db, cache.id)
+            .await
+            .unwrap_or(0);
+
         caches_with_stats.push(CacheWithStats {
             cache,
             object_count,
+            incomplete_count,
             can_push,
             can_pull,
             can_delete,
@@ -160,6 +184,74 @@ pub async fn list_caches(
     .into_response()
 }
 
+/// GET /ui/caches/:name/incomplete - Show the paths whose NARs are incomplete.
+///
+/// Only users who can push to the cache can repair them, so only
+/// they get to see them.
+pub async fn incomplete_paths(
+    AxumState(web_ui): AxumState<WebUiState>,
+    jar: PrivateCookieJar,
+    Path(cache_name): Path<String>,
+) -> impl IntoResponse {
+    // Get session user
+    let user = match get_session_user(&web_ui, &jar).await {
+        Some(user) => user,
+        None => return Redirect::to("/ui/login").into_response(),
+    };
+
+    let db = match web_ui.app_state.database().await {
+        Ok(db) => db,
+        Err(_) => {
+            return Html("Database error".to_string()).into_response();
+        }
+    };
+
+    if !user.is_admin {
+        let permissions = queries::get_user_permissions(db, user.id)
+            .await
+            .unwrap_or_default();
+
+        if !get_effective_permissions(&permissions, &cache_name).can_push {
+            return Redirect::to("/ui/caches").into_response();
+        }
+    }
+
+    let cache = match cache_name.parse::<attic::cache::CacheName>() {
+        Ok(name) => match queries::find_cache(db, &name).await {
+            Ok(cache) => cache,
+            Err(_) => return Redirect::to("/ui/caches").into_response(),
+        },
+        Err(_) => return Redirect::to("/ui/caches").into_response(),
+    };
+
+    let paths = queries::find_incomplete_objects(db, cache.id)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|object| IncompletePath {
+            store_path: object.store_path,
+            hits: object.hits,
+            last_hit_at: object
+                .last_hit_at
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "-".to_string()),
+        })
+        .collect();
+
+    let template = IncompletePathsTemplate {
+        user,
+        cache_name: cache.name,
+        paths,
+    };
+
+    Html(
+        template
+            .render()
+            .unwrap_or_else(|_| "Template error".to_string()),
+    )
+    .into_response()
+}
+
 /// Checks if user has permission to create any cache.
 fn user_can_create_any_cache(permissions: &[UserCachePermissionModel]) -> bool {
     permissions.iter().any(|p| p.can_create_cache)
@@ -289,57 +381,16 @@ pub async fn create_cache(
     let priority = req.priority.unwrap_or(41);
     let is_public = req.is_public.as_deref() == Some("true");
 
-    if is_admin {
-        // Admin creates cache without owner tracking
-        let result = queries::insert_cache(
-            db,
-            name,
-            &keypair.export_keypair(),
-            is_public,
-            store_dir,
-            priority,
-            "[]",
-        )
-        .await;
-
-        match result {
-            Ok(0) => {
-                // Cache already exists
-                (
-                    StatusCode::CONFLICT,
-                    Json(CacheApiResult {
-                        success: false,
-                        error: Some("A cache with this name already exists".to_string()),
-                    }),
-                )
-                    .into_response()
-            }
-            Ok(_) => Redirect::to("/ui/caches").into_response(),
-            Err(_) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(CacheApiResult {
-                    success: false,
-                    error: Some("Failed to create cache".to_string()),
-                }),
-            )
-                .into_response(),
-        }
-    } else {
-        // User creates cache with owner tracking
-        let result = queries::create_cache_with_owner(
-            db,
-            name,
-            &keypair.export_keypair(),
-            is_public,
-            store_dir,
-            priority,
-            &[],
-            Some(user.id),
-        )
-        .await;
+    // Admins create caches without owner tracking
+    let owner = if is_admin { None } else { Some(user.id) };
+    let result = insert_cache(
+        &web_ui, name, &keypair, is_public, store_dir, priority, owner,
+    )
+    .await;
 
-        match result {
-            Ok(_cache) => {
+    match result {
+        Ok(true) => {
+            if !is_admin {
                 // Grant full permissions to the creator
                 let perm_result = queries::set_user_permission(
                     db, user.id, name, true, // can_pull
@@ -359,32 +410,80 @@ pub async fn create_cache(
                     );
                     // Don't fail the whole operation - cache was created
                 }
-
-                Redirect::to("/ui/caches").into_response()
-            }
-            Err(e) => {
-                // Check if it's a uniqueness constraint violation
-                let error_str = format!("{:?}", e);
-                if error_str.contains("UNIQUE constraint") || error_str.contains("already exists") {
-                    (
-                        StatusCode::CONFLICT,
-                        Json(CacheApiResult {
-                            success: false,
-                            error: Some("A cache with this name already exists".to_string()),
-                        }),
-                    )
-                        .into_response()
-                } else {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(CacheApiResult {
-                            success: false,
-                            error: Some("Failed to create cache".to_string()),
-                        }),
-                    )
-                        .into_response()
-                }
             }
+
+            Redirect::to("/ui/caches").into_response()
+        }
+        Ok(false) => (
+            StatusCode::CONFLICT,
+            Json(CacheApiResult {
+                success: false,
+                error: Some("A cache with this name already exists".to_string()),
+            }),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(CacheApiResult {
+                success: false,
+                error: Some("Failed to create cache".to_string()),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Creates a cache, returning `false` if it already exists.
+///
+/// The keypair is sealed for the cache in the same transaction.
+async fn insert_cache(
+    web_ui: &WebUiState,
+    name: &str,
+    keypair: &NixKeypair,
+    is_public: bool,
+    store_dir: &str,
+    priority: i32,
+    owner: Option<i64>,
+) -> ServerResult<bool> {
+    let cache_name: CacheName = name.parse()?;
+
+    let txn = web_ui
+        .app_state
+        .database()
+        .await?
+        .begin_transaction()
+        .await
+        .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+    let conn = txn.connection();
+
+    let result = async {
+        let num_inserted = queries::insert_cache_with_owner(
+            conn, name, "", is_public, store_dir, priority, "[]", owner,
+        )
+        .await?;
+
+        if num_inserted == 0 {
+            return Ok(false);
+        }
+
+        let cache = queries::find_cache(conn, &cache_name).await?;
+        signing::store_new_cache_keypair(conn, &web_ui.app_state.master_keys, cache.id, keypair)
+            .await?;
+
+        Ok(true)
+    }
+    .await;
+
+    match result {
+        Ok(inserted) => {
+            txn.commit()
+                .await
+                .map_err(|e| ServerError::database_error(TursoDbError(e.to_string())))?;
+            Ok(inserted)
+        }
+        Err(e) => {
+            let _ = txn.rollback().await;
+            Err(e)
         }
     }
 }
@@ -479,13 +578,20 @@ pub async fn delete_cache(
     };
 
     match result {
-        Ok(()) => (
-            StatusCode::OK,
-            Json(CacheApiResult {
-                success: true,
-                error: None,
-            }),
-        ),
+        Ok(()) => {
+            web_ui
+                .app_state
+                .narinfo_cache
+                .invalidate_cache(&cache_name_parsed);
+
+            (
+                StatusCode::OK,
+                Json(CacheApiResult {
+                    success: true,
+                    error: None,
+                }),
+            )
+        }
         Err(_) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(CacheApiResult {
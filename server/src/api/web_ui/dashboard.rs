@@ -9,8 +9,12 @@ use axum_extra::extract::cookie::PrivateCookieJar;
 
 use super::auth::get_session_user;
 use super::WebUiState;
-use crate::database::models::{CacheModel, UserCachePermissionModel, UserModel};
+use crate::database::models::{CacheModel, GcRunModel, UserCachePermissionModel, UserModel};
 use crate::database::queries;
+use crate::gc::format_bytes;
+
+/// The number of garbage collection runs shown to admins.
+const RECENT_GC_RUNS: u64 = 5;
 
 /// Dashboard template.
 #[derive(Template)]
@@ -19,17 +23,49 @@ struct DashboardTemplate {
     user: UserModel,
     caches: Vec<CacheWithStats>,
     total_objects: i64,
+    gc_runs: Vec<GcRunSummary>,
 }
 
 /// Cache with statistics for display.
 pub struct CacheWithStats {
     pub cache: CacheModel,
     pub object_count: i64,
+
+    /// Objects whose NARs are missing chunks.
+    pub incomplete_count: i64,
     pub can_push: bool,
     pub can_pull: bool,
     pub can_delete: bool,
 }
 
+/// A garbage collection run formatted for display.
+pub struct GcRunSummary {
+    pub started_at: String,
+    pub duration: String,
+    pub objects: u64,
+    pub nars: u64,
+    pub chunks: u64,
+    pub bytes_freed: String,
+    pub error: Option<String>,
+}
+
+impl From<GcRunModel> for GcRunSummary {
+    fn from(run: GcRunModel) -> Self {
+        let duration = (run.finished_at - run.started_at).num_seconds().max(0);
+        let report = run.report.0;
+
+        Self {
+            started_at: run.started_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+            duration: format!("{}s", duration),
+            objects: report.objects,
+            nars: report.nars,
+            chunks: report.chunks,
+            bytes_freed: format_bytes(report.bytes_freed),
+            error: run.error,
+        }
+    }
+}
+
 /// GET /ui or /ui/dashboard - Show the dashboard.
 pub async fn dashboard(
     AxumState(web_ui): AxumState<WebUiState>,
@@ -86,19 +122,37 @@ pub async fn dashboard(
             .unwrap_or(0);
         total_objects += object_count;
 
+        let incomplete_count = queries::count_incomplete_objects(db, cache.id)
+            .await
+            .unwrap_or(0);
+
         caches_with_stats.push(CacheWithStats {
             cache,
             object_count,
+            incomplete_count,
             can_push,
             can_pull,
             can_delete: false, // Dashboard doesn't need delete functionality
         });
     }
 
+    // Garbage collection is server-wide, so only admins get to see it
+    let gc_runs = if user.is_admin {
+        queries::find_recent_gc_runs(db, RECENT_GC_RUNS)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(GcRunSummary::from)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
     let template = DashboardTemplate {
         user,
         caches: caches_with_stats,
         total_objects,
+        gc_runs,
     };
 
     Html(
@@ -113,6 +113,7 @@ pub fn get_router(config: &WebUiConfig, app_state: State) -> Option<Router<()>>
             get(caches::list_caches).post(caches::create_cache),
         )
         .route("/ui/caches/:name", delete(caches::delete_cache))
+        .route("/ui/caches/:name/incomplete", get(caches::incomplete_paths))
         .route(
             "/ui/tokens",
             get(tokens::tokens_page).post(tokens::create_token),
@@ -1,10 +1,13 @@
 use std::sync::Arc;
 
+use async_compression::tokio::bufread::{BrotliDecoder, XzDecoder, ZstdDecoder};
 use digest::Output as DigestOutput;
 use sha2::{Digest, Sha256};
 use tokio::io::{AsyncBufRead, AsyncRead};
 use tokio::sync::OnceCell;
 
+use crate::error::{ErrorKind, ServerResult};
+use crate::narinfo::Compression;
 use attic::io::HashReader;
 
 pub type CompressorFn<C> = Box<dyn FnOnce(C) -> Box<dyn AsyncRead + Unpin + Send> + Send>;
@@ -79,3 +82,59 @@ impl CompressionStream {
         self.file_compute.get()
     }
 }
+
+/// Returns a stream that decompresses a compressed NAR or file.
+pub fn get_decompressor<S>(
+    compression: Compression,
+    stream: S,
+) -> ServerResult<Box<dyn AsyncRead + Unpin + Send>>
+where
+    S: AsyncBufRead + Unpin + Send + 'static,
+{
+    match compression {
+        Compression::None => Ok(Box::new(stream)),
+        Compression::Xz => Ok(Box::new(XzDecoder::new(stream))),
+        Compression::Zstd => Ok(Box::new(ZstdDecoder::new(stream))),
+        Compression::Brotli => Ok(Box::new(BrotliDecoder::new(stream))),
+        Compression::Bzip2 => Err(ErrorKind::InvalidCompressionType {
+            name: compression.as_str().to_string(),
+        }
+        .into()),
+    }
+}
+
+/// Returns a stream that decompresses a whole compressed file.
+///
+/// Unlike [`get_decompressor`], the stream keeps reading after the
+/// compressed data ends, so trailing garbage is an error instead of
+/// being silently ignored.
+pub fn get_file_decompressor<S>(
+    compression: Compression,
+    stream: S,
+) -> ServerResult<Box<dyn AsyncRead + Unpin + Send>>
+where
+    S: AsyncBufRead + Unpin + Send + 'static,
+{
+    match compression {
+        Compression::None => Ok(Box::new(stream)),
+        Compression::Xz => {
+            let mut decoder = XzDecoder::new(stream);
+            decoder.multiple_members(true);
+            Ok(Box::new(decoder))
+        }
+        Compression::Zstd => {
+            let mut decoder = ZstdDecoder::new(stream);
+            decoder.multiple_members(true);
+            Ok(Box::new(decoder))
+        }
+        Compression::Brotli => {
+            let mut decoder = BrotliDecoder::new(stream);
+            decoder.multiple_members(true);
+            Ok(Box::new(decoder))
+        }
+        Compression::Bzip2 => Err(ErrorKind::InvalidCompressionType {
+            name: compression.as_str().to_string(),
+        }
+        .into()),
+    }
+}
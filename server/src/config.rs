@@ -1,6 +1,6 @@
 //! Server configuration.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
@@ -18,7 +18,10 @@ use crate::access::{
     decode_token_rs256_secret_base64, HS256Key, RS256KeyPair, RS256PublicKey,
 };
 use crate::narinfo::Compression as NixCompression;
+use crate::signing::envelope::MasterKey;
 use crate::storage::{LocalStorageConfig, S3StorageConfig};
+use attic::cache::CacheName;
+use attic::signing::NixPublicKey;
 
 /// Application prefix in XDG base directories.
 ///
@@ -42,6 +45,14 @@ const ENV_TOKEN_RS256_SECRET_BASE64: &str = "ATTIC_SERVER_TOKEN_RS256_SECRET_BAS
 /// received JWTs only).
 const ENV_TOKEN_RS256_PUBKEY_BASE64: &str = "ATTIC_SERVER_TOKEN_RS256_PUBKEY_BASE64";
 
+/// Environment variable storing the base64-encoded master key that cache signing keys are
+/// encrypted with.
+const ENV_SIGNING_KEYS_MASTER_KEY_BASE64: &str = "ATTIC_SERVER_SIGNING_KEYS_MASTER_KEY_BASE64";
+
+/// Environment variable storing the base64-encoded previous master keys, separated by spaces.
+const ENV_SIGNING_KEYS_PREVIOUS_MASTER_KEYS_BASE64: &str =
+    "ATTIC_SERVER_SIGNING_KEYS_PREVIOUS_MASTER_KEYS_BASE64";
+
 /// Environment variable storing the database connection string.
 const ENV_DATABASE_URL: &str = "ATTIC_SERVER_DATABASE_URL";
 
@@ -128,6 +139,34 @@ pub struct Config {
     #[serde(default = "Default::default")]
     pub garbage_collection: GarbageCollectionConfig,
 
+    /// Storage scrubbing.
+    #[serde(default = "Default::default")]
+    pub scrub: ScrubConfig,
+
+    /// Access time tracking.
+    #[serde(rename = "access-tracking")]
+    #[serde(default = "Default::default")]
+    pub access_tracking: AccessTrackingConfig,
+
+    /// Caching of rendered `.narinfo`s.
+    #[serde(rename = "narinfo-cache")]
+    #[serde(default = "Default::default")]
+    pub narinfo_cache: NarInfoCacheConfig,
+
+    /// Fetching from upstream substituters.
+    #[serde(default = "Default::default")]
+    pub upstream: UpstreamConfig,
+
+    /// Encryption of cache signing keys.
+    #[serde(rename = "signing-keys")]
+    #[serde(default = "Default::default")]
+    pub signing_keys: SigningKeysConfig,
+
+    /// External agents that sign for caches, by cache name.
+    #[serde(rename = "signing-agents")]
+    #[serde(default = "Default::default")]
+    pub signing_agents: HashMap<CacheName, SigningAgentConfig>,
+
     /// JSON Web Token.
     #[serde(default = "Default::default")]
     pub jwt: JWTConfig,
@@ -250,6 +289,20 @@ pub struct DatabaseConfig {
     #[serde(rename = "sync-interval")]
     #[serde(with = "humantime_serde", default)]
     pub sync_interval: Option<Duration>,
+
+    /// Maximum number of idle connections to keep open.
+    ///
+    /// More connections are opened when needed, and closed once
+    /// released if this many are already idle.
+    #[serde(rename = "pool-size")]
+    #[serde(default = "default_db_pool_size")]
+    pub pool_size: usize,
+
+    /// How long to keep retrying a statement while the database is
+    /// locked by another connection.
+    #[serde(rename = "busy-timeout")]
+    #[serde(with = "humantime_serde", default = "default_db_busy_timeout")]
+    pub busy_timeout: Duration,
 }
 
 /// File storage configuration.
@@ -437,6 +490,168 @@ pub struct GarbageCollectionConfig {
     #[serde(rename = "default-retention-period")]
     #[serde(with = "humantime_serde", default = "default_default_retention_period")]
     pub default_retention_period: Duration,
+
+    /// The default storage quota of caches, in bytes.
+    ///
+    /// When the storage attributable to a cache exceeds its quota,
+    /// the least recently accessed objects are garbage-collected
+    /// until it fits again.
+    ///
+    /// Zero (default) means quota-based garbage-collection is
+    /// disabled by default. You can enable it on a per-cache basis.
+    #[serde(rename = "default-storage-quota", default)]
+    pub default_storage_quota: u64,
+
+    /// The maximum number of chunks to delete from the storage at once.
+    #[serde(rename = "deletion-concurrency")]
+    #[serde(default = "default_gc_deletion_concurrency")]
+    pub deletion_concurrency: usize,
+
+    /// The number of chunks to delete per batch.
+    #[serde(rename = "deletion-batch-size")]
+    #[serde(default = "default_gc_deletion_batch_size")]
+    pub deletion_batch_size: usize,
+
+    /// How long to wait before retrying a failed chunk deletion.
+    ///
+    /// The delay doubles with each failed attempt, up to
+    /// `max-deletion-retry-backoff`.
+    #[serde(rename = "deletion-retry-backoff")]
+    #[serde(
+        with = "humantime_serde",
+        default = "default_gc_deletion_retry_backoff"
+    )]
+    pub deletion_retry_backoff: Duration,
+
+    /// The maximum delay between retries of a failed chunk deletion.
+    #[serde(rename = "max-deletion-retry-backoff")]
+    #[serde(
+        with = "humantime_serde",
+        default = "default_gc_max_deletion_retry_backoff"
+    )]
+    pub max_deletion_retry_backoff: Duration,
+
+    /// How long a NAR uploaded through the Nix binary cache protocol
+    /// is kept waiting for its `.narinfo`.
+    ///
+    /// Staged NARs that are older are deleted.
+    #[serde(rename = "staged-nar-expiry")]
+    #[serde(with = "humantime_serde", default = "default_gc_staged_nar_expiry")]
+    pub staged_nar_expiry: Duration,
+}
+
+/// Storage scrub config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScrubConfig {
+    /// The frequency to scrub the storage at.
+    ///
+    /// Scrubbing reads back every chunk to check it against its
+    /// recorded hashes. If zero (default), automatic scrubbing is
+    /// disabled, but it can still be run manually with `atticadm scrub`.
+    #[serde(with = "humantime_serde", default)]
+    pub interval: Duration,
+
+    /// The maximum number of chunks to verify at once.
+    #[serde(default = "default_scrub_concurrency")]
+    pub concurrency: usize,
+}
+
+/// Access time tracking config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccessTrackingConfig {
+    /// How often to write the access times of downloaded objects.
+    ///
+    /// Access times are collected in memory and written together.
+    /// Those not yet written when the server crashes are lost. If zero,
+    /// they are written as each download is served.
+    #[serde(rename = "flush-interval")]
+    #[serde(with = "humantime_serde", default = "default_access_flush_interval")]
+    pub flush_interval: Duration,
+}
+
+/// `.narinfo` cache config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NarInfoCacheConfig {
+    /// The maximum number of `.narinfo`s to keep in memory.
+    ///
+    /// If zero, the cache is disabled.
+    #[serde(default = "default_narinfo_cache_capacity")]
+    pub capacity: usize,
+
+    /// How long to keep a `.narinfo`.
+    ///
+    /// Changes made by this server are reflected right away. Changes
+    /// made elsewhere, like by garbage collection or other servers
+    /// sharing the database, take up to this long to be seen.
+    #[serde(with = "humantime_serde", default = "default_narinfo_cache_ttl")]
+    pub ttl: Duration,
+
+    /// How long to remember that a path is missing.
+    #[serde(rename = "negative-ttl")]
+    #[serde(
+        with = "humantime_serde",
+        default = "default_narinfo_cache_negative_ttl"
+    )]
+    pub negative_ttl: Duration,
+}
+
+/// Upstream substituter config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpstreamConfig {
+    /// How long to wait for a connection to an upstream.
+    #[serde(rename = "connect-timeout")]
+    #[serde(with = "humantime_serde", default = "default_upstream_connect_timeout")]
+    pub connect_timeout: Duration,
+
+    /// How long to wait for an upstream to send more data.
+    ///
+    /// This applies to each read rather than the whole request, so
+    /// large NARs can take longer as long as they keep coming.
+    #[serde(rename = "read-timeout")]
+    #[serde(with = "humantime_serde", default = "default_upstream_read_timeout")]
+    pub read_timeout: Duration,
+}
+
+/// Encryption of cache signing keys.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SigningKeysConfig {
+    /// The master key that signing keys are encrypted with.
+    ///
+    /// If unset, signing keys are stored in plaintext.
+    #[serde(rename = "master-key-base64")]
+    #[serde(deserialize_with = "deserialize_master_key_base64")]
+    #[serde(default = "load_master_key_from_env")]
+    pub master_key: Option<MasterKey>,
+
+    /// Master keys that signing keys were encrypted with before.
+    ///
+    /// Signing keys encrypted with them can still be decrypted. Once
+    /// `atticadm keys rewrap` has encrypted them all with the current
+    /// master key, the previous ones can be removed.
+    #[serde(rename = "previous-master-keys-base64")]
+    #[serde(deserialize_with = "deserialize_master_keys_base64")]
+    #[serde(default = "load_previous_master_keys_from_env")]
+    pub previous_master_keys: Vec<MasterKey>,
+}
+
+/// An external agent that signs for a cache.
+///
+/// The keypairs of the cache in the database are then not used to sign.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SigningAgentConfig {
+    /// The Unix socket the agent listens on.
+    #[serde(rename = "socket-path")]
+    pub socket_path: PathBuf,
+
+    /// The public key of the agent.
+    ///
+    /// Signatures returned by the agent are checked against it.
+    #[serde(rename = "public-key")]
+    pub public_key: NixPublicKey,
+
+    /// How long to wait for the agent to sign.
+    #[serde(with = "humantime_serde", default = "default_signing_agent_timeout")]
+    pub timeout: Duration,
 }
 
 fn load_jwt_signing_config_from_env() -> JWTSigningConfig {
@@ -528,6 +743,25 @@ fn load_token_rs256_pubkey_from_env() -> Option<JWTSigningConfig> {
     Some(JWTSigningConfig::RS256VerifyOnly(pubkey))
 }
 
+fn load_master_key_from_env() -> Option<MasterKey> {
+    let s = read_non_empty_var(ENV_SIGNING_KEYS_MASTER_KEY_BASE64)
+        .expect("Master key environment cannot be read")?;
+
+    Some(MasterKey::from_base64(&s).expect("Master key cannot be decoded"))
+}
+
+fn load_previous_master_keys_from_env() -> Vec<MasterKey> {
+    let Some(s) = read_non_empty_var(ENV_SIGNING_KEYS_PREVIOUS_MASTER_KEYS_BASE64)
+        .expect("Previous master keys environment cannot be read")
+    else {
+        return Vec::new();
+    };
+
+    s.split_whitespace()
+        .map(|key| MasterKey::from_base64(key).expect("Previous master key cannot be decoded"))
+        .collect()
+}
+
 fn load_database_url_from_env() -> String {
     env::var(ENV_DATABASE_URL).expect(&format!(
         "Database URL must be specified in either database.url \
@@ -589,6 +823,57 @@ impl Default for GarbageCollectionConfig {
         Self {
             interval: Duration::from_secs(43200),
             default_retention_period: Duration::ZERO,
+            default_storage_quota: 0,
+            deletion_concurrency: default_gc_deletion_concurrency(),
+            deletion_batch_size: default_gc_deletion_batch_size(),
+            deletion_retry_backoff: default_gc_deletion_retry_backoff(),
+            max_deletion_retry_backoff: default_gc_max_deletion_retry_backoff(),
+            staged_nar_expiry: default_gc_staged_nar_expiry(),
+        }
+    }
+}
+
+impl Default for ScrubConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::ZERO,
+            concurrency: default_scrub_concurrency(),
+        }
+    }
+}
+
+impl Default for AccessTrackingConfig {
+    fn default() -> Self {
+        Self {
+            flush_interval: default_access_flush_interval(),
+        }
+    }
+}
+
+impl Default for SigningKeysConfig {
+    fn default() -> Self {
+        Self {
+            master_key: load_master_key_from_env(),
+            previous_master_keys: load_previous_master_keys_from_env(),
+        }
+    }
+}
+
+impl Default for NarInfoCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: default_narinfo_cache_capacity(),
+            ttl: default_narinfo_cache_ttl(),
+            negative_ttl: default_narinfo_cache_negative_ttl(),
+        }
+    }
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: default_upstream_connect_timeout(),
+            read_timeout: default_upstream_read_timeout(),
         }
     }
 }
@@ -617,6 +902,30 @@ where
     ))
 }
 
+fn deserialize_master_key_base64<'de, D>(deserializer: D) -> Result<Option<MasterKey>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    use de::Error;
+
+    let s = String::deserialize(deserializer)?;
+    let key = MasterKey::from_base64(&s).map_err(Error::custom)?;
+
+    Ok(Some(key))
+}
+
+fn deserialize_master_keys_base64<'de, D>(deserializer: D) -> Result<Vec<MasterKey>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    use de::Error;
+
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| MasterKey::from_base64(s).map_err(Error::custom))
+        .collect()
+}
+
 fn deserialize_token_hs256_secret_base64<'de, D>(deserializer: D) -> Result<HS256Key, D::Error>
 where
     D: de::Deserializer<'de>,
@@ -663,6 +972,14 @@ fn default_db_heartbeat() -> bool {
     false
 }
 
+fn default_db_pool_size() -> usize {
+    8
+}
+
+fn default_db_busy_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
 fn default_soft_delete_caches() -> bool {
     false
 }
@@ -679,6 +996,58 @@ fn default_default_retention_period() -> Duration {
     Duration::ZERO
 }
 
+fn default_gc_deletion_concurrency() -> usize {
+    20
+}
+
+fn default_gc_deletion_batch_size() -> usize {
+    500
+}
+
+fn default_gc_deletion_retry_backoff() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_gc_max_deletion_retry_backoff() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+fn default_gc_staged_nar_expiry() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+fn default_scrub_concurrency() -> usize {
+    4
+}
+
+fn default_access_flush_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_signing_agent_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_narinfo_cache_capacity() -> usize {
+    10_000
+}
+
+fn default_narinfo_cache_ttl() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+fn default_narinfo_cache_negative_ttl() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_upstream_connect_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_upstream_read_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
 fn default_max_nar_info_size() -> usize {
     1 * 1024 * 1024 // 1 MiB
 }
@@ -0,0 +1,351 @@
+//! Database consistency checks.
+//!
+//! Crashes between statements, restores from backups and manual edits
+//! can leave the database in states the server never produces itself:
+//! objects pointing at NARs that are gone, NARs whose chunks no longer
+//! add up, or holder counts that never drop back to zero and keep
+//! garbage collection from making progress. The checker looks for these
+//! and, if asked, repairs them.
+//!
+//! It only needs the database, so it can run against a copy of the
+//! SQLite file without the storage or the rest of the configuration.
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::connection::TursoConnection;
+use super::migrations;
+use super::queries;
+use crate::scrub::DamagedPath;
+
+/// The number of rows to fix at once.
+const BATCH_SIZE: usize = 500;
+
+/// The number of example IDs to keep for each problem.
+const MAX_EXAMPLES: usize = 10;
+
+/// What to check and repair.
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    /// How long NARs and chunks may stay pending before they are
+    /// considered abandoned.
+    pub pending_age: Duration,
+
+    /// Whether to repair the problems found.
+    pub fix: bool,
+
+    /// Whether to reset positive holder counts.
+    ///
+    /// Holders are taken by requests in progress, so this is only safe
+    /// while no server is using the database.
+    pub reset_holders: bool,
+}
+
+/// What a consistency check found.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckReport {
+    /// Whether repairs were requested.
+    pub fix: bool,
+
+    /// The result of each check, in the order they were run.
+    pub problems: Vec<Problem>,
+
+    /// Store paths using NARs that can't be reassembled, by cache.
+    pub damaged_paths: Vec<DamagedPath>,
+}
+
+/// The result of one check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Problem {
+    /// A short name for the check.
+    pub check: String,
+
+    /// What the check looks for.
+    pub description: String,
+
+    /// The number of rows found.
+    pub found: u64,
+
+    /// The IDs of some of the rows found.
+    pub examples: Vec<i64>,
+
+    /// The number of rows repaired.
+    pub fixed: u64,
+}
+
+impl CheckReport {
+    /// Returns the number of problems that were found but not repaired.
+    pub fn unfixed(&self) -> u64 {
+        self.problems
+            .iter()
+            .map(|problem| problem.found.saturating_sub(problem.fixed))
+            .sum()
+    }
+
+    fn push(&mut self, check: &str, description: &str, ids: &[i64], fixed: u64) {
+        if !ids.is_empty() {
+            tracing::warn!("{}: found {}, fixed {}", check, ids.len(), fixed);
+        }
+
+        self.problems.push(Problem {
+            check: check.to_string(),
+            description: description.to_string(),
+            found: ids.len() as u64,
+            examples: ids.iter().take(MAX_EXAMPLES).copied().collect(),
+            fixed,
+        });
+    }
+}
+
+/// Checks the consistency of the database, repairing problems if asked.
+#[instrument(skip_all)]
+pub async fn check_database(db: &TursoConnection, options: &CheckOptions) -> Result<CheckReport> {
+    if migrations::needs_migration(db).await? {
+        return Err(anyhow!(
+            "The database has pending migrations. Start the server once to apply them."
+        ));
+    }
+
+    tracing::info!("Checking the consistency of the database...");
+
+    let mut report = CheckReport {
+        fix: options.fix,
+        ..Default::default()
+    };
+    let cutoff = Utc::now() - chrono::Duration::from_std(options.pending_age)?;
+
+    // Abandoned uploads
+    let ids = queries::find_stale_pending_nar_ids(db, &cutoff).await?;
+    let mut fixed = 0;
+    if options.fix {
+        for batch in ids.chunks(BATCH_SIZE) {
+            fixed += queries::delete_nars_with_dependents(db, batch).await?;
+        }
+    }
+    report.push(
+        "stale-pending-nars",
+        "NARs pending for longer than the pending age",
+        &ids,
+        fixed,
+    );
+
+    // Their files, if any, are left for `atticadm storage reconcile`
+    let ids = queries::find_stale_pending_chunk_ids(db, &cutoff).await?;
+    let mut fixed = 0;
+    if options.fix {
+        for batch in ids.chunks(BATCH_SIZE) {
+            queries::detach_chunks(db, batch).await?;
+            fixed += queries::delete_chunks_by_ids(db, batch).await?;
+        }
+    }
+    report.push(
+        "stale-pending-chunks",
+        "Chunks pending for longer than the pending age",
+        &ids,
+        fixed,
+    );
+
+    // Referential integrity
+    let ids = queries::find_dangling_object_ids(db).await?;
+    let mut fixed = 0;
+    if options.fix {
+        for batch in ids.chunks(BATCH_SIZE) {
+            fixed += queries::delete_objects_by_ids(db, batch).await?;
+        }
+    }
+    report.push(
+        "dangling-objects",
+        "Objects whose NAR or cache doesn't exist",
+        &ids,
+        fixed,
+    );
+
+    let ids = queries::find_dangling_chunkref_ids(db).await?;
+    let mut fixed = 0;
+    if options.fix {
+        for batch in ids.chunks(BATCH_SIZE) {
+            fixed += queries::delete_chunkrefs_by_ids(db, batch).await?;
+        }
+    }
+    report.push(
+        "dangling-chunkrefs",
+        "Chunk references whose NAR doesn't exist",
+        &ids,
+        fixed,
+    );
+
+    // The NARs become incomplete and are repaired by pushing them again
+    let ids = queries::find_missing_chunk_ids(db).await?;
+    let mut fixed = 0;
+    if options.fix {
+        for batch in ids.chunks(BATCH_SIZE) {
+            queries::detach_chunks(db, batch).await?;
+            fixed += batch.len() as u64;
+        }
+    }
+    report.push(
+        "missing-chunks",
+        "Chunks referenced by NARs but don't exist",
+        &ids,
+        fixed,
+    );
+
+    // NARs that can't be reassembled
+    let ids = queries::find_nars_with_broken_chunk_sequences(db).await?;
+    let mut fixed = 0;
+    for batch in ids.chunks(BATCH_SIZE) {
+        for (cache, store_path) in queries::find_store_paths_by_nar_ids(db, batch).await? {
+            report.damaged_paths.push(DamagedPath { cache, store_path });
+        }
+
+        if options.fix {
+            fixed += queries::delete_nars_with_dependents(db, batch).await?;
+        }
+    }
+    report.damaged_paths.sort();
+    report.damaged_paths.dedup();
+    report.push(
+        "broken-chunk-sequences",
+        "NARs whose chunk references don't match their number of chunks",
+        &ids,
+        fixed,
+    );
+
+    let ids = queries::find_incomplete_nars_flagged_complete(db).await?;
+    let mut fixed = 0;
+    if options.fix {
+        for batch in ids.chunks(BATCH_SIZE) {
+            fixed += queries::mark_nars_incomplete(db, batch).await?;
+        }
+    }
+    report.push(
+        "incomplete-nars",
+        "NARs with detached chunks that are flagged as complete",
+        &ids,
+        fixed,
+    );
+
+    // Holder counts
+    let ids = queries::find_held_nar_ids(db, false).await?;
+    let mut fixed = 0;
+    if options.fix {
+        for batch in ids.chunks(BATCH_SIZE) {
+            fixed += queries::reset_nar_holders(db, batch).await?;
+        }
+    }
+    report.push(
+        "negative-nar-holders",
+        "NARs with a negative holder count",
+        &ids,
+        fixed,
+    );
+
+    let ids = queries::find_held_chunk_ids(db, false).await?;
+    let mut fixed = 0;
+    if options.fix {
+        for batch in ids.chunks(BATCH_SIZE) {
+            fixed += queries::reset_chunk_holders(db, batch).await?;
+        }
+    }
+    report.push(
+        "negative-chunk-holders",
+        "Chunks with a negative holder count",
+        &ids,
+        fixed,
+    );
+
+    // Positive counts are normal while a server is running, so they are
+    // only reported when they are to be reset
+    if options.reset_holders {
+        let ids = queries::find_held_nar_ids(db, true).await?;
+        let mut fixed = 0;
+        for batch in ids.chunks(BATCH_SIZE) {
+            fixed += queries::reset_nar_holders(db, batch).await?;
+        }
+        report.push(
+            "leaked-nar-holders",
+            "NARs with a positive holder count",
+            &ids,
+            fixed,
+        );
+
+        let ids = queries::find_held_chunk_ids(db, true).await?;
+        let mut fixed = 0;
+        for batch in ids.chunks(BATCH_SIZE) {
+            fixed += queries::reset_chunk_holders(db, batch).await?;
+        }
+        report.push(
+            "leaked-chunk-holders",
+            "Chunks with a positive holder count",
+            &ids,
+            fixed,
+        );
+    }
+
+    tracing::info!(
+        "Found {} problems, {} left unfixed",
+        report.problems.iter().map(|p| p.found).sum::<u64>(),
+        report.unfixed()
+    );
+
+    Ok(report)
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.fix {
+            writeln!(f, "Database check report")?;
+        } else {
+            writeln!(f, "Database check report (nothing was fixed)")?;
+        }
+        writeln!(f)?;
+
+        let width = self
+            .problems
+            .iter()
+            .map(|problem| problem.check.len())
+            .max()
+            .unwrap_or(0)
+            .max("CHECK".len());
+
+        writeln!(f, "{:<width$}  {:>8}  {:>8}", "CHECK", "FOUND", "FIXED")?;
+        for problem in self.problems.iter() {
+            writeln!(
+                f,
+                "{:<width$}  {:>8}  {:>8}",
+                problem.check, problem.found, problem.fixed
+            )?;
+        }
+
+        for problem in self.problems.iter().filter(|p| p.found != 0) {
+            writeln!(f)?;
+            writeln!(f, "{}:", problem.description)?;
+
+            let examples: Vec<String> = problem.examples.iter().map(|id| id.to_string()).collect();
+            write!(f, "  IDs: {}", examples.join(", "))?;
+            if problem.found > problem.examples.len() as u64 {
+                write!(
+                    f,
+                    " and {} more",
+                    problem.found - problem.examples.len() as u64
+                )?;
+            }
+            writeln!(f)?;
+        }
+
+        if !self.damaged_paths.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Damaged store paths:")?;
+            for path in self.damaged_paths.iter() {
+                writeln!(f, "  {}  {}", path.cache, path.store_path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -2,17 +2,36 @@
 //!
 //! Supports both local SQLite databases and remote Turso databases with
 //! optional embedded replicas for low-latency reads.
-
+//!
+//! Queries run on connections leased from a pool, so readers don't wait
+//! for each other or, in WAL mode, for the writer. A transaction keeps
+//! its connection until it ends, and only statements executed through
+//! [`TransactionGuard::connection`] are part of it.
+//!
+//! SQLite only allows one writer at a time. Statements that find the
+//! database locked are retried with a backoff until the busy timeout
+//! runs out. SQLite only reports the lock when a statement is first
+//! stepped, which for queries happens when reading the first row, so
+//! queries are retried until that row is read. No busy handler is set
+//! on the connections, as it would sleep on a runtime thread.
+
+use std::future::Future;
+use std::ops::Deref;
 use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
-use libsql::{params::IntoParams, Builder, Connection, Database};
-use tokio::sync::{Mutex, OwnedMutexGuard, RwLock};
+use chrono::{DateTime, Utc};
+use libsql::params::IntoParams;
+use libsql::{Builder, Connection, Database, Row};
+use tokio::sync::{Mutex, OwnedMutexGuard};
 
 use crate::config::DatabaseConfig;
 
+/// The longest time to wait between attempts while the database is busy.
+const MAX_BUSY_DELAY: Duration = Duration::from_millis(100);
+
 /// Configuration for Turso connection.
 #[derive(Debug, Clone)]
 pub struct TursoConfig {
@@ -24,6 +43,10 @@ pub struct TursoConfig {
     pub local_replica_path: Option<PathBuf>,
     /// Sync interval for embedded replicas (default: 60 seconds)
     pub sync_interval: Duration,
+    /// Maximum number of idle connections kept open (default: 8)
+    pub pool_size: usize,
+    /// How long to wait for locks held by other connections (default: 5 seconds)
+    pub busy_timeout: Duration,
 }
 
 impl TursoConfig {
@@ -33,6 +56,8 @@ impl TursoConfig {
             auth_token: config.auth_token.clone(),
             local_replica_path: config.local_replica_path.clone(),
             sync_interval: config.sync_interval.unwrap_or(Duration::from_secs(60)),
+            pool_size: config.pool_size,
+            busy_timeout: config.busy_timeout,
         }
     }
 
@@ -40,6 +65,25 @@ impl TursoConfig {
     pub fn is_remote(&self) -> bool {
         self.url.starts_with("libsql://") || self.url.starts_with("https://")
     }
+
+    /// Returns true if reads are served from a local embedded replica.
+    pub fn has_replica(&self) -> bool {
+        self.is_remote() && self.local_replica_path.is_some()
+    }
+
+    /// Returns the path of a local SQLite database.
+    fn local_path(&self) -> &str {
+        self.url
+            .strip_prefix("sqlite://")
+            .or_else(|| self.url.strip_prefix("sqlite:"))
+            .unwrap_or(&self.url)
+    }
+
+    /// Returns true if this is an in-memory SQLite database.
+    fn is_memory(&self) -> bool {
+        let path = self.local_path();
+        !self.is_remote() && (path == ":memory:" || path.contains("mode=memory"))
+    }
 }
 
 /// A connection to a Turso/libSQL database.
@@ -47,56 +91,94 @@ impl TursoConfig {
 /// This wrapper handles both local SQLite and remote Turso connections,
 /// including embedded replicas.
 pub struct TursoConnection {
+    pool: Arc<Pool>,
+    /// The connection of the transaction this handle belongs to.
+    transaction: Option<Connection>,
+}
+
+/// The connections to a database, shared by all handles.
+struct Pool {
     database: Database,
-    connection: RwLock<Connection>,
     config: TursoConfig,
-    /// Mutex to serialize transaction operations.
-    /// SQLite/libsql connections only support one active transaction at a time,
-    /// so we need to ensure only one transaction is active across all concurrent requests.
-    transaction_lock: Arc<Mutex<()>>,
+    /// Connections that are not leased.
+    idle: StdMutex<Vec<Connection>>,
+    /// The only connection to an in-memory database.
+    ///
+    /// Every connection to an in-memory database opens a database of its
+    /// own, so all queries share this one and transactions are serialized
+    /// through the mutex instead.
+    memory: Option<(Connection, Arc<Mutex<()>>)>,
+    /// The state of the embedded replica, if any.
+    replica: Option<StdMutex<ReplicaStatus>>,
 }
 
-/// A guard that holds a database transaction and releases the lock when dropped.
+/// The state of an embedded replica.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaStatus {
+    /// When the replica last caught up with the primary.
+    pub last_synced_at: Option<DateTime<Utc>>,
+    /// The replication index the replica has caught up to.
+    pub frame_no: Option<u64>,
+    /// Why the last sync failed, if it did.
+    pub last_error: Option<String>,
+}
+
+/// A connection leased from the pool.
 ///
-/// This ensures that only one transaction can be active at a time on the connection.
-/// The transaction is automatically rolled back if not explicitly committed.
+/// The connection is returned to the pool when dropped.
+pub struct PooledConnection {
+    connection: Option<Connection>,
+    /// The pool to return the connection to, if it was leased.
+    pool: Option<Arc<Pool>>,
+}
+
+/// Rows returned by a query.
 ///
-/// IMPORTANT: The mutex guard is held until both:
-/// 1. The transaction is committed or rolled back
-/// 2. This guard is dropped
+/// The connection they are read from stays leased until they are dropped.
+/// A row keeps the statement open on its own, so it must not outlive the
+/// rows it came from: the connection would go back to the pool with its
+/// read snapshot still held.
+pub struct Rows {
+    rows: libsql::Rows,
+    /// The first row, read when running the query.
+    first: Option<Option<Row>>,
+    _connection: Option<PooledConnection>,
+}
+
+/// A guard that holds a database transaction and its connection.
 ///
+/// The transaction is automatically rolled back if not explicitly committed.
 /// When dropped without explicit commit/rollback, the ROLLBACK is executed
-/// asynchronously, but the mutex is held until the ROLLBACK completes.
+/// asynchronously, and the connection is only returned to the pool after
+/// the ROLLBACK completes.
 pub struct TransactionGuard {
-    connection: Arc<TursoConnection>,
-    /// The mutex guard that serializes transactions.
-    /// This is wrapped in Option so we can take ownership in Drop and pass it
-    /// to the spawned rollback task, ensuring the mutex isn't released until
-    /// the rollback completes.
-    guard: Option<OwnedMutexGuard<()>>,
+    /// A handle whose queries run inside the transaction.
+    handle: TursoConnection,
+    connection: Option<PooledConnection>,
+    /// Held for the whole transaction on in-memory databases.
+    serial: Option<OwnedMutexGuard<()>>,
     committed: bool,
 }
 
 impl TransactionGuard {
     /// Commits the transaction.
     pub async fn commit(mut self) -> Result<()> {
-        self.connection.execute("COMMIT", ()).await?;
+        self.handle.execute("COMMIT", ()).await?;
         self.committed = true;
-        // guard will be dropped here, releasing the mutex
+        // The connection is returned to the pool here
         Ok(())
     }
 
     /// Rolls back the transaction.
     pub async fn rollback(mut self) -> Result<()> {
-        self.connection.execute("ROLLBACK", ()).await?;
+        self.handle.execute("ROLLBACK", ()).await?;
         self.committed = true; // Mark as handled so Drop doesn't try to rollback again
-                               // guard will be dropped here, releasing the mutex
         Ok(())
     }
 
-    /// Returns a reference to the underlying connection for executing queries within the transaction.
-    pub fn connection(&self) -> &Arc<TursoConnection> {
-        &self.connection
+    /// Returns a handle for executing queries within the transaction.
+    pub fn connection(&self) -> &TursoConnection {
+        &self.handle
     }
 }
 
@@ -106,28 +188,121 @@ impl Drop for TransactionGuard {
             // Transaction was not committed, we should rollback.
             // Since we can't do async in Drop, we spawn a task.
             //
-            // CRITICAL: We take the mutex guard and pass it to the spawned task.
-            // This ensures the mutex is NOT released until after the ROLLBACK completes.
-            // Without this, a new transaction could start before the ROLLBACK finishes,
-            // causing "cannot start a transaction within a transaction" errors.
-            let connection = self.connection.clone();
-            let guard = self.guard.take();
+            // The connection is only returned to the pool once the ROLLBACK
+            // completes, so no one else can use it while it's still inside
+            // the transaction. If the ROLLBACK fails, the connection is
+            // discarded instead.
+            let connection = self.connection.take();
+            let serial = self.serial.take();
             tokio::spawn(async move {
-                if let Err(e) = connection.execute("ROLLBACK", ()).await {
-                    tracing::warn!("Failed to rollback transaction on drop: {}", e);
+                if let Some(connection) = &connection {
+                    if let Err(e) = connection.execute("ROLLBACK", ()).await {
+                        tracing::warn!("Failed to rollback transaction on drop: {}", e);
+                    }
                 }
-                // Now the guard is dropped, releasing the mutex
-                drop(guard);
+                drop(connection);
+                drop(serial);
             });
         }
-        // If committed, the guard is dropped here (or was already dropped if taken)
+    }
+}
+
+impl Pool {
+    /// Opens a new connection.
+    async fn open(&self) -> Result<Connection> {
+        let connection = self.database.connect()?;
+
+        // Apply SQLite optimizations for local databases
+        if !self.config.is_remote() || self.config.local_replica_path.is_some() {
+            // These pragmas improve performance for SQLite
+            // We ignore errors as these are optimizations, not requirements
+            let pragmas = [
+                "PRAGMA synchronous=normal",
+                "PRAGMA temp_store=memory",
+                "PRAGMA mmap_size=30000000000",
+            ];
+
+            for pragma in pragmas {
+                if let Err(e) = run_pragma(&connection, pragma).await {
+                    tracing::debug!("Failed to set pragma ({}): {}", pragma, e);
+                }
+            }
+        }
+
+        Ok(connection)
+    }
+
+    /// Leases a connection, opening a new one if none is idle.
+    ///
+    /// The pool never makes callers wait, so code that holds on to one
+    /// connection while leasing another cannot deadlock.
+    async fn lease(self: &Arc<Self>) -> Result<PooledConnection> {
+        let connection = match &self.memory {
+            Some((connection, _)) => connection.clone(),
+            None => {
+                let idle = self.idle.lock().unwrap().pop();
+                match idle {
+                    Some(connection) => connection,
+                    None => self.open().await?,
+                }
+            }
+        };
+
+        Ok(PooledConnection {
+            connection: Some(connection),
+            pool: Some(self.clone()),
+        })
+    }
+
+    /// Returns a connection to the pool.
+    fn release(&self, connection: Connection) {
+        if self.memory.is_some() {
+            return;
+        }
+
+        if !connection.is_autocommit() {
+            tracing::warn!("Discarding a connection that is still inside a transaction");
+            return;
+        }
+
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.config.pool_size {
+            idle.push(connection);
+        }
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let (Some(pool), Some(connection)) = (&self.pool, self.connection.take()) {
+            pool.release(connection);
+        }
+    }
+}
+
+impl Rows {
+    /// Returns the next row, if any.
+    pub async fn next(&mut self) -> libsql::Result<Option<Row>> {
+        match self.first.take() {
+            Some(row) => Ok(row),
+            None => self.rows.next().await,
+        }
     }
 }
 
 impl std::fmt::Debug for TursoConnection {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("TursoConnection")
-            .field("config", &self.config)
+            .field("config", &self.pool.config)
+            .field("transaction", &self.transaction.is_some())
             .finish_non_exhaustive()
     }
 }
@@ -164,104 +339,220 @@ impl TursoConnection {
             }
         } else {
             // Local SQLite database
-            let path = config
-                .url
-                .strip_prefix("sqlite://")
-                .or_else(|| config.url.strip_prefix("sqlite:"))
-                .unwrap_or(&config.url);
+            let path = config.local_path();
 
             tracing::info!("Connecting to local SQLite database at {}", path);
             Builder::new_local(path).build().await?
         };
 
-        let connection = database.connect()?;
-
-        // Apply SQLite optimizations for local databases
-        if !config.is_remote() || config.local_replica_path.is_some() {
-            // These pragmas improve performance for SQLite
-            // We ignore errors as these are optimizations, not requirements
-            let pragmas = [
-                "PRAGMA journal_mode=WAL",
-                "PRAGMA synchronous=normal",
-                "PRAGMA temp_store=memory",
-                "PRAGMA mmap_size=30000000000",
-            ];
+        let mut pool = Pool {
+            database,
+            config,
+            idle: StdMutex::new(Vec::new()),
+            memory: None,
+            replica: None,
+        };
+        let connection = pool.open().await?;
 
-            for pragma in pragmas {
-                if let Err(e) = connection.execute(pragma, ()).await {
-                    tracing::debug!("Failed to set pragma ({}): {}", pragma, e);
-                }
+        // The journal mode is a property of the database file, so it only
+        // needs to be set once
+        if !pool.config.is_remote() {
+            if let Err(e) = run_pragma(&connection, "PRAGMA journal_mode=WAL").await {
+                tracing::debug!("Failed to enable WAL mode: {}", e);
             }
         }
 
+        if pool.config.is_memory() {
+            pool.memory = Some((connection, Arc::new(Mutex::new(()))));
+        } else {
+            pool.idle.get_mut().unwrap().push(connection);
+        }
+
+        if pool.config.has_replica() {
+            pool.replica = Some(StdMutex::new(ReplicaStatus::default()));
+        }
+
+        let conn = Self {
+            pool: Arc::new(pool),
+            transaction: None,
+        };
+
         // Sync the embedded replica if configured
-        if config.is_remote() && config.local_replica_path.is_some() {
+        if conn.pool.replica.is_some() {
             tracing::info!("Performing initial sync of embedded replica");
-            database.sync().await?;
+            conn.sync().await?;
         }
 
-        Ok(Arc::new(Self {
-            database,
-            connection: RwLock::new(connection),
-            config,
-            transaction_lock: Arc::new(Mutex::new(())),
-        }))
+        Ok(Arc::new(conn))
     }
 
-    /// Returns a reference to the underlying connection.
+    /// Returns a connection for direct use.
     ///
-    /// Note: libsql connections are not thread-safe for concurrent writes,
-    /// so callers should use this carefully.
-    pub async fn conn(&self) -> tokio::sync::RwLockReadGuard<'_, Connection> {
-        self.connection.read().await
-    }
-
-    /// Returns a mutable reference to the underlying connection.
-    pub async fn conn_mut(&self) -> tokio::sync::RwLockWriteGuard<'_, Connection> {
-        self.connection.write().await
+    /// Handles belonging to a transaction return the connection of the
+    /// transaction. Otherwise, the connection is leased from the pool
+    /// until the returned value is dropped.
+    pub async fn conn(&self) -> Result<PooledConnection> {
+        match &self.transaction {
+            Some(connection) => Ok(PooledConnection {
+                connection: Some(connection.clone()),
+                pool: None,
+            }),
+            None => self.pool.lease().await,
+        }
     }
 
     /// Executes a query and returns the number of affected rows.
     pub async fn execute<P: IntoParams>(&self, sql: &str, params: P) -> Result<u64> {
-        let conn = self.connection.read().await;
-        Ok(conn.execute(sql, params).await?)
+        let conn = self.conn().await?;
+        let params = params.into_params()?;
+        let affected = self
+            .retry_busy(|| conn.execute(sql, params.clone()))
+            .await?;
+
+        Ok(affected)
     }
 
     /// Executes a query and returns the results.
-    pub async fn query<P: IntoParams>(&self, sql: &str, params: P) -> Result<libsql::Rows> {
-        let conn = self.connection.read().await;
-        Ok(conn.query(sql, params).await?)
+    pub async fn query<P: IntoParams>(&self, sql: &str, params: P) -> Result<Rows> {
+        let conn = self.conn().await?;
+        let params = params.into_params()?;
+        let (rows, first) = self
+            .retry_busy(|| async {
+                let mut rows = conn.query(sql, params.clone()).await?;
+                let first = rows.next().await?;
+                Ok((rows, first))
+            })
+            .await?;
+
+        Ok(Rows {
+            rows,
+            first: Some(first),
+            _connection: Some(conn),
+        })
     }
 
     /// Syncs the embedded replica with the remote database.
     ///
     /// This is a no-op for local databases or remote connections without replicas.
     pub async fn sync(&self) -> Result<()> {
-        if self.config.is_remote() && self.config.local_replica_path.is_some() {
-            self.database.sync().await?;
+        let Some(replica) = &self.pool.replica else {
+            return Ok(());
+        };
+
+        let result = self.pool.database.sync().await;
+
+        let mut status = replica.lock().unwrap();
+        match result {
+            Ok(replicated) => {
+                status.last_synced_at = Some(Utc::now());
+                status.frame_no = replicated.frame_no().or(status.frame_no);
+                status.last_error = None;
+                Ok(())
+            }
+            Err(e) => {
+                status.last_error = Some(e.to_string());
+                Err(e.into())
+            }
         }
-        Ok(())
+    }
+
+    /// Returns the state of the embedded replica, if any.
+    pub fn replica_status(&self) -> Option<ReplicaStatus> {
+        self.pool
+            .replica
+            .as_ref()
+            .map(|replica| replica.lock().unwrap().clone())
     }
 
     /// Returns the database configuration.
     pub fn config(&self) -> &TursoConfig {
-        &self.config
+        &self.pool.config
     }
 
-    /// Begins a transaction and returns a guard that ensures proper serialization.
+    /// Begins a write transaction on a connection of its own.
     ///
-    /// Only one transaction can be active at a time on the connection.
-    /// The guard must be used to commit or rollback the transaction.
-    /// If the guard is dropped without committing, the transaction is rolled back.
-    pub async fn begin_transaction(self: &Arc<Self>) -> Result<TransactionGuard> {
-        let guard = Arc::clone(&self.transaction_lock).lock_owned().await;
-        self.execute("BEGIN IMMEDIATE", ()).await?;
+    /// The transaction is started with `BEGIN IMMEDIATE`, so it holds
+    /// the write lock from the start and can't fail to upgrade a read
+    /// lock halfway through. While another connection holds the lock,
+    /// starting the transaction is retried until the busy timeout.
+    ///
+    /// Queries must be run through [`TransactionGuard::connection`] to be
+    /// part of the transaction. If the guard is dropped without
+    /// committing, the transaction is rolled back.
+    pub async fn begin_transaction(&self) -> Result<TransactionGuard> {
+        if self.transaction.is_some() {
+            return Err(anyhow!("Nested transactions are not supported"));
+        }
+
+        let serial = match &self.pool.memory {
+            Some((_, lock)) => Some(lock.clone().lock_owned().await),
+            None => None,
+        };
+
+        let connection = self.pool.lease().await?;
+        self.retry_busy(|| connection.execute("BEGIN IMMEDIATE", ()))
+            .await?;
+
         Ok(TransactionGuard {
-            connection: Arc::clone(self),
-            guard: Some(guard),
+            handle: TursoConnection {
+                pool: self.pool.clone(),
+                transaction: Some((*connection).clone()),
+            },
+            connection: Some(connection),
+            serial,
             committed: false,
         })
     }
+
+    /// Runs a statement, retrying while the database is locked by another
+    /// connection.
+    ///
+    /// A statement that fails this way hasn't changed anything, so it's
+    /// safe to run again.
+    async fn retry_busy<T, F, Fut>(&self, mut f: F) -> libsql::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = libsql::Result<T>>,
+    {
+        let start = Instant::now();
+        let mut delay = Duration::from_millis(5);
+
+        loop {
+            match f().await {
+                Err(e) if is_busy(&e) && start.elapsed() < self.pool.config.busy_timeout => {
+                    tracing::debug!("Database is busy, retrying in {:?}", delay);
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_BUSY_DELAY);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Runs a PRAGMA statement, which may return rows.
+async fn run_pragma(connection: &Connection, pragma: &str) -> libsql::Result<()> {
+    let mut rows = connection.query(pragma, ()).await?;
+    while rows.next().await?.is_some() {}
+    Ok(())
+}
+
+/// Returns whether an error is due to another connection holding a lock.
+///
+/// A stale read snapshot is also reported as busy, but retrying can't
+/// help with that.
+fn is_busy(error: &libsql::Error) -> bool {
+    const SQLITE_BUSY: i32 = 5;
+    const SQLITE_BUSY_SNAPSHOT: i32 = SQLITE_BUSY | (2 << 8);
+
+    let code = match error {
+        libsql::Error::SqliteFailure(code, _) => *code,
+        libsql::Error::RemoteSqliteFailure(code, 0, _) => *code,
+        libsql::Error::RemoteSqliteFailure(_, extended_code, _) => *extended_code,
+        _ => return false,
+    };
+
+    code & 0xff == SQLITE_BUSY && code != SQLITE_BUSY_SNAPSHOT
 }
 
 #[cfg(test)]
@@ -278,6 +569,8 @@ mod tests {
             auth_token: Some("token".to_string()),
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
         assert!(config.is_remote());
     }
@@ -289,6 +582,8 @@ mod tests {
             auth_token: Some("token".to_string()),
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
         assert!(config.is_remote());
     }
@@ -300,6 +595,8 @@ mod tests {
             auth_token: None,
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
         assert!(!config.is_remote());
     }
@@ -311,6 +608,8 @@ mod tests {
             auth_token: None,
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
         assert!(!config.is_remote());
     }
@@ -322,6 +621,8 @@ mod tests {
             auth_token: None,
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
         assert!(!config.is_remote());
     }
@@ -333,6 +634,8 @@ mod tests {
             auth_token: None,
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
         assert!(!config.is_remote());
     }
@@ -344,6 +647,8 @@ mod tests {
             auth_token: Some("token".to_string()),
             local_replica_path: Some(PathBuf::from("/tmp/replica.db")),
             sync_interval: Duration::from_secs(30),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
         assert!(config.is_remote());
         assert!(config.local_replica_path.is_some());
@@ -362,6 +667,8 @@ mod tests {
             auth_token: None,
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
 
         let conn = TursoConnection::connect(config)
@@ -386,6 +693,8 @@ mod tests {
             auth_token: None,
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
 
         let conn = TursoConnection::connect(config)
@@ -404,6 +713,8 @@ mod tests {
             auth_token: None, // Missing auth token
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
 
         let result = TursoConnection::connect(config).await;
@@ -514,7 +825,8 @@ mod tests {
         // Start transaction
         let txn = conn.begin_transaction().await.expect("Begin failed");
 
-        conn.execute("INSERT INTO test_table (value) VALUES (?1)", ["in-txn"])
+        txn.connection()
+            .execute("INSERT INTO test_table (value) VALUES (?1)", ["in-txn"])
             .await
             .expect("Insert failed");
 
@@ -550,7 +862,8 @@ mod tests {
         // Start transaction
         let txn = conn.begin_transaction().await.expect("Begin failed");
 
-        conn.execute("INSERT INTO test_table (value) VALUES (?1)", ["in-txn"])
+        txn.connection()
+            .execute("INSERT INTO test_table (value) VALUES (?1)", ["in-txn"])
             .await
             .expect("Insert failed");
 
@@ -594,7 +907,7 @@ mod tests {
             .expect("Create failed");
 
         // Use conn() for read access
-        let guard = conn.conn().await;
+        let guard = conn.conn().await.expect("Lease failed");
         let mut rows = guard
             .query("SELECT 1", ())
             .await
@@ -613,6 +926,8 @@ mod tests {
             auth_token: None,
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
         let conn1 = TursoConnection::connect(config1).await;
         assert!(conn1.is_ok());
@@ -625,6 +940,8 @@ mod tests {
             auth_token: None,
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
         let conn2 = TursoConnection::connect(config2).await;
         assert!(conn2.is_ok());
@@ -753,11 +1070,12 @@ mod tests {
                     let txn = c.begin_transaction().await?;
 
                     // Update the chunk state
-                    c.execute(
-                        "UPDATE chunks SET state = 'valid' WHERE id = ?1",
-                        [id as i64],
-                    )
-                    .await?;
+                    txn.connection()
+                        .execute(
+                            "UPDATE chunks SET state = 'valid' WHERE id = ?1",
+                            [id as i64],
+                        )
+                        .await?;
 
                     // Commit
                     txn.commit().await?;
@@ -800,12 +1118,14 @@ mod tests {
         let txn = conn.begin_transaction().await.expect("Begin failed");
 
         // Execute a successful query
-        conn.execute("INSERT INTO test_table (value) VALUES ('first')", ())
+        txn.connection()
+            .execute("INSERT INTO test_table (value) VALUES ('first')", ())
             .await
             .expect("First insert should succeed");
 
         // Execute a failing query (NOT NULL constraint violation)
-        let fail_result = conn
+        let fail_result = txn
+            .connection()
             .execute("INSERT INTO test_table (value) VALUES (NULL)", ())
             .await;
         assert!(
@@ -852,7 +1172,8 @@ mod tests {
         // Start and drop a transaction without committing
         {
             let txn = conn.begin_transaction().await.expect("Begin failed");
-            conn.execute("INSERT INTO test_table (value) VALUES ('in-txn')", ())
+            txn.connection()
+                .execute("INSERT INTO test_table (value) VALUES ('in-txn')", ())
                 .await
                 .expect("Insert in txn failed");
             // txn is dropped here without commit - should trigger rollback
@@ -864,7 +1185,8 @@ mod tests {
 
         // Should be able to start a new transaction
         let txn2 = conn.begin_transaction().await.expect("Second begin failed");
-        conn.execute("INSERT INTO test_table (value) VALUES ('after')", ())
+        txn2.connection()
+            .execute("INSERT INTO test_table (value) VALUES ('after')", ())
             .await
             .expect("Insert after failed");
         txn2.commit().await.expect("Second commit failed");
@@ -903,7 +1225,8 @@ mod tests {
                 .await
                 .expect(&format!("Begin {} failed", i));
 
-            conn.execute("UPDATE counter SET count = count + 1 WHERE id = 1", ())
+            txn.connection()
+                .execute("UPDATE counter SET count = count + 1 WHERE id = 1", ())
                 .await
                 .expect(&format!("Update {} failed", i));
 
@@ -1004,15 +1327,17 @@ mod tests {
                     let txn = c.begin_transaction().await?;
 
                     // Update chunk state
-                    c.execute("UPDATE chunk SET state = 'valid' WHERE id = ?1", [chunk_id])
+                    txn.connection()
+                        .execute("UPDATE chunk SET state = 'valid' WHERE id = ?1", [chunk_id])
                         .await?;
 
                     // Insert chunkref
-                    c.execute(
-                        "INSERT INTO chunkref (nar_id, chunk_id, seq) VALUES (?1, ?2, ?3)",
-                        (nar, chunk_id, seq as i64),
-                    )
-                    .await?;
+                    txn.connection()
+                        .execute(
+                            "INSERT INTO chunkref (nar_id, chunk_id, seq) VALUES (?1, ?2, ?3)",
+                            (nar, chunk_id, seq as i64),
+                        )
+                        .await?;
 
                     txn.commit().await?;
 
@@ -1033,16 +1358,20 @@ mod tests {
         // Step 3: Final transaction to update NAR and create object
         let final_txn = conn.begin_transaction().await.expect("Final begin failed");
 
-        conn.execute("UPDATE nar SET state = 'valid' WHERE id = ?1", [nar_id])
+        final_txn
+            .connection()
+            .execute("UPDATE nar SET state = 'valid' WHERE id = ?1", [nar_id])
             .await
             .expect("Update NAR failed");
 
-        conn.execute(
-            "INSERT INTO object (nar_id, path) VALUES (?1, '/nix/store/test')",
-            [nar_id],
-        )
-        .await
-        .expect("Insert object failed");
+        final_txn
+            .connection()
+            .execute(
+                "INSERT INTO object (nar_id, path) VALUES (?1, '/nix/store/test')",
+                [nar_id],
+            )
+            .await
+            .expect("Insert object failed");
 
         final_txn.commit().await.expect("Final commit failed");
 
@@ -1088,7 +1417,7 @@ mod tests {
         // Task 1: Will succeed
         let handle1 = tokio::spawn(async move {
             let txn = conn1.begin_transaction().await?;
-            conn1
+            txn.connection()
                 .execute("INSERT INTO test_table (value) VALUES ('success1')", ())
                 .await?;
             txn.commit().await?;
@@ -1099,7 +1428,8 @@ mod tests {
         let handle2 = tokio::spawn(async move {
             let txn = conn2.begin_transaction().await?;
             // This will fail
-            let result = conn2
+            let result = txn
+                .connection()
                 .execute("INSERT INTO test_table (value) VALUES ('conflict')", ())
                 .await;
             if result.is_err() {
@@ -1121,12 +1451,13 @@ mod tests {
 
         // A subsequent transaction should work fine
         let txn = conn.begin_transaction().await.expect("Begin failed");
-        conn.execute(
-            "INSERT INTO test_table (value) VALUES ('after_failure')",
-            (),
-        )
-        .await
-        .expect("Insert after failure should work");
+        txn.connection()
+            .execute(
+                "INSERT INTO test_table (value) VALUES ('after_failure')",
+                (),
+            )
+            .await
+            .expect("Insert after failure should work");
         txn.commit()
             .await
             .expect("Commit after failure should work");
@@ -1159,10 +1490,10 @@ mod tests {
         // Start first transaction
         let txn1 = conn.begin_transaction().await.expect("Begin 1 failed");
 
-        // Try to start a second transaction - this should block due to mutex
+        // Try to start a second transaction - this should wait for the write lock
         let conn_clone = conn.clone();
         let handle = tokio::spawn(async move {
-            // This will wait for txn1 to release the mutex
+            // This will wait for txn1 to release the write lock
             let result = tokio::time::timeout(
                 tokio::time::Duration::from_millis(200),
                 conn_clone.begin_transaction(),
@@ -1171,7 +1502,7 @@ mod tests {
             result
         });
 
-        // The second transaction should timeout waiting for the mutex
+        // The second transaction should timeout waiting for the write lock
         let result = handle.await.expect("Task panicked");
         assert!(
             result.is_err(),
@@ -1179,29 +1510,27 @@ mod tests {
         );
 
         // First transaction should still work
-        conn.execute("INSERT INTO test_table (value) VALUES ('from_txn1')", ())
+        txn1.connection()
+            .execute("INSERT INTO test_table (value) VALUES ('from_txn1')", ())
             .await
             .expect("Insert in txn1 failed");
         txn1.commit().await.expect("Commit txn1 failed");
 
         // Now a new transaction should work
         let txn2 = conn.begin_transaction().await.expect("Begin 2 failed");
-        conn.execute("INSERT INTO test_table (value) VALUES ('from_txn2')", ())
+        txn2.connection()
+            .execute("INSERT INTO test_table (value) VALUES ('from_txn2')", ())
             .await
             .expect("Insert in txn2 failed");
         txn2.commit().await.expect("Commit txn2 failed");
     }
 
-    /// Test that TransactionGuard::Drop properly holds the mutex until ROLLBACK completes.
-    ///
-    /// Previously there was a bug where:
-    /// 1. The mutex guard was released immediately on drop
-    /// 2. The ROLLBACK was spawned asynchronously
-    /// 3. A new transaction could start before ROLLBACK finished
-    /// 4. This caused "cannot start a transaction within a transaction" errors
+    /// Test that TransactionGuard::Drop only returns the connection to the pool
+    /// once ROLLBACK completes.
     ///
-    /// The fix ensures the mutex is passed to the spawned rollback task,
-    /// so it's not released until after ROLLBACK completes.
+    /// Otherwise, a new transaction could start on the connection before the
+    /// ROLLBACK finished, causing "cannot start a transaction within a
+    /// transaction" errors.
     #[tokio::test]
     async fn test_transaction_guard_drop_race_condition() {
         let (conn, _temp_dir) = create_temp_db().await;
@@ -1216,24 +1545,25 @@ mod tests {
         // Start a transaction and drop it without committing
         // This simulates an error path where the guard goes out of scope
         {
-            let _txn = conn.begin_transaction().await.expect("Begin failed");
-            conn.execute(
-                "INSERT INTO test_table (value) VALUES ('from_dropped_txn')",
-                (),
-            )
-            .await
-            .expect("Insert failed");
-            // Dropped here - ROLLBACK is spawned, mutex is held by the spawned task
+            let txn = conn.begin_transaction().await.expect("Begin failed");
+            txn.connection()
+                .execute(
+                    "INSERT INTO test_table (value) VALUES ('from_dropped_txn')",
+                    (),
+                )
+                .await
+                .expect("Insert failed");
+            // Dropped here - ROLLBACK is spawned, the connection is held by the spawned task
         }
 
-        // Start another transaction - this should block until the ROLLBACK completes
-        // With the fix, this works correctly because the mutex is held until ROLLBACK finishes
+        // Start another transaction - this waits for the write lock until the ROLLBACK completes
         let txn2 = conn
             .begin_transaction()
             .await
             .expect("Begin 2 should succeed after ROLLBACK completes");
 
-        conn.execute("INSERT INTO test_table (value) VALUES ('from_txn2')", ())
+        txn2.connection()
+            .execute("INSERT INTO test_table (value) VALUES ('from_txn2')", ())
             .await
             .expect("Insert in txn2 failed");
 
@@ -1260,8 +1590,44 @@ mod tests {
         assert_eq!(value, "from_txn2");
     }
 
-    /// Test that demonstrates queries executing outside a transaction
-    /// while another transaction is active (non-transactional queries interleaving).
+    /// Test that writes returning rows wait for a transaction to end,
+    /// although SQLite only reports the lock once the first row is read.
+    #[tokio::test]
+    async fn test_query_returning_waits_for_transaction() {
+        let (conn, _temp_dir) = create_temp_db().await;
+
+        conn.execute(
+            "CREATE TABLE test_table (id INTEGER PRIMARY KEY, value TEXT)",
+            (),
+        )
+        .await
+        .expect("Create table failed");
+
+        let txn = conn.begin_transaction().await.expect("Begin failed");
+
+        let conn_clone = conn.clone();
+        let handle = tokio::spawn(async move {
+            let mut rows = conn_clone
+                .query(
+                    "INSERT INTO test_table (value) VALUES ('outside') RETURNING id",
+                    (),
+                )
+                .await?;
+            let row = rows.next().await?.expect("No row");
+            Ok::<_, anyhow::Error>(row.get::<i64>(0)?)
+        });
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        assert!(!handle.is_finished());
+
+        txn.commit().await.expect("Commit failed");
+
+        let result = handle.await.expect("Task panicked");
+        assert_eq!(1, result.expect("Query should succeed"));
+    }
+
+    /// Test that queries executed outside a transaction are not part of it,
+    /// and that writes wait for the transaction to end.
     #[tokio::test]
     async fn test_concurrent_queries_with_active_transaction() {
         let (conn, _temp_dir) = create_temp_db().await;
@@ -1283,19 +1649,29 @@ mod tests {
         // Start a transaction
         let txn = conn.begin_transaction().await.expect("Begin failed");
 
-        conn.execute(
-            "UPDATE test_table SET counter = counter + 1 WHERE id = 1",
-            (),
-        )
-        .await
-        .expect("Update in txn failed");
+        txn.connection()
+            .execute(
+                "UPDATE test_table SET counter = counter + 1 WHERE id = 1",
+                (),
+            )
+            .await
+            .expect("Update in txn failed");
+
+        // Queries outside the transaction don't see its changes
+        let counter: i64 = {
+            let mut rows = conn
+                .query("SELECT counter FROM test_table WHERE id = 1", ())
+                .await
+                .expect("Query failed");
+            let row = rows.next().await.expect("Next failed").expect("No row");
+            row.get(0).expect("Get failed")
+        };
+        assert_eq!(counter, 0);
 
-        // While the transaction is active, spawn tasks that execute queries
-        // (not transactions, just regular queries)
+        // While the transaction is active, spawn a task that executes a write
+        // (not a transaction, just a regular query)
         let conn_clone = conn.clone();
         let handle = tokio::spawn(async move {
-            // This query executes while txn is active
-            // It should see the uncommitted changes (within the same connection)
             conn_clone
                 .execute(
                     "UPDATE test_table SET counter = counter + 10 WHERE id = 1",
@@ -1304,13 +1680,17 @@ mod tests {
                 .await
         });
 
-        // Wait for the spawned query
-        let result = handle.await.expect("Task panicked");
-        assert!(result.is_ok(), "Query should succeed: {:?}", result.err());
+        // The write waits for the transaction
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        assert!(!handle.is_finished());
 
         // Commit the original transaction
         txn.commit().await.expect("Commit failed");
 
+        // Wait for the spawned query
+        let result = handle.await.expect("Task panicked");
+        assert!(result.is_ok(), "Query should succeed: {:?}", result.err());
+
         // Verify the final counter value
         // Should be 11 (1 from txn + 10 from concurrent query)
         let mut rows = conn
@@ -1352,11 +1732,12 @@ mod tests {
 
             let result = async {
                 // Update chunk
-                conn.execute(
-                    "UPDATE chunks SET state = 'valid', file_size = 100 WHERE id = 1",
-                    (),
-                )
-                .await?;
+                txn.connection()
+                    .execute(
+                        "UPDATE chunks SET state = 'valid', file_size = 100 WHERE id = 1",
+                        (),
+                    )
+                    .await?;
 
                 if should_fail {
                     return Err(anyhow::anyhow!("Simulated failure"));
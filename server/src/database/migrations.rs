@@ -275,6 +275,192 @@ const MIGRATIONS: &[Migration] = &[
             CREATE INDEX IF NOT EXISTS idx_cache_created_by ON cache (created_by_user_id);
         "#,
     },
+    Migration {
+        name: "m20240301_000001_create_staged_nar_table",
+        up_sql: r#"
+            CREATE TABLE IF NOT EXISTS staged_nar (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                cache_id INTEGER NOT NULL,
+                file_name TEXT NOT NULL,
+                nar_hash TEXT NOT NULL,
+                nar_size INTEGER NOT NULL,
+                remote_file TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (cache_id) REFERENCES cache(id) ON DELETE CASCADE,
+                UNIQUE (cache_id, file_name)
+            );
+            CREATE INDEX IF NOT EXISTS idx_staged_nar_created_at ON staged_nar (created_at);
+        "#,
+    },
+    Migration {
+        name: "m20240401_000001_create_nar_listing_table",
+        up_sql: r#"
+            CREATE TABLE IF NOT EXISTS nar_listing (
+                nar_id INTEGER PRIMARY KEY,
+                listing BLOB NOT NULL,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (nar_id) REFERENCES nar(id) ON DELETE CASCADE
+            );
+        "#,
+    },
+    Migration {
+        name: "m20240501_000001_create_build_log_table",
+        up_sql: r#"
+            CREATE TABLE IF NOT EXISTS build_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                cache_id INTEGER NOT NULL,
+                drv_name TEXT NOT NULL,
+                remote_file TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                created_by TEXT,
+                FOREIGN KEY (cache_id) REFERENCES cache(id) ON DELETE CASCADE,
+                UNIQUE (cache_id, drv_name)
+            );
+        "#,
+    },
+    Migration {
+        name: "m20240601_000001_create_realisation_table",
+        up_sql: r#"
+            CREATE TABLE IF NOT EXISTS realisation (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                cache_id INTEGER NOT NULL,
+                output_id TEXT NOT NULL,
+                out_path TEXT NOT NULL,
+                signatures TEXT NOT NULL,
+                dependent_realisations TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                created_by TEXT,
+                FOREIGN KEY (cache_id) REFERENCES cache(id) ON DELETE CASCADE,
+                UNIQUE (cache_id, output_id)
+            );
+        "#,
+    },
+    Migration {
+        name: "m20240701_000001_create_cache_upstream_table",
+        up_sql: r#"
+            CREATE TABLE IF NOT EXISTS cache_upstream (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                cache_id INTEGER NOT NULL,
+                url TEXT NOT NULL,
+                trusted_keys TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (cache_id) REFERENCES cache(id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_cache_upstream_cache ON cache_upstream(cache_id);
+        "#,
+    },
+    Migration {
+        name: "m20240801_000001_add_cache_storage_quota",
+        up_sql: r#"
+            ALTER TABLE cache ADD COLUMN storage_quota INTEGER;
+            CREATE INDEX IF NOT EXISTS idx_object_nar ON object (nar_id);
+        "#,
+    },
+    Migration {
+        name: "m20240901_000001_create_pin_table",
+        up_sql: r#"
+            CREATE TABLE IF NOT EXISTS pin (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                cache_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                store_path_hash TEXT NOT NULL,
+                store_path TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                created_by TEXT,
+                FOREIGN KEY (cache_id) REFERENCES cache(id) ON DELETE CASCADE,
+                UNIQUE (cache_id, name)
+            );
+        "#,
+    },
+    Migration {
+        name: "m20241001_000001_create_gc_run_table",
+        up_sql: r#"
+            CREATE TABLE IF NOT EXISTS gc_run (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                started_at TEXT NOT NULL,
+                finished_at TEXT NOT NULL,
+                error TEXT,
+                report TEXT NOT NULL
+            );
+        "#,
+    },
+    Migration {
+        name: "m20241101_000001_add_chunk_deletion_retries",
+        up_sql: r#"
+            ALTER TABLE chunk ADD COLUMN deletion_attempts INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE chunk ADD COLUMN next_deletion_attempt_at TEXT;
+            ALTER TABLE chunk ADD COLUMN last_deletion_error TEXT;
+            CREATE INDEX IF NOT EXISTS idx_chunk_state ON chunk (state);
+        "#,
+    },
+    Migration {
+        name: "m20241201_000001_create_incomplete_nar_table",
+        up_sql: r#"
+            CREATE TABLE IF NOT EXISTS incomplete_nar (
+                nar_id INTEGER PRIMARY KEY,
+                hits INTEGER NOT NULL,
+                first_hit_at TEXT NOT NULL,
+                last_hit_at TEXT NOT NULL,
+                FOREIGN KEY (nar_id) REFERENCES nar(id) ON DELETE CASCADE
+            );
+            CREATE INDEX IF NOT EXISTS idx_nar_completeness_hint ON nar (completeness_hint);
+        "#,
+    },
+    // Existing NARs are backfilled from their chunks, if all are present
+    Migration {
+        name: "m20250101_000001_add_nar_file_hash_and_size",
+        up_sql: r#"
+            ALTER TABLE nar ADD COLUMN file_hash TEXT;
+            ALTER TABLE nar ADD COLUMN file_size INTEGER;
+
+            UPDATE nar SET file_size = (
+                SELECT SUM(ch.file_size)
+                FROM chunkref cr
+                INNER JOIN chunk ch ON ch.id = cr.chunk_id
+                WHERE cr.nar_id = nar.id
+            )
+            WHERE state = 'V' AND num_chunks > 0 AND NOT EXISTS (
+                SELECT 1
+                FROM chunkref cr
+                LEFT JOIN chunk ch ON ch.id = cr.chunk_id
+                WHERE cr.nar_id = nar.id AND ch.file_size IS NULL
+            );
+
+            UPDATE nar SET file_hash = (
+                SELECT ch.file_hash
+                FROM chunkref cr
+                INNER JOIN chunk ch ON ch.id = cr.chunk_id
+                WHERE cr.nar_id = nar.id
+            )
+            WHERE num_chunks = 1 AND file_size IS NOT NULL;
+        "#,
+    },
+    // The primary keypair stays in the cache table, this only holds
+    // the keys of rotations
+    Migration {
+        name: "m20250201_000001_create_cache_key_table",
+        up_sql: r#"
+            CREATE TABLE IF NOT EXISTS cache_key (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                cache_id INTEGER NOT NULL,
+                keypair TEXT NOT NULL,
+                state TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY (cache_id) REFERENCES cache(id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_cache_key_cache ON cache_key(cache_id);
+        "#,
+    },
+    Migration {
+        name: "m20250301_000001_add_cache_upload_signature_policy",
+        up_sql: r#"
+            ALTER TABLE cache ADD COLUMN upload_trusted_keys TEXT NOT NULL DEFAULT '[]';
+            ALTER TABLE cache ADD COLUMN require_trusted_signature INTEGER NOT NULL DEFAULT 0;
+        "#,
+    },
 ];
 
 /// Runs all pending database migrations.
@@ -436,6 +622,8 @@ mod tests {
             auth_token: None,
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
 
         let conn = TursoConnection::connect(config)
@@ -686,4 +874,69 @@ mod tests {
             .await;
         assert!(result.is_err(), "Object with invalid FKs should fail");
     }
+
+    #[tokio::test]
+    async fn test_nar_file_hash_and_size_backfilled() {
+        let (conn, _temp_dir) = create_test_db().await;
+        run_migrations(&conn).await.expect("Migrations failed");
+
+        let statements = [
+            // A NAR stored as a single chunk
+            r#"INSERT INTO nar (id, state, nar_hash, nar_size, compression, num_chunks, completeness_hint, holders_count, created_at)
+               VALUES (1, 'V', 'sha256:a', 1000, 'zstd', 1, 1, 0, datetime('now'))"#,
+            // A NAR stored as two chunks
+            r#"INSERT INTO nar (id, state, nar_hash, nar_size, compression, num_chunks, completeness_hint, holders_count, created_at)
+               VALUES (2, 'V', 'sha256:b', 2000, 'zstd', 2, 1, 0, datetime('now'))"#,
+            // A NAR missing one of its chunks
+            r#"INSERT INTO nar (id, state, nar_hash, nar_size, compression, num_chunks, completeness_hint, holders_count, created_at)
+               VALUES (3, 'V', 'sha256:c', 2000, 'zstd', 2, 0, 0, datetime('now'))"#,
+            r#"INSERT INTO chunk (id, state, chunk_hash, chunk_size, file_hash, file_size, compression, remote_file, remote_file_id, holders_count, created_at)
+               VALUES (1, 'V', 'sha256:a', 1000, 'sha256:fa', 400, 'zstd', '{}', 'c1', 0, datetime('now'))"#,
+            r#"INSERT INTO chunk (id, state, chunk_hash, chunk_size, file_hash, file_size, compression, remote_file, remote_file_id, holders_count, created_at)
+               VALUES (2, 'V', 'sha256:b1', 1000, 'sha256:fb1', 300, 'zstd', '{}', 'c2', 0, datetime('now'))"#,
+            r#"INSERT INTO chunk (id, state, chunk_hash, chunk_size, file_hash, file_size, compression, remote_file, remote_file_id, holders_count, created_at)
+               VALUES (3, 'V', 'sha256:b2', 1000, 'sha256:fb2', 200, 'zstd', '{}', 'c3', 0, datetime('now'))"#,
+            "INSERT INTO chunkref (nar_id, seq, chunk_id, chunk_hash, compression) VALUES (1, 0, 1, 'sha256:a', 'zstd')",
+            "INSERT INTO chunkref (nar_id, seq, chunk_id, chunk_hash, compression) VALUES (2, 0, 2, 'sha256:b1', 'zstd')",
+            "INSERT INTO chunkref (nar_id, seq, chunk_id, chunk_hash, compression) VALUES (2, 1, 3, 'sha256:b2', 'zstd')",
+            "INSERT INTO chunkref (nar_id, seq, chunk_id, chunk_hash, compression) VALUES (3, 0, 2, 'sha256:b1', 'zstd')",
+            "INSERT INTO chunkref (nar_id, seq, chunk_id, chunk_hash, compression) VALUES (3, 1, NULL, 'sha256:c2', 'zstd')",
+        ];
+        for sql in statements {
+            conn.execute(sql, ()).await.expect("Insert failed");
+        }
+
+        // Apply the migration again, as if the rows predated it
+        let migration = MIGRATIONS
+            .iter()
+            .find(|m| m.name == "m20250101_000001_add_nar_file_hash_and_size")
+            .unwrap();
+        conn.execute("DELETE FROM _migrations WHERE name = ?1", [migration.name])
+            .await
+            .expect("Delete failed");
+        apply_migration(&conn, migration)
+            .await
+            .expect("Migration failed");
+
+        let mut rows = conn
+            .query("SELECT file_hash, file_size FROM nar ORDER BY id", ())
+            .await
+            .expect("Query failed");
+
+        let mut files = Vec::new();
+        while let Some(row) = rows.next().await.expect("Next failed") {
+            let file_hash: Option<String> = row.get(0).expect("Get failed");
+            let file_size: Option<i64> = row.get(1).expect("Get failed");
+            files.push((file_hash, file_size));
+        }
+
+        assert_eq!(
+            files,
+            vec![
+                (Some("sha256:fa".to_string()), Some(400)),
+                (None, Some(500)),
+                (None, None),
+            ]
+        );
+    }
 }
@@ -4,6 +4,7 @@
 //! which offers embedded replicas for low-latency reads and always-on cloud
 //! databases without cold starts.
 
+pub mod check;
 pub mod connection;
 pub mod migrations;
 pub mod models;
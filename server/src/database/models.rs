@@ -2,6 +2,7 @@
 //!
 //! These models use manual parsing from libsql::Row values.
 
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::str::FromStr;
 
@@ -11,9 +12,12 @@ use libsql::Row;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::error::{ServerError, ServerResult};
+use crate::gc::GcReport;
 use crate::narinfo::{Compression, NarInfo};
+use crate::realisation::Realisation;
+use crate::signing::envelope::{self, KeypairSlot, MasterKeys};
 use crate::storage::RemoteFile;
-use attic::error::AtticResult;
+use attic::api::v1::pin::Pin;
 use attic::hash::Hash;
 use attic::signing::NixKeypair;
 
@@ -77,6 +81,10 @@ pub enum ChunkState {
     ConfirmedDeduplicated,
     /// The chunk is being deleted.
     Deleted,
+    /// The chunk no longer matches its hashes.
+    ///
+    /// It is detached from its NARs and deleted by garbage collection.
+    Corrupt,
 }
 
 impl ChunkState {
@@ -86,6 +94,7 @@ impl ChunkState {
             "P" => Ok(Self::PendingUpload),
             "C" => Ok(Self::ConfirmedDeduplicated),
             "D" => Ok(Self::Deleted),
+            "X" => Ok(Self::Corrupt),
             _ => Err(anyhow!("Invalid chunk state: {}", s)),
         }
     }
@@ -96,6 +105,47 @@ impl ChunkState {
             Self::PendingUpload => "P",
             Self::ConfirmedDeduplicated => "C",
             Self::Deleted => "D",
+            Self::Corrupt => "X",
+        }
+    }
+}
+
+/// The state of a signing key of a cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKeyState {
+    /// The key is staged for the next rotation.
+    Next,
+    /// Paths are signed with the key.
+    Active,
+    /// The key was rotated out.
+    Retired,
+}
+
+impl CacheKeyState {
+    pub fn from_db_value(s: &str) -> Result<Self> {
+        match s {
+            "N" => Ok(Self::Next),
+            "A" => Ok(Self::Active),
+            "R" => Ok(Self::Retired),
+            _ => Err(anyhow!("Invalid cache key state: {}", s)),
+        }
+    }
+
+    pub fn to_db_value(&self) -> &'static str {
+        match self {
+            Self::Next => "N",
+            Self::Active => "A",
+            Self::Retired => "R",
+        }
+    }
+}
+
+impl From<CacheKeyState> for attic::api::v1::cache_config::CacheKeyState {
+    fn from(state: CacheKeyState) -> Self {
+        match state {
+            CacheKeyState::Next => Self::Next,
+            CacheKeyState::Active => Self::Active,
+            CacheKeyState::Retired => Self::Retired,
         }
     }
 }
@@ -114,6 +164,15 @@ pub struct CacheModel {
     pub deleted_at: Option<DateTime<Utc>>,
     pub retention_period: Option<i32>,
     pub created_by_user_id: Option<i64>,
+    pub storage_quota: Option<i64>,
+
+    /// The public keys that signatures of uploaded paths are verified with.
+    ///
+    /// If empty, uploaded signatures are stored as they are.
+    pub upload_trusted_keys: Json<Vec<String>>,
+
+    /// Whether uploaded paths must be signed by a trusted key.
+    pub require_trusted_signature: bool,
 }
 
 impl CacheModel {
@@ -139,6 +198,9 @@ impl CacheModel {
                 .transpose()?,
             retention_period: row.get::<Option<i64>>(start + 9)?.map(|v| v as i32),
             created_by_user_id: row.get::<Option<i64>>(start + 10)?,
+            storage_quota: row.get::<Option<i64>>(start + 11)?,
+            upload_trusted_keys: Json::from_str(&row.get::<String>(start + 12)?)?,
+            require_trusted_signature: row.get::<i64>(start + 13)? != 0,
         })
     }
 
@@ -149,12 +211,14 @@ impl CacheModel {
 
     /// Returns the number of columns in this model.
     pub const fn column_count() -> usize {
-        11
+        14
     }
 
     /// Returns the signing keypair for this cache.
-    pub fn keypair(&self) -> AtticResult<NixKeypair> {
-        NixKeypair::from_str(&self.keypair)
+    ///
+    /// The keypair is decrypted if it's stored encrypted.
+    pub fn keypair(&self, keys: &MasterKeys) -> ServerResult<NixKeypair> {
+        envelope::open(keys, &self.keypair, KeypairSlot::Cache(self.id))
     }
 }
 
@@ -170,6 +234,14 @@ pub struct NarModel {
     pub completeness_hint: bool,
     pub holders_count: i32,
     pub created_at: DateTime<Utc>,
+
+    /// The hash of the file served for the NAR.
+    ///
+    /// Only known for NARs stored as a single chunk.
+    pub file_hash: Option<String>,
+
+    /// The size of the file served for the NAR.
+    pub file_size: Option<i64>,
 }
 
 impl NarModel {
@@ -190,6 +262,8 @@ impl NarModel {
             completeness_hint: row.get::<i64>(start + 6)? != 0,
             holders_count: row.get::<i64>(start + 7)? as i32,
             created_at: parse_datetime(&row.get::<String>(start + 8)?)?,
+            file_hash: row.get::<Option<String>>(start + 9)?,
+            file_size: row.get::<Option<i64>>(start + 10)?,
         })
     }
 
@@ -200,7 +274,7 @@ impl NarModel {
 
     /// Returns the number of columns in this model.
     pub const fn column_count() -> usize {
-        9
+        11
     }
 }
 
@@ -271,14 +345,18 @@ impl ObjectModel {
             store_path: PathBuf::from(self.store_path.to_owned()),
             url: format!("nar/{}.nar", self.store_path_hash.as_str()),
             compression: Compression::from_str(&nar.compression)?,
-            file_hash: None,
-            file_size: None,
+            file_hash: nar.file_hash.as_deref().map(Hash::from_typed).transpose()?,
+            file_size: nar
+                .file_size
+                .map(usize::try_from)
+                .transpose()
+                .map_err(ServerError::database_error)?,
             nar_hash: Hash::from_typed(&nar.nar_hash)?,
             nar_size,
             system: self.system.to_owned(),
             references: self.references.0.to_owned(),
             deriver: self.deriver.to_owned(),
-            signature: None,
+            signatures: Vec::new(),
             ca: self.ca.to_owned(),
         })
     }
@@ -388,6 +466,293 @@ impl ChunkRefModel {
     }
 }
 
+/// A NAR uploaded through the Nix binary cache protocol awaiting its `.narinfo`.
+///
+/// The decompressed NAR is kept as a single file on the storage backend
+/// until the client uploads the `.narinfo` referencing it by `file_name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StagedNarModel {
+    pub id: i64,
+    pub cache_id: i64,
+    pub file_name: String,
+    pub nar_hash: String,
+    pub nar_size: i64,
+    pub remote_file: Json<RemoteFile>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl StagedNarModel {
+    /// Parses a StagedNarModel from a database row.
+    pub fn from_row(row: &Row) -> Result<Self> {
+        Self::from_row_at(row, 0)
+    }
+
+    /// Parses a StagedNarModel from a row starting at the given index.
+    pub fn from_row_at(row: &Row, start: i32) -> Result<Self> {
+        Ok(Self {
+            id: row.get::<i64>(start)?,
+            cache_id: row.get::<i64>(start + 1)?,
+            file_name: row.get::<String>(start + 2)?,
+            nar_hash: row.get::<String>(start + 3)?,
+            nar_size: row.get::<i64>(start + 4)?,
+            remote_file: Json::from_str(&row.get::<String>(start + 5)?)?,
+            created_at: parse_datetime(&row.get::<String>(start + 6)?)?,
+        })
+    }
+
+    /// Returns the number of columns in this model.
+    pub const fn column_count() -> usize {
+        7
+    }
+}
+
+/// A build log uploaded to a cache.
+///
+/// Logs are stored as single Brotli-compressed files on the storage
+/// backend and are keyed by the base name of the derivation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildLogModel {
+    pub id: i64,
+    pub cache_id: i64,
+    pub drv_name: String,
+    pub remote_file: Json<RemoteFile>,
+    pub created_at: DateTime<Utc>,
+    pub created_by: Option<String>,
+}
+
+impl BuildLogModel {
+    /// Parses a BuildLogModel from a database row.
+    pub fn from_row(row: &Row) -> Result<Self> {
+        Self::from_row_at(row, 0)
+    }
+
+    /// Parses a BuildLogModel from a row starting at the given index.
+    pub fn from_row_at(row: &Row, start: i32) -> Result<Self> {
+        Ok(Self {
+            id: row.get::<i64>(start)?,
+            cache_id: row.get::<i64>(start + 1)?,
+            drv_name: row.get::<String>(start + 2)?,
+            remote_file: Json::from_str(&row.get::<String>(start + 3)?)?,
+            created_at: parse_datetime(&row.get::<String>(start + 4)?)?,
+            created_by: row.get::<Option<String>>(start + 5)?,
+        })
+    }
+
+    /// Returns the number of columns in this model.
+    pub const fn column_count() -> usize {
+        6
+    }
+}
+
+/// A realisation of a derivation output in a cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RealisationModel {
+    pub id: i64,
+    pub cache_id: i64,
+    pub output_id: String,
+    pub out_path: String,
+    pub signatures: Json<Vec<String>>,
+    pub dependent_realisations: Json<BTreeMap<String, String>>,
+    pub created_at: DateTime<Utc>,
+    pub created_by: Option<String>,
+}
+
+impl RealisationModel {
+    /// Parses a RealisationModel from a database row.
+    pub fn from_row(row: &Row) -> Result<Self> {
+        Self::from_row_at(row, 0)
+    }
+
+    /// Parses a RealisationModel from a row starting at the given index.
+    pub fn from_row_at(row: &Row, start: i32) -> Result<Self> {
+        Ok(Self {
+            id: row.get::<i64>(start)?,
+            cache_id: row.get::<i64>(start + 1)?,
+            output_id: row.get::<String>(start + 2)?,
+            out_path: row.get::<String>(start + 3)?,
+            signatures: Json::from_str(&row.get::<String>(start + 4)?)?,
+            dependent_realisations: Json::from_str(&row.get::<String>(start + 5)?)?,
+            created_at: parse_datetime(&row.get::<String>(start + 6)?)?,
+            created_by: row.get::<Option<String>>(start + 7)?,
+        })
+    }
+
+    /// Returns the number of columns in this model.
+    pub const fn column_count() -> usize {
+        8
+    }
+
+    /// Converts the model into a realisation.
+    pub fn to_realisation(&self) -> Realisation {
+        Realisation {
+            dependent_realisations: self.dependent_realisations.0.clone(),
+            id: self.output_id.clone(),
+            out_path: self.out_path.clone(),
+            signatures: self.signatures.0.clone(),
+        }
+    }
+}
+
+/// A named pin of a store path in a cache.
+///
+/// Pinned paths and their closures are never garbage-collected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinModel {
+    pub id: i64,
+    pub cache_id: i64,
+    pub name: String,
+    pub store_path_hash: String,
+    pub store_path: String,
+    pub created_at: DateTime<Utc>,
+    pub created_by: Option<String>,
+}
+
+impl PinModel {
+    /// Parses a PinModel from a database row.
+    pub fn from_row(row: &Row) -> Result<Self> {
+        Self::from_row_at(row, 0)
+    }
+
+    /// Parses a PinModel from a row starting at the given index.
+    pub fn from_row_at(row: &Row, start: i32) -> Result<Self> {
+        Ok(Self {
+            id: row.get::<i64>(start)?,
+            cache_id: row.get::<i64>(start + 1)?,
+            name: row.get::<String>(start + 2)?,
+            store_path_hash: row.get::<String>(start + 3)?,
+            store_path: row.get::<String>(start + 4)?,
+            created_at: parse_datetime(&row.get::<String>(start + 5)?)?,
+            created_by: row.get::<Option<String>>(start + 6)?,
+        })
+    }
+
+    /// Returns the number of columns in this model.
+    pub const fn column_count() -> usize {
+        7
+    }
+
+    /// Converts the model into a pin.
+    pub fn to_pin(&self) -> Pin {
+        Pin {
+            name: self.name.clone(),
+            store_path: self.store_path.clone(),
+            created_at: self.created_at.to_rfc3339(),
+        }
+    }
+}
+
+/// A recorded garbage collection run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcRunModel {
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+
+    /// Why the run failed, if it did.
+    pub error: Option<String>,
+
+    /// What the run deleted, up to the failure if any.
+    pub report: Json<GcReport>,
+}
+
+impl GcRunModel {
+    /// Parses a GcRunModel from a database row.
+    pub fn from_row(row: &Row) -> Result<Self> {
+        Self::from_row_at(row, 0)
+    }
+
+    /// Parses a GcRunModel from a row starting at the given index.
+    pub fn from_row_at(row: &Row, start: i32) -> Result<Self> {
+        Ok(Self {
+            id: row.get::<i64>(start)?,
+            started_at: parse_datetime(&row.get::<String>(start + 1)?)?,
+            finished_at: parse_datetime(&row.get::<String>(start + 2)?)?,
+            error: row.get::<Option<String>>(start + 3)?,
+            report: Json::from_str(&row.get::<String>(start + 4)?)?,
+        })
+    }
+
+    /// Returns the number of columns in this model.
+    pub const fn column_count() -> usize {
+        5
+    }
+}
+
+/// An upstream substituter of a cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheUpstreamModel {
+    pub id: i64,
+    pub cache_id: i64,
+    pub url: String,
+    pub trusted_keys: Json<Vec<String>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl CacheUpstreamModel {
+    /// Parses a CacheUpstreamModel from a database row.
+    pub fn from_row(row: &Row) -> Result<Self> {
+        Self::from_row_at(row, 0)
+    }
+
+    /// Parses a CacheUpstreamModel from a row starting at the given index.
+    pub fn from_row_at(row: &Row, start: i32) -> Result<Self> {
+        Ok(Self {
+            id: row.get::<i64>(start)?,
+            cache_id: row.get::<i64>(start + 1)?,
+            url: row.get::<String>(start + 2)?,
+            trusted_keys: Json::from_str(&row.get::<String>(start + 3)?)?,
+            created_at: parse_datetime(&row.get::<String>(start + 4)?)?,
+        })
+    }
+
+    /// Returns the number of columns in this model.
+    pub const fn column_count() -> usize {
+        5
+    }
+}
+
+/// A signing key of a cache, other than its primary keypair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKeyModel {
+    pub id: i64,
+    pub cache_id: i64,
+    pub keypair: String,
+    pub state: CacheKeyState,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl CacheKeyModel {
+    /// Parses a CacheKeyModel from a database row.
+    pub fn from_row(row: &Row) -> Result<Self> {
+        Self::from_row_at(row, 0)
+    }
+
+    /// Parses a CacheKeyModel from a row starting at the given index.
+    pub fn from_row_at(row: &Row, start: i32) -> Result<Self> {
+        Ok(Self {
+            id: row.get::<i64>(start)?,
+            cache_id: row.get::<i64>(start + 1)?,
+            keypair: row.get::<String>(start + 2)?,
+            state: CacheKeyState::from_db_value(&row.get::<String>(start + 3)?)?,
+            created_at: parse_datetime(&row.get::<String>(start + 4)?)?,
+            updated_at: parse_datetime(&row.get::<String>(start + 5)?)?,
+        })
+    }
+
+    /// Returns the number of columns in this model.
+    pub const fn column_count() -> usize {
+        6
+    }
+
+    /// Returns the keypair.
+    ///
+    /// The keypair is decrypted if it's stored encrypted.
+    pub fn keypair(&self, keys: &MasterKeys) -> ServerResult<NixKeypair> {
+        envelope::open(keys, &self.keypair, KeypairSlot::CacheKey(self.cache_id))
+    }
+}
+
 // ============================================================================
 // Web UI Models
 // ============================================================================
@@ -552,7 +917,7 @@ impl SessionModel {
 }
 
 /// Parses a datetime string from the database.
-fn parse_datetime(s: &str) -> Result<DateTime<Utc>> {
+pub(super) fn parse_datetime(s: &str) -> Result<DateTime<Utc>> {
     // SQLite stores timestamps in various formats
     // Try RFC3339 first, then other common formats
     DateTime::parse_from_rfc3339(s)
@@ -587,6 +952,8 @@ mod tests {
     fn test_chunk_state_conversion() {
         assert_eq!(ChunkState::from_db_value("V").unwrap(), ChunkState::Valid);
         assert_eq!(ChunkState::Valid.to_db_value(), "V");
+        assert_eq!(ChunkState::from_db_value("X").unwrap(), ChunkState::Corrupt);
+        assert_eq!(ChunkState::Corrupt.to_db_value(), "X");
     }
 
     #[test]
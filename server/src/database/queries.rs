@@ -1,20 +1,23 @@
 //! Raw SQL query implementations for Turso database backend.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::anyhow;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 
 use crate::error::{ErrorKind, ServerError, ServerResult};
 use crate::narinfo::Compression;
+use crate::storage::RemoteFile;
 use attic::cache::CacheName;
 use attic::hash::Hash;
 use attic::nix_store::StorePathHash;
 
 use super::connection::TursoConnection;
 use super::models::{
-    CacheModel, ChunkModel, ChunkState, CredentialModel, NarModel, NarState, ObjectModel,
-    SessionModel, UserCachePermissionModel, UserModel,
+    parse_datetime, BuildLogModel, CacheKeyModel, CacheKeyState, CacheModel, CacheUpstreamModel,
+    ChunkModel, ChunkState, CredentialModel, GcRunModel, NarModel, NarState, ObjectModel, PinModel,
+    RealisationModel, SessionModel, StagedNarModel, UserCachePermissionModel, UserModel,
 };
 use super::{ChunkGuard, NarGuard};
 
@@ -40,7 +43,8 @@ pub async fn find_cache(conn: &TursoConnection, cache: &CacheName) -> ServerResu
     let sql = r#"
         SELECT id, name, keypair, is_public, store_dir, priority,
                upstream_cache_key_names, created_at, deleted_at, retention_period,
-               created_by_user_id
+               created_by_user_id, storage_quota, upload_trusted_keys,
+               require_trusted_signature
         FROM cache
         WHERE name = ?1 AND deleted_at IS NULL
     "#;
@@ -80,9 +84,11 @@ async fn find_object_without_chunks(
             o.created_at, o.last_accessed_at, o.created_by,
             c.id, c.name, c.keypair, c.is_public, c.store_dir, c.priority,
             c.upstream_cache_key_names, c.created_at, c.deleted_at, c.retention_period,
-            c.created_by_user_id,
+            c.created_by_user_id, c.storage_quota, c.upload_trusted_keys,
+            c.require_trusted_signature,
             n.id, n.state, n.nar_hash, n.nar_size, n.compression,
-            n.num_chunks, n.completeness_hint, n.holders_count, n.created_at
+            n.num_chunks, n.completeness_hint, n.holders_count, n.created_at,
+            n.file_hash, n.file_size
         FROM object o
         INNER JOIN cache c ON o.cache_id = c.id
         INNER JOIN nar n ON o.nar_id = n.id
@@ -129,9 +135,11 @@ async fn find_object_with_chunks(
             o.created_at, o.last_accessed_at, o.created_by,
             c.id, c.name, c.keypair, c.is_public, c.store_dir, c.priority,
             c.upstream_cache_key_names, c.created_at, c.deleted_at, c.retention_period,
-            c.created_by_user_id,
+            c.created_by_user_id, c.storage_quota, c.upload_trusted_keys,
+            c.require_trusted_signature,
             n.id, n.state, n.nar_hash, n.nar_size, n.compression,
             n.num_chunks, n.completeness_hint, n.holders_count, n.created_at,
+            n.file_hash, n.file_size,
             ch.id, ch.state, ch.chunk_hash, ch.chunk_size, ch.file_hash,
             ch.file_size, ch.compression, ch.remote_file, ch.remote_file_id,
             ch.holders_count, ch.created_at,
@@ -209,7 +217,8 @@ pub async fn find_and_lock_nar(
             LIMIT 1
         )
         RETURNING id, state, nar_hash, nar_size, compression,
-                  num_chunks, completeness_hint, holders_count, created_at
+                  num_chunks, completeness_hint, holders_count, created_at,
+                  file_hash, file_size
     "#;
 
     let mut rows = conn
@@ -288,6 +297,37 @@ pub async fn bump_object_last_accessed(conn: &TursoConnection, object_id: i64) -
     Ok(())
 }
 
+/// Sets the last_accessed_at timestamps of multiple objects in one statement.
+///
+/// Timestamps older than the stored ones are ignored.
+pub async fn bump_objects_last_accessed(
+    conn: &TursoConnection,
+    accesses: &[(i64, DateTime<Utc>)],
+) -> ServerResult<u64> {
+    if accesses.is_empty() {
+        return Ok(0);
+    }
+
+    let accesses: Vec<(i64, String)> = accesses
+        .iter()
+        .map(|(id, accessed_at)| (*id, accessed_at.to_rfc3339()))
+        .collect();
+    let accesses_json = serde_json::to_string(&accesses).map_err(db_err)?;
+
+    let sql = r#"
+        UPDATE object
+        SET last_accessed_at = MAX(
+            COALESCE(last_accessed_at, ''),
+            json_extract(access.value, '$[1]')
+        )
+        FROM json_each(?1) AS access
+        WHERE object.id = json_extract(access.value, '$[0]')
+    "#;
+
+    let affected = conn.execute(sql, [accesses_json]).await.map_err(db_err)?;
+    Ok(affected)
+}
+
 // ============================================================================
 // Additional query functions for other parts of the codebase
 // ============================================================================
@@ -336,7 +376,8 @@ pub async fn create_cache_with_owner(
         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
         RETURNING id, name, keypair, is_public, store_dir, priority,
                   upstream_cache_key_names, created_at, deleted_at, retention_period,
-                  created_by_user_id
+                  created_by_user_id, storage_quota, upload_trusted_keys,
+                  require_trusted_signature
     "#;
 
     let mut rows = conn
@@ -378,7 +419,8 @@ pub async fn create_nar(
                         completeness_hint, holders_count, created_at)
         VALUES (?1, ?2, ?3, ?4, ?5, 0, 0, ?6)
         RETURNING id, state, nar_hash, nar_size, compression,
-                  num_chunks, completeness_hint, holders_count, created_at
+                  num_chunks, completeness_hint, holders_count, created_at,
+                  file_hash, file_size
     "#;
 
     let mut rows = conn
@@ -437,6 +479,14 @@ pub async fn update_nar_completeness_hint(
     let sql = "UPDATE nar SET completeness_hint = ?1 WHERE id = ?2";
     let hint = if completeness_hint { 1i64 } else { 0i64 };
     conn.execute(sql, (hint, nar_id)).await.map_err(db_err)?;
+
+    if completeness_hint {
+        // The NAR has been repaired
+        conn.execute("DELETE FROM incomplete_nar WHERE nar_id = ?1", [nar_id])
+            .await
+            .map_err(db_err)?;
+    }
+
     Ok(())
 }
 
@@ -475,7 +525,8 @@ pub async fn insert_nar(
                         completeness_hint, holders_count, created_at)
         VALUES (?1, ?2, ?3, ?4, ?5, 0, 0, ?6)
         RETURNING id, state, nar_hash, nar_size, compression,
-                  num_chunks, completeness_hint, holders_count, created_at
+                  num_chunks, completeness_hint, holders_count, created_at,
+                  file_hash, file_size
     "#;
 
     let mut rows = conn
@@ -499,6 +550,20 @@ pub async fn insert_nar(
     }
 }
 
+/// Records the hash and size of the file served for a NAR.
+pub async fn update_nar_file(
+    conn: &TursoConnection,
+    nar_id: i64,
+    file_hash: Option<&str>,
+    file_size: Option<i64>,
+) -> ServerResult<()> {
+    let sql = "UPDATE nar SET file_hash = ?1, file_size = ?2 WHERE id = ?3";
+    conn.execute(sql, (file_hash, file_size, nar_id))
+        .await
+        .map_err(db_err)?;
+    Ok(())
+}
+
 /// Updates a NAR's state, num_chunks, and optionally completeness_hint.
 pub async fn update_nar(
     conn: &TursoConnection,
@@ -560,11 +625,52 @@ fn substitute_params(sql: &str, params: &[String]) -> String {
 
 /// Deletes a NAR by ID.
 pub async fn delete_nar(conn: &TursoConnection, nar_id: i64) -> ServerResult<()> {
+    conn.execute("DELETE FROM nar_listing WHERE nar_id = ?1", [nar_id])
+        .await
+        .map_err(db_err)?;
+    conn.execute("DELETE FROM incomplete_nar WHERE nar_id = ?1", [nar_id])
+        .await
+        .map_err(db_err)?;
+
     let sql = "DELETE FROM nar WHERE id = ?1";
     conn.execute(sql, [nar_id]).await.map_err(db_err)?;
     Ok(())
 }
 
+/// Stores the compressed listing of a NAR.
+pub async fn insert_nar_listing(
+    conn: &TursoConnection,
+    nar_id: i64,
+    listing: &[u8],
+) -> ServerResult<()> {
+    let now = Utc::now().to_rfc3339();
+
+    let sql = r#"
+        INSERT OR REPLACE INTO nar_listing (nar_id, listing, created_at)
+        VALUES (?1, ?2, ?3)
+    "#;
+
+    conn.execute(sql, (nar_id, listing.to_vec(), now.as_str()))
+        .await
+        .map_err(db_err)?;
+    Ok(())
+}
+
+/// Finds the compressed listing of a NAR.
+pub async fn find_nar_listing(
+    conn: &TursoConnection,
+    nar_id: i64,
+) -> ServerResult<Option<Vec<u8>>> {
+    let sql = "SELECT listing FROM nar_listing WHERE nar_id = ?1";
+
+    let mut rows = conn.query(sql, [nar_id]).await.map_err(db_err)?;
+
+    match rows.next().await.map_err(db_err)? {
+        Some(row) => Ok(Some(row.get::<Vec<u8>>(0).map_err(db_err)?)),
+        None => Ok(None),
+    }
+}
+
 // ============================================================================
 // Chunk operations for upload_path.rs
 // ============================================================================
@@ -801,7 +907,8 @@ pub async fn insert_object_upsert(
             system = excluded.system,
             deriver = excluded.deriver,
             sigs = excluded.sigs,
-            ca = excluded.ca
+            ca = excluded.ca,
+            last_accessed_at = excluded.created_at
         RETURNING id
     "#;
 
@@ -842,219 +949,2036 @@ pub struct CacheWithRetention {
     pub retention_period: i32,
 }
 
-/// Finds caches with non-zero retention periods.
-pub async fn find_caches_with_retention(
+/// Finds caches with non-zero retention periods.
+pub async fn find_caches_with_retention(
+    conn: &TursoConnection,
+    default_retention_seconds: i64,
+) -> ServerResult<Vec<CacheWithRetention>> {
+    let sql = r#"
+        SELECT id, name, COALESCE(retention_period, ?1) as retention_period
+        FROM cache
+        WHERE deleted_at IS NULL
+          AND COALESCE(retention_period, ?1) != 0
+    "#;
+
+    let mut rows = conn
+        .query(sql, [default_retention_seconds])
+        .await
+        .map_err(db_err)?;
+
+    let mut caches = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        caches.push(CacheWithRetention {
+            id: row.get::<i64>(0).map_err(db_err)?,
+            name: row.get::<String>(1).map_err(db_err)?,
+            retention_period: row.get::<i64>(2).map_err(db_err)? as i32,
+        });
+    }
+
+    Ok(caches)
+}
+
+/// An object considered for garbage collection.
+pub struct GcObject {
+    pub id: i64,
+    pub nar_id: i64,
+    pub store_path_hash: String,
+    pub store_path: String,
+    pub references: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_accessed_at: Option<DateTime<Utc>>,
+}
+
+/// Finds all objects in a cache along with their references.
+pub async fn find_gc_objects_by_cache(
+    conn: &TursoConnection,
+    cache_id: i64,
+) -> ServerResult<Vec<GcObject>> {
+    let sql = r#"
+        SELECT id, nar_id, store_path_hash, store_path, "references", created_at, last_accessed_at
+        FROM object
+        WHERE cache_id = ?1
+        ORDER BY id ASC
+    "#;
+
+    let mut rows = conn.query(sql, [cache_id]).await.map_err(db_err)?;
+
+    let mut objects = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        objects.push(GcObject {
+            id: row.get::<i64>(0).map_err(db_err)?,
+            nar_id: row.get::<i64>(1).map_err(db_err)?,
+            store_path_hash: row.get::<String>(2).map_err(db_err)?,
+            store_path: row.get::<String>(3).map_err(db_err)?,
+            references: serde_json::from_str(&row.get::<String>(4).map_err(db_err)?)
+                .map_err(db_err)?,
+            created_at: parse_datetime(&row.get::<String>(5).map_err(db_err)?).map_err(db_err)?,
+            last_accessed_at: row
+                .get::<Option<String>>(6)
+                .map_err(db_err)?
+                .map(|s| parse_datetime(&s))
+                .transpose()
+                .map_err(db_err)?,
+        });
+    }
+
+    Ok(objects)
+}
+
+/// Cache info for quota-based GC.
+pub struct CacheWithQuota {
+    pub id: i64,
+    pub name: String,
+    pub storage_quota: i64,
+}
+
+/// Finds caches with non-zero storage quotas.
+pub async fn find_caches_with_quota(
+    conn: &TursoConnection,
+    default_storage_quota: i64,
+) -> ServerResult<Vec<CacheWithQuota>> {
+    let sql = r#"
+        SELECT id, name, COALESCE(storage_quota, ?1) as storage_quota
+        FROM cache
+        WHERE deleted_at IS NULL
+          AND COALESCE(storage_quota, ?1) != 0
+    "#;
+
+    let mut rows = conn
+        .query(sql, [default_storage_quota])
+        .await
+        .map_err(db_err)?;
+
+    let mut caches = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        caches.push(CacheWithQuota {
+            id: row.get::<i64>(0).map_err(db_err)?,
+            name: row.get::<String>(1).map_err(db_err)?,
+            storage_quota: row.get::<i64>(2).map_err(db_err)?,
+        });
+    }
+
+    Ok(caches)
+}
+
+/// Storage usage of an object for quota-based GC.
+pub struct ObjectUsage {
+    pub id: i64,
+    pub nar_id: i64,
+
+    /// The stored size of the NAR, summed over its chunks.
+    pub nar_stored_size: i64,
+
+    /// The number of caches with objects referencing the NAR.
+    pub nar_cache_count: i64,
+}
+
+/// Finds the objects in a cache along with the storage they use.
+pub async fn find_object_usage_by_cache(
+    conn: &TursoConnection,
+    cache_id: i64,
+) -> ServerResult<Vec<ObjectUsage>> {
+    let sql = r#"
+        SELECT
+            o.id, o.nar_id,
+            (SELECT COALESCE(SUM(COALESCE(ch.file_size, ch.chunk_size)), 0)
+             FROM chunkref cr
+             INNER JOIN chunk ch ON cr.chunk_id = ch.id
+             WHERE cr.nar_id = o.nar_id) as nar_stored_size,
+            (SELECT COUNT(DISTINCT o2.cache_id)
+             FROM object o2
+             WHERE o2.nar_id = o.nar_id) as nar_cache_count
+        FROM object o
+        WHERE o.cache_id = ?1
+    "#;
+
+    let mut rows = conn.query(sql, [cache_id]).await.map_err(db_err)?;
+
+    let mut objects = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        objects.push(ObjectUsage {
+            id: row.get::<i64>(0).map_err(db_err)?,
+            nar_id: row.get::<i64>(1).map_err(db_err)?,
+            nar_stored_size: row.get::<i64>(2).map_err(db_err)?,
+            nar_cache_count: row.get::<i64>(3).map_err(db_err)?,
+        });
+    }
+
+    Ok(objects)
+}
+
+/// Deletes objects by their IDs.
+/// Returns the number of deleted rows.
+pub async fn delete_objects_by_ids(
+    conn: &TursoConnection,
+    object_ids: &[i64],
+) -> ServerResult<u64> {
+    if object_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = object_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "DELETE FROM object WHERE id IN ({})",
+        placeholders.join(", ")
+    );
+
+    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
+    Ok(affected)
+}
+
+/// Deletes objects by their IDs, unless they are pinned or were
+/// created or accessed since the time given for each of them.
+/// Returns the number of deleted rows.
+pub async fn delete_unused_objects(
+    conn: &TursoConnection,
+    objects: &[(i64, DateTime<Utc>)],
+) -> ServerResult<u64> {
+    if objects.is_empty() {
+        return Ok(0);
+    }
+
+    let objects: Vec<(i64, String)> = objects
+        .iter()
+        .map(|(id, unused_since)| (*id, unused_since.to_rfc3339()))
+        .collect();
+    let objects_json = serde_json::to_string(&objects).map_err(db_err)?;
+
+    let sql = r#"
+        DELETE FROM object
+        WHERE id IN (SELECT json_extract(value, '$[0]') FROM json_each(?1))
+          AND EXISTS (
+              SELECT 1 FROM json_each(?1) AS selected
+              WHERE json_extract(selected.value, '$[0]') = object.id
+                AND object.created_at < json_extract(selected.value, '$[1]')
+                AND (object.last_accessed_at IS NULL
+                     OR object.last_accessed_at < json_extract(selected.value, '$[1]'))
+          )
+          AND NOT EXISTS (
+              SELECT 1 FROM pin
+              WHERE pin.cache_id = object.cache_id
+                AND pin.store_path_hash = object.store_path_hash
+          )
+    "#;
+
+    let affected = conn.execute(sql, [objects_json]).await.map_err(db_err)?;
+    Ok(affected)
+}
+
+/// Counts the objects referencing each of the given NARs.
+///
+/// NARs without any objects are left out.
+pub async fn count_objects_by_nar_ids(
+    conn: &TursoConnection,
+    nar_ids: &[i64],
+) -> ServerResult<HashMap<i64, i64>> {
+    if nar_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let placeholders: Vec<String> = nar_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "SELECT nar_id, COUNT(*) FROM object WHERE nar_id IN ({}) GROUP BY nar_id",
+        placeholders.join(", ")
+    );
+
+    let mut rows = conn.query(&sql, ()).await.map_err(db_err)?;
+
+    let mut counts = HashMap::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        counts.insert(
+            row.get::<i64>(0).map_err(db_err)?,
+            row.get::<i64>(1).map_err(db_err)?,
+        );
+    }
+
+    Ok(counts)
+}
+
+/// A chunk referenced by a NAR.
+pub struct NarChunk {
+    pub nar_id: i64,
+    pub chunk_id: i64,
+
+    /// The size of the chunk in the storage backend.
+    pub stored_size: i64,
+}
+
+/// Finds the chunks referenced by the given NARs.
+pub async fn find_nar_chunks(
+    conn: &TursoConnection,
+    nar_ids: &[i64],
+) -> ServerResult<Vec<NarChunk>> {
+    if nar_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders: Vec<String> = nar_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        r#"
+        SELECT cr.nar_id, ch.id, COALESCE(ch.file_size, ch.chunk_size)
+        FROM chunkref cr
+        INNER JOIN chunk ch ON cr.chunk_id = ch.id
+        WHERE cr.nar_id IN ({})
+    "#,
+        placeholders.join(", ")
+    );
+
+    let mut rows = conn.query(&sql, ()).await.map_err(db_err)?;
+
+    let mut chunks = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        chunks.push(NarChunk {
+            nar_id: row.get::<i64>(0).map_err(db_err)?,
+            chunk_id: row.get::<i64>(1).map_err(db_err)?,
+            stored_size: row.get::<i64>(2).map_err(db_err)?,
+        });
+    }
+
+    Ok(chunks)
+}
+
+/// Counts the chunkrefs referencing each of the given chunks.
+///
+/// Chunks without any chunkrefs are left out.
+pub async fn count_chunkrefs_by_chunk_ids(
+    conn: &TursoConnection,
+    chunk_ids: &[i64],
+) -> ServerResult<HashMap<i64, i64>> {
+    if chunk_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let placeholders: Vec<String> = chunk_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "SELECT chunk_id, COUNT(*) FROM chunkref WHERE chunk_id IN ({}) GROUP BY chunk_id",
+        placeholders.join(", ")
+    );
+
+    let mut rows = conn.query(&sql, ()).await.map_err(db_err)?;
+
+    let mut counts = HashMap::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        counts.insert(
+            row.get::<i64>(0).map_err(db_err)?,
+            row.get::<i64>(1).map_err(db_err)?,
+        );
+    }
+
+    Ok(counts)
+}
+
+/// Finds the sizes of the given chunks in the storage backend.
+pub async fn find_chunk_stored_sizes(
+    conn: &TursoConnection,
+    chunk_ids: &[i64],
+) -> ServerResult<HashMap<i64, i64>> {
+    if chunk_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let placeholders: Vec<String> = chunk_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "SELECT id, COALESCE(file_size, chunk_size) FROM chunk WHERE id IN ({})",
+        placeholders.join(", ")
+    );
+
+    let mut rows = conn.query(&sql, ()).await.map_err(db_err)?;
+
+    let mut sizes = HashMap::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        sizes.insert(
+            row.get::<i64>(0).map_err(db_err)?,
+            row.get::<i64>(1).map_err(db_err)?,
+        );
+    }
+
+    Ok(sizes)
+}
+
+/// Finds orphan NAR IDs (NARs with no objects referencing them).
+pub async fn find_orphan_nar_ids(conn: &TursoConnection) -> ServerResult<Vec<i64>> {
+    let sql = r#"
+        SELECT n.id
+        FROM nar n
+        LEFT JOIN object o ON o.nar_id = n.id
+        WHERE o.id IS NULL
+          AND n.state = 'V'
+          AND n.holders_count = 0
+    "#;
+
+    let mut rows = conn.query(sql, ()).await.map_err(db_err)?;
+
+    let mut ids = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        ids.push(row.get::<i64>(0).map_err(db_err)?);
+    }
+
+    Ok(ids)
+}
+
+/// Deletes NARs by their IDs.
+/// Returns the number of deleted rows.
+pub async fn delete_nars_by_ids(conn: &TursoConnection, nar_ids: &[i64]) -> ServerResult<u64> {
+    if nar_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = nar_ids.iter().map(|id| id.to_string()).collect();
+
+    let sql = format!(
+        "DELETE FROM nar_listing WHERE nar_id IN ({})",
+        placeholders.join(", ")
+    );
+    conn.execute(&sql, ()).await.map_err(db_err)?;
+
+    let sql = format!(
+        "DELETE FROM incomplete_nar WHERE nar_id IN ({})",
+        placeholders.join(", ")
+    );
+    conn.execute(&sql, ()).await.map_err(db_err)?;
+
+    let sql = format!("DELETE FROM nar WHERE id IN ({})", placeholders.join(", "));
+
+    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
+    Ok(affected)
+}
+
+/// Finds orphan chunks (chunks with no chunkrefs referencing them).
+///
+/// Corrupt chunks are always orphans once they have been marked.
+pub async fn find_orphan_chunk_ids(conn: &TursoConnection) -> ServerResult<Vec<i64>> {
+    let sql = r#"
+        SELECT c.id
+        FROM chunk c
+        LEFT JOIN chunkref cr ON cr.chunk_id = c.id
+        WHERE cr.id IS NULL
+          AND c.state IN ('V', 'X')
+          AND c.holders_count = 0
+    "#;
+
+    let mut rows = conn.query(sql, ()).await.map_err(db_err)?;
+
+    let mut ids = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        ids.push(row.get::<i64>(0).map_err(db_err)?);
+    }
+
+    Ok(ids)
+}
+
+/// Transitions chunks to Deleted state.
+/// Returns the number of affected rows.
+pub async fn transition_chunks_to_deleted(
+    conn: &TursoConnection,
+    chunk_ids: &[i64],
+) -> ServerResult<u64> {
+    if chunk_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = chunk_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "UPDATE chunk SET state = 'D' WHERE id IN ({})",
+        placeholders.join(", ")
+    );
+
+    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
+    Ok(affected)
+}
+
+/// A chunk in Deleted state.
+pub struct DeletedChunk {
+    pub chunk: ChunkModel,
+
+    /// The number of failed attempts to delete the chunk from the storage.
+    pub deletion_attempts: i64,
+}
+
+/// Finds chunks in Deleted state, ordered by ID.
+///
+/// Only chunks with IDs greater than `after_id` are returned. If
+/// `due_at` is set, chunks whose next deletion attempt is scheduled
+/// later are skipped.
+pub async fn find_deleted_chunks(
+    conn: &TursoConnection,
+    after_id: i64,
+    due_at: Option<&DateTime<Utc>>,
+    limit: u64,
+) -> ServerResult<Vec<DeletedChunk>> {
+    let sql = r#"
+        SELECT id, state, chunk_hash, chunk_size, file_hash, file_size,
+               compression, remote_file, remote_file_id, holders_count, created_at,
+               deletion_attempts
+        FROM chunk
+        WHERE state = 'D'
+          AND id > ?1
+          AND (?2 IS NULL OR next_deletion_attempt_at IS NULL OR next_deletion_attempt_at <= ?2)
+        ORDER BY id ASC
+        LIMIT ?3
+    "#;
+
+    let mut rows = conn
+        .query(
+            sql,
+            (after_id, due_at.map(|t| t.to_rfc3339()), limit as i64),
+        )
+        .await
+        .map_err(db_err)?;
+
+    let mut chunks = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        chunks.push(DeletedChunk {
+            chunk: ChunkModel::from_row(&row).map_err(db_err)?,
+            deletion_attempts: row
+                .get::<i64>(ChunkModel::column_count() as i32)
+                .map_err(db_err)?,
+        });
+    }
+
+    Ok(chunks)
+}
+
+/// Records a failed attempt to delete a chunk from the storage.
+pub async fn record_chunk_deletion_failure(
+    conn: &TursoConnection,
+    chunk_id: i64,
+    error: &str,
+    next_attempt_at: &DateTime<Utc>,
+) -> ServerResult<()> {
+    let sql = r#"
+        UPDATE chunk
+        SET deletion_attempts = deletion_attempts + 1,
+            last_deletion_error = ?2,
+            next_deletion_attempt_at = ?3
+        WHERE id = ?1
+    "#;
+
+    conn.execute(sql, (chunk_id, error, next_attempt_at.to_rfc3339()))
+        .await
+        .map_err(db_err)?;
+
+    Ok(())
+}
+
+/// Chunks in Deleted state waiting to be deleted from the storage.
+pub struct DeletionBacklog {
+    pub chunks: i64,
+
+    /// Chunks whose next deletion attempt is due.
+    pub due: i64,
+
+    /// The stored size of the chunks.
+    pub stored_size: i64,
+
+    /// The most failed attempts of any chunk.
+    pub max_attempts: i64,
+
+    /// The most common errors of failed attempts, with the number of
+    /// chunks that failed with each.
+    pub errors: Vec<(String, i64)>,
+}
+
+/// Summarizes the chunks in Deleted state.
+pub async fn find_deletion_backlog(
+    conn: &TursoConnection,
+    now: &DateTime<Utc>,
+) -> ServerResult<DeletionBacklog> {
+    let sql = r#"
+        SELECT
+            COUNT(*),
+            COALESCE(SUM(CASE
+                WHEN next_deletion_attempt_at IS NULL OR next_deletion_attempt_at <= ?1 THEN 1
+                ELSE 0
+            END), 0),
+            COALESCE(SUM(COALESCE(file_size, chunk_size)), 0),
+            COALESCE(MAX(deletion_attempts), 0)
+        FROM chunk
+        WHERE state = 'D'
+    "#;
+
+    let mut rows = conn.query(sql, [now.to_rfc3339()]).await.map_err(db_err)?;
+    let row = rows
+        .next()
+        .await
+        .map_err(db_err)?
+        .ok_or_else(|| db_err("Failed to summarize deleted chunks"))?;
+
+    let mut backlog = DeletionBacklog {
+        chunks: row.get::<i64>(0).map_err(db_err)?,
+        due: row.get::<i64>(1).map_err(db_err)?,
+        stored_size: row.get::<i64>(2).map_err(db_err)?,
+        max_attempts: row.get::<i64>(3).map_err(db_err)?,
+        errors: Vec::new(),
+    };
+
+    let sql = r#"
+        SELECT last_deletion_error, COUNT(*)
+        FROM chunk
+        WHERE state = 'D' AND last_deletion_error IS NOT NULL
+        GROUP BY last_deletion_error
+        ORDER BY COUNT(*) DESC
+        LIMIT 10
+    "#;
+
+    let mut rows = conn.query(sql, ()).await.map_err(db_err)?;
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        backlog.errors.push((
+            row.get::<String>(0).map_err(db_err)?,
+            row.get::<i64>(1).map_err(db_err)?,
+        ));
+    }
+
+    Ok(backlog)
+}
+
+/// Deletes chunks by their IDs.
+/// Returns the number of deleted rows.
+pub async fn delete_chunks_by_ids(conn: &TursoConnection, chunk_ids: &[i64]) -> ServerResult<u64> {
+    if chunk_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = chunk_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "DELETE FROM chunk WHERE id IN ({})",
+        placeholders.join(", ")
+    );
+
+    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
+    Ok(affected)
+}
+
+// ============================================================================
+// Queries for NAR deduplication (gc.rs)
+// ============================================================================
+
+/// A valid NAR that shares its hash with other valid NARs.
+pub struct DuplicateNar {
+    pub id: i64,
+    pub nar_hash: String,
+    pub holders_count: i64,
+}
+
+/// Finds valid NARs whose hashes are shared with other valid NARs.
+///
+/// NARs with the same hash are adjacent, and the first of each is the
+/// one to keep: complete NARs come before incomplete ones, then older
+/// ones before newer ones.
+pub async fn find_duplicate_nars(conn: &TursoConnection) -> ServerResult<Vec<DuplicateNar>> {
+    let sql = r#"
+        SELECT id, nar_hash, holders_count
+        FROM nar
+        WHERE state = 'V'
+          AND nar_hash IN (
+              SELECT nar_hash FROM nar
+              WHERE state = 'V'
+              GROUP BY nar_hash
+              HAVING COUNT(*) > 1
+          )
+        ORDER BY nar_hash, completeness_hint DESC, id
+    "#;
+
+    let mut rows = conn.query(sql, ()).await.map_err(db_err)?;
+
+    let mut nars = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        nars.push(DuplicateNar {
+            id: row.get::<i64>(0).map_err(db_err)?,
+            nar_hash: row.get::<String>(1).map_err(db_err)?,
+            holders_count: row.get::<i64>(2).map_err(db_err)?,
+        });
+    }
+
+    Ok(nars)
+}
+
+/// Increments the holders_count of a valid NAR.
+/// Returns whether the NAR is still valid.
+pub async fn increment_nar_holders(conn: &TursoConnection, nar_id: i64) -> ServerResult<bool> {
+    let sql = "UPDATE nar SET holders_count = holders_count + 1 WHERE id = ?1 AND state = 'V'";
+
+    let affected = conn.execute(sql, [nar_id]).await.map_err(db_err)?;
+
+    Ok(affected != 0)
+}
+
+/// Moves the objects of duplicate NARs to the NAR to keep.
+///
+/// Duplicates that are held by uploads in progress are skipped. The
+/// NAR listing of a duplicate is kept if the NAR to keep has none.
+///
+/// Returns the number of objects moved.
+pub async fn merge_duplicate_nars(
+    conn: &TursoConnection,
+    nar_id: i64,
+    duplicate_ids: &[i64],
+) -> ServerResult<u64> {
+    if duplicate_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = duplicate_ids.iter().map(|id| id.to_string()).collect();
+
+    let sql = format!(
+        r#"
+        UPDATE object SET nar_id = ?1
+        WHERE nar_id IN (
+            SELECT id FROM nar
+            WHERE id IN ({})
+              AND holders_count = 0
+        )
+    "#,
+        placeholders.join(", ")
+    );
+    let affected = conn.execute(&sql, [nar_id]).await.map_err(db_err)?;
+
+    let sql = format!(
+        r#"
+        INSERT OR IGNORE INTO nar_listing (nar_id, listing, created_at)
+        SELECT ?1, listing, created_at FROM nar_listing
+        WHERE nar_id IN ({})
+        LIMIT 1
+    "#,
+        placeholders.join(", ")
+    );
+    conn.execute(&sql, [nar_id]).await.map_err(db_err)?;
+
+    Ok(affected)
+}
+
+// ============================================================================
+// Queries for garbage collection runs (gc.rs, dashboard.rs)
+// ============================================================================
+
+/// Records a garbage collection run.
+pub async fn insert_gc_run(
+    conn: &TursoConnection,
+    started_at: &DateTime<Utc>,
+    finished_at: &DateTime<Utc>,
+    error: Option<&str>,
+    report: &str,
+) -> ServerResult<()> {
+    let sql = r#"
+        INSERT INTO gc_run (started_at, finished_at, error, report)
+        VALUES (?1, ?2, ?3, ?4)
+    "#;
+
+    conn.execute(
+        sql,
+        (
+            started_at.to_rfc3339(),
+            finished_at.to_rfc3339(),
+            error,
+            report,
+        ),
+    )
+    .await
+    .map_err(db_err)?;
+
+    Ok(())
+}
+
+/// Finds the most recent garbage collection runs, newest first.
+pub async fn find_recent_gc_runs(
+    conn: &TursoConnection,
+    limit: u64,
+) -> ServerResult<Vec<GcRunModel>> {
+    let sql = r#"
+        SELECT id, started_at, finished_at, error, report
+        FROM gc_run
+        ORDER BY id DESC
+        LIMIT ?1
+    "#;
+
+    let mut rows = conn.query(sql, [limit as i64]).await.map_err(db_err)?;
+
+    let mut runs = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        runs.push(GcRunModel::from_row(&row).map_err(db_err)?);
+    }
+
+    Ok(runs)
+}
+
+// ============================================================================
+// Queries for storage scrubbing (scrub.rs)
+// ============================================================================
+
+/// Finds chunks in Valid state, ordered by ID.
+///
+/// Only chunks with IDs greater than `after_id` are returned.
+pub async fn find_valid_chunks(
+    conn: &TursoConnection,
+    after_id: i64,
+    limit: u64,
+) -> ServerResult<Vec<ChunkModel>> {
+    let sql = r#"
+        SELECT id, state, chunk_hash, chunk_size, file_hash, file_size,
+               compression, remote_file, remote_file_id, holders_count, created_at
+        FROM chunk
+        WHERE state = 'V' AND id > ?1
+        ORDER BY id ASC
+        LIMIT ?2
+    "#;
+
+    let mut rows = conn
+        .query(sql, (after_id, limit as i64))
+        .await
+        .map_err(db_err)?;
+
+    let mut chunks = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        chunks.push(ChunkModel::from_row(&row).map_err(db_err)?);
+    }
+
+    Ok(chunks)
+}
+
+/// Finds the store paths whose NARs use any of the given chunks.
+///
+/// Returns (cache name, store path) pairs, sorted.
+pub async fn find_store_paths_by_chunk_ids(
+    conn: &TursoConnection,
+    chunk_ids: &[i64],
+) -> ServerResult<Vec<(String, String)>> {
+    if chunk_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders: Vec<String> = chunk_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        r#"
+        SELECT DISTINCT c.name, o.store_path
+        FROM chunkref cr
+        INNER JOIN object o ON o.nar_id = cr.nar_id
+        INNER JOIN cache c ON c.id = o.cache_id
+        WHERE cr.chunk_id IN ({})
+          AND c.deleted_at IS NULL
+        ORDER BY c.name, o.store_path
+    "#,
+        placeholders.join(", ")
+    );
+
+    let mut rows = conn.query(&sql, ()).await.map_err(db_err)?;
+
+    let mut paths = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        paths.push((
+            row.get::<String>(0).map_err(db_err)?,
+            row.get::<String>(1).map_err(db_err)?,
+        ));
+    }
+
+    Ok(paths)
+}
+
+/// Takes corrupt chunks out of service.
+///
+/// The chunks are marked Corrupt so that new uploads don't deduplicate
+/// against them, the NARs using them are flagged as incomplete, and
+/// their chunkrefs are detached so that uploading the NARs again
+/// repairs them. Returns the number of affected NARs.
+pub async fn mark_chunks_corrupt(conn: &TursoConnection, chunk_ids: &[i64]) -> ServerResult<u64> {
+    if chunk_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = chunk_ids.iter().map(|id| id.to_string()).collect();
+    let placeholders = placeholders.join(", ");
+
+    let sql = format!(
+        "UPDATE chunk SET state = 'X' WHERE id IN ({})",
+        placeholders
+    );
+    conn.execute(&sql, ()).await.map_err(db_err)?;
+
+    detach_chunks(conn, chunk_ids).await
+}
+
+/// Detaches chunks from the NARs using them.
+///
+/// The NARs are flagged as incomplete. Returns the number of
+/// affected NARs.
+pub async fn detach_chunks(conn: &TursoConnection, chunk_ids: &[i64]) -> ServerResult<u64> {
+    if chunk_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = chunk_ids.iter().map(|id| id.to_string()).collect();
+    let placeholders = placeholders.join(", ");
+
+    let sql = format!(
+        r#"
+        UPDATE nar SET completeness_hint = 0
+        WHERE id IN (SELECT nar_id FROM chunkref WHERE chunk_id IN ({}))
+    "#,
+        placeholders
+    );
+    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
+
+    let sql = format!(
+        "UPDATE chunkref SET chunk_id = NULL WHERE chunk_id IN ({})",
+        placeholders
+    );
+    conn.execute(&sql, ()).await.map_err(db_err)?;
+
+    Ok(affected)
+}
+
+// ============================================================================
+// Queries for database consistency checks (check.rs)
+// ============================================================================
+
+/// Collects the integer in the first column of each row.
+async fn query_ids(conn: &TursoConnection, sql: &str) -> ServerResult<Vec<i64>> {
+    let mut rows = conn.query(sql, ()).await.map_err(db_err)?;
+
+    let mut ids = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        ids.push(row.get::<i64>(0).map_err(db_err)?);
+    }
+
+    Ok(ids)
+}
+
+/// Finds NARs that have been pending since before a time.
+pub async fn find_stale_pending_nar_ids(
+    conn: &TursoConnection,
+    before: &DateTime<Utc>,
+) -> ServerResult<Vec<i64>> {
+    let sql = format!(
+        "SELECT id FROM nar WHERE state = 'P' AND created_at < '{}' ORDER BY id",
+        before.to_rfc3339()
+    );
+
+    query_ids(conn, &sql).await
+}
+
+/// Finds chunks that have been pending since before a time.
+pub async fn find_stale_pending_chunk_ids(
+    conn: &TursoConnection,
+    before: &DateTime<Utc>,
+) -> ServerResult<Vec<i64>> {
+    let sql = format!(
+        "SELECT id FROM chunk WHERE state = 'P' AND created_at < '{}' ORDER BY id",
+        before.to_rfc3339()
+    );
+
+    query_ids(conn, &sql).await
+}
+
+/// Finds objects whose NAR or cache doesn't exist.
+pub async fn find_dangling_object_ids(conn: &TursoConnection) -> ServerResult<Vec<i64>> {
+    let sql = r#"
+        SELECT o.id
+        FROM object o
+        LEFT JOIN nar n ON n.id = o.nar_id
+        LEFT JOIN cache c ON c.id = o.cache_id
+        WHERE n.id IS NULL OR c.id IS NULL
+        ORDER BY o.id
+    "#;
+
+    query_ids(conn, sql).await
+}
+
+/// Finds chunkrefs whose NAR doesn't exist.
+pub async fn find_dangling_chunkref_ids(conn: &TursoConnection) -> ServerResult<Vec<i64>> {
+    let sql = r#"
+        SELECT cr.id
+        FROM chunkref cr
+        LEFT JOIN nar n ON n.id = cr.nar_id
+        WHERE n.id IS NULL
+        ORDER BY cr.id
+    "#;
+
+    query_ids(conn, sql).await
+}
+
+/// Finds the IDs of chunks that chunkrefs point to but don't exist.
+pub async fn find_missing_chunk_ids(conn: &TursoConnection) -> ServerResult<Vec<i64>> {
+    let sql = r#"
+        SELECT DISTINCT cr.chunk_id
+        FROM chunkref cr
+        LEFT JOIN chunk ch ON ch.id = cr.chunk_id
+        WHERE cr.chunk_id IS NOT NULL AND ch.id IS NULL
+        ORDER BY cr.chunk_id
+    "#;
+
+    query_ids(conn, sql).await
+}
+
+/// Finds valid NARs whose chunkrefs don't number 0 to `num_chunks - 1`.
+///
+/// Such NARs would be reassembled with chunks missing, repeated or in
+/// the wrong order.
+pub async fn find_nars_with_broken_chunk_sequences(
+    conn: &TursoConnection,
+) -> ServerResult<Vec<i64>> {
+    let sql = r#"
+        SELECT n.id
+        FROM nar n
+        LEFT JOIN chunkref cr ON cr.nar_id = n.id
+        WHERE n.state = 'V'
+        GROUP BY n.id
+        HAVING COUNT(cr.id) != n.num_chunks
+            OR COUNT(DISTINCT cr.seq) != n.num_chunks
+            OR (n.num_chunks > 0 AND (MIN(cr.seq) != 0 OR MAX(cr.seq) != n.num_chunks - 1))
+        ORDER BY n.id
+    "#;
+
+    query_ids(conn, sql).await
+}
+
+/// Finds valid NARs that are flagged as complete but have detached chunks.
+pub async fn find_incomplete_nars_flagged_complete(
+    conn: &TursoConnection,
+) -> ServerResult<Vec<i64>> {
+    let sql = r#"
+        SELECT DISTINCT n.id
+        FROM nar n
+        INNER JOIN chunkref cr ON cr.nar_id = n.id
+        WHERE n.state = 'V'
+          AND n.completeness_hint = 1
+          AND cr.chunk_id IS NULL
+        ORDER BY n.id
+    "#;
+
+    query_ids(conn, sql).await
+}
+
+/// Finds NARs whose holders_count is negative, or positive if
+/// `positive` is set.
+pub async fn find_held_nar_ids(conn: &TursoConnection, positive: bool) -> ServerResult<Vec<i64>> {
+    let condition = if positive { "> 0" } else { "< 0" };
+    let sql = format!(
+        "SELECT id FROM nar WHERE holders_count {} ORDER BY id",
+        condition
+    );
+
+    query_ids(conn, &sql).await
+}
+
+/// Finds chunks whose holders_count is negative, or positive if
+/// `positive` is set.
+pub async fn find_held_chunk_ids(conn: &TursoConnection, positive: bool) -> ServerResult<Vec<i64>> {
+    let condition = if positive { "> 0" } else { "< 0" };
+    let sql = format!(
+        "SELECT id FROM chunk WHERE holders_count {} ORDER BY id",
+        condition
+    );
+
+    query_ids(conn, &sql).await
+}
+
+/// Finds the caches and store paths of objects using the given NARs.
+///
+/// Returns (cache name, store path) pairs.
+pub async fn find_store_paths_by_nar_ids(
+    conn: &TursoConnection,
+    nar_ids: &[i64],
+) -> ServerResult<Vec<(String, String)>> {
+    if nar_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders: Vec<String> = nar_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        r#"
+        SELECT DISTINCT c.name, o.store_path
+        FROM object o
+        INNER JOIN cache c ON c.id = o.cache_id
+        WHERE o.nar_id IN ({})
+          AND c.deleted_at IS NULL
+        ORDER BY c.name, o.store_path
+    "#,
+        placeholders.join(", ")
+    );
+
+    let mut rows = conn.query(&sql, ()).await.map_err(db_err)?;
+
+    let mut paths = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        paths.push((
+            row.get::<String>(0).map_err(db_err)?,
+            row.get::<String>(1).map_err(db_err)?,
+        ));
+    }
+
+    Ok(paths)
+}
+
+/// Deletes NARs along with their objects and chunkrefs.
+/// Returns the number of deleted NARs.
+pub async fn delete_nars_with_dependents(
+    conn: &TursoConnection,
+    nar_ids: &[i64],
+) -> ServerResult<u64> {
+    if nar_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = nar_ids.iter().map(|id| id.to_string()).collect();
+    let placeholders = placeholders.join(", ");
+
+    for table in ["object", "chunkref"] {
+        let sql = format!("DELETE FROM {} WHERE nar_id IN ({})", table, placeholders);
+        conn.execute(&sql, ()).await.map_err(db_err)?;
+    }
+
+    delete_nars_by_ids(conn, nar_ids).await
+}
+
+/// Deletes chunkrefs by their IDs.
+/// Returns the number of deleted rows.
+pub async fn delete_chunkrefs_by_ids(
+    conn: &TursoConnection,
+    chunkref_ids: &[i64],
+) -> ServerResult<u64> {
+    if chunkref_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = chunkref_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "DELETE FROM chunkref WHERE id IN ({})",
+        placeholders.join(", ")
+    );
+
+    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
+    Ok(affected)
+}
+
+/// Flags NARs as incomplete.
+/// Returns the number of affected rows.
+pub async fn mark_nars_incomplete(conn: &TursoConnection, nar_ids: &[i64]) -> ServerResult<u64> {
+    if nar_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = nar_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "UPDATE nar SET completeness_hint = 0 WHERE id IN ({})",
+        placeholders.join(", ")
+    );
+
+    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
+    Ok(affected)
+}
+
+/// Resets the holders_count of NARs to zero.
+/// Returns the number of affected rows.
+pub async fn reset_nar_holders(conn: &TursoConnection, nar_ids: &[i64]) -> ServerResult<u64> {
+    if nar_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = nar_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "UPDATE nar SET holders_count = 0 WHERE id IN ({})",
+        placeholders.join(", ")
+    );
+
+    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
+    Ok(affected)
+}
+
+/// Resets the holders_count of chunks to zero.
+/// Returns the number of affected rows.
+pub async fn reset_chunk_holders(conn: &TursoConnection, chunk_ids: &[i64]) -> ServerResult<u64> {
+    if chunk_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let placeholders: Vec<String> = chunk_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "UPDATE chunk SET holders_count = 0 WHERE id IN ({})",
+        placeholders.join(", ")
+    );
+
+    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
+    Ok(affected)
+}
+
+// ============================================================================
+// Queries for storage reconciliation (reconcile.rs)
+// ============================================================================
+
+/// A chunk and the file it is stored in.
+pub struct ChunkFile {
+    pub id: i64,
+    pub state: ChunkState,
+    pub remote_file_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Finds chunks whose remote file IDs sort between `after` and
+/// `before` (both exclusive), ordered by remote file ID.
+pub async fn find_chunk_files(
+    conn: &TursoConnection,
+    after: &str,
+    before: &str,
+    limit: u64,
+) -> ServerResult<Vec<ChunkFile>> {
+    let sql = r#"
+        SELECT id, state, remote_file_id, created_at
+        FROM chunk
+        WHERE remote_file_id > ?1 AND remote_file_id < ?2
+        ORDER BY remote_file_id ASC
+        LIMIT ?3
+    "#;
+
+    let mut rows = conn
+        .query(sql, (after, before, limit as i64))
+        .await
+        .map_err(db_err)?;
+
+    let mut chunks = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        chunks.push(ChunkFile {
+            id: row.get::<i64>(0).map_err(db_err)?,
+            state: ChunkState::from_db_value(&row.get::<String>(1).map_err(db_err)?)
+                .map_err(db_err)?,
+            remote_file_id: row.get::<String>(2).map_err(db_err)?,
+            created_at: parse_datetime(&row.get::<String>(3).map_err(db_err)?).map_err(db_err)?,
+        });
+    }
+
+    Ok(chunks)
+}
+
+/// Finds the remote files of staged NARs and build logs.
+pub async fn find_staged_nar_and_build_log_files(
+    conn: &TursoConnection,
+) -> ServerResult<Vec<RemoteFile>> {
+    let sql = r#"
+        SELECT remote_file FROM staged_nar
+        UNION ALL
+        SELECT remote_file FROM build_log
+    "#;
+
+    let mut rows = conn.query(sql, ()).await.map_err(db_err)?;
+
+    let mut files = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        let remote_file = row.get::<String>(0).map_err(db_err)?;
+        files.push(serde_json::from_str(&remote_file).map_err(db_err)?);
+    }
+
+    Ok(files)
+}
+
+// ============================================================================
+// Queries for get_missing_paths.rs
+// ============================================================================
+
+/// Finds objects by store path hashes that have complete NARs.
+/// Returns the store_path_hash values that exist in the cache.
+pub async fn find_objects_by_store_path_hashes(
+    conn: &TursoConnection,
+    cache_name: &str,
+    store_path_hashes: &[String],
+) -> ServerResult<Vec<String>> {
+    if store_path_hashes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Build the IN clause with quoted strings
+    let quoted: Vec<String> = store_path_hashes
+        .iter()
+        .map(|h| format!("'{}'", h.replace('\'', "''")))
+        .collect();
+
+    let sql = format!(
+        r#"
+        SELECT o.store_path_hash
+        FROM object o
+        INNER JOIN cache c ON o.cache_id = c.id
+        INNER JOIN nar n ON o.nar_id = n.id
+        WHERE c.name = ?1
+          AND c.deleted_at IS NULL
+          AND o.store_path_hash IN ({})
+          AND n.completeness_hint = 1
+    "#,
+        quoted.join(", ")
+    );
+
+    let mut rows = conn.query(&sql, [cache_name]).await.map_err(db_err)?;
+
+    let mut found = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        found.push(row.get::<String>(0).map_err(db_err)?);
+    }
+
+    Ok(found)
+}
+
+/// Finds objects by store path hashes whose NARs are incomplete.
+/// Returns the store_path_hash values that need to be uploaded again.
+pub async fn find_incomplete_objects_by_store_path_hashes(
+    conn: &TursoConnection,
+    cache_name: &str,
+    store_path_hashes: &[String],
+) -> ServerResult<Vec<String>> {
+    if store_path_hashes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let quoted: Vec<String> = store_path_hashes
+        .iter()
+        .map(|h| format!("'{}'", h.replace('\'', "''")))
+        .collect();
+
+    let sql = format!(
+        r#"
+        SELECT o.store_path_hash
+        FROM object o
+        INNER JOIN cache c ON o.cache_id = c.id
+        INNER JOIN nar n ON o.nar_id = n.id
+        WHERE c.name = ?1
+          AND c.deleted_at IS NULL
+          AND o.store_path_hash IN ({})
+          AND n.completeness_hint = 0
+    "#,
+        quoted.join(", ")
+    );
+
+    let mut rows = conn.query(&sql, [cache_name]).await.map_err(db_err)?;
+
+    let mut found = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        found.push(row.get::<String>(0).map_err(db_err)?);
+    }
+
+    Ok(found)
+}
+
+// ============================================================================
+// Queries for incomplete NARs (binary_cache.rs, incomplete_paths.rs)
+// ============================================================================
+
+/// An object whose NAR is missing chunks.
+pub struct IncompleteObject {
+    pub store_path_hash: String,
+    pub store_path: String,
+
+    /// How many times the NAR was requested while incomplete.
+    pub hits: i64,
+    pub first_hit_at: Option<DateTime<Utc>>,
+    pub last_hit_at: Option<DateTime<Utc>>,
+}
+
+/// Records that a NAR was requested but is missing chunks.
+///
+/// The NAR is marked as incomplete so that clients pushing the
+/// paths using it upload them again.
+pub async fn record_incomplete_nar_hit(conn: &TursoConnection, nar_id: i64) -> ServerResult<()> {
+    let now = Utc::now().to_rfc3339();
+
+    conn.execute(
+        "UPDATE nar SET completeness_hint = 0 WHERE id = ?1",
+        [nar_id],
+    )
+    .await
+    .map_err(db_err)?;
+
+    let sql = r#"
+        INSERT INTO incomplete_nar (nar_id, hits, first_hit_at, last_hit_at)
+        VALUES (?1, 1, ?2, ?2)
+        ON CONFLICT(nar_id) DO UPDATE SET
+            hits = hits + 1,
+            last_hit_at = excluded.last_hit_at
+    "#;
+    conn.execute(sql, (nar_id, now.as_str()))
+        .await
+        .map_err(db_err)?;
+
+    Ok(())
+}
+
+/// Finds the objects of a cache whose NARs are incomplete.
+///
+/// The most recently requested ones come first, followed by those
+/// that were never requested.
+pub async fn find_incomplete_objects(
+    conn: &TursoConnection,
+    cache_id: i64,
+) -> ServerResult<Vec<IncompleteObject>> {
+    let sql = r#"
+        SELECT o.store_path_hash, o.store_path,
+               COALESCE(i.hits, 0), i.first_hit_at, i.last_hit_at
+        FROM object o
+        INNER JOIN nar n ON o.nar_id = n.id
+        LEFT JOIN incomplete_nar i ON i.nar_id = n.id
+        WHERE o.cache_id = ?1
+          AND n.completeness_hint = 0
+        ORDER BY i.last_hit_at DESC, o.store_path ASC
+    "#;
+
+    let mut rows = conn.query(sql, [cache_id]).await.map_err(db_err)?;
+
+    let mut objects = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        let first_hit_at = row.get::<Option<String>>(3).map_err(db_err)?;
+        let last_hit_at = row.get::<Option<String>>(4).map_err(db_err)?;
+
+        objects.push(IncompleteObject {
+            store_path_hash: row.get::<String>(0).map_err(db_err)?,
+            store_path: row.get::<String>(1).map_err(db_err)?,
+            hits: row.get::<i64>(2).map_err(db_err)?,
+            first_hit_at: first_hit_at
+                .map(|t| parse_datetime(&t))
+                .transpose()
+                .map_err(db_err)?,
+            last_hit_at: last_hit_at
+                .map(|t| parse_datetime(&t))
+                .transpose()
+                .map_err(db_err)?,
+        });
+    }
+
+    Ok(objects)
+}
+
+/// Counts the objects of a cache whose NARs are incomplete.
+pub async fn count_incomplete_objects(conn: &TursoConnection, cache_id: i64) -> ServerResult<i64> {
+    let sql = r#"
+        SELECT COUNT(*)
+        FROM object o
+        INNER JOIN nar n ON o.nar_id = n.id
+        WHERE o.cache_id = ?1
+          AND n.completeness_hint = 0
+    "#;
+
+    let mut rows = conn.query(sql, [cache_id]).await.map_err(db_err)?;
+
+    match rows.next().await.map_err(db_err)? {
+        Some(row) => row.get::<i64>(0).map_err(db_err),
+        None => Ok(0),
+    }
+}
+
+// ============================================================================
+// Queries for delete_paths.rs
+// ============================================================================
+
+/// Deletes objects from a cache that match any of the selectors.
+///
+/// Name globs are matched against the part of the store path after
+/// `{hash}-` using SQLite's `GLOB` operator. The NARs of the deleted
+/// objects become orphans and are reaped by the garbage collector.
+///
+/// Returns the store paths of the deleted objects.
+pub async fn delete_objects_by_selectors(
+    conn: &TursoConnection,
+    cache_id: i64,
+    store_path_hashes: &[String],
+    store_paths: &[String],
+    name_globs: &[String],
+) -> ServerResult<Vec<String>> {
+    let quote = |s: &String| format!("'{}'", s.replace('\'', "''"));
+
+    let mut conditions = Vec::new();
+
+    if !store_path_hashes.is_empty() {
+        let quoted: Vec<String> = store_path_hashes.iter().map(quote).collect();
+        conditions.push(format!("store_path_hash IN ({})", quoted.join(", ")));
+    }
+
+    if !store_paths.is_empty() {
+        let quoted: Vec<String> = store_paths.iter().map(quote).collect();
+        conditions.push(format!("store_path IN ({})", quoted.join(", ")));
+    }
+
+    for glob in name_globs {
+        conditions.push(format!(
+            "store_path GLOB ('*/' || store_path_hash || '-' || {})",
+            quote(glob)
+        ));
+    }
+
+    if conditions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let sql = format!(
+        r#"
+        DELETE FROM object
+        WHERE cache_id = ?1
+          AND ({})
+        RETURNING store_path
+    "#,
+        conditions.join(" OR ")
+    );
+
+    let mut rows = conn.query(&sql, [cache_id]).await.map_err(db_err)?;
+
+    let mut deleted = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        deleted.push(row.get::<String>(0).map_err(db_err)?);
+    }
+
+    Ok(deleted)
+}
+
+// ============================================================================
+// Queries for the Nix binary cache write protocol (binary_cache.rs)
+// ============================================================================
+
+/// Finds a staged NAR by the file name it was uploaded as.
+pub async fn find_staged_nar(
+    conn: &TursoConnection,
+    cache_id: i64,
+    file_name: &str,
+) -> ServerResult<Option<StagedNarModel>> {
+    let sql = r#"
+        SELECT id, cache_id, file_name, nar_hash, nar_size, remote_file, created_at
+        FROM staged_nar
+        WHERE cache_id = ?1 AND file_name = ?2
+    "#;
+
+    let mut rows = conn
+        .query(sql, (cache_id, file_name))
+        .await
+        .map_err(db_err)?;
+
+    match rows.next().await.map_err(db_err)? {
+        Some(row) => Ok(Some(StagedNarModel::from_row(&row).map_err(db_err)?)),
+        None => Ok(None),
+    }
+}
+
+/// Inserts a staged NAR, replacing any existing one with the same file name.
+pub async fn insert_staged_nar(
+    conn: &TursoConnection,
+    cache_id: i64,
+    file_name: &str,
+    nar_hash: &str,
+    nar_size: i64,
+    remote_file: &str,
+) -> ServerResult<StagedNarModel> {
+    let now = Utc::now().to_rfc3339();
+
+    let sql = r#"
+        INSERT INTO staged_nar (cache_id, file_name, nar_hash, nar_size, remote_file, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT(cache_id, file_name) DO UPDATE SET
+            nar_hash = excluded.nar_hash,
+            nar_size = excluded.nar_size,
+            remote_file = excluded.remote_file,
+            created_at = excluded.created_at
+        RETURNING id, cache_id, file_name, nar_hash, nar_size, remote_file, created_at
+    "#;
+
+    let mut rows = conn
+        .query(
+            sql,
+            (
+                cache_id,
+                file_name,
+                nar_hash,
+                nar_size,
+                remote_file,
+                now.as_str(),
+            ),
+        )
+        .await
+        .map_err(db_err)?;
+
+    match rows.next().await.map_err(db_err)? {
+        Some(row) => StagedNarModel::from_row(&row).map_err(db_err),
+        None => Err(ErrorKind::DatabaseError(anyhow!("Failed to insert staged NAR")).into()),
+    }
+}
+
+/// Deletes a staged NAR.
+pub async fn delete_staged_nar(conn: &TursoConnection, staged_nar_id: i64) -> ServerResult<()> {
+    let sql = "DELETE FROM staged_nar WHERE id = ?1";
+    conn.execute(sql, [staged_nar_id]).await.map_err(db_err)?;
+    Ok(())
+}
+
+/// Finds staged NARs that were uploaded before the cutoff time.
+pub async fn find_staged_nars_before(
+    conn: &TursoConnection,
+    cutoff: &str,
+) -> ServerResult<Vec<StagedNarModel>> {
+    let sql = r#"
+        SELECT id, cache_id, file_name, nar_hash, nar_size, remote_file, created_at
+        FROM staged_nar
+        WHERE created_at < ?1
+    "#;
+
+    let mut rows = conn.query(sql, [cutoff]).await.map_err(db_err)?;
+
+    let mut staged_nars = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        staged_nars.push(StagedNarModel::from_row(&row).map_err(db_err)?);
+    }
+
+    Ok(staged_nars)
+}
+
+// ============================================================================
+// Queries for build logs (binary_cache.rs)
+// ============================================================================
+
+/// Finds the build log of a derivation in a cache.
+pub async fn find_build_log(
+    conn: &TursoConnection,
+    cache_id: i64,
+    drv_name: &str,
+) -> ServerResult<Option<BuildLogModel>> {
+    let sql = r#"
+        SELECT id, cache_id, drv_name, remote_file, created_at, created_by
+        FROM build_log
+        WHERE cache_id = ?1 AND drv_name = ?2
+    "#;
+
+    let mut rows = conn
+        .query(sql, (cache_id, drv_name))
+        .await
+        .map_err(db_err)?;
+
+    match rows.next().await.map_err(db_err)? {
+        Some(row) => Ok(Some(BuildLogModel::from_row(&row).map_err(db_err)?)),
+        None => Ok(None),
+    }
+}
+
+/// Inserts a build log, replacing any existing log of the same derivation.
+pub async fn insert_build_log(
+    conn: &TursoConnection,
+    cache_id: i64,
+    drv_name: &str,
+    remote_file: &str,
+    created_by: Option<&str>,
+) -> ServerResult<BuildLogModel> {
+    let now = Utc::now().to_rfc3339();
+
+    let sql = r#"
+        INSERT INTO build_log (cache_id, drv_name, remote_file, created_at, created_by)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT(cache_id, drv_name) DO UPDATE SET
+            remote_file = excluded.remote_file,
+            created_at = excluded.created_at,
+            created_by = excluded.created_by
+        RETURNING id, cache_id, drv_name, remote_file, created_at, created_by
+    "#;
+
+    let mut rows = conn
+        .query(
+            sql,
+            (cache_id, drv_name, remote_file, now.as_str(), created_by),
+        )
+        .await
+        .map_err(db_err)?;
+
+    match rows.next().await.map_err(db_err)? {
+        Some(row) => BuildLogModel::from_row(&row).map_err(db_err),
+        None => Err(ErrorKind::DatabaseError(anyhow!("Failed to insert build log")).into()),
+    }
+}
+
+// ============================================================================
+// Queries for realisations (binary_cache.rs)
+// ============================================================================
+
+/// Finds the realisation of a derivation output in a cache.
+pub async fn find_realisation(
+    conn: &TursoConnection,
+    cache_id: i64,
+    output_id: &str,
+) -> ServerResult<Option<RealisationModel>> {
+    let sql = r#"
+        SELECT id, cache_id, output_id, out_path, signatures, dependent_realisations,
+               created_at, created_by
+        FROM realisation
+        WHERE cache_id = ?1 AND output_id = ?2
+    "#;
+
+    let mut rows = conn
+        .query(sql, (cache_id, output_id))
+        .await
+        .map_err(db_err)?;
+
+    match rows.next().await.map_err(db_err)? {
+        Some(row) => Ok(Some(RealisationModel::from_row(&row).map_err(db_err)?)),
+        None => Ok(None),
+    }
+}
+
+/// Inserts a realisation, replacing any existing one of the same derivation output.
+pub async fn insert_realisation(
+    conn: &TursoConnection,
+    cache_id: i64,
+    output_id: &str,
+    out_path: &str,
+    signatures: &str,
+    dependent_realisations: &str,
+    created_by: Option<&str>,
+) -> ServerResult<RealisationModel> {
+    let now = Utc::now().to_rfc3339();
+
+    let sql = r#"
+        INSERT INTO realisation
+            (cache_id, output_id, out_path, signatures, dependent_realisations, created_at, created_by)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT(cache_id, output_id) DO UPDATE SET
+            out_path = excluded.out_path,
+            signatures = excluded.signatures,
+            dependent_realisations = excluded.dependent_realisations,
+            created_at = excluded.created_at,
+            created_by = excluded.created_by
+        RETURNING id, cache_id, output_id, out_path, signatures, dependent_realisations,
+                  created_at, created_by
+    "#;
+
+    let mut rows = conn
+        .query(
+            sql,
+            (
+                cache_id,
+                output_id,
+                out_path,
+                signatures,
+                dependent_realisations,
+                now.as_str(),
+                created_by,
+            ),
+        )
+        .await
+        .map_err(db_err)?;
+
+    match rows.next().await.map_err(db_err)? {
+        Some(row) => RealisationModel::from_row(&row).map_err(db_err),
+        None => Err(ErrorKind::DatabaseError(anyhow!("Failed to insert realisation")).into()),
+    }
+}
+
+// ============================================================================
+// Queries for upstream substituters (cache_config.rs, upstream.rs)
+// ============================================================================
+
+/// Finds the upstream substituters of a cache, in the order they were configured.
+pub async fn find_cache_upstreams(
+    conn: &TursoConnection,
+    cache_id: i64,
+) -> ServerResult<Vec<CacheUpstreamModel>> {
+    let sql = r#"
+        SELECT id, cache_id, url, trusted_keys, created_at
+        FROM cache_upstream
+        WHERE cache_id = ?1
+        ORDER BY id
+    "#;
+
+    let mut rows = conn.query(sql, [cache_id]).await.map_err(db_err)?;
+
+    let mut upstreams = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        upstreams.push(CacheUpstreamModel::from_row(&row).map_err(db_err)?);
+    }
+
+    Ok(upstreams)
+}
+
+/// Inserts an upstream substituter after the existing ones of a cache.
+pub async fn insert_cache_upstream(
+    conn: &TursoConnection,
+    cache_id: i64,
+    url: &str,
+    trusted_keys: &str,
+) -> ServerResult<()> {
+    let now = Utc::now().to_rfc3339();
+
+    let sql = r#"
+        INSERT INTO cache_upstream (cache_id, url, trusted_keys, created_at)
+        VALUES (?1, ?2, ?3, ?4)
+    "#;
+
+    conn.execute(sql, (cache_id, url, trusted_keys, now.as_str()))
+        .await
+        .map_err(db_err)?;
+
+    Ok(())
+}
+
+/// Deletes all upstream substituters of a cache.
+pub async fn delete_cache_upstreams(conn: &TursoConnection, cache_id: i64) -> ServerResult<()> {
+    let sql = "DELETE FROM cache_upstream WHERE cache_id = ?1";
+    conn.execute(sql, [cache_id]).await.map_err(db_err)?;
+    Ok(())
+}
+
+// ============================================================================
+// Queries for cache keys (cache_config.rs, binary_cache.rs, signing)
+// ============================================================================
+
+/// Finds the keys of a cache other than its primary keypair, oldest first.
+pub async fn find_cache_keys(
     conn: &TursoConnection,
-    default_retention_seconds: i64,
-) -> ServerResult<Vec<CacheWithRetention>> {
+    cache_id: i64,
+) -> ServerResult<Vec<CacheKeyModel>> {
     let sql = r#"
-        SELECT id, name, COALESCE(retention_period, ?1) as retention_period
-        FROM cache
-        WHERE deleted_at IS NULL
-          AND COALESCE(retention_period, ?1) != 0
+        SELECT id, cache_id, keypair, state, created_at, updated_at
+        FROM cache_key
+        WHERE cache_id = ?1
+        ORDER BY id
     "#;
 
-    let mut rows = conn
-        .query(sql, [default_retention_seconds])
-        .await
-        .map_err(db_err)?;
+    let mut rows = conn.query(sql, [cache_id]).await.map_err(db_err)?;
 
-    let mut caches = Vec::new();
+    let mut keys = Vec::new();
     while let Some(row) = rows.next().await.map_err(db_err)? {
-        caches.push(CacheWithRetention {
-            id: row.get::<i64>(0).map_err(db_err)?,
-            name: row.get::<String>(1).map_err(db_err)?,
-            retention_period: row.get::<i64>(2).map_err(db_err)? as i32,
-        });
+        keys.push(CacheKeyModel::from_row(&row).map_err(db_err)?);
     }
 
-    Ok(caches)
+    Ok(keys)
 }
 
-/// Deletes objects from a cache that are older than the cutoff time.
-/// Returns the number of deleted rows.
-pub async fn delete_objects_by_cache_and_cutoff(
+/// Inserts a key of a cache.
+pub async fn insert_cache_key(
     conn: &TursoConnection,
     cache_id: i64,
-    cutoff: &str,
-) -> ServerResult<u64> {
+    keypair: &str,
+    state: CacheKeyState,
+) -> ServerResult<()> {
+    let now = Utc::now().to_rfc3339();
+
     let sql = r#"
-        DELETE FROM object
-        WHERE cache_id = ?1
-          AND created_at < ?2
-          AND (last_accessed_at IS NULL OR last_accessed_at < ?2)
+        INSERT INTO cache_key (cache_id, keypair, state, created_at, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?4)
     "#;
 
-    let affected = conn
-        .execute(sql, (cache_id, cutoff))
+    conn.execute(sql, (cache_id, keypair, state.to_db_value(), now.as_str()))
         .await
         .map_err(db_err)?;
 
-    Ok(affected)
+    Ok(())
 }
 
-/// Finds orphan NAR IDs (NARs with no objects referencing them).
-pub async fn find_orphan_nar_ids(conn: &TursoConnection) -> ServerResult<Vec<i64>> {
+/// Updates the state of a key of a cache.
+pub async fn update_cache_key_state(
+    conn: &TursoConnection,
+    key_id: i64,
+    state: CacheKeyState,
+) -> ServerResult<()> {
+    let now = Utc::now().to_rfc3339();
+
+    let sql = "UPDATE cache_key SET state = ?1, updated_at = ?2 WHERE id = ?3";
+    conn.execute(sql, (state.to_db_value(), now.as_str(), key_id))
+        .await
+        .map_err(db_err)?;
+
+    Ok(())
+}
+
+/// Retires all keys of a cache that aren't retired yet.
+///
+/// Returns the number of keys retired.
+pub async fn retire_cache_keys(conn: &TursoConnection, cache_id: i64) -> ServerResult<u64> {
+    let now = Utc::now().to_rfc3339();
+
     let sql = r#"
-        SELECT n.id
-        FROM nar n
-        LEFT JOIN object o ON o.nar_id = n.id
-        WHERE o.id IS NULL
-          AND n.state = 'V'
-          AND n.holders_count = 0
+        UPDATE cache_key SET state = ?1, updated_at = ?2
+        WHERE cache_id = ?3 AND state != ?1
     "#;
 
+    conn.execute(
+        sql,
+        (CacheKeyState::Retired.to_db_value(), now.as_str(), cache_id),
+    )
+    .await
+    .map_err(db_err)
+}
+
+/// Returns the IDs and keypairs of all caches, including deleted ones.
+pub async fn find_cache_keypairs(conn: &TursoConnection) -> ServerResult<Vec<(i64, String)>> {
+    let sql = "SELECT id, keypair FROM cache ORDER BY id";
     let mut rows = conn.query(sql, ()).await.map_err(db_err)?;
 
-    let mut ids = Vec::new();
+    let mut keypairs = Vec::new();
     while let Some(row) = rows.next().await.map_err(db_err)? {
-        ids.push(row.get::<i64>(0).map_err(db_err)?);
+        keypairs.push((
+            row.get::<i64>(0).map_err(db_err)?,
+            row.get::<String>(1).map_err(db_err)?,
+        ));
     }
 
-    Ok(ids)
+    Ok(keypairs)
 }
 
-/// Deletes NARs by their IDs.
-/// Returns the number of deleted rows.
-pub async fn delete_nars_by_ids(conn: &TursoConnection, nar_ids: &[i64]) -> ServerResult<u64> {
-    if nar_ids.is_empty() {
-        return Ok(0);
+/// Replaces the keypair of a cache, unless it was changed meanwhile.
+///
+/// Returns the number of rows updated.
+pub async fn replace_cache_keypair(
+    conn: &TursoConnection,
+    cache_id: i64,
+    old_keypair: &str,
+    new_keypair: &str,
+) -> ServerResult<u64> {
+    let sql = "UPDATE cache SET keypair = ?1 WHERE id = ?2 AND keypair = ?3";
+    conn.execute(sql, (new_keypair, cache_id, old_keypair))
+        .await
+        .map_err(db_err)
+}
+
+/// Returns the IDs, cache IDs and keypairs of the keys of all caches.
+pub async fn find_cache_key_keypairs(
+    conn: &TursoConnection,
+) -> ServerResult<Vec<(i64, i64, String)>> {
+    let sql = "SELECT id, cache_id, keypair FROM cache_key ORDER BY id";
+    let mut rows = conn.query(sql, ()).await.map_err(db_err)?;
+
+    let mut keypairs = Vec::new();
+    while let Some(row) = rows.next().await.map_err(db_err)? {
+        keypairs.push((
+            row.get::<i64>(0).map_err(db_err)?,
+            row.get::<i64>(1).map_err(db_err)?,
+            row.get::<String>(2).map_err(db_err)?,
+        ));
     }
 
-    let placeholders: Vec<String> = nar_ids.iter().map(|id| id.to_string()).collect();
-    let sql = format!("DELETE FROM nar WHERE id IN ({})", placeholders.join(", "));
+    Ok(keypairs)
+}
 
-    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
-    Ok(affected)
+/// Replaces the keypair of a key of a cache, unless it was changed meanwhile.
+///
+/// Returns the number of rows updated.
+pub async fn replace_cache_key_keypair(
+    conn: &TursoConnection,
+    key_id: i64,
+    old_keypair: &str,
+    new_keypair: &str,
+) -> ServerResult<u64> {
+    let sql = "UPDATE cache_key SET keypair = ?1 WHERE id = ?2 AND keypair = ?3";
+    conn.execute(sql, (new_keypair, key_id, old_keypair))
+        .await
+        .map_err(db_err)
 }
 
-/// Finds orphan chunks (chunks with no chunkrefs referencing them).
-pub async fn find_orphan_chunk_ids(conn: &TursoConnection) -> ServerResult<Vec<i64>> {
+// ============================================================================
+// Queries for pins (pin.rs, gc.rs)
+// ============================================================================
+
+/// Finds the pins of a cache, ordered by name.
+pub async fn find_pins_by_cache(
+    conn: &TursoConnection,
+    cache_id: i64,
+) -> ServerResult<Vec<PinModel>> {
     let sql = r#"
-        SELECT c.id
-        FROM chunk c
-        LEFT JOIN chunkref cr ON cr.chunk_id = c.id
-        WHERE cr.id IS NULL
-          AND c.state = 'V'
-          AND c.holders_count = 0
+        SELECT id, cache_id, name, store_path_hash, store_path, created_at, created_by
+        FROM pin
+        WHERE cache_id = ?1
+        ORDER BY name
     "#;
 
-    let mut rows = conn.query(sql, ()).await.map_err(db_err)?;
+    let mut rows = conn.query(sql, [cache_id]).await.map_err(db_err)?;
 
-    let mut ids = Vec::new();
+    let mut pins = Vec::new();
     while let Some(row) = rows.next().await.map_err(db_err)? {
-        ids.push(row.get::<i64>(0).map_err(db_err)?);
+        pins.push(PinModel::from_row(&row).map_err(db_err)?);
     }
 
-    Ok(ids)
+    Ok(pins)
 }
 
-/// Transitions chunks to Deleted state.
-/// Returns the number of affected rows.
-pub async fn transition_chunks_to_deleted(
+/// Finds a pin of a cache by name.
+pub async fn find_pin(
     conn: &TursoConnection,
-    chunk_ids: &[i64],
-) -> ServerResult<u64> {
-    if chunk_ids.is_empty() {
-        return Ok(0);
-    }
+    cache_id: i64,
+    name: &str,
+) -> ServerResult<Option<PinModel>> {
+    let sql = r#"
+        SELECT id, cache_id, name, store_path_hash, store_path, created_at, created_by
+        FROM pin
+        WHERE cache_id = ?1 AND name = ?2
+    "#;
 
-    let placeholders: Vec<String> = chunk_ids.iter().map(|id| id.to_string()).collect();
-    let sql = format!(
-        "UPDATE chunk SET state = 'D' WHERE id IN ({})",
-        placeholders.join(", ")
-    );
+    let mut rows = conn.query(sql, (cache_id, name)).await.map_err(db_err)?;
 
-    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
-    Ok(affected)
+    match rows.next().await.map_err(db_err)? {
+        Some(row) => Ok(Some(PinModel::from_row(&row).map_err(db_err)?)),
+        None => Ok(None),
+    }
 }
 
-/// Finds chunks in Deleted state (limited).
-pub async fn find_deleted_chunks(
+/// Inserts a pin, replacing any existing pin of the same name.
+pub async fn insert_pin(
     conn: &TursoConnection,
-    limit: u64,
-) -> ServerResult<Vec<ChunkModel>> {
-    let sql = format!(
-        r#"
-        SELECT id, state, chunk_hash, chunk_size, file_hash, file_size,
-               compression, remote_file, remote_file_id, holders_count, created_at
-        FROM chunk
-        WHERE state = 'D'
-        LIMIT {}
-    "#,
-        limit
-    );
+    cache_id: i64,
+    name: &str,
+    store_path_hash: &str,
+    store_path: &str,
+    created_by: Option<&str>,
+) -> ServerResult<PinModel> {
+    let now = Utc::now().to_rfc3339();
 
-    let mut rows = conn.query(&sql, ()).await.map_err(db_err)?;
+    let sql = r#"
+        INSERT INTO pin (cache_id, name, store_path_hash, store_path, created_at, created_by)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT(cache_id, name) DO UPDATE SET
+            store_path_hash = excluded.store_path_hash,
+            store_path = excluded.store_path,
+            created_at = excluded.created_at,
+            created_by = excluded.created_by
+        RETURNING id, cache_id, name, store_path_hash, store_path, created_at, created_by
+    "#;
 
-    let mut chunks = Vec::new();
-    while let Some(row) = rows.next().await.map_err(db_err)? {
-        chunks.push(ChunkModel::from_row(&row).map_err(db_err)?);
-    }
+    let mut rows = conn
+        .query(
+            sql,
+            (
+                cache_id,
+                name,
+                store_path_hash,
+                store_path,
+                now.as_str(),
+                created_by,
+            ),
+        )
+        .await
+        .map_err(db_err)?;
 
-    Ok(chunks)
+    match rows.next().await.map_err(db_err)? {
+        Some(row) => PinModel::from_row(&row).map_err(db_err),
+        None => Err(ErrorKind::DatabaseError(anyhow!("Failed to insert pin")).into()),
+    }
 }
 
-/// Deletes chunks by their IDs.
+/// Deletes a pin of a cache by name.
 /// Returns the number of deleted rows.
-pub async fn delete_chunks_by_ids(conn: &TursoConnection, chunk_ids: &[i64]) -> ServerResult<u64> {
-    if chunk_ids.is_empty() {
-        return Ok(0);
-    }
+pub async fn delete_pin(conn: &TursoConnection, cache_id: i64, name: &str) -> ServerResult<u64> {
+    let sql = "DELETE FROM pin WHERE cache_id = ?1 AND name = ?2";
 
-    let placeholders: Vec<String> = chunk_ids.iter().map(|id| id.to_string()).collect();
-    let sql = format!(
-        "DELETE FROM chunk WHERE id IN ({})",
-        placeholders.join(", ")
-    );
+    let affected = conn.execute(sql, (cache_id, name)).await.map_err(db_err)?;
 
-    let affected = conn.execute(&sql, ()).await.map_err(db_err)?;
     Ok(affected)
 }
 
-// ============================================================================
-// Queries for get_missing_paths.rs
-// ============================================================================
-
-/// Finds objects by store path hashes that have complete NARs.
-/// Returns the store_path_hash values that exist in the cache.
-pub async fn find_objects_by_store_path_hashes(
+/// Deletes the pins of a cache pointing to any of the store paths.
+/// Returns the number of deleted rows.
+pub async fn delete_pins_by_store_paths(
     conn: &TursoConnection,
-    cache_name: &str,
-    store_path_hashes: &[String],
-) -> ServerResult<Vec<String>> {
-    if store_path_hashes.is_empty() {
-        return Ok(Vec::new());
+    cache_id: i64,
+    store_paths: &[String],
+) -> ServerResult<u64> {
+    if store_paths.is_empty() {
+        return Ok(0);
     }
 
-    // Build the IN clause with quoted strings
-    let quoted: Vec<String> = store_path_hashes
+    let quoted: Vec<String> = store_paths
         .iter()
-        .map(|h| format!("'{}'", h.replace('\'', "''")))
+        .map(|s| format!("'{}'", s.replace('\'', "''")))
         .collect();
-
     let sql = format!(
-        r#"
-        SELECT o.store_path_hash
-        FROM object o
-        INNER JOIN cache c ON o.cache_id = c.id
-        INNER JOIN nar n ON o.nar_id = n.id
-        WHERE c.name = ?1
-          AND c.deleted_at IS NULL
-          AND o.store_path_hash IN ({})
-          AND n.completeness_hint = 1
-    "#,
+        "DELETE FROM pin WHERE cache_id = ?1 AND store_path IN ({})",
         quoted.join(", ")
     );
 
-    let mut rows = conn.query(&sql, [cache_name]).await.map_err(db_err)?;
-
-    let mut found = Vec::new();
-    while let Some(row) = rows.next().await.map_err(db_err)? {
-        found.push(row.get::<String>(0).map_err(db_err)?);
-    }
+    let affected = conn.execute(&sql, [cache_id]).await.map_err(db_err)?;
 
-    Ok(found)
+    Ok(affected)
 }
 
 // ============================================================================
@@ -1071,6 +2995,9 @@ pub async fn update_cache(
     priority: Option<i32>,
     upstream_cache_key_names: Option<&str>,
     retention_period: Option<Option<i32>>,
+    storage_quota: Option<Option<i64>>,
+    upload_trusted_keys: Option<&str>,
+    require_trusted_signature: Option<bool>,
 ) -> ServerResult<u64> {
     let mut updates = Vec::new();
 
@@ -1098,6 +3025,21 @@ pub async fn update_cache(
             None => updates.push("retention_period = NULL".to_string()),
         }
     }
+    if let Some(sq) = storage_quota {
+        match sq {
+            Some(quota) => updates.push(format!("storage_quota = {}", quota)),
+            None => updates.push("storage_quota = NULL".to_string()),
+        }
+    }
+    if let Some(k) = upload_trusted_keys {
+        updates.push(format!("upload_trusted_keys = '{}'", k.replace('\'', "''")));
+    }
+    if let Some(r) = require_trusted_signature {
+        updates.push(format!(
+            "require_trusted_signature = {}",
+            if r { 1 } else { 0 }
+        ));
+    }
 
     if updates.is_empty() {
         return Ok(0);
@@ -1568,7 +3510,8 @@ pub async fn list_all_caches(conn: &TursoConnection) -> ServerResult<Vec<CacheMo
     let sql = r#"
         SELECT id, name, keypair, is_public, store_dir, priority,
                upstream_cache_key_names, created_at, deleted_at, retention_period,
-               created_by_user_id
+               created_by_user_id, storage_quota, upload_trusted_keys,
+               require_trusted_signature
         FROM cache
         WHERE deleted_at IS NULL
         ORDER BY name ASC
@@ -1613,6 +3556,8 @@ mod tests {
             auth_token: None,
             local_replica_path: None,
             sync_interval: Duration::from_secs(60),
+            pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
         };
 
         let conn = TursoConnection::connect(config)
@@ -2021,16 +3966,20 @@ mod tests {
             .await
             .expect("Create cache failed");
 
-        // Update multiple fields (keypair, is_public, store_dir, priority, upstream, retention)
+        // Update multiple fields (keypair, is_public, store_dir, priority, upstream, retention, quota,
+        // upload signature policy)
         let affected = update_cache(
             &conn,
             cache.id,
-            None,                 // keypair
-            Some(false),          // is_public
-            Some("/other/store"), // store_dir
-            Some(50),             // priority
-            None,                 // upstream_cache_key_names
-            Some(Some(86400)),    // retention_period
+            None,                       // keypair
+            Some(false),                // is_public
+            Some("/other/store"),       // store_dir
+            Some(50),                   // priority
+            None,                       // upstream_cache_key_names
+            Some(Some(86400)),          // retention_period
+            Some(Some(1 << 30)),        // storage_quota
+            Some(r#"["hydra-1:key"]"#), // upload_trusted_keys
+            Some(true),                 // require_trusted_signature
         )
         .await
         .expect("Update failed");
@@ -2045,6 +3994,9 @@ mod tests {
         assert_eq!(updated.store_dir, "/other/store");
         assert_eq!(updated.priority, 50);
         assert_eq!(updated.retention_period, Some(86400));
+        assert_eq!(updated.storage_quota, Some(1 << 30));
+        assert_eq!(updated.upload_trusted_keys.0, vec!["hydra-1:key"]);
+        assert!(updated.require_trusted_signature);
     }
 
     #[tokio::test]
@@ -2056,9 +4008,11 @@ mod tests {
             .expect("Create cache failed");
 
         // Update with no fields
-        let affected = update_cache(&conn, cache.id, None, None, None, None, None, None)
-            .await
-            .expect("Update failed");
+        let affected = update_cache(
+            &conn, cache.id, None, None, None, None, None, None, None, None, None,
+        )
+        .await
+        .expect("Update failed");
 
         assert_eq!(affected, 0);
     }
@@ -2089,14 +4043,29 @@ mod tests {
             None,
             None,
             Some(Some(3600)),
+            None,
+            None,
+            None,
         )
         .await
         .expect("Set retention failed");
 
         // Clear it
-        update_cache(&conn, cache.id, None, None, None, None, None, Some(None))
-            .await
-            .expect("Clear retention failed");
+        update_cache(
+            &conn,
+            cache.id,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(None),
+            None,
+            None,
+            None,
+        )
+        .await
+        .expect("Clear retention failed");
 
         let cache_name = "retention-test".parse().expect("Invalid cache name");
         let updated = find_cache(&conn, &cache_name).await.expect("Find failed");
@@ -2328,7 +4297,7 @@ mod tests {
 
         // Update chunk to valid
         let updated_chunk = update_chunk(
-            &conn,
+            txn.connection(),
             chunk.id,
             Some(ChunkState::Valid),
             Some("sha256:filehash"),
@@ -2341,13 +4310,20 @@ mod tests {
         assert_eq!(updated_chunk.state, ChunkState::Valid);
 
         // Create NAR
-        let nar = insert_nar(&conn, NarState::Valid, "sha256:uploadnar", 1024, "zstd", 1)
-            .await
-            .expect("Insert NAR failed");
+        let nar = insert_nar(
+            txn.connection(),
+            NarState::Valid,
+            "sha256:uploadnar",
+            1024,
+            "zstd",
+            1,
+        )
+        .await
+        .expect("Insert NAR failed");
 
         // Create chunkref
         insert_chunkref(
-            &conn,
+            txn.connection(),
             nar.id,
             0,
             Some(chunk.id),
@@ -2359,7 +4335,7 @@ mod tests {
 
         // Create object - this is where the references bug would manifest
         let object_result = insert_object_upsert(
-            &conn,
+            txn.connection(),
             cache.id,
             nar.id,
             "uploadhash789",
@@ -51,18 +51,27 @@ pub enum ErrorKind {
     /// The requested object does not exist.
     NoSuchObject,
 
+    /// The requested pin does not exist.
+    NoSuchPin,
+
     /// Invalid compression type "{name}".
     InvalidCompressionType { name: String },
 
     /// The requested NAR has missing chunks and needs to be repaired.
     IncompleteNar,
 
+    /// This server is a read-only substituter. Push to the primary server instead.
+    ReadOnly,
+
     /// Database error: {0:#}
     DatabaseError(AnyError),
 
     /// Storage error: {0:#}
     StorageError(AnyError),
 
+    /// Signing error: {0:#}
+    SigningError(AnyError),
+
     /// Manifest serialization error: {0}
     ManifestSerializationError(super::nix_manifest::Error),
 
@@ -96,6 +105,10 @@ impl ServerError {
         ErrorKind::RequestError(AnyError::new(error)).into()
     }
 
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
     pub fn set_discovery_permission(&mut self, perm: bool) {
         self.discovery_permission = perm;
     }
@@ -140,6 +153,7 @@ impl IntoResponse for ServerError {
             self.kind,
             ErrorKind::DatabaseError(_)
                 | ErrorKind::StorageError(_)
+                | ErrorKind::SigningError(_)
                 | ErrorKind::ManifestSerializationError(_)
                 | ErrorKind::AtticError(_)
         ) {
@@ -174,13 +188,16 @@ impl ErrorKind {
             Self::InternalServerError => "InternalServerError",
 
             Self::NoSuchObject => "NoSuchObject",
+            Self::NoSuchPin => "NoSuchPin",
             Self::NoSuchCache => "NoSuchCache",
             Self::CacheAlreadyExists => "CacheAlreadyExists",
             Self::InvalidCompressionType { .. } => "InvalidCompressionType",
             Self::IncompleteNar => "IncompleteNar",
+            Self::ReadOnly => "ReadOnly",
             Self::AtticError(e) => e.name(),
             Self::DatabaseError(_) => "DatabaseError",
             Self::StorageError(_) => "StorageError",
+            Self::SigningError(_) => "SigningError",
             Self::ManifestSerializationError(_) => "ManifestSerializationError",
             Self::AccessError(_) => "AccessError",
             Self::RequestError(_) => "RequestError",
@@ -193,6 +210,7 @@ impl ErrorKind {
         match self {
             Self::NoSuchCache => Self::Unauthorized,
             Self::NoSuchObject => Self::Unauthorized,
+            Self::NoSuchPin => Self::Unauthorized,
             Self::AccessError(_) => Self::Unauthorized,
 
             _ => self,
@@ -206,6 +224,7 @@ impl ErrorKind {
 
             Self::DatabaseError(_) => Self::InternalServerError,
             Self::StorageError(_) => Self::InternalServerError,
+            Self::SigningError(_) => Self::InternalServerError,
             Self::ManifestSerializationError(_) => Self::InternalServerError,
 
             _ => self,
@@ -221,8 +240,10 @@ impl ErrorKind {
             Self::AccessError(_) => StatusCode::FORBIDDEN,
             Self::NoSuchCache => StatusCode::NOT_FOUND,
             Self::NoSuchObject => StatusCode::NOT_FOUND,
+            Self::NoSuchPin => StatusCode::NOT_FOUND,
             Self::CacheAlreadyExists => StatusCode::BAD_REQUEST,
             Self::IncompleteNar => StatusCode::SERVICE_UNAVAILABLE,
+            Self::ReadOnly => StatusCode::METHOD_NOT_ALLOWED,
             Self::ManifestSerializationError(_) => StatusCode::BAD_REQUEST,
             Self::RequestError(_) => StatusCode::BAD_REQUEST,
             Self::InvalidCompressionType { .. } => StatusCode::BAD_REQUEST,
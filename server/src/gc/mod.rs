@@ -0,0 +1,959 @@
+//! Garbage collection.
+
+mod report;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures::future::join_all;
+use tokio::sync::Semaphore;
+use tokio::time;
+use tracing::instrument;
+
+use super::{State, StateInner};
+use crate::config::Config;
+use crate::database::models::PinModel;
+use crate::database::queries::{self, DeletionBacklog, GcObject, ObjectUsage};
+
+pub use report::{format_bytes, CacheGcReport, GcReport};
+
+/// The number of IDs to query or delete at once.
+const BATCH_SIZE: usize = 500;
+
+/// Objects selected for deletion, by object ID.
+type Selection = HashMap<i64, Selected>;
+
+/// An object selected for deletion.
+#[derive(Debug, Clone, Copy)]
+struct Selected {
+    /// The index of the cache in the report.
+    cache_index: usize,
+
+    /// The NAR the object referenced when it was selected.
+    nar_id: i64,
+
+    /// The object is only deleted if it wasn't created or accessed since.
+    ///
+    /// Objects can be downloaded or pushed again between the selection
+    /// and the deletion, which must keep them.
+    unused_since: DateTime<Utc>,
+}
+
+/// Runs garbage collection periodically.
+pub async fn run_garbage_collection(config: Config) {
+    let interval = config.garbage_collection.interval;
+
+    if interval == Duration::ZERO {
+        // disabled
+        return;
+    }
+
+    loop {
+        // We don't stop even if it errors
+        if let Err(e) = run_garbage_collection_once(config.clone()).await {
+            tracing::warn!("Garbage collection failed: {}", e);
+        }
+
+        time::sleep(interval).await;
+    }
+}
+
+/// Runs garbage collection once.
+///
+/// The results are recorded in the database, even if garbage
+/// collection fails halfway.
+#[instrument(skip_all)]
+pub async fn run_garbage_collection_once(config: Config) -> Result<GcReport> {
+    tracing::info!("Running garbage collection...");
+
+    let state = StateInner::new(config, false).await;
+    let started_at = Utc::now();
+
+    let mut report = GcReport::new(false);
+    let result = collect_garbage(&state, &mut report).await;
+
+    let error = result.as_ref().err().map(|e| e.to_string());
+    if let Err(e) = record_gc_run(&state, started_at, &report, error.as_deref()).await {
+        tracing::warn!("Failed to record garbage collection run: {}", e);
+    }
+
+    result.map(|_| report)
+}
+
+/// Works out what garbage collection would delete without deleting anything.
+#[instrument(skip_all)]
+pub async fn plan_garbage_collection(config: Config) -> Result<GcReport> {
+    tracing::info!("Planning garbage collection...");
+
+    let state = StateInner::new(config, false).await;
+
+    let mut report = GcReport::new(true);
+    collect_garbage(&state, &mut report).await?;
+
+    Ok(report)
+}
+
+/// Walks through all phases of garbage collection.
+///
+/// Objects are first selected from all caches, then deleted together.
+/// In a dry run, the phases after the selection are only accounted for.
+async fn collect_garbage(state: &State, report: &mut GcReport) -> Result<()> {
+    let mut selection = Selection::new();
+
+    run_reap_stale_staged_nars(state, report).await?;
+    run_deduplicate_nars(state, report).await?;
+    run_time_based_garbage_collection(state, report, &mut selection).await?;
+    run_quota_based_garbage_collection(state, report, &mut selection).await?;
+    account_freed_storage(state, report, &selection).await?;
+
+    if report.dry_run {
+        report.objects = selection.len() as u64;
+        return Ok(());
+    }
+
+    report.objects = delete_objects(state, &selection).await?;
+    tracing::info!("Deleted {} objects in total", report.objects);
+
+    run_reap_orphan_nars(state, report).await?;
+    run_reap_orphan_chunks(state, report).await?;
+
+    Ok(())
+}
+
+/// Records a garbage collection run in the database.
+async fn record_gc_run(
+    state: &State,
+    started_at: DateTime<Utc>,
+    report: &GcReport,
+    error: Option<&str>,
+) -> Result<()> {
+    let db = state.database().await?;
+    let report = serde_json::to_string(report)?;
+
+    queries::insert_gc_run(db, &started_at, &Utc::now(), error, &report).await?;
+
+    Ok(())
+}
+
+#[instrument(skip_all)]
+async fn run_reap_stale_staged_nars(state: &State, report: &mut GcReport) -> Result<()> {
+    let db = state.database().await?;
+    let storage = state.storage().await?;
+
+    let staged_nar_expiry = state.config.garbage_collection.staged_nar_expiry;
+    let cutoff = Utc::now()
+        .checked_sub_signed(ChronoDuration::from_std(staged_nar_expiry)?)
+        .ok_or_else(|| anyhow!("Somehow subtracting staged NAR expiry underflowed"))?;
+
+    let staged_nars = queries::find_staged_nars_before(db, &cutoff.to_rfc3339()).await?;
+
+    if staged_nars.is_empty() {
+        tracing::info!("No stale staged NARs found");
+        return Ok(());
+    }
+
+    if report.dry_run {
+        report.staged_nars = staged_nars.len() as u64;
+        return Ok(());
+    }
+
+    let mut deleted = 0;
+    for staged_nar in staged_nars {
+        // Leave the row so that the deletion is retried next time
+        if let Err(e) = storage.delete_file_db(&staged_nar.remote_file.0).await {
+            tracing::warn!("Deletion failed: {}", e);
+            continue;
+        }
+
+        queries::delete_staged_nar(db, staged_nar.id).await?;
+        deleted += 1;
+    }
+
+    tracing::info!("Deleted {} stale staged NARs", deleted);
+    report.staged_nars = deleted;
+
+    Ok(())
+}
+
+/// Merges valid NARs that have the same hash.
+///
+/// Clients racing to upload the same NAR each create their own `nar`
+/// row. The objects of the duplicates are moved to the NAR that is
+/// kept, and the duplicates are then reaped as orphans along with the
+/// chunks that only they used. Duplicates held by uploads in progress
+/// are left for a later run.
+#[instrument(skip_all)]
+async fn run_deduplicate_nars(state: &State, report: &mut GcReport) -> Result<()> {
+    let db = state.database().await?;
+
+    // (NAR to keep, its duplicates)
+    let mut groups: Vec<(i64, Vec<i64>)> = Vec::new();
+    let mut last_hash = None;
+    for nar in queries::find_duplicate_nars(db).await? {
+        if last_hash.as_ref() != Some(&nar.nar_hash) {
+            groups.push((nar.id, Vec::new()));
+            last_hash = Some(nar.nar_hash);
+        } else if nar.holders_count == 0 {
+            groups.last_mut().unwrap().1.push(nar.id);
+        }
+    }
+    groups.retain(|(_, duplicate_ids)| !duplicate_ids.is_empty());
+
+    if groups.is_empty() {
+        tracing::info!("No duplicate NARs found");
+        return Ok(());
+    }
+
+    let duplicate_ids: Vec<i64> = groups
+        .iter()
+        .flat_map(|(_, duplicate_ids)| duplicate_ids.iter().copied())
+        .collect();
+    report.duplicate_bytes_freed = account_exclusive_chunks(state, &duplicate_ids).await?;
+
+    if report.dry_run {
+        report.duplicate_nars = duplicate_ids.len() as u64;
+        return Ok(());
+    }
+
+    let mut merged = 0;
+    let mut moved = 0;
+    for (nar_id, duplicate_ids) in groups {
+        // Hold the NAR so it can't be reaped while objects move to it
+        if !queries::increment_nar_holders(db, nar_id).await? {
+            continue;
+        }
+
+        let result = queries::merge_duplicate_nars(db, nar_id, &duplicate_ids).await;
+        queries::decrement_nar_holders(db, nar_id).await?;
+
+        moved += result?;
+        merged += duplicate_ids.len() as u64;
+    }
+
+    tracing::info!("Merged {} duplicate NARs, moving {} objects", merged, moved);
+    report.duplicate_nars = merged;
+
+    Ok(())
+}
+
+/// Works out the stored size of chunks used by the given NARs and
+/// nothing else.
+async fn account_exclusive_chunks(state: &State, nar_ids: &[i64]) -> Result<u64> {
+    let db = state.database().await?;
+
+    let mut nar_chunks = Vec::new();
+    for batch in nar_ids.chunks(BATCH_SIZE) {
+        nar_chunks.extend(queries::find_nar_chunks(db, batch).await?);
+    }
+
+    // Chunk ID -> (stored size, number of references from the NARs)
+    let mut chunks: HashMap<i64, (i64, i64)> = HashMap::new();
+    for nar_chunk in nar_chunks.iter() {
+        chunks
+            .entry(nar_chunk.chunk_id)
+            .or_insert((nar_chunk.stored_size, 0))
+            .1 += 1;
+    }
+
+    let chunk_ids: Vec<i64> = chunks.keys().copied().collect();
+    let mut references_per_chunk = HashMap::new();
+    for batch in chunk_ids.chunks(BATCH_SIZE) {
+        references_per_chunk.extend(queries::count_chunkrefs_by_chunk_ids(db, batch).await?);
+    }
+
+    let bytes = chunks
+        .iter()
+        .filter(|(chunk_id, (_, references))| {
+            references_per_chunk.get(chunk_id).copied().unwrap_or(0) <= *references
+        })
+        .map(|(_, (size, _))| *size)
+        .sum::<i64>();
+
+    Ok(bytes as u64)
+}
+
+/// Selects objects that haven't been accessed within the retention period.
+///
+/// Pinned objects are always kept. Objects in the closure of an object
+/// that is kept are kept as well, even if they haven't been accessed
+/// themselves.
+#[instrument(skip_all)]
+async fn run_time_based_garbage_collection(
+    state: &State,
+    report: &mut GcReport,
+    selection: &mut Selection,
+) -> Result<()> {
+    let db = state.database().await?;
+    let now = Utc::now();
+
+    let default_retention_period = state.config.garbage_collection.default_retention_period;
+    let default_retention_seconds = default_retention_period.as_secs() as i64;
+
+    // Find caches with retention periods set
+    let caches = queries::find_caches_with_retention(db, default_retention_seconds).await?;
+
+    tracing::info!(
+        "Found {} caches subject to time-based garbage collection",
+        caches.len()
+    );
+
+    for cache in caches {
+        let period = ChronoDuration::seconds(cache.retention_period.into());
+        let cutoff = now.checked_sub_signed(period).ok_or_else(|| {
+            anyhow!(
+                "Somehow subtracting retention period for cache {} underflowed",
+                cache.name
+            )
+        })?;
+
+        let objects = queries::find_gc_objects_by_cache(db, cache.id).await?;
+        let pins = queries::find_pins_by_cache(db, cache.id).await?;
+
+        let mut expired = Vec::new();
+        let mut kept = 0;
+        for candidate in order_for_collection(&objects, &pins) {
+            if candidate.effective_accessed_at < cutoff {
+                expired.push(candidate.object);
+                continue;
+            }
+
+            if accessed_at(candidate.object) >= cutoff {
+                continue;
+            }
+
+            match (candidate.pin, candidate.kept_alive_by) {
+                (Some(pin), None) => {
+                    tracing::info!(
+                        "Keeping {} in {}: pinned as {}",
+                        candidate.object.store_path,
+                        cache.name,
+                        pin
+                    );
+                }
+                (Some(pin), Some(referrer)) => {
+                    tracing::info!(
+                        "Keeping {} in {}: in the closure of {}, pinned as {}",
+                        candidate.object.store_path,
+                        cache.name,
+                        referrer.store_path,
+                        pin
+                    );
+                }
+                (None, Some(referrer)) => {
+                    tracing::info!(
+                        "Keeping {} in {}: in the closure of {}, last accessed at {}",
+                        candidate.object.store_path,
+                        cache.name,
+                        referrer.store_path,
+                        candidate.effective_accessed_at.to_rfc3339()
+                    );
+                }
+                (None, None) => continue,
+            }
+
+            kept += 1;
+        }
+
+        tracing::info!(
+            "{} objects in {} (ID {}) expired, kept {} expired objects that are pinned or referenced by retained objects",
+            expired.len(),
+            cache.name,
+            cache.id,
+            kept
+        );
+
+        if expired.is_empty() && kept == 0 {
+            continue;
+        }
+
+        let index = report.cache_index(&cache.name);
+        report.caches[index].expired += expired.len() as u64;
+        report.caches[index].kept += kept;
+
+        for object in expired {
+            selection.insert(
+                object.id,
+                Selected {
+                    cache_index: index,
+                    nar_id: object.nar_id,
+                    unused_since: cutoff,
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Selects the least recently accessed objects from caches over quota.
+///
+/// The size attributable to a cache is the stored size of each NAR
+/// it references, split evenly between all caches referencing the
+/// same NAR. A NAR is only counted once per cache no matter how many
+/// objects in the cache point to it, and its share is only freed once
+/// the last of them is evicted. Objects already selected by time-based
+/// garbage collection don't count towards the quota.
+///
+/// Like time-based garbage collection, an object is never evicted
+/// while an object referencing it is kept, and pinned objects are
+/// never evicted. Objects accessed after they were selected are kept.
+#[instrument(skip_all)]
+async fn run_quota_based_garbage_collection(
+    state: &State,
+    report: &mut GcReport,
+    selection: &mut Selection,
+) -> Result<()> {
+    let db = state.database().await?;
+
+    let default_storage_quota = state.config.garbage_collection.default_storage_quota;
+    let default_storage_quota = i64::try_from(default_storage_quota)
+        .map_err(|_| anyhow!("The default storage quota is too large"))?;
+
+    let caches = queries::find_caches_with_quota(db, default_storage_quota).await?;
+
+    tracing::info!(
+        "Found {} caches subject to quota-based garbage collection",
+        caches.len()
+    );
+
+    for cache in caches {
+        let usage: HashMap<i64, ObjectUsage> = queries::find_object_usage_by_cache(db, cache.id)
+            .await?
+            .into_iter()
+            .filter(|object| !selection.contains_key(&object.id))
+            .map(|object| (object.id, object))
+            .collect();
+
+        // NAR ID -> (attributable size, number of objects in the cache)
+        let mut nars: HashMap<i64, (i64, usize)> = HashMap::new();
+        for object in usage.values() {
+            let share = object.nar_stored_size / object.nar_cache_count.max(1);
+            nars.entry(object.nar_id).or_insert((share, 0)).1 += 1;
+        }
+
+        let mut total: i64 = nars.values().map(|(share, _)| share).sum();
+        if total <= cache.storage_quota {
+            continue;
+        }
+
+        tracing::info!(
+            "{} (ID {}) uses {} bytes, exceeding its quota of {} bytes",
+            cache.name,
+            cache.id,
+            total,
+            cache.storage_quota
+        );
+
+        let objects = queries::find_gc_objects_by_cache(db, cache.id).await?;
+        let pins = queries::find_pins_by_cache(db, cache.id).await?;
+
+        let mut evicted = Vec::new();
+        for candidate in order_for_collection(&objects, &pins) {
+            if total <= cache.storage_quota {
+                break;
+            }
+
+            // Everything from here on is pinned
+            if candidate.pin.is_some() {
+                tracing::warn!(
+                    "{} (ID {}) is still over its quota, but the remaining objects are pinned",
+                    cache.name,
+                    cache.id
+                );
+                break;
+            }
+
+            // Already expired, or uploaded since we looked at the usage
+            let Some(object) = usage.get(&candidate.object.id) else {
+                continue;
+            };
+
+            evicted.push((object, accessed_at(candidate.object)));
+
+            let (share, count) = nars.get_mut(&object.nar_id).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                total -= *share;
+            }
+        }
+
+        tracing::info!(
+            "Evicting {} objects from {} (ID {})",
+            evicted.len(),
+            cache.name,
+            cache.id
+        );
+
+        if evicted.is_empty() {
+            continue;
+        }
+
+        let index = report.cache_index(&cache.name);
+        report.caches[index].evicted += evicted.len() as u64;
+
+        for (object, accessed_at) in evicted {
+            selection.insert(
+                object.id,
+                Selected {
+                    cache_index: index,
+                    nar_id: object.nar_id,
+                    // Only evicted if it wasn't accessed after we looked
+                    unused_since: accessed_at + ChronoDuration::nanoseconds(1),
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Works out the storage freed by deleting the selected objects.
+///
+/// A NAR becomes orphaned once all objects referencing it are deleted,
+/// and a chunk once all NARs referencing it are. The per-cache figures
+/// are filled in for all runs, while the totals are only filled in for
+/// dry runs since real runs count what they actually delete.
+#[instrument(skip_all)]
+async fn account_freed_storage(
+    state: &State,
+    report: &mut GcReport,
+    selection: &Selection,
+) -> Result<()> {
+    let db = state.database().await?;
+
+    // NAR ID -> number of selected objects referencing it
+    let mut selected_per_nar: HashMap<i64, i64> = HashMap::new();
+    for selected in selection.values() {
+        *selected_per_nar.entry(selected.nar_id).or_default() += 1;
+    }
+
+    let selected_nar_ids: Vec<i64> = selected_per_nar.keys().copied().collect();
+    let mut objects_per_nar = HashMap::new();
+    for batch in selected_nar_ids.chunks(BATCH_SIZE) {
+        objects_per_nar.extend(queries::count_objects_by_nar_ids(db, batch).await?);
+    }
+
+    let mut orphan_nars: HashSet<i64> = selected_per_nar
+        .iter()
+        .filter(|(nar_id, selected)| {
+            objects_per_nar.get(nar_id).copied().unwrap_or(0) <= **selected
+        })
+        .map(|(&nar_id, _)| nar_id)
+        .collect();
+    orphan_nars.extend(queries::find_orphan_nar_ids(db).await?);
+
+    let affected_nar_ids: Vec<i64> = orphan_nars
+        .iter()
+        .chain(selected_nar_ids.iter())
+        .copied()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    let mut nar_chunks = Vec::new();
+    for batch in affected_nar_ids.chunks(BATCH_SIZE) {
+        nar_chunks.extend(queries::find_nar_chunks(db, batch).await?);
+    }
+
+    // Chunk ID -> (stored size, number of references from orphan NARs)
+    let mut chunks: HashMap<i64, (i64, i64)> = HashMap::new();
+    // NAR ID -> chunk IDs
+    let mut chunks_per_nar: HashMap<i64, Vec<i64>> = HashMap::new();
+    for nar_chunk in nar_chunks.iter() {
+        let entry = chunks
+            .entry(nar_chunk.chunk_id)
+            .or_insert((nar_chunk.stored_size, 0));
+        if orphan_nars.contains(&nar_chunk.nar_id) {
+            entry.1 += 1;
+        }
+
+        chunks_per_nar
+            .entry(nar_chunk.nar_id)
+            .or_default()
+            .push(nar_chunk.chunk_id);
+    }
+
+    let chunk_ids: Vec<i64> = chunks.keys().copied().collect();
+    let mut references_per_chunk = HashMap::new();
+    for batch in chunk_ids.chunks(BATCH_SIZE) {
+        references_per_chunk.extend(queries::count_chunkrefs_by_chunk_ids(db, batch).await?);
+    }
+
+    let freed_chunks: HashSet<i64> = chunks
+        .iter()
+        .filter(|(chunk_id, (_, orphan_references))| {
+            references_per_chunk.get(chunk_id).copied().unwrap_or(0) <= *orphan_references
+        })
+        .map(|(&chunk_id, _)| chunk_id)
+        .collect();
+
+    // Each chunk is only counted once per cache
+    let mut chunks_per_cache: Vec<HashSet<i64>> = vec![HashSet::new(); report.caches.len()];
+    for selected in selection.values() {
+        if let Some(chunk_ids) = chunks_per_nar.get(&selected.nar_id) {
+            chunks_per_cache[selected.cache_index].extend(chunk_ids);
+        }
+    }
+
+    for (cache, chunk_ids) in report.caches.iter_mut().zip(chunks_per_cache) {
+        for chunk_id in chunk_ids {
+            let size = chunks[&chunk_id].0 as u64;
+            if freed_chunks.contains(&chunk_id) {
+                cache.bytes_freed += size;
+            } else {
+                cache.chunks_shared += 1;
+                cache.bytes_shared += size;
+            }
+        }
+    }
+
+    if !report.dry_run {
+        return Ok(());
+    }
+
+    // Chunks that were orphaned before this run
+    let orphan_chunk_ids = queries::find_orphan_chunk_ids(db).await?;
+    let mut orphan_chunk_sizes = HashMap::new();
+    for batch in orphan_chunk_ids.chunks(BATCH_SIZE) {
+        orphan_chunk_sizes.extend(queries::find_chunk_stored_sizes(db, batch).await?);
+    }
+
+    report.nars = orphan_nars.len() as u64;
+    report.chunks = (freed_chunks.len() + orphan_chunk_sizes.len()) as u64;
+    report.bytes_freed = freed_chunks
+        .iter()
+        .map(|chunk_id| chunks[chunk_id].0)
+        .chain(orphan_chunk_sizes.into_values())
+        .sum::<i64>() as u64;
+
+    Ok(())
+}
+
+/// An object in the order it becomes eligible for garbage collection.
+struct CollectionCandidate<'a> {
+    object: &'a GcObject,
+
+    /// The latest access of any object whose closure includes this one.
+    effective_accessed_at: DateTime<Utc>,
+
+    /// The object that was accessed at `effective_accessed_at`, if not
+    /// this one.
+    kept_alive_by: Option<&'a GcObject>,
+
+    /// The pin keeping the object alive, either directly or through
+    /// `kept_alive_by`. Pinned objects are never collected.
+    pin: Option<&'a str>,
+}
+
+/// Orders the objects of a cache for closure-aware garbage collection.
+///
+/// Accessing an object counts as accessing its whole closure within
+/// the cache, and pinned objects count as accessed forever. Objects are
+/// ordered by their effective access time, with referrers before the
+/// objects they reference. Collecting any prefix of the order thus never
+/// breaks the closure of an object that is kept.
+fn order_for_collection<'a>(
+    objects: &'a [GcObject],
+    pins: &'a [PinModel],
+) -> Vec<CollectionCandidate<'a>> {
+    let index: HashMap<&str, usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| (object.store_path_hash.as_str(), i))
+        .collect();
+
+    // References are store path base names starting with the hash
+    let references: Vec<Vec<usize>> = objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| {
+            object
+                .references
+                .iter()
+                .filter_map(|reference| reference.get(..32))
+                .filter_map(|hash| index.get(hash).copied())
+                .filter(|&j| j != i)
+                .collect()
+        })
+        .collect();
+
+    let mut num_referrers = vec![0usize; objects.len()];
+    for &j in references.iter().flatten() {
+        num_referrers[j] += 1;
+    }
+
+    let mut effective: Vec<DateTime<Utc>> = objects.iter().map(accessed_at).collect();
+    let mut source: Vec<usize> = (0..objects.len()).collect();
+
+    let mut pinned_as: HashMap<usize, &str> = HashMap::new();
+    for pin in pins {
+        if let Some(&i) = index.get(pin.store_path_hash.as_str()) {
+            effective[i] = DateTime::<Utc>::MAX_UTC;
+            pinned_as.entry(i).or_insert(pin.name.as_str());
+        }
+    }
+
+    // Visit objects in topological order, propagating access times
+    // from referrers to the objects they reference
+    let mut queue: VecDeque<usize> = (0..objects.len())
+        .filter(|&i| num_referrers[i] == 0)
+        .collect();
+    let mut order = Vec::with_capacity(objects.len());
+
+    while let Some(i) = queue.pop_front() {
+        order.push(i);
+
+        for &j in references[i].iter() {
+            if effective[i] > effective[j] {
+                effective[j] = effective[i];
+                source[j] = source[i];
+            }
+
+            num_referrers[j] -= 1;
+            if num_referrers[j] == 0 {
+                queue.push_back(j);
+            }
+        }
+    }
+
+    // Nix doesn't produce reference cycles, but don't lose objects if
+    // the database has them somehow
+    order.extend((0..objects.len()).filter(|&i| num_referrers[i] != 0));
+
+    // Stable, so referrers stay ahead of objects with the same time
+    order.sort_by_key(|&i| effective[i]);
+
+    order
+        .into_iter()
+        .map(|i| CollectionCandidate {
+            object: &objects[i],
+            effective_accessed_at: effective[i],
+            kept_alive_by: (source[i] != i).then(|| &objects[source[i]]),
+            pin: pinned_as.get(&source[i]).copied(),
+        })
+        .collect()
+}
+
+/// Returns when an object was last accessed or created.
+fn accessed_at(object: &GcObject) -> DateTime<Utc> {
+    object
+        .last_accessed_at
+        .map_or(object.created_at, |t| t.max(object.created_at))
+}
+
+/// Deletes the selected objects.
+///
+/// Objects that were used or pinned since they were selected are kept.
+async fn delete_objects(state: &State, selection: &Selection) -> Result<u64> {
+    let db = state.database().await?;
+
+    let objects: Vec<(i64, DateTime<Utc>)> = selection
+        .iter()
+        .map(|(&object_id, selected)| (object_id, selected.unused_since))
+        .collect();
+
+    let mut deleted = 0;
+    for batch in objects.chunks(BATCH_SIZE) {
+        deleted += queries::delete_unused_objects(db, batch).await?;
+    }
+
+    let kept = selection.len() as u64 - deleted;
+    if kept > 0 {
+        tracing::info!(
+            "Kept {} objects used or pinned since they were selected",
+            kept
+        );
+    }
+
+    Ok(deleted)
+}
+
+#[instrument(skip_all)]
+async fn run_reap_orphan_nars(state: &State, report: &mut GcReport) -> Result<()> {
+    let db = state.database().await?;
+
+    // Find all orphan NARs
+    let orphan_nar_ids = queries::find_orphan_nar_ids(db).await?;
+
+    if orphan_nar_ids.is_empty() {
+        tracing::info!("No orphan NARs found");
+        return Ok(());
+    }
+
+    // Delete them
+    let deleted = queries::delete_nars_by_ids(db, &orphan_nar_ids).await?;
+
+    tracing::info!("Deleted {} orphan NARs", deleted);
+    report.nars = deleted;
+
+    Ok(())
+}
+
+#[instrument(skip_all)]
+async fn run_reap_orphan_chunks(state: &State, report: &mut GcReport) -> Result<()> {
+    let db = state.database().await?;
+
+    // Find all orphan chunks
+    let orphan_chunk_ids = queries::find_orphan_chunk_ids(db).await?;
+
+    if orphan_chunk_ids.is_empty() {
+        tracing::info!("No orphan chunks found");
+    } else {
+        // Transition their state to Deleted
+        let transitioned = queries::transition_chunks_to_deleted(db, &orphan_chunk_ids).await?;
+        tracing::debug!("Transitioned {} chunks to Deleted state", transitioned);
+    }
+
+    // Delete chunks in Deleted state, including earlier failures that
+    // are due for a retry
+    let deletions = delete_chunks(state, false).await?;
+
+    tracing::info!(
+        "Deleted {} orphan chunks, {} deletions failed",
+        deletions.deleted,
+        deletions.failed
+    );
+    report.chunks = deletions.deleted;
+    report.bytes_freed = deletions.bytes_freed;
+    report.chunk_deletions_failed = deletions.failed;
+
+    Ok(())
+}
+
+/// The outcome of deleting chunks in Deleted state.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkDeletions {
+    /// Chunks deleted from the storage and the database.
+    pub deleted: u64,
+
+    /// The stored size of the deleted chunks.
+    pub bytes_freed: u64,
+
+    /// Chunks that failed to be deleted from the storage.
+    pub failed: u64,
+}
+
+/// Returns the chunks waiting to be deleted from the storage.
+pub async fn find_deletion_backlog(config: Config) -> Result<DeletionBacklog> {
+    let state = StateInner::new(config, false).await;
+    let db = state.database().await?;
+
+    Ok(queries::find_deletion_backlog(db, &Utc::now()).await?)
+}
+
+/// Retries deleting all chunks in Deleted state once, ignoring backoffs.
+#[instrument(skip_all)]
+pub async fn retry_chunk_deletions(config: Config) -> Result<ChunkDeletions> {
+    let state = StateInner::new(config, false).await;
+
+    delete_chunks(&state, true).await
+}
+
+/// Deletes chunks in Deleted state from the storage, then the database.
+///
+/// Each chunk is attempted at most once. Chunks that fail to be deleted
+/// from the storage stay in Deleted state and are retried by a later
+/// run once their backoff has passed, unless `ignore_backoff` is set.
+async fn delete_chunks(state: &State, ignore_backoff: bool) -> Result<ChunkDeletions> {
+    let db = state.database().await?;
+    let storage = state.storage().await?;
+    let config = &state.config.garbage_collection;
+
+    let now = Utc::now();
+    let due_at = (!ignore_backoff).then_some(now);
+    let batch_size = config.deletion_batch_size.max(1);
+    let delete_limit = Arc::new(Semaphore::new(config.deletion_concurrency.max(1)));
+
+    let mut deletions = ChunkDeletions::default();
+    let mut after_id = 0;
+    loop {
+        let chunks =
+            queries::find_deleted_chunks(db, after_id, due_at.as_ref(), batch_size as u64).await?;
+
+        let Some(last) = chunks.last() else {
+            break;
+        };
+        after_id = last.chunk.id;
+
+        // Delete the chunks from remote storage
+        let futures: Vec<_> = chunks
+            .into_iter()
+            .map(|deleted_chunk| {
+                let delete_limit = delete_limit.clone();
+                let storage = storage.clone();
+                async move {
+                    let permit = delete_limit.acquire().await;
+                    let result = storage
+                        .delete_file_db(&deleted_chunk.chunk.remote_file.0)
+                        .await;
+                    drop(permit);
+                    (deleted_chunk, result)
+                }
+            })
+            .collect();
+
+        // Deletions can result in spurious failures, tolerate them
+        let mut deleted_chunk_ids = Vec::new();
+        for (deleted_chunk, result) in join_all(futures).await {
+            let chunk = &deleted_chunk.chunk;
+
+            if let Err(e) = result {
+                tracing::warn!("Deletion of chunk {} failed: {}", chunk.id, e);
+
+                let backoff = deletion_backoff(
+                    config.deletion_retry_backoff,
+                    config.max_deletion_retry_backoff,
+                    deleted_chunk.deletion_attempts,
+                );
+                let next_attempt_at = now + ChronoDuration::from_std(backoff)?;
+                queries::record_chunk_deletion_failure(
+                    db,
+                    chunk.id,
+                    &e.to_string(),
+                    &next_attempt_at,
+                )
+                .await?;
+
+                deletions.failed += 1;
+                continue;
+            }
+
+            deleted_chunk_ids.push(chunk.id);
+            deletions.bytes_freed += chunk.file_size.unwrap_or(chunk.chunk_size) as u64;
+        }
+
+        // Finally, delete them from the database
+        deletions.deleted += queries::delete_chunks_by_ids(db, &deleted_chunk_ids).await?;
+    }
+
+    Ok(deletions)
+}
+
+/// Returns how long to wait before retrying a failed chunk deletion.
+///
+/// The delay doubles with each earlier failed attempt.
+fn deletion_backoff(base: Duration, max: Duration, attempts: i64) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.clamp(0, 32) as u32);
+
+    base.checked_mul(factor).unwrap_or(max).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deletion_backoff() {
+        let base = Duration::from_secs(60);
+        let max = Duration::from_secs(24 * 60 * 60);
+
+        assert_eq!(base, deletion_backoff(base, max, 0));
+        assert_eq!(Duration::from_secs(120), deletion_backoff(base, max, 1));
+        assert_eq!(Duration::from_secs(480), deletion_backoff(base, max, 3));
+        assert_eq!(max, deletion_backoff(base, max, 20));
+        assert_eq!(max, deletion_backoff(base, max, i64::MAX));
+    }
+}
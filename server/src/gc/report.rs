@@ -0,0 +1,229 @@
+//! Garbage collection reports.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// What a garbage collection run deleted, or would delete in a dry run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcReport {
+    /// Whether nothing was actually deleted.
+    pub dry_run: bool,
+
+    /// Caches that had objects selected for deletion.
+    pub caches: Vec<CacheGcReport>,
+
+    /// Stale NARs uploaded through the Nix binary cache protocol.
+    pub staged_nars: u64,
+
+    /// Objects deleted from all caches.
+    pub objects: u64,
+
+    /// NARs no longer referenced by any object.
+    pub nars: u64,
+
+    /// Chunks no longer referenced by any NAR.
+    pub chunks: u64,
+
+    /// Storage freed by deleting the chunks.
+    pub bytes_freed: u64,
+
+    /// Chunks that failed to be deleted from the storage.
+    ///
+    /// They are retried by later runs.
+    #[serde(default)]
+    pub chunk_deletions_failed: u64,
+
+    /// NARs merged into another NAR with the same hash.
+    #[serde(default)]
+    pub duplicate_nars: u64,
+
+    /// The stored size of chunks only used by duplicate NARs.
+    ///
+    /// They are deleted along with the other orphan chunks.
+    #[serde(default)]
+    pub duplicate_bytes_freed: u64,
+}
+
+/// What garbage collection deleted from a single cache.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheGcReport {
+    /// The name of the cache.
+    pub name: String,
+
+    /// Objects deleted because they fell out of the retention period.
+    pub expired: u64,
+
+    /// Objects deleted to bring the cache under its storage quota.
+    pub evicted: u64,
+
+    /// Expired objects kept because they are pinned or referenced by
+    /// retained objects.
+    pub kept: u64,
+
+    /// Storage freed by deleting objects from this cache.
+    ///
+    /// Chunks freed by deletions in several caches count towards each
+    /// of them.
+    pub bytes_freed: u64,
+
+    /// Chunks of deleted objects that are still used by objects that
+    /// are kept, usually in other caches.
+    pub chunks_shared: u64,
+
+    /// The stored size of `chunks_shared`.
+    pub bytes_shared: u64,
+}
+
+impl GcReport {
+    /// Creates an empty report.
+    pub fn new(dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the index of the report for a cache, adding it if needed.
+    pub(super) fn cache_index(&mut self, name: &str) -> usize {
+        if let Some(i) = self.caches.iter().position(|cache| cache.name == name) {
+            return i;
+        }
+
+        self.caches.push(CacheGcReport {
+            name: name.to_string(),
+            ..Default::default()
+        });
+        self.caches.len() - 1
+    }
+}
+
+impl fmt::Display for GcReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.dry_run {
+            writeln!(f, "Garbage collection plan (dry run, nothing was deleted)")?;
+        } else {
+            writeln!(f, "Garbage collection report")?;
+        }
+        writeln!(f)?;
+
+        if self.caches.is_empty() {
+            writeln!(f, "No objects to delete in any cache")?;
+        } else {
+            let header = ["CACHE", "EXPIRED", "EVICTED", "KEPT", "FREED", "SHARED", ""];
+            let rows: Vec<[String; 7]> = self
+                .caches
+                .iter()
+                .map(|cache| {
+                    [
+                        cache.name.clone(),
+                        cache.expired.to_string(),
+                        cache.evicted.to_string(),
+                        cache.kept.to_string(),
+                        format_bytes(cache.bytes_freed),
+                        format_bytes(cache.bytes_shared),
+                        format!("({} chunks)", cache.chunks_shared),
+                    ]
+                })
+                .collect();
+
+            let mut widths = header.map(str::len);
+            for row in rows.iter() {
+                for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                    *width = (*width).max(cell.len());
+                }
+            }
+
+            let header = header.map(str::to_string);
+            for row in std::iter::once(&header).chain(rows.iter()) {
+                let mut line = format!("{:<width$}", row[0], width = widths[0]);
+                for (cell, width) in row.iter().zip(widths.iter()).skip(1) {
+                    line.push_str(&format!("  {:>width$}", cell, width = width));
+                }
+                writeln!(f, "{}", line.trim_end())?;
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "  Stale staged NARs: {}", self.staged_nars)?;
+        writeln!(f, "            Objects: {}", self.objects)?;
+        writeln!(f, "        Orphan NARs: {}", self.nars)?;
+        writeln!(f, "      Orphan chunks: {}", self.chunks)?;
+        writeln!(f, "      Storage freed: {}", format_bytes(self.bytes_freed))?;
+
+        if self.duplicate_nars != 0 {
+            writeln!(
+                f,
+                "     Duplicate NARs: {} ({})",
+                self.duplicate_nars,
+                format_bytes(self.duplicate_bytes_freed)
+            )?;
+        }
+
+        if self.chunk_deletions_failed != 0 {
+            writeln!(
+                f,
+                "   Failed deletions: {} (will be retried)",
+                self.chunk_deletions_failed
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a size in bytes with binary units.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!("0 B", format_bytes(0));
+        assert_eq!("1023 B", format_bytes(1023));
+        assert_eq!("1.0 KiB", format_bytes(1024));
+        assert_eq!("1.5 MiB", format_bytes(1024 * 1024 * 3 / 2));
+        assert_eq!("100.0 GiB", format_bytes(100 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_table() {
+        let report = GcReport {
+            dry_run: true,
+            caches: vec![CacheGcReport {
+                name: "hello".to_string(),
+                expired: 12,
+                kept: 3,
+                bytes_freed: 2048,
+                chunks_shared: 4,
+                bytes_shared: 512,
+                ..Default::default()
+            }],
+            objects: 12,
+            ..Default::default()
+        };
+
+        let table = report.to_string();
+        assert!(table.contains("dry run"));
+        assert!(table.contains("CACHE  EXPIRED  EVICTED  KEPT    FREED  SHARED"));
+        assert!(table.contains("hello       12        0     3  2.0 KiB   512 B  (4 chunks)"));
+        assert!(table.contains("Objects: 12"));
+    }
+}
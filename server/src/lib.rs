@@ -14,6 +14,7 @@
 )]
 
 pub mod access;
+mod access_tracker;
 #[cfg(not(test))]
 mod api;
 #[cfg(test)]
@@ -27,18 +28,25 @@ pub mod gc;
 mod middleware;
 #[cfg(test)]
 pub(crate) mod middleware;
+mod nar_listing;
 mod narinfo;
 pub mod nix_manifest;
 pub mod oobe;
+mod realisation;
+pub mod reconcile;
+pub mod scrub;
+pub mod signing;
 #[cfg(not(test))]
 mod storage;
 #[cfg(test)]
 pub(crate) mod storage;
+pub mod substituter;
+mod upstream;
 
 #[cfg(test)]
 mod tests;
 
-use std::future::IntoFuture;
+use std::future;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -51,18 +59,23 @@ use axum::{
     Router,
 };
 use tokio::net::TcpListener;
+use tokio::signal;
 use tokio::sync::OnceCell;
 use tokio::time;
 use tower_http::catch_panic::CatchPanicLayer;
 use tower_http::trace::TraceLayer;
 
 use access::http::{apply_auth, AuthState};
+use access_tracker::AccessTracker;
 use attic::cache::CacheName;
 use config::{Config, StorageConfig};
 use database::connection::{TursoConfig, TursoConnection};
 use error::{ErrorKind, ServerError, ServerResult};
 use middleware::{init_request_state, restrict_host, set_visibility_header};
+use narinfo::cache::NarInfoCache;
+use signing::envelope::MasterKeys;
 use storage::{LocalBackend, S3Backend, StorageBackend};
+use upstream::UpstreamFetcher;
 
 type State = Arc<StateInner>;
 type RequestState = Arc<RequestStateInner>;
@@ -78,6 +91,21 @@ pub struct StateInner {
 
     /// Handle to the storage backend.
     storage: OnceCell<Arc<Box<dyn StorageBackend>>>,
+
+    /// Fetcher for upstream substituters.
+    upstream: UpstreamFetcher,
+
+    /// Whether the server refuses to change anything.
+    read_only: bool,
+
+    /// Tracker of object access times.
+    access_tracker: AccessTracker,
+
+    /// Cache of rendered `.narinfo`s.
+    narinfo_cache: NarInfoCache,
+
+    /// The master keys that signing keys are encrypted with.
+    master_keys: MasterKeys,
 }
 
 /// Request state.
@@ -106,11 +134,19 @@ struct RequestStateInner {
 }
 
 impl StateInner {
-    async fn new(config: Config) -> State {
+    /// Creates the state of a server.
+    ///
+    /// A read-only server, like the substituter, refuses to change anything.
+    async fn new(config: Config, read_only: bool) -> State {
         Arc::new(Self {
+            access_tracker: AccessTracker::new(&config.access_tracking),
+            narinfo_cache: NarInfoCache::new(&config.narinfo_cache),
+            upstream: UpstreamFetcher::new(&config.upstream),
+            master_keys: MasterKeys::new(&config.signing_keys),
             config,
             database: OnceCell::new(),
             storage: OnceCell::new(),
+            read_only,
         })
     }
 
@@ -146,6 +182,17 @@ impl StateInner {
             .await
     }
 
+    /// Writes what is left in memory before the server stops.
+    async fn shutdown(&self) {
+        let Some(db) = self.database.get() else {
+            return;
+        };
+
+        if let Err(e) = self.access_tracker.flush(db).await {
+            tracing::warn!("Failed to write access times: {}", e);
+        }
+    }
+
     /// Sends periodic heartbeat queries to the database.
     async fn run_db_heartbeat(&self) -> ServerResult<()> {
         let db = self.database().await?;
@@ -211,7 +258,17 @@ async fn fallback(_: Uri) -> ServerResult<()> {
 pub async fn run_api_server(cli_listen: Option<SocketAddr>, config: Config) -> Result<()> {
     eprintln!("Starting API server...");
 
-    let state = StateInner::new(config).await;
+    let state = StateInner::new(config, false).await;
+
+    if state.config.signing_keys.master_key.is_some() {
+        let plaintext = signing::count_plaintext_keys(state.database().await?).await?;
+        if plaintext > 0 {
+            tracing::warn!(
+                "{} signing keys are stored in plaintext although a master key is configured. Run `atticd --mode db-migrations` to encrypt them.",
+                plaintext
+            );
+        }
+    }
 
     let listen = if let Some(cli_listen) = cli_listen {
         cli_listen
@@ -238,24 +295,67 @@ pub async fn run_api_server(cli_listen: Option<SocketAddr>, config: Config) -> R
 
     let listener = TcpListener::bind(&listen).await?;
 
-    let (server_ret, _) = tokio::join!(axum::serve(listener, rest).into_future(), async {
-        if state.config.database.heartbeat {
+    if state.config.database.heartbeat {
+        let state = state.clone();
+        tokio::spawn(async move {
             let _ = state.run_db_heartbeat().await;
-        }
-    },);
+        });
+    }
 
-    server_ret?;
+    let db = state.database().await?.clone();
+    let tracker_state = state.clone();
+    tokio::spawn(async move { tracker_state.access_tracker.run(&db).await });
+
+    axum::serve(listener, rest)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    state.shutdown().await;
 
     Ok(())
 }
 
+/// Waits for the server to be asked to stop.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    eprintln!("Shutting down...");
+}
+
 /// Runs database migrations.
 pub async fn run_migrations(config: Config) -> Result<()> {
     eprintln!("Running migrations...");
 
-    let state = StateInner::new(config).await;
+    let state = StateInner::new(config, false).await;
     let db = state.database().await?;
     database::migrations::run_migrations(db).await?;
 
+    if state.config.signing_keys.master_key.is_some() {
+        let report = signing::seal_plaintext_keys(db, &state.master_keys).await?;
+        if report.sealed > 0 {
+            eprintln!("Encrypted {} signing keys", report.sealed);
+        }
+    }
+
     Ok(())
 }
@@ -2,7 +2,7 @@ use std::env;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::{Parser, ValueEnum};
 use tokio::join;
 use tokio::task::spawn;
@@ -31,6 +31,16 @@ struct Opts {
     #[clap(long, default_value = "monolithic")]
     mode: ServerMode,
 
+    /// Only report what garbage collection would delete.
+    ///
+    /// This is only valid with `--mode garbage-collector-once`.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Format of the garbage collection report.
+    #[clap(long, default_value = "table")]
+    report_format: ReportFormat,
+
     /// Whether to enable tokio-console.
     ///
     /// The console server will listen on its default port.
@@ -46,7 +56,10 @@ enum ServerMode {
     /// Run the API server.
     ApiServer,
 
-    /// Run the garbage collector periodically.
+    /// Serve the binary cache read-only, preferably from an embedded replica.
+    Substituter,
+
+    /// Run the garbage collector and storage scrubs periodically.
     GarbageCollector,
 
     /// Run the database migrations then exit.
@@ -59,10 +72,25 @@ enum ServerMode {
     CheckConfig,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReportFormat {
+    /// A human-readable table.
+    Table,
+
+    /// JSON.
+    Json,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Opts::parse();
 
+    if opts.dry_run && opts.mode != ServerMode::GarbageCollectorOnce {
+        return Err(anyhow!(
+            "--dry-run is only valid with --mode garbage-collector-once"
+        ));
+    }
+
     init_logging(opts.tokio_console);
     dump_version();
 
@@ -73,24 +101,37 @@ async fn main() -> Result<()> {
         ServerMode::Monolithic => {
             attic_server::run_migrations(config.clone()).await?;
 
-            let (api_server, _) = join!(
-                attic_server::run_api_server(opts.listen, config.clone()),
-                attic_server::gc::run_garbage_collection(config.clone()),
-            );
+            spawn(attic_server::gc::run_garbage_collection(config.clone()));
+            spawn(attic_server::scrub::run_scrub(config.clone()));
 
-            api_server?;
+            attic_server::run_api_server(opts.listen, config.clone()).await?;
         }
         ServerMode::ApiServer => {
             attic_server::run_api_server(opts.listen, config).await?;
         }
+        ServerMode::Substituter => {
+            attic_server::substituter::run_substituter(opts.listen, config).await?;
+        }
         ServerMode::GarbageCollector => {
-            attic_server::gc::run_garbage_collection(config.clone()).await;
+            join!(
+                attic_server::gc::run_garbage_collection(config.clone()),
+                attic_server::scrub::run_scrub(config.clone()),
+            );
         }
         ServerMode::DbMigrations => {
             attic_server::run_migrations(config).await?;
         }
         ServerMode::GarbageCollectorOnce => {
-            attic_server::gc::run_garbage_collection_once(config).await?;
+            let report = if opts.dry_run {
+                attic_server::gc::plan_garbage_collection(config).await?
+            } else {
+                attic_server::gc::run_garbage_collection_once(config).await?
+            };
+
+            match opts.report_format {
+                ReportFormat::Table => print!("{}", report),
+                ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            }
         }
         ServerMode::CheckConfig => {
             // config is valid, let's just exit :)
@@ -0,0 +1,524 @@
+//! NAR listings.
+//!
+//! A NAR listing describes the file tree inside a NAR without its
+//! contents. It's served as `{storePathHash}.ls` and is used by
+//! `nix store ls` and `nix why-depends` to inspect paths without
+//! downloading them. The format is the same one cache.nixos.org serves:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "root": {
+//!     "type": "directory",
+//!     "entries": {
+//!       "bin": {
+//!         "type": "directory",
+//!         "entries": {
+//!           "hello": { "type": "regular", "size": 123, "executable": true, "narOffset": 400 }
+//!         }
+//!       }
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! The listing is built incrementally as the NAR streams through
+//! the upload path, so we never need to hold the NAR in memory.
+
+#[cfg(test)]
+mod tests;
+
+use std::collections::BTreeMap;
+use std::marker::Unpin;
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use async_compression::tokio::bufread::BrotliEncoder;
+use serde::Serialize;
+use tokio::io::{self, AsyncBufRead, AsyncRead, AsyncReadExt, ReadBuf};
+use tokio::sync::OnceCell;
+
+/// The magic string at the beginning of every NAR.
+const NAR_MAGIC: &[u8] = b"nix-archive-1";
+
+/// Maximum length of a string token that isn't file contents.
+///
+/// File names are limited to 255 bytes on most file systems and
+/// symlink targets to PATH_MAX. Anything bigger than this is not
+/// a NAR we can list.
+const MAX_STRING_LEN: u64 = 64 * 1024;
+
+/// A NAR listing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NarListing {
+    /// Version of the listing format.
+    pub version: u32,
+
+    /// The root node.
+    pub root: NarListingEntry,
+}
+
+/// A node in a NAR listing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NarListingEntry {
+    /// A regular file.
+    Regular {
+        size: u64,
+
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        executable: bool,
+
+        /// Offset of the file contents in the NAR.
+        #[serde(rename = "narOffset")]
+        nar_offset: u64,
+    },
+
+    /// A symbolic link.
+    Symlink { target: String },
+
+    /// A directory.
+    Directory {
+        entries: BTreeMap<String, NarListingEntry>,
+    },
+}
+
+/// Incremental NAR listing parser.
+///
+/// Bytes of the NAR are fed in as they arrive. File contents are
+/// skipped and only the structure of the archive is kept.
+#[derive(Debug)]
+pub struct NarListingParser {
+    /// Number of bytes of the NAR processed so far.
+    offset: u64,
+
+    /// Bytes of the current token that have been received.
+    buf: Vec<u8>,
+
+    /// What the tokenizer is reading.
+    read: Read,
+
+    /// What the parser expects next.
+    expect: Expect,
+
+    /// The regular file or symlink being parsed.
+    current: Option<NarListingEntry>,
+
+    /// Directories being parsed, innermost last.
+    directories: Vec<DirectoryFrame>,
+
+    /// The finished root node.
+    root: Option<NarListingEntry>,
+}
+
+/// What the tokenizer is reading.
+#[derive(Debug, Clone, Copy)]
+enum Read {
+    /// The 8-byte length of a string.
+    Length { contents: bool },
+
+    /// The bytes of a string, including padding.
+    String { len: usize, padded: usize },
+
+    /// File contents (including padding) that we don't keep.
+    Skip { remaining: u64 },
+}
+
+/// A token in the NAR.
+enum Token {
+    /// A string.
+    String(Vec<u8>),
+
+    /// The start of file contents.
+    Contents { size: u64, offset: u64 },
+}
+
+/// What the parser expects next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    Magic,
+    Open,
+    TypeKey,
+    TypeValue,
+    RegularAttribute,
+    ExecutableValue,
+    Contents,
+    RegularClose,
+    SymlinkKey,
+    SymlinkTarget,
+    SymlinkClose,
+    DirectoryEntry,
+    EntryOpen,
+    EntryNameKey,
+    EntryName,
+    EntryNodeKey,
+    EntryClose,
+    Done,
+    Invalid,
+}
+
+/// A directory being parsed.
+#[derive(Debug, Default)]
+struct DirectoryFrame {
+    entries: BTreeMap<String, NarListingEntry>,
+
+    /// Name of the entry whose node is being parsed.
+    name: Option<String>,
+}
+
+/// AsyncRead filter that builds the listing of the NAR being read.
+///
+/// The listing becomes available as soon as the end of the NAR is
+/// reached, even if the underlying stream is never read to EOF.
+pub struct NarListingReader<R: AsyncRead + Unpin> {
+    inner: R,
+    state: ReaderState,
+}
+
+struct ReaderState {
+    /// The parser, until the end of the NAR is reached.
+    parser: Option<NarListingParser>,
+    bytes_parsed: usize,
+    bytes_consumed: usize,
+    finalized: Arc<OnceCell<NarListing>>,
+}
+
+impl NarListing {
+    /// Returns the listing as Brotli-compressed JSON.
+    ///
+    /// This is how listings are stored and served.
+    pub async fn to_compressed_json(&self) -> io::Result<Vec<u8>> {
+        let json = serde_json::to_vec(self)?;
+
+        let mut compressed = Vec::new();
+        BrotliEncoder::new(json.as_slice())
+            .read_to_end(&mut compressed)
+            .await?;
+
+        Ok(compressed)
+    }
+}
+
+impl NarListingParser {
+    pub fn new() -> Self {
+        Self {
+            offset: 0,
+            buf: Vec::new(),
+            read: Read::Length { contents: false },
+            expect: Expect::Magic,
+            current: None,
+            directories: Vec::new(),
+            root: None,
+        }
+    }
+
+    /// Returns whether the end of the NAR has been reached.
+    pub fn is_done(&self) -> bool {
+        self.expect == Expect::Done
+    }
+
+    /// Returns whether the data is not a NAR we can list.
+    pub fn is_invalid(&self) -> bool {
+        self.expect == Expect::Invalid
+    }
+
+    /// Feeds some bytes of the NAR to the parser.
+    ///
+    /// Data after the end of the NAR or after the parser has
+    /// encountered invalid data is ignored.
+    pub fn feed(&mut self, mut data: &[u8]) {
+        while !data.is_empty() && !self.is_done() && !self.is_invalid() {
+            let consumed = self.step(data);
+            self.offset += consumed as u64;
+            data = &data[consumed..];
+        }
+    }
+
+    /// Returns the listing if the entire NAR has been parsed.
+    pub fn finish(self) -> Option<NarListing> {
+        self.root.map(|root| NarListing { version: 1, root })
+    }
+
+    /// Consumes some bytes and processes the token if it's complete.
+    ///
+    /// Returns the number of bytes consumed.
+    fn step(&mut self, data: &[u8]) -> usize {
+        match self.read {
+            Read::Length { contents } => {
+                let consumed = self.fill(data, 8);
+                if self.buf.len() < 8 {
+                    return consumed;
+                }
+
+                let len = u64::from_le_bytes(self.buf[..8].try_into().unwrap());
+                self.buf.clear();
+
+                let padded = len.checked_add(7).map(|l| l & !7);
+
+                if contents {
+                    let Some(padded) = padded else {
+                        self.expect = Expect::Invalid;
+                        return consumed;
+                    };
+
+                    self.read = if padded == 0 {
+                        Read::Length { contents: false }
+                    } else {
+                        Read::Skip { remaining: padded }
+                    };
+                    self.token(Token::Contents {
+                        size: len,
+                        offset: self.offset + consumed as u64,
+                    });
+                } else if len > MAX_STRING_LEN {
+                    self.expect = Expect::Invalid;
+                } else {
+                    self.read = Read::String {
+                        len: len as usize,
+                        padded: padded.unwrap() as usize,
+                    };
+                }
+
+                consumed
+            }
+            Read::String { len, padded } => {
+                let consumed = self.fill(data, padded);
+                if self.buf.len() < padded {
+                    return consumed;
+                }
+
+                if self.buf[len..].iter().any(|b| *b != 0) {
+                    self.expect = Expect::Invalid;
+                    return consumed;
+                }
+
+                let mut s = mem::take(&mut self.buf);
+                s.truncate(len);
+
+                self.read = Read::Length { contents: false };
+                self.token(Token::String(s));
+
+                consumed
+            }
+            Read::Skip { remaining } => {
+                let consumed = remaining.min(data.len() as u64);
+                let remaining = remaining - consumed;
+
+                self.read = if remaining == 0 {
+                    Read::Length { contents: false }
+                } else {
+                    Read::Skip { remaining }
+                };
+
+                consumed as usize
+            }
+        }
+    }
+
+    /// Buffers bytes until `self.buf` has `len` bytes.
+    fn fill(&mut self, data: &[u8], len: usize) -> usize {
+        let consumed = (len - self.buf.len()).min(data.len());
+        self.buf.extend_from_slice(&data[..consumed]);
+        consumed
+    }
+
+    /// Processes a token.
+    fn token(&mut self, token: Token) {
+        let s = match token {
+            Token::String(s) => s,
+            Token::Contents { size, offset } => {
+                if self.expect != Expect::Contents {
+                    self.expect = Expect::Invalid;
+                    return;
+                }
+
+                if let Some(NarListingEntry::Regular {
+                    size: s,
+                    nar_offset,
+                    ..
+                }) = &mut self.current
+                {
+                    *s = size;
+                    *nar_offset = offset;
+                }
+
+                self.expect = Expect::RegularClose;
+                return;
+            }
+        };
+
+        self.expect = match (self.expect, s.as_slice()) {
+            (Expect::Magic, NAR_MAGIC) => Expect::Open,
+            (Expect::Open, b"(") => Expect::TypeKey,
+            (Expect::TypeKey, b"type") => Expect::TypeValue,
+
+            (Expect::TypeValue, b"regular") => {
+                self.current = Some(NarListingEntry::Regular {
+                    size: 0,
+                    executable: false,
+                    nar_offset: 0,
+                });
+                Expect::RegularAttribute
+            }
+            (Expect::TypeValue, b"symlink") => Expect::SymlinkKey,
+            (Expect::TypeValue, b"directory") => {
+                self.directories.push(DirectoryFrame::default());
+                Expect::DirectoryEntry
+            }
+
+            (Expect::RegularAttribute, b"executable") => {
+                if let Some(NarListingEntry::Regular { executable, .. }) = &mut self.current {
+                    *executable = true;
+                }
+                Expect::ExecutableValue
+            }
+            (Expect::ExecutableValue, b"") => Expect::RegularAttribute,
+            (Expect::RegularAttribute, b"contents") => {
+                self.read = Read::Length { contents: true };
+                Expect::Contents
+            }
+            (Expect::RegularAttribute | Expect::RegularClose, b")") => {
+                let node = self.current.take().unwrap();
+                self.finish_node(node)
+            }
+
+            (Expect::SymlinkKey, b"target") => Expect::SymlinkTarget,
+            (Expect::SymlinkTarget, target) => {
+                self.current = Some(NarListingEntry::Symlink {
+                    target: String::from_utf8_lossy(target).into_owned(),
+                });
+                Expect::SymlinkClose
+            }
+            (Expect::SymlinkClose, b")") => {
+                let node = self.current.take().unwrap();
+                self.finish_node(node)
+            }
+
+            (Expect::DirectoryEntry, b"entry") => Expect::EntryOpen,
+            (Expect::DirectoryEntry, b")") => {
+                let frame = self.directories.pop().unwrap();
+                self.finish_node(NarListingEntry::Directory {
+                    entries: frame.entries,
+                })
+            }
+            (Expect::EntryOpen, b"(") => Expect::EntryNameKey,
+            (Expect::EntryNameKey, b"name") => Expect::EntryName,
+            (Expect::EntryName, name) => {
+                if name.is_empty() || name == b"." || name == b".." || name.contains(&b'/') {
+                    Expect::Invalid
+                } else {
+                    let frame = self.directories.last_mut().unwrap();
+                    frame.name = Some(String::from_utf8_lossy(name).into_owned());
+                    Expect::EntryNodeKey
+                }
+            }
+            (Expect::EntryNodeKey, b"node") => Expect::Open,
+            (Expect::EntryClose, b")") => Expect::DirectoryEntry,
+
+            _ => Expect::Invalid,
+        };
+    }
+
+    /// Attaches a finished node to its parent directory.
+    fn finish_node(&mut self, node: NarListingEntry) -> Expect {
+        match self.directories.last_mut() {
+            Some(frame) => {
+                let name = frame.name.take().unwrap();
+                frame.entries.insert(name, node);
+                Expect::EntryClose
+            }
+            None => {
+                self.root = Some(node);
+                Expect::Done
+            }
+        }
+    }
+}
+
+impl Default for NarListingParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: AsyncRead + Unpin> NarListingReader<R> {
+    pub fn new(inner: R) -> (Self, Arc<OnceCell<NarListing>>) {
+        let finalized = Arc::new(OnceCell::new());
+
+        (
+            Self {
+                inner,
+                state: ReaderState {
+                    parser: Some(NarListingParser::new()),
+                    bytes_parsed: 0,
+                    bytes_consumed: 0,
+                    finalized: finalized.clone(),
+                },
+            },
+            finalized,
+        )
+    }
+}
+
+impl ReaderState {
+    fn parse_unconsumed(&mut self, unconsumed: &[u8]) {
+        // Same bookkeeping as `HashReader`: bytes returned by `poll_fill_buf`
+        // may be returned again until they are consumed.
+        let unparsed_offset = self.bytes_parsed - self.bytes_consumed;
+
+        if unparsed_offset < unconsumed.len() {
+            let unparsed = &unconsumed[unparsed_offset..];
+            self.bytes_parsed += unparsed.len();
+
+            if let Some(parser) = &mut self.parser {
+                parser.feed(unparsed);
+
+                if parser.is_done() {
+                    let listing = self.parser.take().and_then(NarListingParser::finish);
+                    if let Some(listing) = listing {
+                        let _ = self.finalized.set(listing);
+                    }
+                } else if parser.is_invalid() {
+                    self.parser = None;
+                }
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for NarListingReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        let old_filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        let unconsumed = &buf.filled()[old_filled..];
+        this.state.parse_unconsumed(unconsumed);
+        this.state.bytes_consumed += unconsumed.len();
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for NarListingReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        let unconsumed = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+        this.state.parse_unconsumed(unconsumed);
+
+        Poll::Ready(Ok(unconsumed))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        Pin::new(&mut this.inner).consume(amt);
+        this.state.bytes_consumed += amt;
+    }
+}
@@ -0,0 +1,194 @@
+use super::*;
+
+use serde_json::json;
+use tokio::io::{AsyncReadExt, BufReader};
+
+fn write_string(nar: &mut Vec<u8>, s: &[u8]) {
+    nar.extend_from_slice(&(s.len() as u64).to_le_bytes());
+    nar.extend_from_slice(s);
+    nar.resize(nar.len() + (8 - s.len() % 8) % 8, 0);
+}
+
+fn write_strings(nar: &mut Vec<u8>, strings: &[&[u8]]) {
+    for s in strings {
+        write_string(nar, s);
+    }
+}
+
+/// Returns a NAR of a single regular file.
+fn regular_nar() -> Vec<u8> {
+    let mut nar = Vec::new();
+    write_strings(
+        &mut nar,
+        &[
+            b"nix-archive-1",
+            b"(",
+            b"type",
+            b"regular",
+            b"contents",
+            b"hello world",
+            b")",
+        ],
+    );
+    nar
+}
+
+/// Returns a NAR of a directory with a regular file, an executable,
+/// a symlink and a subdirectory.
+fn directory_nar() -> Vec<u8> {
+    let mut nar = Vec::new();
+    write_strings(&mut nar, &[b"nix-archive-1", b"(", b"type", b"directory"]);
+
+    write_strings(&mut nar, &[b"entry", b"(", b"name", b"bin", b"node"]);
+    write_strings(&mut nar, &[b"(", b"type", b"directory"]);
+    write_strings(&mut nar, &[b"entry", b"(", b"name", b"hello", b"node"]);
+    write_strings(
+        &mut nar,
+        &[
+            b"(",
+            b"type",
+            b"regular",
+            b"executable",
+            b"",
+            b"contents",
+            b"#!/bin/sh\necho hello\n",
+            b")",
+        ],
+    );
+    write_strings(&mut nar, &[b")", b")", b")"]);
+
+    write_strings(&mut nar, &[b"entry", b"(", b"name", b"empty", b"node"]);
+    write_strings(
+        &mut nar,
+        &[b"(", b"type", b"regular", b"contents", b"", b")"],
+    );
+    write_strings(&mut nar, &[b")"]);
+
+    write_strings(&mut nar, &[b"entry", b"(", b"name", b"lib", b"node"]);
+    write_strings(
+        &mut nar,
+        &[
+            b"(",
+            b"type",
+            b"symlink",
+            b"target",
+            b"/nix/store/lib",
+            b")",
+        ],
+    );
+    write_strings(&mut nar, &[b")"]);
+
+    write_strings(&mut nar, &[b")"]);
+    nar
+}
+
+fn parse(nar: &[u8]) -> Option<NarListing> {
+    let mut parser = NarListingParser::new();
+    parser.feed(nar);
+    parser.finish()
+}
+
+#[test]
+fn test_regular() {
+    let listing = parse(&regular_nar()).expect("Could not list NAR");
+
+    assert_eq!(
+        NarListingEntry::Regular {
+            size: 11,
+            executable: false,
+            nar_offset: 96,
+        },
+        listing.root
+    );
+
+    assert_eq!(
+        json!({
+            "version": 1,
+            "root": {
+                "type": "regular",
+                "size": 11,
+                "narOffset": 96,
+            },
+        }),
+        serde_json::to_value(&listing).unwrap()
+    );
+}
+
+#[test]
+fn test_directory() {
+    let nar = directory_nar();
+    let listing = parse(&nar).expect("Could not list NAR");
+
+    let value = serde_json::to_value(&listing).unwrap();
+    let hello = &value["root"]["entries"]["bin"]["entries"]["hello"];
+    assert_eq!("regular", hello["type"]);
+    assert_eq!(21, hello["size"]);
+    assert_eq!(true, hello["executable"]);
+
+    // The offset must point to the file contents
+    let offset = hello["narOffset"].as_u64().unwrap() as usize;
+    assert_eq!(b"#!/bin/sh\necho hello\n", &nar[offset..offset + 21]);
+
+    assert_eq!(
+        json!({ "type": "symlink", "target": "/nix/store/lib" }),
+        value["root"]["entries"]["lib"]
+    );
+    assert_eq!(0, value["root"]["entries"]["empty"]["size"]);
+    assert!(value["root"]["entries"]["empty"]
+        .get("executable")
+        .is_none());
+}
+
+#[test]
+fn test_byte_by_byte() {
+    let nar = directory_nar();
+
+    let mut parser = NarListingParser::new();
+    for b in nar.iter() {
+        assert!(!parser.is_done());
+        parser.feed(std::slice::from_ref(b));
+    }
+    assert!(parser.is_done());
+
+    assert_eq!(parse(&nar), parser.finish());
+}
+
+#[test]
+fn test_invalid() {
+    // Not a NAR
+    assert!(parse(b"hello world").is_none());
+
+    // Truncated
+    let nar = directory_nar();
+    assert!(parse(&nar[..nar.len() - 8]).is_none());
+
+    // Bad entry name
+    let mut nar = Vec::new();
+    write_strings(&mut nar, &[b"nix-archive-1", b"(", b"type", b"directory"]);
+    write_strings(&mut nar, &[b"entry", b"(", b"name", b"../etc", b"node"]);
+    write_strings(
+        &mut nar,
+        &[b"(", b"type", b"symlink", b"target", b"/", b")", b")", b")"],
+    );
+
+    let mut parser = NarListingParser::new();
+    parser.feed(&nar);
+    assert!(parser.is_invalid());
+    assert!(parser.finish().is_none());
+}
+
+#[tokio::test]
+async fn test_reader() {
+    let nar = directory_nar();
+
+    // The listing is available even if the stream is never read to EOF
+    let data = [nar.as_slice(), b"trailing"].concat();
+    let (stream, listing) = NarListingReader::new(BufReader::with_capacity(7, data.as_slice()));
+    let mut stream = stream.take(nar.len() as u64);
+
+    let mut read = Vec::new();
+    stream.read_to_end(&mut read).await.unwrap();
+
+    assert_eq!(nar, read);
+    assert_eq!(parse(&nar).as_ref(), listing.get());
+}
@@ -0,0 +1,275 @@
+//! In-process cache of rendered `.narinfo`s.
+//!
+//! Nix asks for thousands of `.narinfo`s per evaluation, and rendering
+//! one takes a database query, parsing the keypair of the cache and a
+//! signature. The cache keeps the rendered, signed `.narinfo` of recently
+//! requested paths, keyed by cache and store path hash, as well as the
+//! paths that were found missing.
+//!
+//! Entries are invalidated when the server itself uploads or deletes
+//! paths, or changes the configuration of a cache. Changes made by other
+//! processes, such as the garbage collector or other servers sharing the
+//! database, are only picked up when entries expire, which is why missing
+//! paths expire quickly.
+//!
+//! Only what is needed to check permissions is cached besides the
+//! `.narinfo` itself, so cached responses are subject to the same
+//! checks as uncached ones.
+
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use serde::Serialize;
+
+use crate::config::NarInfoCacheConfig;
+use attic::cache::CacheName;
+use attic::nix_store::StorePathHash;
+
+/// A cached lookup of a `.narinfo`.
+#[derive(Debug, Clone)]
+pub enum CachedNarInfo {
+    /// The rendered `.narinfo` of the path.
+    Found {
+        /// The rendered `.narinfo`.
+        narinfo: String,
+
+        /// Whether the cache is public.
+        is_public: bool,
+    },
+
+    /// The path isn't in the cache.
+    Missing,
+}
+
+/// A lookup that missed the cache.
+///
+/// The result of the lookup can only be inserted if nothing was
+/// invalidated in the meantime, as it may be stale otherwise.
+#[derive(Debug)]
+pub struct Ticket {
+    epoch: u64,
+}
+
+/// Hit rate statistics of the cache.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NarInfoCacheStats {
+    /// The number of lookups answered with a `.narinfo`.
+    pub hits: u64,
+
+    /// The number of lookups answered with a missing path.
+    pub negative_hits: u64,
+
+    /// The number of lookups that went to the database.
+    pub misses: u64,
+
+    /// The fraction of lookups answered by the cache.
+    pub hit_rate: f64,
+
+    /// The number of entries evicted to make room for others.
+    pub evictions: u64,
+
+    /// The number of entries invalidated.
+    pub invalidations: u64,
+
+    /// The number of entries in the cache.
+    pub entries: usize,
+}
+
+/// An LRU cache of rendered `.narinfo`s.
+#[derive(Debug)]
+pub struct NarInfoCache {
+    entries: Option<Mutex<LruCache<(String, String), Entry>>>,
+
+    /// How long found paths are kept.
+    ttl: Duration,
+
+    /// How long missing paths are kept.
+    negative_ttl: Duration,
+
+    /// Incremented on every invalidation.
+    epoch: AtomicU64,
+
+    hits: AtomicU64,
+    negative_hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    narinfo: CachedNarInfo,
+    expires_at: Instant,
+}
+
+impl NarInfoCache {
+    pub fn new(config: &NarInfoCacheConfig) -> Self {
+        Self {
+            entries: NonZeroUsize::new(config.capacity).map(|c| Mutex::new(LruCache::new(c))),
+            ttl: config.ttl,
+            negative_ttl: config.negative_ttl,
+            epoch: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            negative_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// Looks up the `.narinfo` of a path.
+    ///
+    /// On a miss, the returned ticket is used to insert the result of
+    /// the lookup.
+    pub fn get(
+        &self,
+        cache: &CacheName,
+        store_path_hash: &StorePathHash,
+    ) -> Result<CachedNarInfo, Ticket> {
+        // Taken before looking, so anything invalidated from now on
+        // keeps the result from being inserted
+        let ticket = Ticket {
+            epoch: self.epoch.load(Ordering::SeqCst),
+        };
+
+        let Some(entries) = &self.entries else {
+            return Err(ticket);
+        };
+
+        let key = key(cache, store_path_hash);
+        let mut entries = entries.lock().unwrap();
+
+        match entries.get(&key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                match entry.narinfo {
+                    CachedNarInfo::Found { .. } => self.hits.fetch_add(1, Ordering::Relaxed),
+                    CachedNarInfo::Missing => self.negative_hits.fetch_add(1, Ordering::Relaxed),
+                };
+                Ok(entry.narinfo.clone())
+            }
+            Some(_) => {
+                entries.pop(&key);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Err(ticket)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Err(ticket)
+            }
+        }
+    }
+
+    /// Inserts the result of a lookup that missed the cache.
+    pub fn insert(
+        &self,
+        ticket: Ticket,
+        cache: &CacheName,
+        store_path_hash: &StorePathHash,
+        narinfo: CachedNarInfo,
+    ) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+
+        let ttl = match narinfo {
+            CachedNarInfo::Found { .. } => self.ttl,
+            CachedNarInfo::Missing => self.negative_ttl,
+        };
+        if ttl.is_zero() {
+            return;
+        }
+
+        let mut entries = entries.lock().unwrap();
+
+        // Checked under the lock, since invalidations take it as well
+        if self.epoch.load(Ordering::SeqCst) != ticket.epoch {
+            return;
+        }
+
+        let key = key(cache, store_path_hash);
+        let entry = Entry {
+            narinfo,
+            expires_at: Instant::now() + ttl,
+        };
+
+        if let Some((evicted, _)) = entries.push(key.clone(), entry) {
+            if evicted != key {
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Invalidates the `.narinfo` of a path.
+    pub fn invalidate(&self, cache: &CacheName, store_path_hash: &StorePathHash) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+
+        let mut entries = entries.lock().unwrap();
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+
+        if entries.pop(&key(cache, store_path_hash)).is_some() {
+            self.invalidations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Invalidates the `.narinfo`s of all paths in a cache.
+    pub fn invalidate_cache(&self, cache: &CacheName) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+
+        let mut entries = entries.lock().unwrap();
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+
+        let keys: Vec<_> = entries
+            .iter()
+            .filter(|((name, _), _)| name == cache.as_str())
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in keys.iter() {
+            entries.pop(key);
+        }
+
+        self.invalidations
+            .fetch_add(keys.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Returns hit rate statistics.
+    pub fn stats(&self) -> NarInfoCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let negative_hits = self.negative_hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+
+        let lookups = hits + negative_hits + misses;
+        let hit_rate = if lookups == 0 {
+            0.0
+        } else {
+            (hits + negative_hits) as f64 / lookups as f64
+        };
+
+        NarInfoCacheStats {
+            hits,
+            negative_hits,
+            misses,
+            hit_rate,
+            evictions: self.evictions.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            entries: self
+                .entries
+                .as_ref()
+                .map_or(0, |entries| entries.lock().unwrap().len()),
+        }
+    }
+}
+
+fn key(cache: &CacheName, store_path_hash: &StorePathHash) -> (String, String) {
+    (
+        cache.as_str().to_owned(),
+        store_path_hash.as_str().to_owned(),
+    )
+}
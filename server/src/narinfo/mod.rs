@@ -50,10 +50,13 @@ use serde_with::serde_as;
 
 use crate::error::{ErrorKind, ServerError, ServerResult};
 use crate::nix_manifest::{self, SpaceDelimitedList};
+use crate::signing::Signer;
 use attic::hash::Hash;
 use attic::mime;
 use attic::signing::NixKeypair;
 
+pub mod cache;
+
 #[cfg(test)]
 mod tests;
 
@@ -134,13 +137,12 @@ pub struct NarInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub deriver: Option<String>,
 
-    /// The signature of the object.
+    /// The signatures of the object.
     ///
-    /// The `Sig` field can be duplicated to include multiple
-    /// signatures, but we only support one for now.
-    #[serde(rename = "Sig")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub signature: Option<String>,
+    /// The `Sig` field is repeated for each signature, which the
+    /// manifest format cannot represent, so it's handled separately.
+    #[serde(skip)]
+    pub signatures: Vec<String>,
 
     /// The content address of the object.
     #[serde(rename = "CA")]
@@ -166,17 +168,40 @@ pub enum Compression {
 impl NarInfo {
     /// Parses a narinfo from a string.
     pub fn from_str(manifest: &str) -> ServerResult<Self> {
-        nix_manifest::from_str(manifest)
+        let mut signatures = Vec::new();
+        let mut rest = String::with_capacity(manifest.len());
+
+        for line in manifest.lines() {
+            if let Some(sig) = line.strip_prefix("Sig:") {
+                signatures.push(sig.trim().to_string());
+            } else {
+                rest.push_str(line);
+                rest.push('\n');
+            }
+        }
+
+        let mut narinfo: Self = nix_manifest::from_str(&rest)?;
+        narinfo.signatures = signatures;
+
+        Ok(narinfo)
     }
 
     /// Returns the serialized representation of the narinfo.
     pub fn to_string(&self) -> ServerResult<String> {
-        nix_manifest::to_string(self)
+        let mut manifest = nix_manifest::to_string(self)?;
+
+        for signature in self.signatures.iter() {
+            manifest.push_str("Sig: ");
+            manifest.push_str(signature);
+            manifest.push('\n');
+        }
+
+        Ok(manifest)
     }
 
-    /// Returns the signature of this object, if it exists.
-    pub fn signature(&self) -> Option<&String> {
-        self.signature.as_ref()
+    /// Returns the signatures of this object.
+    pub fn signatures(&self) -> &[String] {
+        &self.signatures
     }
 
     /// Returns the store directory of this object.
@@ -188,43 +213,24 @@ impl NarInfo {
     /// Signs the narinfo and adds the signature to the narinfo.
     pub fn sign(&mut self, keypair: &NixKeypair) {
         let signature = self.sign_readonly(keypair);
-        self.signature = Some(signature);
+        self.add_signature(signature);
+    }
+
+    /// Signs the narinfo with a signer and adds the signature to the narinfo.
+    pub async fn sign_with(&mut self, signer: &dyn Signer) -> ServerResult<()> {
+        let signature = signer.sign(&self.fingerprint()).await?;
+        self.add_signature(signature);
+        Ok(())
     }
 
     /// Returns the fingerprint of the object.
     pub fn fingerprint(&self) -> Vec<u8> {
-        let store_dir = self.store_dir();
-        let mut fingerprint = b"1;".to_vec();
-
-        // 1;{storePath};{narHash};{narSize};{commaDelimitedReferences}
-
-        // storePath
-        fingerprint.extend(self.store_path.as_os_str().as_bytes());
-        fingerprint.extend(b";");
-
-        // narHash
-        fingerprint.extend(self.nar_hash.to_typed_base32().as_bytes());
-        fingerprint.extend(b";");
-
-        // narSize
-        let mut buf = itoa::Buffer::new();
-        let nar_size = buf.format(self.nar_size);
-        fingerprint.extend(nar_size.as_bytes());
-        fingerprint.extend(b";");
-
-        // commaDelimitedReferences
-        let mut iter = self.references.iter().peekable();
-        while let Some(reference) = iter.next() {
-            fingerprint.extend(store_dir.as_os_str().as_bytes());
-            fingerprint.extend(b"/");
-            fingerprint.extend(reference.as_bytes());
-
-            if iter.peek().is_some() {
-                fingerprint.extend(b",");
-            }
-        }
-
-        fingerprint
+        fingerprint(
+            &self.store_path,
+            &self.nar_hash,
+            self.nar_size,
+            &self.references,
+        )
     }
 
     /// Signs the narinfo with a keypair, returning the signature.
@@ -232,11 +238,61 @@ impl NarInfo {
         let fingerprint = self.fingerprint();
         keypair.sign(&fingerprint)
     }
+
+    fn add_signature(&mut self, signature: String) {
+        if !self.signatures.contains(&signature) {
+            self.signatures.push(signature);
+        }
+    }
+}
+
+/// Returns the fingerprint that signatures of an object are made over.
+///
+/// `references` only include the base paths, like in a `.narinfo`.
+pub fn fingerprint(
+    store_path: &Path,
+    nar_hash: &Hash,
+    nar_size: usize,
+    references: &[String],
+) -> Vec<u8> {
+    // An invalid store path just makes signatures fail to verify
+    let store_dir = store_path.parent().unwrap_or(Path::new(""));
+    let mut fingerprint = b"1;".to_vec();
+
+    // 1;{storePath};{narHash};{narSize};{commaDelimitedReferences}
+
+    // storePath
+    fingerprint.extend(store_path.as_os_str().as_bytes());
+    fingerprint.extend(b";");
+
+    // narHash
+    fingerprint.extend(nar_hash.to_typed_base32().as_bytes());
+    fingerprint.extend(b";");
+
+    // narSize
+    let mut buf = itoa::Buffer::new();
+    let nar_size = buf.format(nar_size);
+    fingerprint.extend(nar_size.as_bytes());
+    fingerprint.extend(b";");
+
+    // commaDelimitedReferences
+    let mut iter = references.iter().peekable();
+    while let Some(reference) = iter.next() {
+        fingerprint.extend(store_dir.as_os_str().as_bytes());
+        fingerprint.extend(b"/");
+        fingerprint.extend(reference.as_bytes());
+
+        if iter.peek().is_some() {
+            fingerprint.extend(b",");
+        }
+    }
+
+    fingerprint
 }
 
 impl IntoResponse for NarInfo {
     fn into_response(self) -> Response {
-        match nix_manifest::to_string(&self) {
+        match self.to_string() {
             Ok(body) => Response::builder()
                 .status(StatusCode::OK)
                 .header("Content-Type", mime::NARINFO)
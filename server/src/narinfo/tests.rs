@@ -53,7 +53,7 @@ Sig: cache.nixos.org-1:lo9EfNIL4eGRuNh7DTbAAffWPpI2SlYC/8uP7JnhgmfRIUNGhSbFe8qEa
             Some("vvb4wxmnjixmrkhmj2xb75z62hrr41i7-hello-2.10.drv".to_string()),
             narinfo.deriver
         );
-        assert_eq!(Some("cache.nixos.org-1:lo9EfNIL4eGRuNh7DTbAAffWPpI2SlYC/8uP7JnhgmfRIUNGhSbFe8qEaKN0mFS02TuhPpXFPNtRkFcCp0hGAQ==".to_string()), narinfo.signature);
+        assert_eq!(vec!["cache.nixos.org-1:lo9EfNIL4eGRuNh7DTbAAffWPpI2SlYC/8uP7JnhgmfRIUNGhSbFe8qEaKN0mFS02TuhPpXFPNtRkFcCp0hGAQ==".to_string()], narinfo.signatures);
     }
 
     verify_narinfo(&narinfo);
@@ -67,6 +67,46 @@ Sig: cache.nixos.org-1:lo9EfNIL4eGRuNh7DTbAAffWPpI2SlYC/8uP7JnhgmfRIUNGhSbFe8qEa
     verify_narinfo(&reparse);
 }
 
+#[test]
+fn test_multiple_signatures() {
+    let s = r#"
+StorePath: /nix/store/xcp9cav49dmsjbwdjlmkjxj10gkpx553-hello-2.10
+URL: nar/0nqgf15qfiacfxrgm2wkw0gwwncjqqzzalj8rs14w9srkydkjsk9.nar.xz
+Compression: xz
+NarHash: sha256:16mvl7v0ylzcg2n3xzjn41qhzbmgcn5iyarx16nn5l2r36n2kqci
+NarSize: 206104
+References: 563528481rvhc5kxwipjmg6rqrl95mdx-glibc-2.33-56 xcp9cav49dmsjbwdjlmkjxj10gkpx553-hello-2.10
+Sig: cache.nixos.org-1:lo9EfNIL4eGRuNh7DTbAAffWPpI2SlYC/8uP7JnhgmfRIUNGhSbFe8qEaKN0mFS02TuhPpXFPNtRkFcCp0hGAQ==
+Sig: other-1:c2lnbmF0dXJl
+    "#;
+
+    let mut narinfo = NarInfo::from_str(s).expect("Could not parse narinfo");
+    assert_eq!(2, narinfo.signatures().len());
+    assert_eq!("other-1:c2lnbmF0dXJl", narinfo.signatures()[1]);
+
+    // Signing twice with the same key adds a single signature
+    let keypair = NixKeypair::generate("attic-test").unwrap();
+    narinfo.sign(&keypair);
+    narinfo.sign(&keypair);
+    assert_eq!(3, narinfo.signatures().len());
+
+    let round_trip = narinfo.to_string().expect("Could not serialize narinfo");
+    assert_eq!(
+        3,
+        round_trip
+            .lines()
+            .filter(|l| l.starts_with("Sig: "))
+            .count()
+    );
+
+    let reparse = NarInfo::from_str(&round_trip).expect("Could not re-parse serialized narinfo");
+    assert_eq!(narinfo.signatures(), reparse.signatures());
+    keypair
+        .to_public_key()
+        .verify(&reparse.fingerprint(), &reparse.signatures()[2])
+        .expect("Could not verify signature");
+}
+
 #[test]
 fn test_deriver() {
     let s = r#"
@@ -123,6 +163,6 @@ Sig: cache.nixos.org-1:lo9EfNIL4eGRuNh7DTbAAffWPpI2SlYC/8uP7JnhgmfRIUNGhSbFe8qEa
     assert_eq!(correct_fingerprint, fingerprint.as_slice());
 
     public_key
-        .verify(&narinfo.fingerprint(), narinfo.signature().unwrap())
+        .verify(&narinfo.fingerprint(), &narinfo.signatures()[0])
         .expect("Could not verify signature");
 }
@@ -0,0 +1,175 @@
+//! Realisations of content-addressed derivations.
+//!
+//! With `ca-derivations`, the output paths of a derivation are only known
+//! after it's built. A realisation maps a derivation output to the store
+//! path it produced, and binary caches serve them under
+//! `realisations/{id}.doi`:
+//!
+//! ```json
+//! {
+//!   "dependentRealisations": {},
+//!   "id": "sha256:1wc5dv6hdfzbqv1rvkq8aiq6dyg6bsm2iv0yl7dj3gbk8s0ljbp6!out",
+//!   "outPath": "xcp9cav49dmsjbwdjlmkjxj10gkpx553-hello-2.10",
+//!   "signatures": ["cache.nixos.org-1:..."]
+//! }
+//! ```
+//!
+//! The `id` is the hash modulo of the derivation and the output name
+//! separated by `!`. Store paths are base names.
+
+#[cfg(test)]
+mod tests;
+
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ErrorKind, ServerError, ServerResult};
+use crate::signing::Signer;
+use attic::mime;
+use attic::nix_store::StorePathHash;
+use attic::signing::NixKeypair;
+
+/// A realisation of a derivation output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Realisation {
+    /// Realisations of the outputs this output depends on.
+    #[serde(default)]
+    pub dependent_realisations: BTreeMap<String, String>,
+
+    /// The derivation output, in the form of `{drvHash}!{outputName}`.
+    pub id: String,
+
+    /// The base name of the store path the output was realised to.
+    pub out_path: String,
+
+    /// Signatures over the fingerprint of the realisation.
+    #[serde(default)]
+    pub signatures: Vec<String>,
+}
+
+/// The signed portion of a realisation.
+///
+/// Nix serializes the realisation without its signatures, with
+/// the keys sorted.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Fingerprint<'a> {
+    dependent_realisations: &'a BTreeMap<String, String>,
+    id: &'a str,
+    out_path: &'a str,
+}
+
+impl Realisation {
+    /// Parses a realisation from JSON.
+    pub fn from_json(json: &str) -> ServerResult<Self> {
+        let realisation: Self = serde_json::from_str(json).map_err(ServerError::request_error)?;
+        realisation.validate()?;
+
+        Ok(realisation)
+    }
+
+    /// Returns the fingerprint of the realisation.
+    pub fn fingerprint(&self) -> Vec<u8> {
+        let fingerprint = Fingerprint {
+            dependent_realisations: &self.dependent_realisations,
+            id: &self.id,
+            out_path: &self.out_path,
+        };
+
+        serde_json::to_vec(&fingerprint).unwrap()
+    }
+
+    /// Signs the realisation with a keypair.
+    ///
+    /// Existing signatures are kept.
+    pub fn sign(&mut self, keypair: &NixKeypair) {
+        let signature = keypair.sign(&self.fingerprint());
+        self.add_signature(signature);
+    }
+
+    /// Signs the realisation with a signer.
+    ///
+    /// Existing signatures are kept.
+    pub async fn sign_with(&mut self, signer: &dyn Signer) -> ServerResult<()> {
+        let signature = signer.sign(&self.fingerprint()).await?;
+        self.add_signature(signature);
+        Ok(())
+    }
+
+    fn add_signature(&mut self, signature: String) {
+        if !self.signatures.contains(&signature) {
+            self.signatures.push(signature);
+            self.signatures.sort();
+        }
+    }
+
+    /// Checks that the realisation is well-formed.
+    fn validate(&self) -> ServerResult<()> {
+        validate_id(&self.id)?;
+        validate_base_name(&self.out_path)?;
+
+        for (id, out_path) in self.dependent_realisations.iter() {
+            validate_id(id)?;
+            validate_base_name(out_path)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl IntoResponse for Realisation {
+    fn into_response(self) -> Response {
+        match serde_json::to_string(&self) {
+            Ok(body) => Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", mime::REALISATION)
+                .body(body)
+                .unwrap()
+                .into_response(),
+            Err(e) => ServerError::request_error(e).into_response(),
+        }
+    }
+}
+
+/// Checks that a derivation output ID is well-formed.
+pub fn validate_id(id: &str) -> ServerResult<()> {
+    let valid = id.split_once('!').is_some_and(|(drv_hash, output_name)| {
+        let hash_valid = drv_hash.split_once(':').is_some_and(|(algo, hash)| {
+            !algo.is_empty() && !hash.is_empty() && hash.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+
+        let output_name_valid = !output_name.is_empty()
+            && output_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-._?=".contains(c));
+
+        hash_valid && output_name_valid
+    });
+
+    if !valid {
+        return Err(ErrorKind::RequestError(anyhow!("Invalid derivation output ID")).into());
+    }
+
+    Ok(())
+}
+
+/// Checks that a store path base name is well-formed.
+fn validate_base_name(base_name: &str) -> ServerResult<()> {
+    let valid = base_name.len() > 33
+        && base_name.as_bytes()[32] == b'-'
+        && !base_name.contains('/')
+        && StorePathHash::new(base_name[..32].to_string()).is_ok();
+
+    if !valid {
+        return Err(ErrorKind::RequestError(anyhow!("Invalid store path")).into());
+    }
+
+    Ok(())
+}
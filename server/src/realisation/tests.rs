@@ -0,0 +1,77 @@
+use super::*;
+
+const REALISATION: &str = r#"{
+    "dependentRealisations": {
+        "sha256:0f5lzp6mq6wycjh7bxmy9qzqqf5kbsqbmnxkc6w5grcb5xnpyd22!out": "563528481rvhc5kxwipjmg6rqrl95mdx-glibc-2.33-56"
+    },
+    "id": "sha256:1wc5dv6hdfzbqv1rvkq8aiq6dyg6bsm2iv0yl7dj3gbk8s0ljbp6!out",
+    "outPath": "xcp9cav49dmsjbwdjlmkjxj10gkpx553-hello-2.10",
+    "signatures": []
+}"#;
+
+#[test]
+fn test_parse() {
+    let realisation = Realisation::from_json(REALISATION).expect("Could not parse realisation");
+
+    assert_eq!(
+        "sha256:1wc5dv6hdfzbqv1rvkq8aiq6dyg6bsm2iv0yl7dj3gbk8s0ljbp6!out",
+        realisation.id
+    );
+    assert_eq!(
+        "xcp9cav49dmsjbwdjlmkjxj10gkpx553-hello-2.10",
+        realisation.out_path
+    );
+    assert_eq!(1, realisation.dependent_realisations.len());
+}
+
+#[test]
+fn test_fingerprint() {
+    let realisation = Realisation::from_json(REALISATION).unwrap();
+
+    // Same as `Realisation::fingerprint` in Nix
+    assert_eq!(
+        concat!(
+            r#"{"dependentRealisations":{"sha256:0f5lzp6mq6wycjh7bxmy9qzqqf5kbsqbmnxkc6w5grcb5xnpyd22!out":"563528481rvhc5kxwipjmg6rqrl95mdx-glibc-2.33-56"},"#,
+            r#""id":"sha256:1wc5dv6hdfzbqv1rvkq8aiq6dyg6bsm2iv0yl7dj3gbk8s0ljbp6!out","#,
+            r#""outPath":"xcp9cav49dmsjbwdjlmkjxj10gkpx553-hello-2.10"}"#,
+        )
+        .as_bytes(),
+        realisation.fingerprint()
+    );
+}
+
+#[test]
+fn test_sign() {
+    let keypair = NixKeypair::generate("test-1").unwrap();
+    let mut realisation = Realisation::from_json(REALISATION).unwrap();
+
+    realisation.sign(&keypair);
+    realisation.sign(&keypair);
+
+    assert_eq!(1, realisation.signatures.len());
+    keypair
+        .to_public_key()
+        .verify(&realisation.fingerprint(), &realisation.signatures[0])
+        .expect("Signature is invalid");
+}
+
+#[test]
+fn test_invalid() {
+    let bad_ids = [
+        "sha256:1wc5dv6hdfzbqv1rvkq8aiq6dyg6bsm2iv0yl7dj3gbk8s0ljbp6",
+        "1wc5dv6hdfzbqv1rvkq8aiq6dyg6bsm2iv0yl7dj3gbk8s0ljbp6!out",
+        "sha256:!out",
+        "sha256:1wc5dv6hdfzbqv1rvkq8aiq6dyg6bsm2iv0yl7dj3gbk8s0ljbp6!",
+        "sha256:1wc5dv6hdfzbqv1rvkq8aiq6dyg6bsm2iv0yl7dj3gbk8s0ljbp6!../out",
+    ];
+
+    for id in bad_ids {
+        assert!(validate_id(id).is_err(), "{} should be invalid", id);
+    }
+
+    let bad_out_path = REALISATION.replace(
+        "xcp9cav49dmsjbwdjlmkjxj10gkpx553-hello-2.10",
+        "/nix/store/xcp9cav49dmsjbwdjlmkjxj10gkpx553-hello-2.10",
+    );
+    assert!(Realisation::from_json(&bad_out_path).is_err());
+}
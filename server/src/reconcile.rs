@@ -0,0 +1,416 @@
+//! Storage reconciliation.
+//!
+//! The storage and the database can drift apart: an upload that dies
+//! between writing a file and committing its chunk leaves the file
+//! behind, and restoring the database from a backup forgets files
+//! uploaded since. Reconciliation lists the storage and walks it
+//! alongside the `chunk` table, both ordered by file name, to find
+//! files that nothing refers to and chunks whose files are missing.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tracing::instrument;
+
+use super::StateInner;
+use crate::config::Config;
+use crate::database::models::ChunkState;
+use crate::database::queries;
+use crate::gc::format_bytes;
+use crate::scrub::DamagedPath;
+use crate::storage::{StorageBackend, StoredFile};
+
+/// The number of files or chunks to fetch at once.
+const PAGE_SIZE: usize = 1000;
+
+/// Suffixes of the files that Attic creates in the storage.
+///
+/// Other files may belong to someone else sharing the bucket and are
+/// never touched.
+const MANAGED_SUFFIXES: [&str; 3] = [".chunk", ".staged-nar", ".log.br"];
+
+/// What to do about the differences found.
+#[derive(Debug, Clone)]
+pub struct ReconcileOptions {
+    /// How old files and chunks must be to be considered.
+    ///
+    /// Newer ones may belong to uploads in progress.
+    pub grace_period: Duration,
+
+    /// Whether to delete unreferenced files.
+    pub delete_unreferenced: bool,
+
+    /// Whether to take valid chunks with missing files out of service.
+    pub mark_missing: bool,
+}
+
+/// Differences between the storage and the database.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconcileReport {
+    /// Files found in the storage.
+    pub files: u64,
+
+    /// The total size of `files`.
+    pub bytes: u64,
+
+    /// Files not created by Attic, which are left alone.
+    pub foreign_files: u64,
+
+    /// Files and chunks skipped because they are within the grace period.
+    pub recent: u64,
+
+    /// Files that no chunk, staged NAR or build log refers to.
+    pub unreferenced: Vec<UnreferencedFile>,
+
+    /// Unreferenced files that were deleted.
+    pub deleted: u64,
+
+    /// Unreferenced files that failed to be deleted.
+    pub deletions_failed: u64,
+
+    /// Chunks whose files are missing from the storage.
+    pub missing: Vec<MissingFile>,
+
+    /// Valid chunks with missing files that were marked corrupt.
+    pub marked: u64,
+
+    /// Store paths using valid chunks with missing files, by cache.
+    pub damaged_paths: Vec<DamagedPath>,
+}
+
+/// A file that nothing refers to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnreferencedFile {
+    /// Name of the file.
+    pub name: String,
+
+    /// Size of the file in bytes.
+    pub size: u64,
+
+    /// When the file was last modified, in RFC 3339 format.
+    pub modified_at: Option<String>,
+}
+
+/// A chunk whose file is missing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MissingFile {
+    /// The ID of the chunk.
+    pub chunk_id: i64,
+
+    /// The state of the chunk.
+    pub state: String,
+
+    /// Name of the missing file.
+    pub name: String,
+}
+
+/// Reconciles the storage with the database once.
+#[instrument(skip_all)]
+pub async fn run_reconcile(config: Config, options: ReconcileOptions) -> Result<ReconcileReport> {
+    tracing::info!("Reconciling the storage with the database...");
+
+    let state = StateInner::new(config, false).await;
+    let db = state.database().await?;
+    let storage = state.storage().await?;
+    let now = Utc::now();
+    let grace_cutoff = now - chrono::Duration::from_std(options.grace_period)?;
+
+    // Remote file IDs of files in the current storage all share a prefix
+    // followed by the file name, so ordering by ID orders by name.
+    let prefix = storage
+        .make_db_reference(String::new())
+        .await?
+        .remote_file_id();
+    let upper_bound = prefix_upper_bound(&prefix);
+
+    let other_files: HashSet<String> = queries::find_staged_nar_and_build_log_files(db)
+        .await?
+        .iter()
+        .map(|file| file.remote_file_id())
+        .collect();
+
+    let mut report = ReconcileReport::default();
+    let mut unreferenced = Vec::new();
+    let mut missing_valid = Vec::new();
+
+    let mut files = Pages::new();
+    let mut chunks = Pages::new();
+
+    loop {
+        if files.needs_page() {
+            let page = storage.list_files(files.cursor(), PAGE_SIZE).await?;
+            files.push_page(page, |file| file.name.clone());
+        }
+        if chunks.needs_page() {
+            let after = chunks.cursor().unwrap_or(&prefix).to_string();
+            let page =
+                queries::find_chunk_files(db, &after, &upper_bound, PAGE_SIZE as u64).await?;
+            chunks.push_page(page, |chunk| chunk.remote_file_id.clone());
+        }
+
+        let file_name = files.front().map(|file| file.name.as_str());
+        let chunk_name = chunks
+            .front()
+            .map(|chunk| &chunk.remote_file_id[prefix.len()..]);
+
+        match (file_name, chunk_name) {
+            (None, None) => break,
+            (Some(file), Some(chunk)) if file == chunk => {
+                let file = files.pop().unwrap();
+                chunks.pop();
+
+                report.files += 1;
+                report.bytes += file.size;
+            }
+            (Some(file), chunk) if chunk.is_none_or(|chunk| file < chunk) => {
+                let file = files.pop().unwrap();
+
+                report.files += 1;
+                report.bytes += file.size;
+
+                if !MANAGED_SUFFIXES.iter().any(|s| file.name.ends_with(s)) {
+                    report.foreign_files += 1;
+                } else if other_files.contains(&format!("{}{}", prefix, file.name)) {
+                    // Staged NAR or build log
+                } else if file.modified_at.is_none_or(|t| t > grace_cutoff) {
+                    report.recent += 1;
+                } else {
+                    tracing::warn!("{} is not referenced by anything", file.name);
+                    unreferenced.push(file);
+                }
+            }
+            _ => {
+                let chunk = chunks.pop().unwrap();
+
+                if chunk.created_at > grace_cutoff {
+                    report.recent += 1;
+                    continue;
+                }
+
+                let name = chunk.remote_file_id[prefix.len()..].to_string();
+                tracing::warn!("The file of chunk {} is missing: {}", chunk.id, name);
+
+                if chunk.state == ChunkState::Valid {
+                    missing_valid.push(chunk.id);
+                }
+                report.missing.push(MissingFile {
+                    chunk_id: chunk.id,
+                    state: chunk.state.to_db_value().to_string(),
+                    name,
+                });
+            }
+        }
+    }
+
+    for batch in missing_valid.chunks(500) {
+        for (cache, store_path) in queries::find_store_paths_by_chunk_ids(db, batch).await? {
+            report.damaged_paths.push(DamagedPath { cache, store_path });
+        }
+
+        if options.mark_missing {
+            report.marked += queries::mark_chunks_corrupt(db, batch).await?;
+        }
+    }
+    report.damaged_paths.sort();
+    report.damaged_paths.dedup();
+
+    if options.delete_unreferenced {
+        let concurrency = state.config.garbage_collection.deletion_concurrency;
+        delete_files(&***storage, &unreferenced, concurrency, &mut report).await;
+    }
+
+    report.unreferenced = unreferenced
+        .into_iter()
+        .map(|file| UnreferencedFile {
+            name: file.name,
+            size: file.size,
+            modified_at: file.modified_at.map(|t| t.to_rfc3339()),
+        })
+        .collect();
+
+    tracing::info!(
+        "Found {} unreferenced files and {} chunks with missing files",
+        report.unreferenced.len(),
+        report.missing.len()
+    );
+
+    Ok(report)
+}
+
+/// Deletes unreferenced files from the storage.
+async fn delete_files(
+    storage: &dyn StorageBackend,
+    files: &[StoredFile],
+    concurrency: usize,
+    report: &mut ReconcileReport,
+) {
+    let delete_limit = Arc::new(Semaphore::new(concurrency.max(1)));
+    let futures: Vec<_> = files
+        .iter()
+        .map(|file| {
+            let delete_limit = delete_limit.clone();
+            async move {
+                let _permit = delete_limit.acquire().await;
+                let result = storage.delete_file(file.name.clone()).await;
+                (file, result)
+            }
+        })
+        .collect();
+
+    for (file, result) in join_all(futures).await {
+        match result {
+            Ok(()) => report.deleted += 1,
+            Err(e) => {
+                tracing::warn!("Failed to delete {}: {}", file.name, e);
+                report.deletions_failed += 1;
+            }
+        }
+    }
+}
+
+/// Returns the smallest string that sorts after every string starting
+/// with `prefix`.
+fn prefix_upper_bound(prefix: &str) -> String {
+    let mut upper: Vec<char> = prefix.chars().collect();
+
+    while let Some(last) = upper.pop() {
+        if let Some(next) = char::from_u32(last as u32 + 1) {
+            upper.push(next);
+            return upper.into_iter().collect();
+        }
+    }
+
+    // Every string starts with the empty prefix
+    char::MAX.to_string()
+}
+
+/// Pages of items fetched in order.
+struct Pages<T> {
+    items: VecDeque<T>,
+    cursor: Option<String>,
+    done: bool,
+}
+
+impl<T> Pages<T> {
+    fn new() -> Self {
+        Self {
+            items: VecDeque::new(),
+            cursor: None,
+            done: false,
+        }
+    }
+
+    /// Returns whether the next page should be fetched.
+    fn needs_page(&self) -> bool {
+        self.items.is_empty() && !self.done
+    }
+
+    /// Returns the key of the last item fetched.
+    fn cursor(&self) -> Option<&str> {
+        self.cursor.as_deref()
+    }
+
+    /// Adds a page of items.
+    ///
+    /// An empty page marks the end.
+    fn push_page(&mut self, page: Vec<T>, key: impl Fn(&T) -> String) {
+        match page.last() {
+            Some(last) => self.cursor = Some(key(last)),
+            None => self.done = true,
+        }
+
+        self.items.extend(page);
+    }
+
+    fn front(&self) -> Option<&T> {
+        self.items.front()
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.items.pop_front()
+    }
+}
+
+impl fmt::Display for ReconcileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Storage reconciliation report")?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "               Files: {} ({})",
+            self.files,
+            format_bytes(self.bytes)
+        )?;
+        writeln!(f, "       Foreign files: {}", self.foreign_files)?;
+        writeln!(f, "Within grace period: {}", self.recent)?;
+        writeln!(
+            f,
+            "  Unreferenced files: {} ({})",
+            self.unreferenced.len(),
+            format_bytes(self.unreferenced.iter().map(|file| file.size).sum())
+        )?;
+        writeln!(f, "       Missing files: {}", self.missing.len())?;
+
+        if self.deleted != 0 || self.deletions_failed != 0 {
+            writeln!(
+                f,
+                "             Deleted: {} ({} failed)",
+                self.deleted, self.deletions_failed
+            )?;
+        }
+        if self.marked != 0 {
+            writeln!(f, "      Marked corrupt: {}", self.marked)?;
+        }
+
+        if !self.unreferenced.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Unreferenced files:")?;
+            for file in self.unreferenced.iter() {
+                writeln!(
+                    f,
+                    "  {}  {}  {}",
+                    file.name,
+                    format_bytes(file.size),
+                    file.modified_at.as_deref().unwrap_or("-")
+                )?;
+            }
+        }
+
+        if !self.missing.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Chunks with missing files:")?;
+            for file in self.missing.iter() {
+                writeln!(f, "  #{} ({})  {}", file.chunk_id, file.state, file.name)?;
+            }
+        }
+
+        if !self.damaged_paths.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Damaged store paths:")?;
+            for path in self.damaged_paths.iter() {
+                writeln!(f, "  {}  {}", path.cache, path.store_path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!("local;", prefix_upper_bound("local:"));
+        assert_eq!("s3:region/bucket0", prefix_upper_bound("s3:region/bucket/"));
+        assert!("local:zzzz.chunk" < prefix_upper_bound("local:").as_str());
+    }
+}
@@ -0,0 +1,285 @@
+//! Storage scrubbing.
+//!
+//! Nothing re-reads chunks after they are uploaded, so damage to the
+//! storage would otherwise only surface when a client fails to download
+//! a NAR. A scrub streams every chunk back from the storage and checks
+//! the compressed file against its recorded hash and size, then the
+//! decompressed chunk against its own.
+//!
+//! Corrupt chunks are marked and detached from their NARs, which are
+//! flagged as incomplete. Clients then see the affected paths as missing
+//! and push them again, which replaces the chunks. The corrupt chunks
+//! themselves are deleted by garbage collection.
+
+mod report;
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::future::join_all;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, BufReader, ReadBuf};
+use tokio::sync::Semaphore;
+use tokio::time;
+use tracing::instrument;
+
+use super::{State, StateInner};
+use crate::compression::get_file_decompressor;
+use crate::config::Config;
+use crate::database::models::ChunkModel;
+use crate::database::queries;
+use crate::narinfo::Compression;
+use crate::storage::{Download, StorageBackend};
+use attic::hash::Hash;
+use attic::io::HashReader;
+
+pub use report::{ChunkProblem, DamagedPath, ScrubReport};
+
+/// The number of chunks to verify per batch.
+const BATCH_SIZE: u64 = 500;
+
+/// The outcome of verifying a chunk.
+enum Verdict {
+    /// The chunk matches its hashes and sizes.
+    Intact,
+
+    /// The chunk doesn't match its hashes or sizes.
+    Corrupt(String),
+
+    /// The chunk could not be read.
+    Unverified(String),
+
+    /// The storage cannot stream the chunk to us.
+    Skipped,
+}
+
+/// A reader that remembers whether the storage failed.
+///
+/// Errors from the decompressor mean that the chunk is corrupt, but
+/// errors from the storage itself don't.
+struct SourceReader {
+    inner: Box<dyn AsyncRead + Unpin + Send>,
+    failed: Arc<AtomicBool>,
+}
+
+/// Scrubs the storage periodically.
+pub async fn run_scrub(config: Config) {
+    let interval = config.scrub.interval;
+
+    if interval == Duration::ZERO {
+        // disabled
+        return;
+    }
+
+    loop {
+        // We don't stop even if it errors
+        if let Err(e) = run_scrub_once(config.clone(), false).await {
+            tracing::warn!("Scrub failed: {}", e);
+        }
+
+        time::sleep(interval).await;
+    }
+}
+
+/// Scrubs the storage once.
+///
+/// In a dry run, corrupt chunks are only reported.
+#[instrument(skip_all)]
+pub async fn run_scrub_once(config: Config, dry_run: bool) -> Result<ScrubReport> {
+    tracing::info!("Scrubbing the storage...");
+
+    let state = StateInner::new(config, false).await;
+
+    let mut report = ScrubReport::new(dry_run);
+    scrub_storage(&state, &mut report).await?;
+
+    Ok(report)
+}
+
+async fn scrub_storage(state: &State, report: &mut ScrubReport) -> Result<()> {
+    let db = state.database().await?;
+    let storage = state.storage().await?;
+    let verify_limit = Arc::new(Semaphore::new(state.config.scrub.concurrency.max(1)));
+
+    let mut after_id = 0;
+    loop {
+        let chunks = queries::find_valid_chunks(db, after_id, BATCH_SIZE).await?;
+        let Some(last) = chunks.last() else {
+            break;
+        };
+        after_id = last.id;
+
+        let futures: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                let verify_limit = verify_limit.clone();
+                let storage = storage.clone();
+                async move {
+                    let permit = verify_limit.acquire().await?;
+                    let verdict = verify_chunk(&**storage, &chunk).await;
+                    drop(permit);
+                    Result::<_, anyhow::Error>::Ok((chunk, verdict))
+                }
+            })
+            .collect();
+
+        let mut corrupt_ids = Vec::new();
+        for result in join_all(futures).await {
+            let (chunk, verdict) = result?;
+            let stored_size = chunk.file_size.unwrap_or(chunk.chunk_size) as u64;
+
+            match verdict {
+                Verdict::Intact => {
+                    report.chunks += 1;
+                    report.bytes += stored_size;
+                }
+                Verdict::Corrupt(problem) => {
+                    tracing::warn!("Chunk {} is corrupt: {}", chunk.id, problem);
+
+                    report.chunks += 1;
+                    report.bytes += stored_size;
+                    report.corrupt.push(ChunkProblem {
+                        id: chunk.id,
+                        chunk_hash: chunk.chunk_hash,
+                        problem,
+                    });
+                    corrupt_ids.push(chunk.id);
+                }
+                Verdict::Unverified(problem) => {
+                    tracing::warn!("Chunk {} could not be verified: {}", chunk.id, problem);
+
+                    report.unverified.push(ChunkProblem {
+                        id: chunk.id,
+                        chunk_hash: chunk.chunk_hash,
+                        problem,
+                    });
+                }
+                Verdict::Skipped => {
+                    report.skipped += 1;
+                }
+            }
+        }
+
+        if corrupt_ids.is_empty() {
+            continue;
+        }
+
+        for (cache, store_path) in queries::find_store_paths_by_chunk_ids(db, &corrupt_ids).await? {
+            tracing::warn!("{} in cache {} is damaged", store_path, cache);
+            report.damaged_paths.push(DamagedPath { cache, store_path });
+        }
+
+        if !report.dry_run {
+            queries::mark_chunks_corrupt(db, &corrupt_ids).await?;
+        }
+    }
+
+    // A path can use corrupt chunks from several batches
+    report.damaged_paths.sort();
+    report.damaged_paths.dedup();
+
+    tracing::info!(
+        "Verified {} chunks, {} corrupt, {} unverified, {} damaged store paths",
+        report.chunks,
+        report.corrupt.len(),
+        report.unverified.len(),
+        report.damaged_paths.len()
+    );
+
+    Ok(())
+}
+
+/// Reads a chunk back from the storage and checks it.
+async fn verify_chunk(storage: &dyn StorageBackend, chunk: &ChunkModel) -> Verdict {
+    let compression: Compression = match chunk.compression.parse() {
+        Ok(compression) => compression,
+        Err(e) => return Verdict::Unverified(e.to_string()),
+    };
+
+    let stream = match storage.download_file_db(&chunk.remote_file.0, true).await {
+        Ok(Download::AsyncRead(stream)) => stream,
+        Ok(Download::Url(_)) => return Verdict::Skipped,
+        Err(e) => return Verdict::Unverified(e.to_string()),
+    };
+
+    let failed = Arc::new(AtomicBool::new(false));
+    let stream = SourceReader {
+        inner: stream,
+        failed: failed.clone(),
+    };
+
+    let (stream, file_compute) = HashReader::new(stream, Sha256::new());
+    let stream = match get_file_decompressor(compression, BufReader::new(stream)) {
+        Ok(stream) => stream,
+        Err(e) => return Verdict::Unverified(e.to_string()),
+    };
+    let (mut stream, chunk_compute) = HashReader::new(stream, Sha256::new());
+
+    if let Err(e) = tokio::io::copy(&mut stream, &mut tokio::io::sink()).await {
+        if failed.load(Ordering::Relaxed) {
+            return Verdict::Unverified(e.to_string());
+        }
+
+        return Verdict::Corrupt(format!("Failed to decompress: {}", e));
+    }
+
+    let (Some((file_hash, file_size)), Some((chunk_hash, chunk_size))) =
+        (file_compute.get(), chunk_compute.get())
+    else {
+        return Verdict::Unverified("The chunk was not read to the end".to_string());
+    };
+
+    if let Some(expected) = chunk.file_size {
+        if *file_size as i64 != expected {
+            return Verdict::Corrupt(format!(
+                "File size is {} bytes, expected {}",
+                file_size, expected
+            ));
+        }
+    }
+
+    if let Some(expected) = &chunk.file_hash {
+        let file_hash = Hash::Sha256(file_hash.as_slice().try_into().unwrap()).to_typed_base16();
+        if file_hash != *expected {
+            return Verdict::Corrupt(format!("File hash is {}, expected {}", file_hash, expected));
+        }
+    }
+
+    if *chunk_size as i64 != chunk.chunk_size {
+        return Verdict::Corrupt(format!(
+            "Chunk size is {} bytes, expected {}",
+            chunk_size, chunk.chunk_size
+        ));
+    }
+
+    let chunk_hash = Hash::Sha256(chunk_hash.as_slice().try_into().unwrap()).to_typed_base16();
+    if chunk_hash != chunk.chunk_hash {
+        return Verdict::Corrupt(format!(
+            "Chunk hash is {}, expected {}",
+            chunk_hash, chunk.chunk_hash
+        ));
+    }
+
+    Verdict::Intact
+}
+
+impl AsyncRead for SourceReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Err(_)) = poll {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+
+        poll
+    }
+}
@@ -0,0 +1,166 @@
+//! Storage scrub reports.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::gc::format_bytes;
+
+/// What a storage scrub found.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrubReport {
+    /// Whether corrupt chunks were left in service.
+    pub dry_run: bool,
+
+    /// Chunks that were read back and verified.
+    pub chunks: u64,
+
+    /// The stored size of `chunks`.
+    pub bytes: u64,
+
+    /// Chunks that the storage cannot stream back to the server.
+    pub skipped: u64,
+
+    /// Chunks that no longer match their recorded hashes or sizes.
+    pub corrupt: Vec<ChunkProblem>,
+
+    /// Chunks that could not be read from the storage.
+    ///
+    /// The storage may just be unavailable, so they are left alone
+    /// and checked again by the next scrub.
+    pub unverified: Vec<ChunkProblem>,
+
+    /// Store paths using corrupt chunks, by cache.
+    pub damaged_paths: Vec<DamagedPath>,
+}
+
+/// A chunk that failed verification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkProblem {
+    /// The ID of the chunk.
+    pub id: i64,
+
+    /// The hash of the uncompressed chunk.
+    pub chunk_hash: String,
+
+    /// What went wrong.
+    pub problem: String,
+}
+
+/// A store path that can no longer be served in full.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DamagedPath {
+    /// The name of the cache.
+    pub cache: String,
+
+    /// The store path.
+    pub store_path: String,
+}
+
+impl ScrubReport {
+    /// Creates an empty report.
+    pub fn new(dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Display for ScrubReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.dry_run {
+            writeln!(f, "Scrub report (dry run, nothing was marked)")?;
+        } else {
+            writeln!(f, "Scrub report")?;
+        }
+        writeln!(f)?;
+
+        writeln!(
+            f,
+            "   Verified chunks: {} ({})",
+            self.chunks,
+            format_bytes(self.bytes)
+        )?;
+        writeln!(f, "    Corrupt chunks: {}", self.corrupt.len())?;
+        writeln!(f, " Unverified chunks: {}", self.unverified.len())?;
+
+        if self.skipped != 0 {
+            writeln!(f, "    Skipped chunks: {}", self.skipped)?;
+        }
+
+        for (title, chunks) in [
+            ("Corrupt chunks:", &self.corrupt),
+            ("Unverified chunks:", &self.unverified),
+        ] {
+            if chunks.is_empty() {
+                continue;
+            }
+
+            writeln!(f)?;
+            writeln!(f, "{}", title)?;
+            for chunk in chunks.iter() {
+                writeln!(f, "  #{} {}: {}", chunk.id, chunk.chunk_hash, chunk.problem)?;
+            }
+        }
+
+        writeln!(f)?;
+        if self.damaged_paths.is_empty() {
+            writeln!(f, "No damaged store paths")?;
+            return Ok(());
+        }
+
+        let width = self
+            .damaged_paths
+            .iter()
+            .map(|path| path.cache.len())
+            .chain(std::iter::once("CACHE".len()))
+            .max()
+            .unwrap_or_default();
+
+        writeln!(f, "{:<width$}  STORE PATH", "CACHE", width = width)?;
+        for path in self.damaged_paths.iter() {
+            writeln!(
+                f,
+                "{:<width$}  {}",
+                path.cache,
+                path.store_path,
+                width = width
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let report = ScrubReport {
+            dry_run: true,
+            chunks: 3,
+            bytes: 4096,
+            corrupt: vec![ChunkProblem {
+                id: 2,
+                chunk_hash: "sha256:abcd".to_string(),
+                problem: "File size is 10 bytes, expected 12".to_string(),
+            }],
+            damaged_paths: vec![DamagedPath {
+                cache: "hello".to_string(),
+                store_path: "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-a".to_string(),
+            }],
+            ..Default::default()
+        };
+
+        let table = report.to_string();
+        assert!(table.contains("dry run"));
+        assert!(table.contains("Verified chunks: 3 (4.0 KiB)"));
+        assert!(table.contains("#2 sha256:abcd: File size is 10 bytes, expected 12"));
+        assert!(table.contains("CACHE  STORE PATH"));
+        assert!(table.contains("hello  /nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-a"));
+        assert!(!table.contains("Skipped"));
+    }
+}
@@ -0,0 +1,162 @@
+//! External signing agents.
+//!
+//! A signing agent holds the keypair of a cache in its own process, so
+//! the keypair never touches the server. The server connects to the Unix
+//! socket of the agent and sends it the fingerprints to sign.
+//!
+//! ## Protocol
+//!
+//! Requests and responses are JSON objects, one per line. Each request
+//! is answered in order, and a connection can carry any number of them:
+//!
+//! ```text
+//! -> {"fingerprint":"1;/nix/store/...;sha256:...;1234;"}
+//! <- {"signature":"my-cache-1:..."}
+//! ```
+//!
+//! An agent that refuses to sign answers with `{"error":"..."}` instead.
+//!
+//! [`serve`] is the reference implementation of an agent, which
+//! `attic-signing-agent` runs.
+
+use std::io;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use super::Signer;
+use crate::config::SigningAgentConfig;
+use crate::error::{ErrorKind, ServerResult};
+use attic::signing::NixKeypair;
+
+/// A request to sign a fingerprint.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignRequest {
+    /// The fingerprint to sign.
+    pub fingerprint: String,
+}
+
+/// The answer to a [`SignRequest`].
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignResponse {
+    /// The signature of the fingerprint.
+    Signature(String),
+
+    /// Why the fingerprint wasn't signed.
+    Error(String),
+}
+
+/// A signer that asks an agent to sign.
+#[derive(Debug, Clone)]
+pub struct AgentSigner {
+    config: SigningAgentConfig,
+}
+
+impl AgentSigner {
+    pub fn new(config: SigningAgentConfig) -> Self {
+        Self { config }
+    }
+
+    async fn request(&self, fingerprint: &str) -> anyhow::Result<String> {
+        let stream = UnixStream::connect(&self.config.socket_path)
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Cannot connect to {}: {}",
+                    self.config.socket_path.display(),
+                    e
+                )
+            })?;
+        let (reader, mut writer) = stream.into_split();
+
+        let request = SignRequest {
+            fingerprint: fingerprint.to_owned(),
+        };
+        let mut line = serde_json::to_string(&request)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+
+        let mut response = String::new();
+        BufReader::new(reader).read_line(&mut response).await?;
+        if response.is_empty() {
+            return Err(anyhow!("The agent closed the connection"));
+        }
+
+        match serde_json::from_str(&response)? {
+            SignResponse::Signature(signature) => Ok(signature),
+            SignResponse::Error(e) => Err(anyhow!("The agent refused to sign: {}", e)),
+        }
+    }
+}
+
+#[async_trait]
+impl Signer for AgentSigner {
+    fn public_key(&self) -> String {
+        self.config.public_key.export()
+    }
+
+    async fn sign(&self, fingerprint: &[u8]) -> ServerResult<String> {
+        let result = async {
+            let fingerprint = std::str::from_utf8(fingerprint)?;
+
+            let signature = tokio::time::timeout(self.config.timeout, self.request(fingerprint))
+                .await
+                .map_err(|_| {
+                    anyhow!(
+                        "The agent didn't answer within {}",
+                        humantime::format_duration(self.config.timeout)
+                    )
+                })??;
+
+            // Don't serve signatures that clients would reject
+            self.config
+                .public_key
+                .verify(fingerprint.as_bytes(), &signature)
+                .map_err(|e| anyhow!("The agent returned an invalid signature: {}", e))?;
+
+            Ok(signature)
+        }
+        .await;
+
+        result.map_err(|e| ErrorKind::SigningError(e).into())
+    }
+}
+
+/// Answers signing requests on a socket with a keypair.
+pub async fn serve(listener: UnixListener, keypair: NixKeypair) -> io::Result<()> {
+    let keypair = Arc::new(keypair);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let keypair = keypair.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &keypair).await {
+                tracing::warn!("Signing connection failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: UnixStream, keypair: &NixKeypair) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let response = match serde_json::from_str::<SignRequest>(&line) {
+            Ok(request) => SignResponse::Signature(keypair.sign(request.fingerprint.as_bytes())),
+            Err(e) => SignResponse::Error(format!("Invalid request: {}", e)),
+        };
+
+        let mut line = serde_json::to_string(&response)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+    }
+
+    Ok(())
+}
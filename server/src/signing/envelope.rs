@@ -0,0 +1,433 @@
+//! Envelope encryption of signing keys.
+//!
+//! Each signing keypair is encrypted with a random data key, and the data
+//! key is encrypted with a master key from the configuration. Both use
+//! AES-256-GCM. Rotating the master key only needs the data keys to be
+//! re-encrypted, which is what `atticadm keys rewrap` does.
+//!
+//! An encrypted keypair is stored in place of the plaintext one, as:
+//!
+//! ```text
+//! attic-sealed:v1:{master key ID}:{encrypted data key}:{encrypted keypair}
+//! ```
+//!
+//! Both encryptions are bound to the [`KeypairSlot`] the keypair is stored
+//! in, so an encrypted keypair copied to another cache can't be opened.
+//!
+//! Plaintext keypairs, such as those stored before a master key was
+//! configured, are still accepted. The configured master keys are kept
+//! as [`MasterKeys`] in the server state and passed to wherever keypairs
+//! are sealed or opened.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::config::SigningKeysConfig;
+use crate::error::{ErrorKind, ServerError, ServerResult};
+use attic::signing::NixKeypair;
+
+/// The prefix of encrypted keypairs.
+const PREFIX: &str = "attic-sealed:v1:";
+
+/// The length of keys, in bytes.
+const KEY_LENGTH: usize = 32;
+
+/// The length of nonces, in bytes.
+const NONCE_LENGTH: usize = 12;
+
+/// A key that data keys are encrypted with.
+#[derive(Clone)]
+pub struct MasterKey {
+    id: String,
+    key: [u8; KEY_LENGTH],
+}
+
+impl MasterKey {
+    /// Decodes a base64-encoded master key.
+    pub fn from_base64(s: &str) -> anyhow::Result<Self> {
+        let key: [u8; KEY_LENGTH] = BASE64_STANDARD
+            .decode(s.trim())?
+            .try_into()
+            .map_err(|_| anyhow!("The master key must be {} bytes long", KEY_LENGTH))?;
+
+        Ok(Self::new(key))
+    }
+
+    /// Generates a random master key.
+    pub fn generate() -> Self {
+        let mut key = [0; KEY_LENGTH];
+        OsRng.fill_bytes(&mut key);
+        Self::new(key)
+    }
+
+    fn new(key: [u8; KEY_LENGTH]) -> Self {
+        let id = hex::encode(&Sha256::digest(key)[..8]);
+        Self { id, key }
+    }
+
+    /// Returns the ID of the key, which is derived from the key itself.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the base64-encoded key.
+    pub fn to_base64(&self) -> String {
+        BASE64_STANDARD.encode(self.key)
+    }
+}
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey").field("id", &self.id).finish()
+    }
+}
+
+/// The configured master keys.
+#[derive(Debug, Clone, Default)]
+pub struct MasterKeys {
+    /// The key that keypairs are encrypted with, if any.
+    current: Option<MasterKey>,
+
+    /// All keys that keypairs can be decrypted with, by ID.
+    known: HashMap<String, MasterKey>,
+}
+
+impl MasterKeys {
+    pub fn new(config: &SigningKeysConfig) -> Self {
+        let known = config
+            .master_key
+            .iter()
+            .chain(config.previous_master_keys.iter())
+            .map(|key| (key.id.clone(), key.clone()))
+            .collect();
+
+        Self {
+            current: config.master_key.clone(),
+            known,
+        }
+    }
+
+    /// Returns the key that keypairs are encrypted with, if any.
+    pub fn current(&self) -> Option<&MasterKey> {
+        self.current.as_ref()
+    }
+}
+
+/// Where a keypair is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeypairSlot {
+    /// The `keypair` column of the cache with the ID.
+    Cache(i64),
+
+    /// The `keypair` column of a key of the cache with the ID.
+    CacheKey(i64),
+}
+
+impl KeypairSlot {
+    /// Returns the associated data the encryption is bound to.
+    fn aad(&self) -> String {
+        match self {
+            Self::Cache(cache_id) => format!("{}cache.keypair:{}", PREFIX, cache_id),
+            Self::CacheKey(cache_id) => format!("{}cache_key.keypair:{}", PREFIX, cache_id),
+        }
+    }
+}
+
+/// Returns whether a stored keypair is encrypted.
+pub fn is_sealed(stored: &str) -> bool {
+    stored.starts_with(PREFIX)
+}
+
+/// Returns the ID of the master key a stored keypair is encrypted with.
+pub fn master_key_id(stored: &str) -> Option<&str> {
+    Envelope::parse(stored)
+        .ok()
+        .map(|envelope| envelope.master_key_id)
+}
+
+/// Prepares a keypair for storage.
+///
+/// The keypair is encrypted if a master key is configured.
+pub fn seal(keys: &MasterKeys, keypair: &NixKeypair, slot: KeypairSlot) -> ServerResult<String> {
+    let Some(master_key) = keys.current() else {
+        return Ok(keypair.export_keypair());
+    };
+
+    let mut data_key = [0; KEY_LENGTH];
+    OsRng.fill_bytes(&mut data_key);
+
+    let aad = slot.aad();
+    let sealed_keypair = encrypt(&data_key, keypair.export_keypair().as_bytes(), &aad)?;
+    let sealed_data_key = encrypt(&master_key.key, &data_key, &aad)?;
+
+    Ok(format!(
+        "{}{}:{}:{}",
+        PREFIX,
+        master_key.id,
+        BASE64_STANDARD.encode(sealed_data_key),
+        BASE64_STANDARD.encode(sealed_keypair),
+    ))
+}
+
+/// Reads a stored keypair, decrypting it if needed.
+pub fn open(keys: &MasterKeys, stored: &str, slot: KeypairSlot) -> ServerResult<NixKeypair> {
+    if !is_sealed(stored) {
+        return Ok(NixKeypair::from_str(stored)?);
+    }
+
+    let aad = slot.aad();
+    let envelope = Envelope::parse(stored)?;
+    let data_key = envelope.data_key(keys, &aad)?;
+    let keypair = decrypt(&data_key, &envelope.sealed_keypair, &aad)?;
+    let keypair = String::from_utf8(keypair).map_err(|_| corrupt())?;
+
+    Ok(NixKeypair::from_str(&keypair)?)
+}
+
+/// Encrypts the data key of a stored keypair with the configured master
+/// key, or encrypts the keypair if it's stored in plaintext.
+///
+/// Returns `None` if nothing needs to be changed.
+pub fn rewrap(keys: &MasterKeys, stored: &str, slot: KeypairSlot) -> ServerResult<Option<String>> {
+    let Some(master_key) = keys.current() else {
+        return Err(ErrorKind::RequestError(anyhow!("No master key is configured")).into());
+    };
+
+    if !is_sealed(stored) {
+        let keypair = NixKeypair::from_str(stored)?;
+        return seal(keys, &keypair, slot).map(Some);
+    }
+
+    let envelope = Envelope::parse(stored)?;
+    if envelope.master_key_id == master_key.id {
+        return Ok(None);
+    }
+
+    let aad = slot.aad();
+    let data_key = envelope.data_key(keys, &aad)?;
+    let sealed_data_key = encrypt(&master_key.key, &data_key, &aad)?;
+
+    Ok(Some(format!(
+        "{}{}:{}:{}",
+        PREFIX,
+        master_key.id,
+        BASE64_STANDARD.encode(sealed_data_key),
+        BASE64_STANDARD.encode(envelope.sealed_keypair),
+    )))
+}
+
+/// The parts of an encrypted keypair.
+struct Envelope<'a> {
+    master_key_id: &'a str,
+    sealed_data_key: Vec<u8>,
+    sealed_keypair: Vec<u8>,
+}
+
+impl<'a> Envelope<'a> {
+    fn parse(stored: &'a str) -> ServerResult<Self> {
+        let rest = stored.strip_prefix(PREFIX).ok_or_else(corrupt)?;
+        let mut parts = rest.split(':');
+
+        let (Some(master_key_id), Some(sealed_data_key), Some(sealed_keypair), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(corrupt());
+        };
+
+        Ok(Self {
+            master_key_id,
+            sealed_data_key: BASE64_STANDARD
+                .decode(sealed_data_key)
+                .map_err(|_| corrupt())?,
+            sealed_keypair: BASE64_STANDARD
+                .decode(sealed_keypair)
+                .map_err(|_| corrupt())?,
+        })
+    }
+
+    /// Decrypts the data key with the master key it was encrypted with.
+    fn data_key(&self, keys: &MasterKeys, aad: &str) -> ServerResult<[u8; KEY_LENGTH]> {
+        let master_key = keys.known.get(self.master_key_id).ok_or_else(|| {
+            ServerError::from(ErrorKind::DatabaseError(anyhow!(
+                "A signing key is encrypted with the unknown master key {}",
+                self.master_key_id
+            )))
+        })?;
+
+        decrypt(&master_key.key, &self.sealed_data_key, aad)?
+            .try_into()
+            .map_err(|_| corrupt())
+    }
+}
+
+/// Encrypts a message bound to `aad`, prepending the nonce.
+fn encrypt(key: &[u8; KEY_LENGTH], message: &[u8], aad: &str) -> ServerResult<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+
+    let mut nonce = [0; NONCE_LENGTH];
+    OsRng.fill_bytes(&mut nonce);
+
+    let payload = Payload {
+        msg: message,
+        aad: aad.as_bytes(),
+    };
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), payload)
+        .map_err(|_| ErrorKind::InternalServerError)?;
+
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+/// Decrypts a message encrypted by `encrypt`.
+fn decrypt(key: &[u8; KEY_LENGTH], sealed: &[u8], aad: &str) -> ServerResult<Vec<u8>> {
+    if sealed.len() < NONCE_LENGTH {
+        return Err(corrupt());
+    }
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+
+    let payload = Payload {
+        msg: ciphertext,
+        aad: aad.as_bytes(),
+    };
+    cipher
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| corrupt())
+}
+
+fn corrupt() -> ServerError {
+    ErrorKind::DatabaseError(anyhow!("A stored signing key cannot be decrypted")).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLOT: KeypairSlot = KeypairSlot::Cache(1);
+
+    fn keys(master_key: &MasterKey, previous: &[&MasterKey]) -> MasterKeys {
+        MasterKeys::new(&SigningKeysConfig {
+            master_key: Some(master_key.clone()),
+            previous_master_keys: previous.iter().map(|&key| key.clone()).collect(),
+        })
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let keys = keys(&MasterKey::generate(), &[]);
+        let keypair = NixKeypair::generate("test-cache").unwrap();
+
+        let stored = seal(&keys, &keypair, SLOT).unwrap();
+        assert!(is_sealed(&stored));
+        assert!(!stored.contains(&keypair.export_keypair()));
+        assert_eq!(keys.current().map(MasterKey::id), master_key_id(&stored));
+
+        let opened = open(&keys, &stored, SLOT).unwrap();
+        assert_eq!(keypair.export_keypair(), opened.export_keypair());
+    }
+
+    #[test]
+    fn test_plaintext() {
+        let keypair = NixKeypair::generate("test-cache").unwrap();
+
+        let stored = seal(&MasterKeys::default(), &keypair, SLOT).unwrap();
+        assert_eq!(keypair.export_keypair(), stored);
+        assert!(!is_sealed(&stored));
+
+        let opened = open(&MasterKeys::default(), &stored, SLOT).unwrap();
+        assert_eq!(keypair.export_keypair(), opened.export_keypair());
+    }
+
+    #[test]
+    fn test_rewrap() {
+        let old_key = MasterKey::generate();
+        let new_key = MasterKey::generate();
+        let keypair = NixKeypair::generate("test-cache").unwrap();
+
+        let stored = seal(&keys(&old_key, &[]), &keypair, SLOT).unwrap();
+
+        let keys = keys(&new_key, &[&old_key]);
+        let rewrapped = rewrap(&keys, &stored, SLOT).unwrap().unwrap();
+        assert_eq!(Some(new_key.id()), master_key_id(&rewrapped));
+        assert_eq!(
+            keypair.export_keypair(),
+            open(&keys, &rewrapped, SLOT).unwrap().export_keypair()
+        );
+
+        // Only the new master key is needed from now on
+        let new_keys = MasterKeys::new(&SigningKeysConfig {
+            master_key: Some(new_key.clone()),
+            previous_master_keys: Vec::new(),
+        });
+        assert!(open(&new_keys, &rewrapped, SLOT).is_ok());
+        assert!(open(&new_keys, &stored, SLOT).is_err());
+
+        // Already encrypted with the master key
+        assert!(rewrap(&keys, &rewrapped, SLOT).unwrap().is_none());
+
+        // Plaintext keypairs are encrypted
+        let sealed = rewrap(&keys, &keypair.export_keypair(), SLOT)
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(new_key.id()), master_key_id(&sealed));
+    }
+
+    #[test]
+    fn test_unknown_master_key() {
+        let keypair = NixKeypair::generate("test-cache").unwrap();
+
+        let stored = seal(&keys(&MasterKey::generate(), &[]), &keypair, SLOT).unwrap();
+        assert!(open(&keys(&MasterKey::generate(), &[]), &stored, SLOT).is_err());
+        assert!(open(&MasterKeys::default(), &stored, SLOT).is_err());
+    }
+
+    #[test]
+    fn test_bound_to_slot() {
+        let old_key = MasterKey::generate();
+        let keypair = NixKeypair::generate("test-cache").unwrap();
+
+        let old_keys = keys(&old_key, &[]);
+        let stored = seal(&old_keys, &keypair, SLOT).unwrap();
+        assert!(open(&old_keys, &stored, KeypairSlot::Cache(2)).is_err());
+        assert!(open(&old_keys, &stored, KeypairSlot::CacheKey(1)).is_err());
+
+        // Rewrapping keeps the binding
+        let new_keys = keys(&MasterKey::generate(), &[&old_key]);
+        let rewrapped = rewrap(&new_keys, &stored, SLOT).unwrap().unwrap();
+        assert!(open(&new_keys, &rewrapped, SLOT).is_ok());
+        assert!(open(&new_keys, &rewrapped, KeypairSlot::Cache(2)).is_err());
+        assert!(rewrap(&new_keys, &stored, KeypairSlot::Cache(2)).is_err());
+    }
+
+    #[test]
+    fn test_tampered() {
+        let keys = keys(&MasterKey::generate(), &[]);
+        let keypair = NixKeypair::generate("test-cache").unwrap();
+
+        let stored = seal(&keys, &keypair, SLOT).unwrap();
+        let (rest, sealed_keypair) = stored.rsplit_once(':').unwrap();
+        let mut bytes = BASE64_STANDARD.decode(sealed_keypair).unwrap();
+        bytes[NONCE_LENGTH] ^= 1;
+        let tampered = format!("{}:{}", rest, BASE64_STANDARD.encode(bytes));
+
+        assert!(open(&keys, &tampered, SLOT).is_err());
+    }
+
+    #[test]
+    fn test_master_key_from_base64() {
+        let key = MasterKey::generate();
+        let decoded = MasterKey::from_base64(&key.to_base64()).unwrap();
+        assert_eq!(key.id(), decoded.id());
+
+        assert!(MasterKey::from_base64("c2hvcnQ=").is_err());
+    }
+}
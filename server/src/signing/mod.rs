@@ -0,0 +1,341 @@
+//! Signing keys of caches.
+//!
+//! Each cache has a primary keypair, which it always signs with. Key
+//! rotations add more keys: a key is first staged as the next key, so
+//! clients can trust it before anything is signed with it, then becomes
+//! the primary keypair while the old one stays active, and the old one is
+//! finally retired. While more than one key is active, paths are signed
+//! with all of them, and Nix accepts any signature from a trusted key.
+//!
+//! Keypairs are stored encrypted if a master key is configured, see
+//! [`envelope`]. A cache can instead be signed by an external agent that
+//! holds its keypair, see [`agent`].
+
+pub mod agent;
+pub mod envelope;
+
+use std::fmt::Debug;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use attic::api::v1::cache_config::CacheKey;
+use attic::signing::NixKeypair;
+
+use crate::config::Config;
+use crate::database::connection::TursoConnection;
+use crate::database::models::{CacheKeyModel, CacheKeyState, CacheModel};
+use crate::database::queries;
+use crate::error::ServerResult;
+use crate::StateInner;
+use agent::AgentSigner;
+use envelope::{KeypairSlot, MasterKeys};
+
+/// Something that signs fingerprints.
+#[async_trait]
+pub trait Signer: Debug + Send + Sync {
+    /// Returns the public key that verifies the signatures.
+    fn public_key(&self) -> String;
+
+    /// Signs a fingerprint, returning the signature.
+    async fn sign(&self, fingerprint: &[u8]) -> ServerResult<String>;
+}
+
+#[async_trait]
+impl Signer for NixKeypair {
+    fn public_key(&self) -> String {
+        self.export_public_key()
+    }
+
+    async fn sign(&self, fingerprint: &[u8]) -> ServerResult<String> {
+        Ok(NixKeypair::sign(self, fingerprint))
+    }
+}
+
+/// The signing keys of a cache.
+#[derive(Debug)]
+pub struct CacheKeys {
+    /// The primary keypair of the cache.
+    primary: NixKeypair,
+
+    /// The keys of rotations, oldest first.
+    keys: Vec<(CacheKeyModel, NixKeypair)>,
+
+    /// The agent that signs instead of the keypairs, if any.
+    agent: Option<AgentSigner>,
+}
+
+impl CacheKeys {
+    /// Loads and decrypts the signing keys of a cache.
+    pub async fn load(
+        conn: &TursoConnection,
+        state: &StateInner,
+        cache: &CacheModel,
+    ) -> ServerResult<Self> {
+        let keys = queries::find_cache_keys(conn, cache.id)
+            .await?
+            .into_iter()
+            .map(|key| {
+                let keypair = key.keypair(&state.master_keys)?;
+                Ok((key, keypair))
+            })
+            .collect::<ServerResult<_>>()?;
+
+        let agent = state
+            .config
+            .signing_agents
+            .iter()
+            .find(|(name, _)| name.as_str() == cache.name)
+            .map(|(_, agent)| AgentSigner::new(agent.clone()));
+
+        Ok(Self {
+            primary: cache.keypair(&state.master_keys)?,
+            keys,
+            agent,
+        })
+    }
+
+    /// Returns the signers to sign with, the primary one first.
+    pub fn signers(&self) -> Vec<&dyn Signer> {
+        if let Some(agent) = &self.agent {
+            return vec![agent];
+        }
+
+        let mut signers: Vec<&dyn Signer> = vec![&self.primary];
+        for (_, keypair) in self.others(CacheKeyState::Active).rev() {
+            signers.push(keypair);
+        }
+        signers
+    }
+
+    /// Returns whether the cache is signed by an agent.
+    pub fn is_external(&self) -> bool {
+        self.agent.is_some()
+    }
+
+    /// Returns the public keys clients should trust, the primary one first.
+    ///
+    /// This includes the next key, if one is staged.
+    pub fn trusted_public_keys(&self) -> Vec<String> {
+        self.to_api()
+            .into_iter()
+            .filter(|key| key.state != CacheKeyState::Retired.into())
+            .map(|key| key.public_key)
+            .collect()
+    }
+
+    /// Returns all keys with their states, the primary one first.
+    pub fn to_api(&self) -> Vec<CacheKey> {
+        if let Some(agent) = &self.agent {
+            return vec![CacheKey {
+                public_key: agent.public_key(),
+                state: CacheKeyState::Active.into(),
+            }];
+        }
+
+        let mut keys = vec![CacheKey {
+            public_key: self.primary.export_public_key(),
+            state: CacheKeyState::Active.into(),
+        }];
+
+        for state in [
+            CacheKeyState::Next,
+            CacheKeyState::Active,
+            CacheKeyState::Retired,
+        ] {
+            for (_, keypair) in self.others(state).rev() {
+                keys.push(CacheKey {
+                    public_key: keypair.export_public_key(),
+                    state: state.into(),
+                });
+            }
+        }
+
+        keys
+    }
+
+    /// Returns the staged next key, if any.
+    pub fn next(&self) -> Option<&CacheKeyModel> {
+        self.others(CacheKeyState::Next).next().map(|(key, _)| key)
+    }
+
+    /// Returns the row of the primary keypair, if it has one.
+    ///
+    /// The original keypair of a cache only gets one once it's rotated.
+    pub fn primary_row(&self) -> Option<&CacheKeyModel> {
+        self.keys
+            .iter()
+            .find(|(_, keypair)| self.is_primary(keypair))
+            .map(|(key, _)| key)
+    }
+
+    /// Returns the active keys other than the primary one.
+    pub fn secondary(&self) -> impl DoubleEndedIterator<Item = &CacheKeyModel> {
+        self.others(CacheKeyState::Active).map(|(key, _)| key)
+    }
+
+    /// Returns a name for a new key that no key of the cache has.
+    ///
+    /// Nix looks up trusted public keys by name, so keys that are
+    /// trusted at the same time must have different names.
+    pub fn next_key_name(&self, cache_name: &str) -> String {
+        let prefix = format!("{}-", cache_name);
+
+        let n = std::iter::once(&self.primary)
+            .chain(self.keys.iter().map(|(_, keypair)| keypair))
+            .filter_map(|keypair| keypair.name().strip_prefix(&prefix)?.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0);
+
+        format!("{}{}", prefix, n + 1)
+    }
+
+    /// Returns whether a keypair is the primary one.
+    ///
+    /// The same keypair may be stored differently in the cache and in
+    /// its row when encrypted, so they are compared by public key.
+    fn is_primary(&self, keypair: &NixKeypair) -> bool {
+        keypair.export_public_key() == self.primary.export_public_key()
+    }
+
+    /// Returns the keys in a state other than the primary one.
+    fn others(
+        &self,
+        state: CacheKeyState,
+    ) -> impl DoubleEndedIterator<Item = &(CacheKeyModel, NixKeypair)> {
+        self.keys
+            .iter()
+            .filter(move |(key, keypair)| key.state == state && !self.is_primary(keypair))
+    }
+}
+
+/// Seals and stores the keypair of a cache that was just created.
+///
+/// Encrypted keypairs are bound to the ID of their cache, which is only
+/// known once the cache exists. Caches are therefore created with an
+/// empty keypair and given their keypair in the same transaction.
+pub async fn store_new_cache_keypair(
+    conn: &TursoConnection,
+    keys: &MasterKeys,
+    cache_id: i64,
+    keypair: &NixKeypair,
+) -> ServerResult<()> {
+    let stored = envelope::seal(keys, keypair, KeypairSlot::Cache(cache_id))?;
+    queries::replace_cache_keypair(conn, cache_id, "", &stored).await?;
+    Ok(())
+}
+
+/// The outcome of encrypting stored keypairs.
+#[derive(Debug, Default, Serialize)]
+pub struct RewrapReport {
+    /// Keypairs that were stored in plaintext and are now encrypted.
+    pub sealed: usize,
+
+    /// Keypairs now encrypted with the current master key.
+    pub rewrapped: usize,
+
+    /// Keypairs that didn't need to be changed.
+    pub unchanged: usize,
+
+    /// Keypairs that couldn't be decrypted.
+    pub failed: usize,
+}
+
+/// Encrypts the keypairs stored in plaintext.
+pub async fn seal_plaintext_keys(
+    conn: &TursoConnection,
+    keys: &MasterKeys,
+) -> ServerResult<RewrapReport> {
+    reseal(conn, keys, false).await
+}
+
+/// Counts the keypairs stored in plaintext.
+pub async fn count_plaintext_keys(conn: &TursoConnection) -> ServerResult<usize> {
+    let cache_keypairs = queries::find_cache_keypairs(conn).await?;
+    let key_keypairs = queries::find_cache_key_keypairs(conn).await?;
+
+    let plaintext = cache_keypairs
+        .iter()
+        .map(|(_, stored)| stored)
+        .chain(key_keypairs.iter().map(|(_, _, stored)| stored))
+        .filter(|stored| !envelope::is_sealed(stored))
+        .count();
+
+    Ok(plaintext)
+}
+
+/// Encrypts all keypairs with the current master key.
+pub async fn run_rewrap(config: Config) -> anyhow::Result<RewrapReport> {
+    if config.signing_keys.master_key.is_none() {
+        return Err(anyhow!("No master key is configured"));
+    }
+
+    let state = StateInner::new(config, false).await;
+    let db = state.database().await?;
+
+    Ok(reseal(db, &state.master_keys, true).await?)
+}
+
+async fn reseal(
+    conn: &TursoConnection,
+    keys: &MasterKeys,
+    rewrap: bool,
+) -> ServerResult<RewrapReport> {
+    let mut report = RewrapReport::default();
+
+    for (cache_id, stored) in queries::find_cache_keypairs(conn).await? {
+        let slot = KeypairSlot::Cache(cache_id);
+        if let Some(new) = reseal_one(keys, &stored, slot, rewrap, &mut report) {
+            queries::replace_cache_keypair(conn, cache_id, &stored, &new).await?;
+        }
+    }
+
+    for (key_id, cache_id, stored) in queries::find_cache_key_keypairs(conn).await? {
+        let slot = KeypairSlot::CacheKey(cache_id);
+        if let Some(new) = reseal_one(keys, &stored, slot, rewrap, &mut report) {
+            queries::replace_cache_key_keypair(conn, key_id, &stored, &new).await?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Returns the keypair encrypted with the current master key, if it
+/// needs to be changed.
+fn reseal_one(
+    keys: &MasterKeys,
+    stored: &str,
+    slot: KeypairSlot,
+    rewrap: bool,
+    report: &mut RewrapReport,
+) -> Option<String> {
+    if !rewrap && envelope::is_sealed(stored) {
+        report.unchanged += 1;
+        return None;
+    }
+
+    match envelope::rewrap(keys, stored, slot) {
+        Ok(Some(new)) => {
+            if envelope::is_sealed(stored) {
+                report.rewrapped += 1;
+            } else {
+                report.sealed += 1;
+            }
+            Some(new)
+        }
+        Ok(None) => {
+            report.unchanged += 1;
+            None
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Failed to encrypt a signing key with master key {:?}: {}",
+                envelope::master_key_id(stored),
+                e
+            );
+            report.failed += 1;
+            None
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use tokio::net::UnixListener;
+use tracing_subscriber::EnvFilter;
+
+use attic::signing::NixKeypair;
+use attic_server::signing::agent;
+
+/// Reference signing agent for Attic.
+///
+/// Signs the fingerprints sent by the server on a Unix socket, so the
+/// keypair of a cache is only known to this process. Configure the server
+/// with `[signing-agents.<cache>]` to use it.
+#[derive(Debug, Parser)]
+#[clap(version, author = "Zhaofeng Li <hello@zhaofeng.li>")]
+struct Opts {
+    /// Path of the socket to listen on.
+    #[clap(short = 's', long)]
+    socket: PathBuf,
+
+    /// Path to a file containing the keypair, as generated by
+    /// `nix-store --generate-binary-cache-key`.
+    #[clap(short = 'k', long, conflicts_with = "generate")]
+    keypair_file: Option<PathBuf>,
+
+    /// Generate a throwaway keypair with this name.
+    ///
+    /// The keypair is lost when the agent stops, which is only useful
+    /// for testing.
+    #[clap(long)]
+    generate: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opts = Opts::parse();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let keypair = if let Some(path) = &opts.keypair_file {
+        NixKeypair::from_str(fs::read_to_string(path)?.trim())?
+    } else if let Some(name) = &opts.generate {
+        NixKeypair::generate(name)?
+    } else {
+        return Err(anyhow!("Either --keypair-file or --generate must be set"));
+    };
+
+    // A socket left over by a previous agent
+    match fs::symlink_metadata(&opts.socket) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(&opts.socket)?,
+        Ok(_) => return Err(anyhow!("{} is not a socket", opts.socket.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let listener = UnixListener::bind(&opts.socket)?;
+
+    eprintln!("Listening on {}", opts.socket.display());
+    println!("{}", keypair.export_public_key());
+
+    agent::serve(listener, keypair).await?;
+
+    Ok(())
+}
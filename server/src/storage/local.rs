@@ -6,11 +6,12 @@ use std::path::Path;
 use std::path::PathBuf;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use tokio::fs::{self, File};
+use tokio::fs::{self, DirEntry, File};
 use tokio::io::{self, AsyncRead};
 
-use super::{Download, RemoteFile, StorageBackend};
+use super::{Download, RemoteFile, StorageBackend, StoredFile};
 use crate::error::{ErrorKind, ServerError, ServerResult};
 
 #[derive(Debug)]
@@ -30,6 +31,12 @@ impl LocalStorageConfig {
     pub fn new_for_test(path: PathBuf) -> Self {
         Self { path }
     }
+
+    /// Returns the directory to store all files under.
+    #[cfg(test)]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 }
 
 /// Reference to a file in local storage.
@@ -107,6 +114,43 @@ async fn upgrade_0_to_1(storage_path: &Path) -> ServerResult<()> {
     Ok(())
 }
 
+/// Returns the entries of a directory of the given kind, sorted by name.
+///
+/// Entries whose names aren't valid UTF-8 are left out, since we
+/// never create them.
+async fn read_dir_sorted(path: &Path, dirs: bool) -> ServerResult<Vec<(String, DirEntry)>> {
+    let mut entries = Vec::new();
+    let mut dir = fs::read_dir(path)
+        .await
+        .map_err(ServerError::storage_error)?;
+
+    while let Some(entry) = dir.next_entry().await.map_err(ServerError::storage_error)? {
+        let file_type = entry
+            .file_type()
+            .await
+            .map_err(ServerError::storage_error)?;
+        if file_type.is_dir() != dirs {
+            continue;
+        }
+
+        if let Ok(name) = entry.file_name().into_string() {
+            entries.push((name, entry));
+        }
+    }
+
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(entries)
+}
+
+/// Returns whether a directory of files whose names start with
+/// `prefix` may contain names that sort after `start_after`.
+fn may_contain_after(prefix: &str, start_after: Option<&str>) -> bool {
+    match start_after {
+        Some(after) => prefix >= after.get(..prefix.len()).unwrap_or(after),
+        None => true,
+    }
+}
+
 impl LocalBackend {
     pub async fn new(config: LocalStorageConfig) -> ServerResult<Self> {
         fs::create_dir_all(&config.path).await.map_err(|e| {
@@ -222,4 +266,46 @@ impl StorageBackend for LocalBackend {
     async fn make_db_reference(&self, name: String) -> ServerResult<RemoteFile> {
         Ok(RemoteFile::Local(LocalRemoteFile { name }))
     }
+
+    async fn list_files(
+        &self,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> ServerResult<Vec<StoredFile>> {
+        let mut files = Vec::new();
+
+        // Files are stored under directories named after their first
+        // one and two characters, so walking the sorted directories
+        // yields the files in order.
+        for (level1, level1_entry) in read_dir_sorted(&self.config.path, true).await? {
+            if !may_contain_after(&level1, start_after) {
+                continue;
+            }
+
+            for (level2, level2_entry) in read_dir_sorted(&level1_entry.path(), true).await? {
+                if !may_contain_after(&level2, start_after) {
+                    continue;
+                }
+
+                for (name, entry) in read_dir_sorted(&level2_entry.path(), false).await? {
+                    if start_after.is_some_and(|after| name.as_str() <= after) {
+                        continue;
+                    }
+
+                    let metadata = entry.metadata().await.map_err(ServerError::storage_error)?;
+                    files.push(StoredFile {
+                        name,
+                        size: metadata.len(),
+                        modified_at: metadata.modified().ok().map(DateTime::<Utc>::from),
+                    });
+
+                    if files.len() == limit {
+                        return Ok(files);
+                    }
+                }
+            }
+        }
+
+        Ok(files)
+    }
 }
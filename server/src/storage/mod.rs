@@ -3,6 +3,7 @@
 mod local;
 mod s3;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncRead;
 
@@ -40,6 +41,19 @@ pub enum Download {
     AsyncRead(Box<dyn AsyncRead + Unpin + Send>),
 }
 
+/// A file in the storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredFile {
+    /// Name of the file, as passed to `upload_file`.
+    pub name: String,
+
+    /// Size of the file in bytes.
+    pub size: u64,
+
+    /// When the file was last modified, if known.
+    pub modified_at: Option<DateTime<Utc>>,
+}
+
 // TODO: Maybe make RemoteFile the one true reference instead of having two sets of APIs?
 /// A storage backend.
 #[async_trait::async_trait]
@@ -69,6 +83,16 @@ pub trait StorageBackend: Send + Sync + std::fmt::Debug {
 
     /// Creates a database reference for a file.
     async fn make_db_reference(&self, name: String) -> ServerResult<RemoteFile>;
+
+    /// Lists files using the current configuration, ordered by name.
+    ///
+    /// Returns up to `limit` files whose names sort after `start_after`.
+    /// An empty list means that there are no more files.
+    async fn list_files(
+        &self,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> ServerResult<Vec<StoredFile>>;
 }
 
 /// Reference to an HTTP link from which the file can be downloaded.
@@ -13,11 +13,12 @@ use aws_sdk_s3::{
     Client,
 };
 use bytes::BytesMut;
+use chrono::DateTime;
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncRead;
 
-use super::{Download, RemoteFile, StorageBackend};
+use super::{Download, RemoteFile, StorageBackend, StoredFile};
 use crate::error::{ErrorKind, ServerError, ServerResult};
 use attic::io::read_chunk_async;
 use attic::util::Finally;
@@ -372,4 +373,40 @@ impl StorageBackend for S3Backend {
             key: name,
         }))
     }
+
+    async fn list_files(
+        &self,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> ServerResult<Vec<StoredFile>> {
+        // ListObjectsV2 returns at most 1000 keys at a time, in
+        // lexicographical order.
+        let listing = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.config.bucket)
+            .set_start_after(start_after.map(str::to_string))
+            .max_keys(limit.min(1000) as i32)
+            .send()
+            .await
+            .map_err(ServerError::storage_error)?;
+
+        tracing::debug!("list_objects_v2 -> {} keys", listing.contents().len());
+
+        let files = listing
+            .contents()
+            .iter()
+            .filter_map(|object| {
+                Some(StoredFile {
+                    name: object.key()?.to_string(),
+                    size: object.size().unwrap_or_default() as u64,
+                    modified_at: object
+                        .last_modified()
+                        .and_then(|t| DateTime::from_timestamp(t.secs(), t.subsec_nanos())),
+                })
+            })
+            .collect();
+
+        Ok(files)
+    }
 }
@@ -0,0 +1,181 @@
+//! Read-only substituter mode.
+//!
+//! A substituter only serves the binary cache, which makes it cheap to
+//! run pull-only nodes close to the machines that use them. Paired with
+//! an embedded replica, reads never leave the node, and everything else
+//! is refused with [`ErrorKind::ReadOnly`].
+//!
+//! The one thing a substituter does write is the access time of the
+//! objects it serves, which garbage collection relies on. Like on any
+//! other server, they are written in batches by the
+//! [access tracker](crate::access_tracker).
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::{
+    extract::{Extension, Request},
+    http::Method,
+    middleware::Next,
+    response::Response,
+    routing::get,
+    Json, Router,
+};
+use chrono::Utc;
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::time;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::trace::TraceLayer;
+
+use crate::access::http::apply_auth;
+use crate::api;
+use crate::config::Config;
+use crate::database::connection::TursoConnection;
+use crate::error::{ErrorKind, ServerResult};
+use crate::middleware::{init_request_state, restrict_host, set_visibility_header};
+use crate::narinfo::cache::NarInfoCacheStats;
+use crate::{fallback, shutdown_signal, State, StateInner};
+
+/// The state of a substituter.
+#[derive(Debug, Serialize)]
+struct SubstituterStatus {
+    /// The mode the server runs in.
+    mode: &'static str,
+
+    /// The state of the embedded replica, if any.
+    replica: Option<ReplicaLag>,
+
+    /// The number of objects with access times waiting to be written.
+    pending_accesses: usize,
+
+    /// Hit rate of the `.narinfo` cache.
+    narinfo_cache: NarInfoCacheStats,
+}
+
+/// How far behind the primary the embedded replica may be.
+#[derive(Debug, Serialize)]
+struct ReplicaLag {
+    /// When the replica last caught up with the primary.
+    last_synced_at: Option<String>,
+
+    /// Seconds since the replica last caught up with the primary.
+    lag_seconds: Option<i64>,
+
+    /// The replication index the replica has caught up to.
+    frame_no: Option<u64>,
+
+    /// Why the last sync failed, if it did.
+    last_error: Option<String>,
+}
+
+/// Returns the router of a substituter.
+pub(crate) fn get_router(state: State) -> Router {
+    Router::new()
+        .merge(api::get_substituter_router())
+        .route("/_substituter/status", get(get_status))
+        .fallback(fallback)
+        // middlewares
+        .layer(axum::middleware::from_fn(refuse_writes))
+        .layer(axum::middleware::from_fn(apply_auth))
+        .layer(axum::middleware::from_fn(set_visibility_header))
+        .layer(axum::middleware::from_fn(init_request_state))
+        .layer(axum::middleware::from_fn(restrict_host))
+        .layer(Extension(state))
+}
+
+/// Refuses requests that could change anything.
+///
+/// Attic API requests are refused as well, since they are only of use
+/// to clients that push.
+async fn refuse_writes(req: Request, next: Next) -> ServerResult<Response> {
+    let read = matches!(*req.method(), Method::GET | Method::HEAD);
+    if !read || req.uri().path().starts_with("/_api/") {
+        return Err(ErrorKind::ReadOnly.into());
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// Reports the state of the substituter.
+///
+/// - GET `/_substituter/status`
+async fn get_status(Extension(state): Extension<State>) -> ServerResult<Json<SubstituterStatus>> {
+    let database = state.database().await?;
+
+    let replica = database.replica_status().map(|status| ReplicaLag {
+        last_synced_at: status.last_synced_at.map(|at| at.to_rfc3339()),
+        lag_seconds: status
+            .last_synced_at
+            .map(|at| (Utc::now() - at).num_seconds()),
+        frame_no: status.frame_no,
+        last_error: status.last_error,
+    });
+
+    let pending_accesses = state.access_tracker.len();
+
+    Ok(Json(SubstituterStatus {
+        mode: "substituter",
+        replica,
+        pending_accesses,
+        narinfo_cache: state.narinfo_cache.stats(),
+    }))
+}
+
+/// Runs a read-only substituter.
+pub async fn run_substituter(cli_listen: Option<SocketAddr>, config: Config) -> Result<()> {
+    eprintln!("Starting substituter...");
+
+    let state = StateInner::new(config, true).await;
+    let db = state.database().await?;
+
+    if db.replica_status().is_none() {
+        tracing::warn!("No embedded replica is configured, so every read goes to the database");
+    }
+
+    let listen = cli_listen.unwrap_or(state.config.listen);
+
+    let rest = get_router(state.clone())
+        .layer(TraceLayer::new_for_http())
+        .layer(CatchPanicLayer::new());
+
+    eprintln!("Listening on {:?}...", listen);
+
+    let listener = TcpListener::bind(&listen).await?;
+
+    let tracker_state = state.clone();
+    let tracker_db = db.clone();
+    tokio::spawn(async move { tracker_state.access_tracker.run(&tracker_db).await });
+
+    let sync_db = db.clone();
+    let sync_interval = state
+        .config
+        .database
+        .sync_interval
+        .unwrap_or(Duration::from_secs(60));
+    tokio::spawn(async move { run_replica_sync(&sync_db, sync_interval).await });
+
+    axum::serve(listener, rest)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    state.shutdown().await;
+
+    Ok(())
+}
+
+/// Syncs the embedded replica periodically, keeping track of its lag.
+async fn run_replica_sync(db: &TursoConnection, interval: Duration) {
+    if db.replica_status().is_none() {
+        return;
+    }
+
+    loop {
+        time::sleep(interval).await;
+
+        if let Err(e) = db.sync().await {
+            tracing::warn!("Failed to sync the embedded replica: {}", e);
+        }
+    }
+}
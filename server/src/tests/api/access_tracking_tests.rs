@@ -0,0 +1,153 @@
+//! Tests for tracking the access times of objects.
+
+use crate::tests::helpers::{
+    minimal_nar, nar_with_contents, test_store_path, test_store_path_2, TestServer,
+};
+
+const NAR_1: &str = "/test-cache/nar/00000000000000000000000000000000.nar";
+const NAR_2: &str = "/test-cache/nar/11111111111111111111111111111111.nar";
+
+/// Uploads two paths to a public cache.
+async fn upload_paths(server: &TestServer) {
+    server.create_cache("test-cache", true).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+    server
+        .upload_nar(
+            "test-cache",
+            &test_store_path(),
+            minimal_nar(),
+            vec![],
+            &token,
+        )
+        .await
+        .assert_ok();
+    server
+        .upload_nar(
+            "test-cache",
+            &test_store_path_2(),
+            nar_with_contents("second"),
+            vec![],
+            &token,
+        )
+        .await
+        .assert_ok();
+}
+
+/// Returns the number of objects with an access time.
+async fn count_accessed(server: &TestServer) -> i64 {
+    let mut rows = server
+        .database()
+        .await
+        .query(
+            "SELECT COUNT(*) FROM object WHERE last_accessed_at IS NOT NULL",
+            (),
+        )
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    row.get::<i64>(0).unwrap()
+}
+
+async fn execute(server: &TestServer, sql: &str) {
+    server.database().await.execute(sql, ()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_access_times_are_coalesced() {
+    let server = TestServer::with_access_tracking().await;
+    upload_paths(&server).await;
+
+    for uri in [NAR_1, NAR_1, NAR_2, NAR_1] {
+        server.get(uri).await.assert_ok();
+    }
+
+    let tracker = &server.state.access_tracker;
+    assert_eq!(2, tracker.len());
+    assert_eq!(0, count_accessed(&server).await);
+
+    let db = server.database().await;
+    assert_eq!(2, tracker.flush(db).await.unwrap());
+    assert!(tracker.is_empty());
+    assert_eq!(2, count_accessed(&server).await);
+
+    // Nothing left to write
+    assert_eq!(0, tracker.flush(db).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_access_times_written_right_away() {
+    let server = TestServer::new().await;
+    upload_paths(&server).await;
+
+    server.get(NAR_1).await.assert_ok();
+
+    assert!(server.state.access_tracker.is_empty());
+    assert_eq!(1, count_accessed(&server).await);
+}
+
+#[tokio::test]
+async fn test_access_times_of_deleted_objects() {
+    let server = TestServer::with_access_tracking().await;
+    upload_paths(&server).await;
+
+    server.get(NAR_1).await.assert_ok();
+    server.get(NAR_2).await.assert_ok();
+    execute(
+        &server,
+        "DELETE FROM object WHERE store_path_hash = '11111111111111111111111111111111'",
+    )
+    .await;
+
+    let tracker = &server.state.access_tracker;
+    assert_eq!(1, tracker.flush(server.database().await).await.unwrap());
+    assert!(tracker.is_empty());
+}
+
+#[tokio::test]
+async fn test_access_times_kept_when_flush_fails() {
+    let server = TestServer::with_access_tracking().await;
+    upload_paths(&server).await;
+
+    server.get(NAR_1).await.assert_ok();
+
+    let tracker = &server.state.access_tracker;
+    let db = server.database().await;
+
+    execute(&server, "ALTER TABLE object RENAME TO object_away").await;
+    assert!(tracker.flush(db).await.is_err());
+    assert_eq!(1, tracker.len());
+
+    execute(&server, "ALTER TABLE object_away RENAME TO object").await;
+    assert_eq!(1, tracker.flush(db).await.unwrap());
+    assert_eq!(1, count_accessed(&server).await);
+}
+
+#[tokio::test]
+async fn test_older_access_times_are_ignored() {
+    let server = TestServer::with_access_tracking().await;
+    upload_paths(&server).await;
+
+    server.get(NAR_1).await.assert_ok();
+
+    // A newer access was written while this one was waiting
+    execute(
+        &server,
+        "UPDATE object SET last_accessed_at = '2100-01-01T00:00:00+00:00'",
+    )
+    .await;
+
+    let tracker = &server.state.access_tracker;
+    let db = server.database().await;
+    assert_eq!(1, tracker.flush(db).await.unwrap());
+
+    let mut rows = db
+        .query(
+            "SELECT COUNT(*) FROM object WHERE last_accessed_at = '2100-01-01T00:00:00+00:00'",
+            (),
+        )
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    assert_eq!(2, row.get::<i64>(0).unwrap());
+}
@@ -1,6 +1,25 @@
 //! Tests for the Nix binary cache protocol endpoints.
 
-use crate::tests::helpers::TestServer;
+use async_compression::tokio::bufread::BrotliDecoder;
+use axum::http::StatusCode;
+use tokio::io::AsyncReadExt;
+
+use attic::api::v1::upload_path::UploadPathNarInfo;
+
+use crate::tests::helpers::{
+    minimal_nar, nar_hash, nar_with_contents, test_store_path_hash, TestResponse, TestServer,
+};
+
+const STORE_PATH: &str = "/nix/store/00000000000000000000000000000000-test";
+
+async fn listing_json(response: &TestResponse) -> serde_json::Value {
+    let mut decompressed = Vec::new();
+    BrotliDecoder::new(response.body.as_slice())
+        .read_to_end(&mut decompressed)
+        .await
+        .expect("Listing is not Brotli-compressed");
+    serde_json::from_slice(&decompressed).expect("Listing is not valid JSON")
+}
 
 // ==================== Nix Cache Info Tests ====================
 
@@ -126,6 +145,228 @@ async fn test_nar_private_cache_no_auth() {
     response.assert_not_found();
 }
 
+// ==================== NAR Listing Tests ====================
+
+async fn nar_listing_roundtrip(server: TestServer) {
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    server
+        .upload_minimal_nar("test-cache", STORE_PATH, &token)
+        .await
+        .assert_ok();
+
+    let response = server
+        .get_with_token("/test-cache/00000000000000000000000000000000.ls", &token)
+        .await;
+    response.assert_ok();
+
+    assert_eq!(
+        Some("br"),
+        response
+            .headers
+            .get("content-encoding")
+            .map(|v| v.to_str().unwrap())
+    );
+    assert_eq!(
+        Some("application/json"),
+        response
+            .headers
+            .get("content-type")
+            .map(|v| v.to_str().unwrap())
+    );
+
+    assert_eq!(
+        serde_json::json!({
+            "version": 1,
+            "root": {
+                "type": "regular",
+                "size": 11,
+                "narOffset": 96,
+            },
+        }),
+        listing_json(&response).await
+    );
+}
+
+#[tokio::test]
+async fn test_nar_listing() {
+    nar_listing_roundtrip(TestServer::new().await).await;
+}
+
+#[tokio::test]
+async fn test_nar_listing_chunked() {
+    nar_listing_roundtrip(TestServer::with_chunking(1).await).await;
+}
+
+#[tokio::test]
+async fn test_nar_listing_not_found() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", true).await;
+
+    let response = server
+        .get("/test-cache/00000000000000000000000000000000.ls")
+        .await;
+    response.assert_not_found();
+}
+
+#[tokio::test]
+async fn test_nar_listing_requires_pull() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let push_token = server.build_token(server.token("test-user").with_push("test-cache"));
+    server
+        .upload_minimal_nar("test-cache", STORE_PATH, &push_token)
+        .await
+        .assert_ok();
+
+    let response = server
+        .get("/test-cache/00000000000000000000000000000000.ls")
+        .await;
+    response.assert_unauthorized();
+}
+
+// ==================== Narinfo Field Tests ====================
+
+fn upload_info(nar: &[u8], system: Option<&str>) -> UploadPathNarInfo {
+    UploadPathNarInfo {
+        cache: "test-cache".parse().unwrap(),
+        store_path_hash: test_store_path_hash(),
+        store_path: STORE_PATH.to_string(),
+        references: vec![],
+        system: system.map(str::to_string),
+        deriver: None,
+        sigs: vec![],
+        ca: None,
+        nar_hash: nar_hash(nar),
+        nar_size: nar.len(),
+    }
+}
+
+/// Returns the value of a field of a `.narinfo`.
+fn narinfo_field(narinfo: &str, field: &str) -> Option<String> {
+    narinfo
+        .lines()
+        .find_map(|line| line.strip_prefix(&format!("{}: ", field)))
+        .map(str::to_string)
+}
+
+async fn upload_and_get_narinfo(server: &TestServer, nar: Vec<u8>, system: Option<&str>) -> String {
+    server.create_cache("test-cache", true).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+    server
+        .upload_nar_with_info(&upload_info(&nar, system), nar, &token)
+        .await
+        .assert_ok();
+
+    let response = server
+        .get("/test-cache/00000000000000000000000000000000.narinfo")
+        .await;
+    response.assert_ok();
+    response.text()
+}
+
+#[tokio::test]
+async fn test_narinfo_system() {
+    let server = TestServer::new().await;
+    let narinfo = upload_and_get_narinfo(&server, minimal_nar(), Some("x86_64-linux")).await;
+
+    assert_eq!(
+        Some("x86_64-linux"),
+        narinfo_field(&narinfo, "System").as_deref()
+    );
+}
+
+#[tokio::test]
+async fn test_narinfo_without_system() {
+    let server = TestServer::new().await;
+    let narinfo = upload_and_get_narinfo(&server, minimal_nar(), None).await;
+
+    assert_eq!(None, narinfo_field(&narinfo, "System"));
+}
+
+#[tokio::test]
+async fn test_upload_invalid_system() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", true).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+    let nar = minimal_nar();
+    let response = server
+        .upload_nar_with_info(
+            &upload_info(&nar, Some("x86_64-linux\nSig: x")),
+            nar,
+            &token,
+        )
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_narinfo_file_hash_and_size() {
+    let server = TestServer::new().await;
+    let nar = minimal_nar();
+    let narinfo = upload_and_get_narinfo(&server, nar.clone(), None).await;
+
+    // NARs are stored uncompressed in tests
+    assert_eq!(
+        narinfo_field(&narinfo, "NarHash"),
+        narinfo_field(&narinfo, "FileHash")
+    );
+    assert_eq!(
+        Some(nar.len().to_string()),
+        narinfo_field(&narinfo, "FileSize")
+    );
+}
+
+#[tokio::test]
+async fn test_narinfo_file_size_chunked() {
+    let server = TestServer::with_chunking(1).await;
+    let nar = nar_with_contents(&"a".repeat(600 * 1024));
+    let narinfo = upload_and_get_narinfo(&server, nar.clone(), None).await;
+
+    // The chunks are served one after another, without a hash of the whole
+    assert_eq!(None, narinfo_field(&narinfo, "FileHash"));
+    assert_eq!(
+        Some(nar.len().to_string()),
+        narinfo_field(&narinfo, "FileSize")
+    );
+}
+
+#[tokio::test]
+async fn test_narinfo_file_size_deduplicated() {
+    let server = TestServer::new().await;
+    let nar = minimal_nar();
+    upload_and_get_narinfo(&server, nar.clone(), None).await;
+
+    // The same NAR in another cache
+    server.create_cache("other-cache", true).await;
+    let token = server.build_token(server.token("test-user").with_push("other-cache"));
+    let mut info = upload_info(&nar, None);
+    info.cache = "other-cache".parse().unwrap();
+    server
+        .upload_nar_with_info(&info, nar.clone(), &token)
+        .await
+        .assert_ok();
+
+    let response = server
+        .get("/other-cache/00000000000000000000000000000000.narinfo")
+        .await;
+    response.assert_ok();
+    assert_eq!(
+        Some(nar.len().to_string()),
+        narinfo_field(&response.text(), "FileSize")
+    );
+}
+
 // ==================== Cache Visibility Header Tests ====================
 
 #[tokio::test]
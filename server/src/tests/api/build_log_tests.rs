@@ -0,0 +1,170 @@
+//! Tests for build log upload and retrieval.
+
+use async_compression::tokio::bufread::{BrotliDecoder, XzEncoder};
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use tokio::io::AsyncReadExt;
+
+use crate::tests::helpers::{TestResponse, TestServer};
+
+const DRV_NAME: &str = "00000000000000000000000000000000-test.drv";
+
+async fn put_log(
+    server: &TestServer,
+    drv_name: &str,
+    body: Vec<u8>,
+    content_encoding: Option<&str>,
+    token: &str,
+) -> TestResponse {
+    let mut request = Request::builder()
+        .method("PUT")
+        .uri(format!("/test-cache/log/{}", drv_name))
+        .header("Host", "localhost")
+        .header("Authorization", format!("Bearer {}", token));
+
+    if let Some(encoding) = content_encoding {
+        request = request.header("Content-Encoding", encoding);
+    }
+
+    server
+        .request(request.body(Body::from(body)).unwrap())
+        .await
+}
+
+async fn decompress_log(response: &TestResponse) -> String {
+    assert_eq!(
+        Some("br"),
+        response
+            .headers
+            .get("content-encoding")
+            .map(|v| v.to_str().unwrap())
+    );
+
+    let mut log = String::new();
+    BrotliDecoder::new(response.body.as_slice())
+        .read_to_string(&mut log)
+        .await
+        .unwrap();
+    log
+}
+
+async fn setup() -> (TestServer, String) {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    (server, token)
+}
+
+#[tokio::test]
+async fn test_build_log_roundtrip() {
+    let (server, token) = setup().await;
+
+    put_log(
+        &server,
+        DRV_NAME,
+        b"building...\ndone\n".to_vec(),
+        None,
+        &token,
+    )
+    .await
+    .assert_ok();
+
+    let response = server
+        .get_with_token(&format!("/test-cache/log/{}", DRV_NAME), &token)
+        .await;
+    response.assert_ok();
+    assert_eq!("building...\ndone\n", decompress_log(&response).await);
+}
+
+#[tokio::test]
+async fn test_build_log_compressed_upload() {
+    let (server, token) = setup().await;
+
+    let mut compressed = Vec::new();
+    XzEncoder::new(b"compressed log\n".as_slice())
+        .read_to_end(&mut compressed)
+        .await
+        .unwrap();
+
+    put_log(&server, DRV_NAME, compressed, Some("xz"), &token)
+        .await
+        .assert_ok();
+
+    let response = server
+        .get_with_token(&format!("/test-cache/log/{}", DRV_NAME), &token)
+        .await;
+    response.assert_ok();
+    assert_eq!("compressed log\n", decompress_log(&response).await);
+}
+
+#[tokio::test]
+async fn test_build_log_replace() {
+    let (server, token) = setup().await;
+
+    put_log(&server, DRV_NAME, b"first attempt\n".to_vec(), None, &token)
+        .await
+        .assert_ok();
+    put_log(
+        &server,
+        DRV_NAME,
+        b"second attempt\n".to_vec(),
+        None,
+        &token,
+    )
+    .await
+    .assert_ok();
+
+    let response = server
+        .get_with_token(&format!("/test-cache/log/{}", DRV_NAME), &token)
+        .await;
+    response.assert_ok();
+    assert_eq!("second attempt\n", decompress_log(&response).await);
+}
+
+#[tokio::test]
+async fn test_build_log_not_found() {
+    let (server, token) = setup().await;
+
+    let response = server
+        .get_with_token(&format!("/test-cache/log/{}", DRV_NAME), &token)
+        .await;
+    response.assert_not_found();
+}
+
+#[tokio::test]
+async fn test_build_log_invalid_drv_name() {
+    let (server, token) = setup().await;
+
+    for drv_name in [
+        "test.drv",
+        "00000000000000000000000000000000-test",
+        "..%2Ftest.drv",
+    ] {
+        let response = put_log(&server, drv_name, b"log".to_vec(), None, &token).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_build_log_unsupported_encoding() {
+    let (server, token) = setup().await;
+
+    let response = put_log(&server, DRV_NAME, b"log".to_vec(), Some("gzip"), &token).await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_build_log_requires_push() {
+    let (server, _) = setup().await;
+    let token = server.build_token(server.token("test-user").with_pull("test-cache"));
+
+    let response = put_log(&server, DRV_NAME, b"log".to_vec(), None, &token).await;
+    response.assert_forbidden();
+}
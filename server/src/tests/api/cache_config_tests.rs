@@ -346,6 +346,47 @@ async fn test_configure_cache_regenerate_keypair() {
     assert_ne!(original_key, new_key);
 }
 
+#[tokio::test]
+async fn test_configure_cache_is_atomic() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", true).await;
+
+    let keypair = || async {
+        let mut rows = server
+            .database()
+            .await
+            .query("SELECT keypair FROM cache", ())
+            .await
+            .unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        row.get::<String>(0).unwrap()
+    };
+    let original_keypair = keypair().await;
+
+    // Make replacing the upstreams fail after the keypair was updated
+    server
+        .database()
+        .await
+        .execute("DROP TABLE cache_upstream", ())
+        .await
+        .unwrap();
+
+    let token = server.build_token(server.token("test-user").with_configure_cache("test-cache"));
+
+    let config = CacheConfig {
+        keypair: Some(KeypairConfig::Generate),
+        upstream_substituters: Some(vec![]),
+        ..CacheConfig::blank()
+    };
+
+    let response = server
+        .patch_json_with_token("/_api/v1/cache-config/test-cache", &config, &token)
+        .await;
+    response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+
+    assert_eq!(original_keypair, keypair().await);
+}
+
 // ==================== Destroy Cache Tests ====================
 
 #[tokio::test]
@@ -0,0 +1,171 @@
+//! Tests for the store path deletion endpoint.
+
+use attic::api::v1::delete_paths::{DeletePathsRequest, DeletePathsResponse};
+use attic::nix_store::StorePathHash;
+
+use crate::database::queries;
+use crate::tests::helpers::TestServer;
+
+fn delete_request(cache: &str) -> DeletePathsRequest {
+    DeletePathsRequest {
+        cache: cache.parse().unwrap(),
+        store_path_hashes: vec![],
+        store_paths: vec![],
+        name_globs: vec![],
+    }
+}
+
+// ==================== Delete Paths Tests ====================
+
+#[tokio::test]
+async fn test_delete_paths_by_hash() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache")
+            .with_delete("test-cache"),
+    );
+
+    server
+        .upload_minimal_nar(
+            "test-cache",
+            "/nix/store/00000000000000000000000000000000-test",
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    let mut request = delete_request("test-cache");
+    request.store_path_hashes =
+        vec![StorePathHash::new("00000000000000000000000000000000".to_string()).unwrap()];
+
+    let response = server
+        .delete_json_with_token("/_api/v1/objects", &request, &token)
+        .await;
+    response.assert_ok();
+
+    let result: DeletePathsResponse = response.json();
+    assert_eq!(
+        result.deleted_paths,
+        vec!["/nix/store/00000000000000000000000000000000-test".to_string()]
+    );
+
+    server
+        .get_with_token(
+            "/test-cache/00000000000000000000000000000000.narinfo",
+            &token,
+        )
+        .await
+        .assert_not_found();
+
+    // The NAR is now an orphan to be reaped by GC
+    let orphans = queries::find_orphan_nar_ids(server.database().await)
+        .await
+        .unwrap();
+    assert_eq!(orphans.len(), 1);
+}
+
+#[tokio::test]
+async fn test_delete_paths_by_store_path_and_glob() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_delete("test-cache"),
+    );
+
+    for path in [
+        "/nix/store/00000000000000000000000000000000-hello-1.0",
+        "/nix/store/11111111111111111111111111111111-hello-2.0",
+        "/nix/store/22222222222222222222222222222222-world-1.0",
+        "/nix/store/33333333333333333333333333333333-secret",
+    ] {
+        server
+            .upload_minimal_nar("test-cache", path, &token)
+            .await
+            .assert_ok();
+    }
+
+    let mut request = delete_request("test-cache");
+    request.store_paths = vec!["/nix/store/33333333333333333333333333333333-secret".to_string()];
+    request.name_globs = vec!["hello-*".to_string()];
+
+    let response = server
+        .delete_json_with_token("/_api/v1/objects", &request, &token)
+        .await;
+    response.assert_ok();
+
+    let mut result: DeletePathsResponse = response.json();
+    result.deleted_paths.sort();
+    assert_eq!(
+        result.deleted_paths,
+        vec![
+            "/nix/store/00000000000000000000000000000000-hello-1.0".to_string(),
+            "/nix/store/11111111111111111111111111111111-hello-2.0".to_string(),
+            "/nix/store/33333333333333333333333333333333-secret".to_string(),
+        ]
+    );
+
+    // The NAR is still held by the remaining object
+    let orphans = queries::find_orphan_nar_ids(server.database().await)
+        .await
+        .unwrap();
+    assert!(orphans.is_empty());
+}
+
+#[tokio::test]
+async fn test_delete_paths_empty_request() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_delete("test-cache"));
+
+    let response = server
+        .delete_json_with_token("/_api/v1/objects", &delete_request("test-cache"), &token)
+        .await;
+    assert!(response.status.is_client_error());
+}
+
+#[tokio::test]
+async fn test_delete_paths_no_permission() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    // Push does not imply delete
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_pull("test-cache")
+            .with_push("test-cache"),
+    );
+
+    let mut request = delete_request("test-cache");
+    request.name_globs = vec!["*".to_string()];
+
+    let response = server
+        .delete_json_with_token("/_api/v1/objects", &request, &token)
+        .await;
+    response.assert_forbidden();
+}
+
+#[tokio::test]
+async fn test_delete_paths_cache_not_found() {
+    let server = TestServer::new().await;
+
+    let token = server.build_token(server.token("test-user").with_delete("nonexistent"));
+
+    let mut request = delete_request("nonexistent");
+    request.name_globs = vec!["*".to_string()];
+
+    let response = server
+        .delete_json_with_token("/_api/v1/objects", &request, &token)
+        .await;
+    response.assert_not_found();
+}
@@ -3,7 +3,9 @@
 use attic::api::v1::get_missing_paths::{GetMissingPathsRequest, GetMissingPathsResponse};
 use attic::nix_store::StorePathHash;
 
-use crate::tests::helpers::TestServer;
+use crate::tests::helpers::{
+    minimal_nar, test_store_path, test_store_path_hash, test_store_path_hash_2, TestServer,
+};
 
 // ==================== Get Missing Paths Tests ====================
 
@@ -31,6 +33,38 @@ async fn test_get_missing_paths_all_missing() {
     assert_eq!(result.missing_paths.len(), 2);
 }
 
+#[tokio::test]
+async fn test_get_missing_paths_after_upload() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    server
+        .upload_nar(
+            "test-cache",
+            &test_store_path(),
+            minimal_nar(),
+            vec![],
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    let request = GetMissingPathsRequest {
+        cache: "test-cache".parse().unwrap(),
+        store_path_hashes: vec![test_store_path_hash(), test_store_path_hash_2()],
+    };
+
+    let response = server
+        .post_json_with_token("/_api/v1/get-missing-paths", &request, &token)
+        .await;
+    response.assert_ok();
+
+    let result: GetMissingPathsResponse = response.json();
+    assert_eq!(result.missing_paths, vec![test_store_path_hash_2()]);
+}
+
 #[tokio::test]
 async fn test_get_missing_paths_empty_request() {
     let server = TestServer::new().await;
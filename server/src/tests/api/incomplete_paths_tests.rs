@@ -0,0 +1,163 @@
+//! Tests for recording and repairing incomplete NARs.
+
+use crate::tests::helpers::{minimal_nar, test_store_path, test_store_path_hash, TestServer};
+use attic::api::v1::get_missing_paths::{GetMissingPathsRequest, GetMissingPathsResponse};
+use attic::api::v1::incomplete_paths::ListIncompletePathsResponse;
+
+async fn setup() -> (TestServer, String) {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    server
+        .upload_nar(
+            "test-cache",
+            &test_store_path(),
+            minimal_nar(),
+            vec![],
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    (server, token)
+}
+
+/// Loses the chunks of all NARs without telling anyone.
+async fn lose_chunks(server: &TestServer) {
+    server
+        .database()
+        .await
+        .execute("UPDATE chunkref SET chunk_id = NULL", ())
+        .await
+        .unwrap();
+}
+
+async fn download_nar(server: &TestServer, token: &str) -> u16 {
+    let uri = format!("/test-cache/nar/{}.nar", test_store_path_hash().as_str());
+    server.get_with_token(&uri, token).await.status.as_u16()
+}
+
+async fn list_incomplete_paths(server: &TestServer, token: &str) -> ListIncompletePathsResponse {
+    let response = server
+        .get_with_token("/_api/v1/incomplete-paths/test-cache", token)
+        .await;
+    response.assert_ok();
+
+    response.json()
+}
+
+async fn get_missing_paths(server: &TestServer, token: &str) -> GetMissingPathsResponse {
+    let request = GetMissingPathsRequest {
+        cache: "test-cache".parse().unwrap(),
+        store_path_hashes: vec![test_store_path_hash()],
+    };
+
+    let response = server
+        .post_json_with_token("/_api/v1/get-missing-paths", &request, token)
+        .await;
+    response.assert_ok();
+
+    response.json()
+}
+
+#[tokio::test]
+async fn test_complete_paths_are_not_listed() {
+    let (server, token) = setup().await;
+
+    assert!(list_incomplete_paths(&server, &token)
+        .await
+        .paths
+        .is_empty());
+
+    let result = get_missing_paths(&server, &token).await;
+    assert!(result.missing_paths.is_empty());
+    assert!(result.incomplete_paths.is_empty());
+}
+
+#[tokio::test]
+async fn test_incomplete_nar_hits_are_recorded() {
+    let (server, token) = setup().await;
+    lose_chunks(&server).await;
+
+    // Nobody knows until someone tries to download it
+    assert!(list_incomplete_paths(&server, &token)
+        .await
+        .paths
+        .is_empty());
+
+    assert_eq!(503, download_nar(&server, &token).await);
+    assert_eq!(503, download_nar(&server, &token).await);
+
+    let paths = list_incomplete_paths(&server, &token).await.paths;
+    assert_eq!(1, paths.len());
+    assert_eq!(test_store_path(), paths[0].store_path);
+    assert_eq!(2, paths[0].hits);
+    assert!(paths[0].last_hit_at.is_some());
+
+    let result = get_missing_paths(&server, &token).await;
+    assert_eq!(vec![test_store_path_hash()], result.missing_paths);
+    assert_eq!(vec![test_store_path_hash()], result.incomplete_paths);
+}
+
+#[tokio::test]
+async fn test_incomplete_nar_served_when_hit_not_recorded() {
+    let (server, token) = setup().await;
+    lose_chunks(&server).await;
+
+    // Recording the hit is only a hint, and mustn't turn the 503 into a 500
+    server
+        .database()
+        .await
+        .execute("DROP TABLE incomplete_nar", ())
+        .await
+        .unwrap();
+
+    assert_eq!(503, download_nar(&server, &token).await);
+}
+
+#[tokio::test]
+async fn test_upload_repairs_incomplete_nar() {
+    let (server, token) = setup().await;
+    lose_chunks(&server).await;
+    assert_eq!(503, download_nar(&server, &token).await);
+
+    server
+        .upload_nar(
+            "test-cache",
+            &test_store_path(),
+            minimal_nar(),
+            vec![],
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    assert_eq!(200, download_nar(&server, &token).await);
+    assert!(list_incomplete_paths(&server, &token)
+        .await
+        .paths
+        .is_empty());
+
+    let result = get_missing_paths(&server, &token).await;
+    assert!(result.missing_paths.is_empty());
+    assert!(result.incomplete_paths.is_empty());
+}
+
+#[tokio::test]
+async fn test_list_incomplete_paths_requires_push() {
+    let (server, _) = setup().await;
+
+    let token = server.build_token(server.token("reader").with_pull("test-cache"));
+
+    server
+        .get_with_token("/_api/v1/incomplete-paths/test-cache", &token)
+        .await
+        .assert_forbidden();
+}
@@ -0,0 +1,199 @@
+//! Tests for rotating the signing keys of caches.
+
+use axum::http::StatusCode;
+
+use attic::api::v1::cache_config::{
+    CacheConfig, CacheKeyState, KeyRotationStep, KeypairConfig, RotateKeyRequest, RotateKeyResponse,
+};
+use attic::signing::NixPublicKey;
+
+use crate::narinfo::NarInfo;
+use crate::tests::helpers::{test_store_path, TestResponse, TestServer};
+
+const NARINFO: &str = "/test-cache/00000000000000000000000000000000.narinfo";
+const ROTATE_KEY: &str = "/_api/v1/cache-config/test-cache/rotate-key";
+
+/// Returns a server with a public cache with one path uploaded.
+async fn server_with_path() -> (TestServer, String) {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", true).await;
+
+    let token = server.build_token(server.token("test-user").with_full_access("test-cache"));
+    server
+        .upload_minimal_nar("test-cache", &test_store_path(), &token)
+        .await
+        .assert_ok();
+
+    (server, token)
+}
+
+async fn rotate(server: &TestServer, token: &str, step: KeyRotationStep) -> TestResponse {
+    server
+        .post_json_with_token(ROTATE_KEY, &RotateKeyRequest { step }, token)
+        .await
+}
+
+async fn public_keys(server: &TestServer) -> Vec<String> {
+    let response = server.get("/_api/v1/cache-config/test-cache").await;
+    response.assert_ok();
+
+    let config: CacheConfig = response.json();
+    let public_keys = config.public_keys.unwrap();
+    assert_eq!(config.public_key.as_ref(), public_keys.first());
+
+    public_keys
+}
+
+/// Returns the public keys that signed the `.narinfo`.
+async fn signers(server: &TestServer, public_keys: &[String]) -> Vec<String> {
+    let response = server.get(NARINFO).await;
+    response.assert_ok();
+
+    let narinfo = NarInfo::from_str(&response.text()).unwrap();
+    let fingerprint = narinfo.fingerprint();
+
+    // Every signature must be valid
+    narinfo
+        .signatures()
+        .iter()
+        .map(|sig| {
+            public_keys
+                .iter()
+                .find(|key| {
+                    NixPublicKey::from_str(key)
+                        .unwrap()
+                        .verify(&fingerprint, sig)
+                        .is_ok()
+                })
+                .expect("The .narinfo has a signature from an unknown key")
+                .to_owned()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_full_rotation() {
+    let (server, token) = server_with_path().await;
+    let old = public_keys(&server).await;
+    assert_eq!(1, old.len());
+
+    // The next key is trusted, but not used yet
+    let response = rotate(&server, &token, KeyRotationStep::Stage).await;
+    response.assert_ok();
+    let staged: RotateKeyResponse = response.json();
+    assert_eq!(2, staged.keys.len());
+    assert_eq!(CacheKeyState::Next, staged.keys[1].state);
+    assert!(staged.keys[1].public_key.starts_with("test-cache-1:"));
+
+    let keys = public_keys(&server).await;
+    assert_eq!(
+        vec![old[0].clone(), staged.keys[1].public_key.clone()],
+        keys
+    );
+    assert_eq!(vec![old[0].clone()], signers(&server, &keys).await);
+
+    // Both keys sign, the new one first
+    rotate(&server, &token, KeyRotationStep::Activate)
+        .await
+        .assert_ok();
+
+    let keys = public_keys(&server).await;
+    assert_eq!(
+        vec![staged.keys[1].public_key.clone(), old[0].clone()],
+        keys
+    );
+    assert_eq!(keys, signers(&server, &keys).await);
+
+    // Only the new key is left
+    let response = rotate(&server, &token, KeyRotationStep::Retire).await;
+    response.assert_ok();
+    let retired: RotateKeyResponse = response.json();
+    assert_eq!(CacheKeyState::Retired, retired.keys[1].state);
+    assert_eq!(old[0], retired.keys[1].public_key);
+
+    let keys = public_keys(&server).await;
+    assert_eq!(vec![staged.keys[1].public_key.clone()], keys);
+    assert_eq!(keys, signers(&server, &keys).await);
+}
+
+#[tokio::test]
+async fn test_second_rotation_gets_new_name() {
+    let (server, token) = server_with_path().await;
+
+    for step in [
+        KeyRotationStep::Stage,
+        KeyRotationStep::Activate,
+        KeyRotationStep::Retire,
+    ] {
+        rotate(&server, &token, step).await.assert_ok();
+    }
+
+    let response = rotate(&server, &token, KeyRotationStep::Stage).await;
+    response.assert_ok();
+
+    let keys = public_keys(&server).await;
+    assert!(keys[0].starts_with("test-cache-1:"));
+    assert!(keys[1].starts_with("test-cache-2:"));
+}
+
+#[tokio::test]
+async fn test_steps_out_of_order() {
+    let (server, token) = server_with_path().await;
+
+    rotate(&server, &token, KeyRotationStep::Activate)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    rotate(&server, &token, KeyRotationStep::Retire)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    rotate(&server, &token, KeyRotationStep::Stage)
+        .await
+        .assert_ok();
+    rotate(&server, &token, KeyRotationStep::Stage)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Nothing was changed by the failed steps
+    assert_eq!(2, public_keys(&server).await.len());
+}
+
+#[tokio::test]
+async fn test_regenerated_keypair_ends_rotation() {
+    let (server, token) = server_with_path().await;
+
+    rotate(&server, &token, KeyRotationStep::Stage)
+        .await
+        .assert_ok();
+    rotate(&server, &token, KeyRotationStep::Activate)
+        .await
+        .assert_ok();
+    rotate(&server, &token, KeyRotationStep::Stage)
+        .await
+        .assert_ok();
+
+    let config = CacheConfig {
+        keypair: Some(KeypairConfig::Generate),
+        ..CacheConfig::blank()
+    };
+    server
+        .patch_json_with_token("/_api/v1/cache-config/test-cache", &config, &token)
+        .await
+        .assert_ok();
+
+    let keys = public_keys(&server).await;
+    assert_eq!(1, keys.len());
+    assert_eq!(keys, signers(&server, &keys).await);
+}
+
+#[tokio::test]
+async fn test_rotation_requires_permission() {
+    let (server, _) = server_with_path().await;
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    rotate(&server, &token, KeyRotationStep::Stage)
+        .await
+        .assert_forbidden();
+
+    assert_eq!(1, public_keys(&server).await.len());
+}
@@ -1,6 +1,18 @@
 //! API endpoint integration tests.
 
+mod access_tracking_tests;
 mod binary_cache_tests;
+mod build_log_tests;
 mod cache_config_tests;
+mod delete_paths_tests;
 mod get_missing_paths_tests;
+mod incomplete_paths_tests;
+mod key_rotation_tests;
+mod narinfo_cache_tests;
+mod pin_tests;
+mod realisation_tests;
+mod signing_agent_tests;
+mod substituter_tests;
 mod upload_path_tests;
+mod upload_signature_tests;
+mod upstream_tests;
@@ -0,0 +1,183 @@
+//! Tests for the in-memory cache of rendered `.narinfo`s.
+
+use attic::api::v1::cache_config::{CacheConfig, KeypairConfig};
+use attic::api::v1::delete_paths::DeletePathsRequest;
+
+use crate::tests::helpers::{test_store_path, test_store_path_hash, TestResponse, TestServer};
+
+const NARINFO: &str = "/test-cache/00000000000000000000000000000000.narinfo";
+
+async fn upload_path(server: &TestServer) {
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+    server
+        .upload_minimal_nar("test-cache", &test_store_path(), &token)
+        .await
+        .assert_ok();
+}
+
+fn signature(response: &TestResponse) -> String {
+    response
+        .text()
+        .lines()
+        .find_map(|line| line.strip_prefix("Sig: "))
+        .expect("The .narinfo should be signed")
+        .to_owned()
+}
+
+#[tokio::test]
+async fn test_narinfo_served_from_cache() {
+    let server = TestServer::with_narinfo_cache().await;
+    server.create_cache("test-cache", true).await;
+    upload_path(&server).await;
+
+    let first = server.get(NARINFO).await;
+    first.assert_ok();
+    let second = server.get(NARINFO).await;
+    second.assert_ok();
+
+    assert_eq!(first.text(), second.text());
+    assert_eq!(
+        first.headers.get("Content-Type"),
+        second.headers.get("Content-Type")
+    );
+
+    let stats = server.state.narinfo_cache.stats();
+    assert_eq!(1, stats.hits);
+    assert_eq!(1, stats.misses);
+    assert_eq!(1, stats.entries);
+    assert_eq!(0.5, stats.hit_rate);
+}
+
+#[tokio::test]
+async fn test_missing_narinfo_invalidated_on_upload() {
+    let server = TestServer::with_narinfo_cache().await;
+    server.create_cache("test-cache", true).await;
+
+    server.get(NARINFO).await.assert_not_found();
+    server.get(NARINFO).await.assert_not_found();
+    assert_eq!(1, server.state.narinfo_cache.stats().negative_hits);
+
+    upload_path(&server).await;
+
+    let response = server.get(NARINFO).await;
+    response.assert_ok();
+    assert!(response.text().contains(&test_store_path()));
+}
+
+#[tokio::test]
+async fn test_narinfo_invalidated_on_delete() {
+    let server = TestServer::with_narinfo_cache().await;
+    server.create_cache("test-cache", true).await;
+    upload_path(&server).await;
+
+    server.get(NARINFO).await.assert_ok();
+
+    let token = server.build_token(server.token("test-user").with_delete("test-cache"));
+    let request = DeletePathsRequest {
+        cache: "test-cache".parse().unwrap(),
+        store_path_hashes: vec![test_store_path_hash()],
+        store_paths: vec![],
+        name_globs: vec![],
+    };
+    server
+        .delete_json_with_token("/_api/v1/objects", &request, &token)
+        .await
+        .assert_ok();
+
+    server.get(NARINFO).await.assert_not_found();
+}
+
+#[tokio::test]
+async fn test_narinfo_invalidated_on_cache_destruction() {
+    let server = TestServer::with_narinfo_cache().await;
+    server.create_cache("test-cache", true).await;
+    upload_path(&server).await;
+
+    server.get(NARINFO).await.assert_ok();
+    assert_eq!(1, server.state.narinfo_cache.stats().entries);
+
+    let token = server.build_token(server.token("test-user").with_destroy_cache("test-cache"));
+    server
+        .delete_with_token("/_api/v1/cache-config/test-cache", &token)
+        .await
+        .assert_ok();
+
+    assert_eq!(0, server.state.narinfo_cache.stats().entries);
+}
+
+#[tokio::test]
+async fn test_narinfo_invalidated_on_keypair_regeneration() {
+    let server = TestServer::with_narinfo_cache().await;
+    server.create_cache("test-cache", true).await;
+    upload_path(&server).await;
+
+    let before = signature(&server.get(NARINFO).await);
+
+    let token = server.build_token(server.token("test-user").with_configure_cache("test-cache"));
+    let config = CacheConfig {
+        keypair: Some(KeypairConfig::Generate),
+        ..CacheConfig::blank()
+    };
+    server
+        .patch_json_with_token("/_api/v1/cache-config/test-cache", &config, &token)
+        .await
+        .assert_ok();
+
+    let after = signature(&server.get(NARINFO).await);
+    assert_ne!(before, after);
+}
+
+#[tokio::test]
+async fn test_narinfo_invalidated_when_cache_made_private() {
+    let server = TestServer::with_narinfo_cache().await;
+    server.create_cache("test-cache", true).await;
+    upload_path(&server).await;
+
+    server.get(NARINFO).await.assert_ok();
+
+    let token = server.build_token(server.token("test-user").with_configure_cache("test-cache"));
+    let config = CacheConfig {
+        is_public: Some(false),
+        ..CacheConfig::blank()
+    };
+    server
+        .patch_json_with_token("/_api/v1/cache-config/test-cache", &config, &token)
+        .await
+        .assert_ok();
+
+    server.get(NARINFO).await.assert_unauthorized();
+}
+
+#[tokio::test]
+async fn test_cached_narinfo_requires_permission() {
+    let server = TestServer::with_narinfo_cache().await;
+    server.create_cache("test-cache", false).await;
+    upload_path(&server).await;
+
+    let token = server.build_token(server.token("test-user").with_pull("test-cache"));
+    server.get_with_token(NARINFO, &token).await.assert_ok();
+
+    server.get(NARINFO).await.assert_unauthorized();
+    assert_eq!(1, server.state.narinfo_cache.stats().hits);
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let server = TestServer::with_narinfo_cache().await;
+    server.create_cache("test-cache", true).await;
+    upload_path(&server).await;
+
+    server.get(NARINFO).await.assert_ok();
+    server.get(NARINFO).await.assert_ok();
+
+    server.get("/_api/v1/stats").await.assert_unauthorized();
+
+    let token = server.build_token(server.token("test-user").with_pull("test-cache"));
+    let response = server.get_with_token("/_api/v1/stats", &token).await;
+    response.assert_ok();
+
+    let stats: serde_json::Value = response.json();
+    assert_eq!(1, stats["narinfo_cache"]["hits"]);
+    assert_eq!(1, stats["narinfo_cache"]["misses"]);
+    assert_eq!(1, stats["narinfo_cache"]["entries"]);
+}
@@ -0,0 +1,237 @@
+//! Tests for pins.
+
+use axum::http::StatusCode;
+
+use crate::tests::helpers::{test_store_path, test_store_path_2, TestServer};
+use attic::api::v1::delete_paths::DeletePathsRequest;
+use attic::api::v1::pin::{CreatePinRequest, ListPinsResponse, Pin};
+
+async fn setup() -> (TestServer, String) {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    server
+        .upload_minimal_nar("test-cache", &test_store_path(), &token)
+        .await
+        .assert_ok();
+
+    (server, token)
+}
+
+fn pin_request(store_path: String) -> CreatePinRequest {
+    CreatePinRequest { store_path }
+}
+
+#[tokio::test]
+async fn test_pin_roundtrip() {
+    let (server, token) = setup().await;
+
+    let response = server
+        .put_json_with_token(
+            "/_api/v1/pins/test-cache/release-24.05",
+            &pin_request(test_store_path()),
+            &token,
+        )
+        .await;
+    response.assert_ok();
+
+    let pin: Pin = response.json();
+    assert_eq!("release-24.05", pin.name);
+    assert_eq!(test_store_path(), pin.store_path);
+
+    let response = server
+        .get_with_token("/_api/v1/pins/test-cache", &token)
+        .await;
+    response.assert_ok();
+
+    let list: ListPinsResponse = response.json();
+    assert_eq!(vec![pin], list.pins);
+
+    let response = server
+        .get_with_token("/test-cache/pins/release-24.05", &token)
+        .await;
+    response.assert_ok();
+    assert_eq!(format!("{}\n", test_store_path()), response.text());
+}
+
+#[tokio::test]
+async fn test_pin_move() {
+    let (server, token) = setup().await;
+
+    server
+        .upload_minimal_nar("test-cache", &test_store_path_2(), &token)
+        .await
+        .assert_ok();
+
+    for store_path in [test_store_path(), test_store_path_2()] {
+        server
+            .put_json_with_token(
+                "/_api/v1/pins/test-cache/latest",
+                &pin_request(store_path),
+                &token,
+            )
+            .await
+            .assert_ok();
+    }
+
+    let response = server
+        .get_with_token("/test-cache/pins/latest", &token)
+        .await;
+    response.assert_ok();
+    assert_eq!(format!("{}\n", test_store_path_2()), response.text());
+}
+
+#[tokio::test]
+async fn test_pin_remove() {
+    let (server, token) = setup().await;
+
+    server
+        .put_json_with_token(
+            "/_api/v1/pins/test-cache/latest",
+            &pin_request(test_store_path()),
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    server
+        .delete_with_token("/_api/v1/pins/test-cache/latest", &token)
+        .await
+        .assert_ok();
+
+    server
+        .get_with_token("/test-cache/pins/latest", &token)
+        .await
+        .assert_not_found();
+
+    server
+        .delete_with_token("/_api/v1/pins/test-cache/latest", &token)
+        .await
+        .assert_not_found();
+}
+
+#[tokio::test]
+async fn test_pin_path_not_in_cache() {
+    let (server, token) = setup().await;
+
+    let response = server
+        .put_json_with_token(
+            "/_api/v1/pins/test-cache/latest",
+            &pin_request(test_store_path_2()),
+            &token,
+        )
+        .await;
+    response.assert_not_found();
+}
+
+#[tokio::test]
+async fn test_pin_removed_with_path() {
+    let (server, token) = setup().await;
+
+    server
+        .put_json_with_token(
+            "/_api/v1/pins/test-cache/latest",
+            &pin_request(test_store_path()),
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    let delete_token = server.build_token(server.token("test-user").with_delete("test-cache"));
+    let request = DeletePathsRequest {
+        cache: "test-cache".parse().unwrap(),
+        store_path_hashes: vec![],
+        store_paths: vec![test_store_path()],
+        name_globs: vec![],
+    };
+    server
+        .delete_json_with_token("/_api/v1/objects", &request, &delete_token)
+        .await
+        .assert_ok();
+
+    let response = server
+        .get_with_token("/_api/v1/pins/test-cache", &token)
+        .await;
+    response.assert_ok();
+    assert!(response.json::<ListPinsResponse>().pins.is_empty());
+
+    server
+        .get_with_token("/test-cache/pins/latest", &token)
+        .await
+        .assert_not_found();
+}
+
+#[tokio::test]
+async fn test_pin_of_missing_path_not_resolved() {
+    let (server, token) = setup().await;
+
+    server
+        .put_json_with_token(
+            "/_api/v1/pins/test-cache/latest",
+            &pin_request(test_store_path()),
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    // Left behind by a deletion from before pins were cleaned up
+    server
+        .database()
+        .await
+        .execute("DELETE FROM object", ())
+        .await
+        .unwrap();
+
+    let response = server
+        .get_with_token("/test-cache/pins/latest", &token)
+        .await;
+    response.assert_not_found();
+    let error: serde_json::Value = response.json();
+    assert_eq!("NoSuchObject", error["error"]);
+}
+
+#[tokio::test]
+async fn test_pin_invalid() {
+    let (server, token) = setup().await;
+
+    for (name, store_path) in [
+        (".hidden", test_store_path()),
+        ("with%20space", test_store_path()),
+        ("latest", "/nix/store/invalid".to_string()),
+        (
+            "latest",
+            "/other/store/00000000000000000000000000000000-test".to_string(),
+        ),
+    ] {
+        let response = server
+            .put_json_with_token(
+                &format!("/_api/v1/pins/test-cache/{}", name),
+                &pin_request(store_path),
+                &token,
+            )
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_pin_requires_push() {
+    let (server, _) = setup().await;
+    let token = server.build_token(server.token("test-user").with_pull("test-cache"));
+
+    let response = server
+        .put_json_with_token(
+            "/_api/v1/pins/test-cache/latest",
+            &pin_request(test_store_path()),
+            &token,
+        )
+        .await;
+    response.assert_forbidden();
+}
@@ -0,0 +1,130 @@
+//! Tests for uploading and retrieving realisations.
+
+use axum::body::Body;
+use axum::http::Request;
+use serde_json::json;
+
+use crate::realisation::Realisation;
+use crate::tests::helpers::{TestResponse, TestServer};
+
+const OUTPUT_ID: &str = "sha256:1wc5dv6hdfzbqv1rvkq8aiq6dyg6bsm2iv0yl7dj3gbk8s0ljbp6!out";
+const OUT_PATH: &str = "00000000000000000000000000000000-test";
+
+async fn put_realisation(
+    server: &TestServer,
+    output_id: &str,
+    body: serde_json::Value,
+    token: &str,
+) -> TestResponse {
+    let request = Request::builder()
+        .method("PUT")
+        .uri(format!("/test-cache/realisations/{}.doi", output_id))
+        .header("Host", "localhost")
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    server.request(request).await
+}
+
+fn realisation_json(output_id: &str) -> serde_json::Value {
+    json!({
+        "dependentRealisations": {},
+        "id": output_id,
+        "outPath": OUT_PATH,
+        "signatures": ["builder-1:c2lnbmF0dXJl"],
+    })
+}
+
+#[tokio::test]
+async fn test_realisation_roundtrip() {
+    let server = TestServer::new().await;
+    let cache = server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    put_realisation(&server, OUTPUT_ID, realisation_json(OUTPUT_ID), &token)
+        .await
+        .assert_ok();
+
+    let response = server
+        .get_with_token(
+            &format!("/test-cache/realisations/{}.doi", OUTPUT_ID),
+            &token,
+        )
+        .await;
+    response.assert_ok();
+
+    let realisation: Realisation = response.json();
+    assert_eq!(OUTPUT_ID, realisation.id);
+    assert_eq!(OUT_PATH, realisation.out_path);
+
+    // The uploaded signature is kept and the cache signs the realisation
+    assert_eq!(2, realisation.signatures.len());
+    assert!(realisation
+        .signatures
+        .contains(&"builder-1:c2lnbmF0dXJl".to_string()));
+
+    let public_key = cache
+        .keypair(&server.state.master_keys)
+        .unwrap()
+        .to_public_key();
+    let fingerprint = realisation.fingerprint();
+    assert!(realisation
+        .signatures
+        .iter()
+        .any(|sig| public_key.verify(&fingerprint, sig).is_ok()));
+}
+
+#[tokio::test]
+async fn test_realisation_not_found() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", true).await;
+
+    let response = server
+        .get(&format!("/test-cache/realisations/{}.doi", OUTPUT_ID))
+        .await;
+    response.assert_not_found();
+}
+
+#[tokio::test]
+async fn test_realisation_id_mismatch() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    let other_id = "sha256:0f5lzp6mq6wycjh7bxmy9qzqqf5kbsqbmnxkc6w5grcb5xnpyd22!out";
+    let response = put_realisation(&server, other_id, realisation_json(OUTPUT_ID), &token).await;
+    assert!(response.status.is_client_error());
+}
+
+#[tokio::test]
+async fn test_realisation_invalid() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    let mut body = realisation_json(OUTPUT_ID);
+    body["outPath"] = json!("/nix/store/00000000000000000000000000000000-test");
+
+    let response = put_realisation(&server, OUTPUT_ID, body, &token).await;
+    assert!(response.status.is_client_error());
+}
+
+#[tokio::test]
+async fn test_realisation_requires_push() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_pull("test-cache"));
+
+    let response = put_realisation(&server, OUTPUT_ID, realisation_json(OUTPUT_ID), &token).await;
+    response.assert_forbidden();
+}
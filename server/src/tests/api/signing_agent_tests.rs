@@ -0,0 +1,122 @@
+//! Tests for signing with an external agent.
+
+use std::path::Path;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use tempfile::TempDir;
+use tokio::net::UnixListener;
+
+use attic::api::v1::cache_config::{CacheConfig, KeyRotationStep, RotateKeyRequest};
+use attic::signing::{NixKeypair, NixPublicKey};
+
+use crate::config::SigningAgentConfig;
+use crate::narinfo::NarInfo;
+use crate::signing::agent;
+use crate::tests::helpers::{test_store_path, TestServer};
+
+const NARINFO: &str = "/test-cache/00000000000000000000000000000000.narinfo";
+
+/// Starts an agent signing with a new keypair, returning its public key.
+fn start_agent(socket_path: &Path) -> NixPublicKey {
+    let keypair = NixKeypair::generate("agent-1").unwrap();
+    let public_key = keypair.to_public_key();
+
+    let listener = UnixListener::bind(socket_path).unwrap();
+    tokio::spawn(agent::serve(listener, keypair));
+
+    public_key
+}
+
+/// Returns a server whose cache is signed by an agent, with one path uploaded.
+async fn server_with_agent(socket_path: &Path, public_key: NixPublicKey) -> TestServer {
+    let agent = SigningAgentConfig {
+        socket_path: socket_path.to_owned(),
+        public_key,
+        timeout: Duration::from_secs(5),
+    };
+    let server =
+        TestServer::with_config_builder(|builder| builder.with_signing_agent("test-cache", agent))
+            .await;
+    server.create_cache("test-cache", true).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+    server
+        .upload_minimal_nar("test-cache", &test_store_path(), &token)
+        .await
+        .assert_ok();
+
+    server
+}
+
+#[tokio::test]
+async fn test_narinfo_signed_by_agent() {
+    let dir = TempDir::new().unwrap();
+    let socket_path = dir.path().join("agent.sock");
+    let public_key = start_agent(&socket_path);
+    let server = server_with_agent(&socket_path, public_key.clone()).await;
+
+    let response = server.get(NARINFO).await;
+    response.assert_ok();
+
+    // Only the agent signs, not the keypair in the database
+    let narinfo = NarInfo::from_str(&response.text()).unwrap();
+    assert_eq!(1, narinfo.signatures().len());
+    public_key
+        .verify(&narinfo.fingerprint(), &narinfo.signatures()[0])
+        .unwrap();
+
+    // Clients are told to trust the key of the agent
+    let response = server.get("/_api/v1/cache-config/test-cache").await;
+    response.assert_ok();
+    let config: CacheConfig = response.json();
+    assert_eq!(Some(vec![public_key.export()]), config.public_keys);
+}
+
+#[tokio::test]
+async fn test_agent_unavailable() {
+    let dir = TempDir::new().unwrap();
+    let socket_path = dir.path().join("agent.sock");
+    let public_key = NixKeypair::generate("agent-1").unwrap().to_public_key();
+    let server = server_with_agent(&socket_path, public_key).await;
+
+    server
+        .get(NARINFO)
+        .await
+        .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn test_signature_from_wrong_key_rejected() {
+    let dir = TempDir::new().unwrap();
+    let socket_path = dir.path().join("agent.sock");
+    start_agent(&socket_path);
+
+    let other_key = NixKeypair::generate("agent-1").unwrap().to_public_key();
+    let server = server_with_agent(&socket_path, other_key).await;
+
+    server
+        .get(NARINFO)
+        .await
+        .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn test_rotation_refused() {
+    let dir = TempDir::new().unwrap();
+    let socket_path = dir.path().join("agent.sock");
+    let public_key = start_agent(&socket_path);
+    let server = server_with_agent(&socket_path, public_key).await;
+
+    let token = server.build_token(server.token("test-user").with_configure_cache("test-cache"));
+    server
+        .post_json_with_token(
+            "/_api/v1/cache-config/test-cache/rotate-key",
+            &RotateKeyRequest {
+                step: KeyRotationStep::Stage,
+            },
+            &token,
+        )
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,141 @@
+//! Tests for the read-only substituter mode.
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+
+use crate::tests::helpers::{minimal_nar, test_store_path, TestResponse, TestServer};
+
+const NARINFO: &str = "/test-cache/00000000000000000000000000000000.narinfo";
+const NAR: &str = "/test-cache/nar/00000000000000000000000000000000.nar";
+
+/// Returns a substituter of a public cache with one path uploaded.
+async fn substituter_with_path() -> TestServer {
+    let server = TestServer::with_access_tracking().await;
+    server.create_cache("test-cache", true).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+    server
+        .upload_minimal_nar("test-cache", &test_store_path(), &token)
+        .await
+        .assert_ok();
+
+    server.into_substituter().await
+}
+
+async fn last_accessed_at(server: &TestServer) -> Option<String> {
+    let mut rows = server
+        .database()
+        .await
+        .query("SELECT last_accessed_at FROM object", ())
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    row.get::<Option<String>>(0).unwrap()
+}
+
+fn assert_read_only(response: &TestResponse) {
+    response.assert_status(StatusCode::METHOD_NOT_ALLOWED);
+    let error: serde_json::Value = response.json();
+    assert_eq!("ReadOnly", error["error"]);
+}
+
+#[tokio::test]
+async fn test_substituter_serves_binary_cache() {
+    let server = substituter_with_path().await;
+
+    server.get("/test-cache/nix-cache-info").await.assert_ok();
+
+    let response = server.get(NARINFO).await;
+    response.assert_ok();
+    assert!(response.text().contains(&test_store_path()));
+
+    let response = server.get(NAR).await;
+    response.assert_ok();
+    assert_eq!(minimal_nar(), response.body);
+}
+
+#[tokio::test]
+async fn test_substituter_refuses_writes() {
+    let server = substituter_with_path().await;
+    let token = server.build_token(server.token("test-user").with_full_access("*"));
+
+    let response = server
+        .upload_minimal_nar("test-cache", &test_store_path(), &token)
+        .await;
+    assert_read_only(&response);
+
+    let request = Request::builder()
+        .method("PUT")
+        .uri(NARINFO)
+        .header("Host", "localhost")
+        .header("Authorization", format!("Bearer {}", token))
+        .body(Body::from("StorePath: /nix/store/x"))
+        .unwrap();
+    assert_read_only(&server.request(request).await);
+
+    let response = server
+        .delete_with_token("/_api/v1/cache-config/test-cache", &token)
+        .await;
+    assert_read_only(&response);
+
+    // The Attic API is only of use for pushing
+    let response = server
+        .get_with_token("/_api/v1/cache-config/test-cache", &token)
+        .await;
+    assert_read_only(&response);
+
+    // Nothing was changed
+    server.get(NARINFO).await.assert_ok();
+}
+
+#[tokio::test]
+async fn test_substituter_buffers_access_times() {
+    let server = substituter_with_path().await;
+
+    server.get(NAR).await.assert_ok();
+    server.get(NAR).await.assert_ok();
+
+    let tracker = &server.state.access_tracker;
+    assert_eq!(1, tracker.len());
+    assert!(last_accessed_at(&server).await.is_none());
+
+    let updated = tracker.flush(server.database().await).await.unwrap();
+    assert_eq!(1, updated);
+    assert!(tracker.is_empty());
+    assert!(last_accessed_at(&server).await.is_some());
+}
+
+#[tokio::test]
+async fn test_substituter_status() {
+    let server = substituter_with_path().await;
+    server.get(NAR).await.assert_ok();
+
+    let response = server.get("/_substituter/status").await;
+    response.assert_ok();
+
+    let status: serde_json::Value = response.json();
+    assert_eq!("substituter", status["mode"]);
+    assert!(status["replica"].is_null());
+    assert_eq!(1, status["pending_accesses"]);
+}
+
+#[tokio::test]
+async fn test_substituter_doesnt_record_incomplete_nars() {
+    let server = substituter_with_path().await;
+    let db = server.database().await;
+    db.execute("UPDATE chunkref SET chunk_id = NULL", ())
+        .await
+        .unwrap();
+
+    server
+        .get(NAR)
+        .await
+        .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+
+    let mut rows = db
+        .query("SELECT COUNT(*) FROM incomplete_nar", ())
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    assert_eq!(0, row.get::<i64>(0).unwrap());
+}
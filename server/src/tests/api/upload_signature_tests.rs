@@ -0,0 +1,192 @@
+//! Tests for verifying the signatures of uploaded paths.
+
+use axum::http::StatusCode;
+
+use attic::api::v1::cache_config::CacheConfig;
+use attic::api::v1::upload_path::UploadPathNarInfo;
+use attic::signing::NixKeypair;
+
+use crate::narinfo;
+use crate::tests::helpers::{
+    minimal_nar, nar_hash, test_store_path, test_store_path_hash, TestResponse, TestServer,
+};
+
+const REFERENCE: &str = "11111111111111111111111111111111-dep";
+
+fn upload_info(sigs: Vec<String>) -> UploadPathNarInfo {
+    let nar = minimal_nar();
+
+    UploadPathNarInfo {
+        cache: "test-cache".parse().unwrap(),
+        store_path_hash: test_store_path_hash(),
+        store_path: test_store_path(),
+        references: vec![REFERENCE.to_string()],
+        system: None,
+        deriver: None,
+        sigs,
+        ca: None,
+        nar_hash: nar_hash(&nar),
+        nar_size: nar.len(),
+    }
+}
+
+/// Signs the path uploaded by [`upload_info`].
+fn sign(keypair: &NixKeypair) -> String {
+    let info = upload_info(vec![]);
+    let fingerprint = narinfo::fingerprint(
+        test_store_path().as_ref(),
+        &info.nar_hash,
+        info.nar_size,
+        &info.references,
+    );
+    keypair.sign(&fingerprint)
+}
+
+async fn upload(server: &TestServer, sigs: Vec<String>) -> TestResponse {
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+    server
+        .upload_nar_with_info(&upload_info(sigs), minimal_nar(), &token)
+        .await
+}
+
+async fn configure(server: &TestServer, config: &CacheConfig) -> TestResponse {
+    let token = server.build_token(server.token("test-user").with_configure_cache("test-cache"));
+    server
+        .patch_json_with_token("/_api/v1/cache-config/test-cache", config, &token)
+        .await
+}
+
+async fn trust(server: &TestServer, keypair: &NixKeypair, require: bool) {
+    let config = CacheConfig {
+        upload_trusted_keys: Some(vec![keypair.export_public_key()]),
+        require_trusted_signature: Some(require),
+        ..CacheConfig::blank()
+    };
+    configure(server, &config).await.assert_ok();
+}
+
+/// Returns the signatures stored for the uploaded path.
+async fn stored_sigs(server: &TestServer) -> Vec<String> {
+    let mut rows = server
+        .database()
+        .await
+        .query("SELECT sigs FROM object", ())
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().expect("No object was stored");
+    serde_json::from_str(&row.get::<String>(0).unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn test_signatures_kept_without_trusted_keys() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let sigs = vec!["builder-1:c2lnbmF0dXJl".to_string()];
+    upload(&server, sigs.clone()).await.assert_ok();
+
+    assert_eq!(sigs, stored_sigs(&server).await);
+}
+
+#[tokio::test]
+async fn test_untrusted_signatures_removed() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let hydra = NixKeypair::generate("hydra-1").unwrap();
+    let other = NixKeypair::generate("other-1").unwrap();
+    trust(&server, &hydra, false).await;
+
+    let valid = sign(&hydra);
+    let forged = hydra.sign(b"something else");
+    upload(&server, vec![forged, sign(&other), valid.clone()])
+        .await
+        .assert_ok();
+
+    assert_eq!(vec![valid], stored_sigs(&server).await);
+}
+
+#[tokio::test]
+async fn test_untrusted_paths_accepted_unless_required() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let hydra = NixKeypair::generate("hydra-1").unwrap();
+    trust(&server, &hydra, false).await;
+
+    upload(&server, vec![]).await.assert_ok();
+    assert!(stored_sigs(&server).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_untrusted_paths_rejected() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let hydra = NixKeypair::generate("hydra-1").unwrap();
+    let other = NixKeypair::generate("other-1").unwrap();
+    trust(&server, &hydra, true).await;
+
+    upload(&server, vec![])
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    upload(&server, vec![sign(&other)])
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    upload(&server, vec![sign(&hydra)]).await.assert_ok();
+}
+
+#[tokio::test]
+async fn test_policy_in_cache_config() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", true).await;
+
+    let hydra = NixKeypair::generate("hydra-1").unwrap();
+    trust(&server, &hydra, true).await;
+
+    let response = server.get("/_api/v1/cache-config/test-cache").await;
+    response.assert_ok();
+
+    let config: CacheConfig = response.json();
+    assert_eq!(
+        Some(vec![hydra.export_public_key()]),
+        config.upload_trusted_keys
+    );
+    assert_eq!(Some(true), config.require_trusted_signature);
+}
+
+#[tokio::test]
+async fn test_invalid_policies_refused() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    // Nothing could ever be uploaded
+    let config = CacheConfig {
+        require_trusted_signature: Some(true),
+        ..CacheConfig::blank()
+    };
+    configure(&server, &config)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let config = CacheConfig {
+        upload_trusted_keys: Some(vec!["not-a-key".to_string()]),
+        ..CacheConfig::blank()
+    };
+    configure(&server, &config)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Removing the keys of a cache that requires a signature
+    let hydra = NixKeypair::generate("hydra-1").unwrap();
+    trust(&server, &hydra, true).await;
+
+    let config = CacheConfig {
+        upload_trusted_keys: Some(vec![]),
+        ..CacheConfig::blank()
+    };
+    configure(&server, &config)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
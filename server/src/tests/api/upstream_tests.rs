@@ -0,0 +1,319 @@
+//! Tests for fetching paths from upstream substituters.
+
+use std::collections::HashMap;
+use std::future::IntoFuture;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_compression::tokio::bufread::XzEncoder;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+
+use crate::database::models::CacheModel;
+use crate::narinfo::NarInfo;
+use crate::tests::helpers::{minimal_nar, minimal_nar_hash, TestServer};
+use attic::api::v1::cache_config::{CacheConfig, UpstreamSubstituter};
+use attic::signing::NixKeypair;
+
+const STORE_PATH_HASH: &str = "00000000000000000000000000000000";
+const STORE_PATH: &str = "/nix/store/00000000000000000000000000000000-test";
+
+type Files = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+/// A stand-in for an upstream binary cache, serving files from memory.
+struct Upstream {
+    url: String,
+    keypair: NixKeypair,
+    files: Files,
+}
+
+impl Upstream {
+    async fn start() -> Self {
+        let files: Files = Arc::new(Mutex::new(HashMap::new()));
+
+        async fn serve(State(files): State<Files>, Path(path): Path<String>) -> Response {
+            match files.lock().unwrap().get(&path) {
+                Some(file) => file.clone().into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            }
+        }
+
+        let router = Router::new()
+            .route("/*path", get(serve))
+            .with_state(files.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(axum::serve(listener, router).into_future());
+
+        Self {
+            url,
+            keypair: NixKeypair::generate("upstream-1").unwrap(),
+            files,
+        }
+    }
+
+    fn substituter(&self) -> UpstreamSubstituter {
+        UpstreamSubstituter {
+            url: self.url.clone(),
+            trusted_keys: vec![self.keypair.export_public_key()],
+        }
+    }
+
+    /// Adds the minimal NAR as an xz-compressed path signed with `keypair`.
+    async fn add_path(&self, keypair: &NixKeypair) {
+        self.add_path_at(keypair, "", "nar/test.nar.xz").await;
+    }
+
+    /// Like `add_path`, but with the `.narinfo` in `dir` and pointing to
+    /// `nar_url`. The NAR itself is always served from `nar/test.nar.xz`.
+    async fn add_path_at(&self, keypair: &NixKeypair, dir: &str, nar_url: &str) {
+        let mut compressed = Vec::new();
+        XzEncoder::new(minimal_nar().as_slice())
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+
+        let manifest = format!(
+            "StorePath: {}\nURL: {}\nCompression: xz\nNarHash: {}\nNarSize: {}\nReferences: \n",
+            STORE_PATH,
+            nar_url,
+            minimal_nar_hash().to_typed_base32(),
+            minimal_nar().len(),
+        );
+        let signature = keypair.sign(&NarInfo::from_str(&manifest).unwrap().fingerprint());
+
+        let mut files = self.files.lock().unwrap();
+        files.insert(
+            format!("{}{}.narinfo", dir, STORE_PATH_HASH),
+            format!("{}Sig: {}\n", manifest, signature).into_bytes(),
+        );
+        files.insert("nar/test.nar.xz".to_string(), compressed);
+    }
+}
+
+async fn setup(upstream: &Upstream) -> (TestServer, CacheModel) {
+    setup_with_server(TestServer::new().await, upstream.substituter()).await
+}
+
+async fn setup_with_server(
+    server: TestServer,
+    substituter: UpstreamSubstituter,
+) -> (TestServer, CacheModel) {
+    let cache = server.create_cache("test-cache", true).await;
+
+    let token = server.build_token(server.token("admin").with_configure_cache("test-cache"));
+    let config = CacheConfig {
+        upstream_substituters: Some(vec![substituter]),
+        ..CacheConfig::blank()
+    };
+
+    server
+        .patch_json_with_token("/_api/v1/cache-config/test-cache", &config, &token)
+        .await
+        .assert_ok();
+
+    (server, cache)
+}
+
+#[tokio::test]
+async fn test_upstream_pull_through() {
+    let upstream = Upstream::start().await;
+    upstream.add_path(&upstream.keypair).await;
+    let (server, cache) = setup(&upstream).await;
+
+    let response = server
+        .get(&format!("/test-cache/{}.narinfo", STORE_PATH_HASH))
+        .await;
+    response.assert_ok();
+
+    let narinfo = NarInfo::from_str(&response.text()).unwrap();
+    assert_eq!(STORE_PATH, narinfo.store_path.to_str().unwrap());
+    assert_eq!(minimal_nar_hash(), narinfo.nar_hash);
+
+    // The path is served like any other path in the cache
+    let public_key = cache
+        .keypair(&server.state.master_keys)
+        .unwrap()
+        .to_public_key();
+    assert!(narinfo
+        .signatures
+        .iter()
+        .any(|sig| public_key.verify(&narinfo.fingerprint(), sig).is_ok()));
+
+    // The NAR is served from the cache even if the upstream goes away
+    upstream.files.lock().unwrap().clear();
+
+    let response = server.get(&format!("/test-cache/{}", narinfo.url)).await;
+    response.assert_ok();
+    assert_eq!(minimal_nar(), response.body);
+}
+
+#[tokio::test]
+async fn test_upstream_nar_pull_through() {
+    let upstream = Upstream::start().await;
+    upstream.add_path(&upstream.keypair).await;
+    let (server, _) = setup(&upstream).await;
+
+    let response = server
+        .get(&format!("/test-cache/nar/{}.nar", STORE_PATH_HASH))
+        .await;
+    response.assert_ok();
+    assert_eq!(minimal_nar(), response.body);
+}
+
+#[tokio::test]
+async fn test_upstream_untrusted_signature() {
+    let upstream = Upstream::start().await;
+    upstream
+        .add_path(&NixKeypair::generate("upstream-1").unwrap())
+        .await;
+    let (server, _) = setup(&upstream).await;
+
+    let response = server
+        .get(&format!("/test-cache/{}.narinfo", STORE_PATH_HASH))
+        .await;
+    response.assert_not_found();
+}
+
+#[tokio::test]
+async fn test_upstream_nar_on_other_host() {
+    let other = Upstream::start().await;
+    other.add_path(&other.keypair).await;
+
+    let upstream = Upstream::start().await;
+    upstream
+        .add_path_at(
+            &upstream.keypair,
+            "",
+            &format!("{}nar/test.nar.xz", other.url),
+        )
+        .await;
+    let (server, _) = setup(&upstream).await;
+
+    let response = server
+        .get(&format!("/test-cache/{}.narinfo", STORE_PATH_HASH))
+        .await;
+    response.assert_not_found();
+}
+
+#[tokio::test]
+async fn test_upstream_nar_outside_of_upstream() {
+    let upstream = Upstream::start().await;
+    upstream
+        .add_path_at(&upstream.keypair, "cache/", "../nar/test.nar.xz")
+        .await;
+
+    let server = TestServer::new().await;
+    let substituter = UpstreamSubstituter {
+        url: format!("{}cache/", upstream.url),
+        ..upstream.substituter()
+    };
+    let (server, _) = setup_with_server(server, substituter).await;
+
+    let response = server
+        .get(&format!("/test-cache/{}.narinfo", STORE_PATH_HASH))
+        .await;
+    response.assert_not_found();
+}
+
+#[tokio::test]
+async fn test_upstream_miss() {
+    let upstream = Upstream::start().await;
+    let (server, _) = setup(&upstream).await;
+
+    let response = server
+        .get(&format!("/test-cache/{}.narinfo", STORE_PATH_HASH))
+        .await;
+    response.assert_not_found();
+}
+
+#[tokio::test]
+async fn test_upstream_stalled() {
+    // Accepts connections, but never answers
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            connections.push(stream);
+        }
+    });
+
+    let server = TestServer::with_config_builder(|builder| {
+        builder.with_upstream_read_timeout(Duration::from_millis(200))
+    })
+    .await;
+    let substituter = UpstreamSubstituter {
+        url,
+        trusted_keys: vec![NixKeypair::generate("upstream-1")
+            .unwrap()
+            .export_public_key()],
+    };
+    let (server, _) = setup_with_server(server, substituter).await;
+
+    let response = tokio::time::timeout(
+        Duration::from_secs(5),
+        server.get(&format!("/test-cache/{}.narinfo", STORE_PATH_HASH)),
+    )
+    .await
+    .expect("The stalled upstream wasn't given up on");
+    response.assert_not_found();
+}
+
+#[tokio::test]
+async fn test_upstream_config() {
+    let upstream = Upstream::start().await;
+    let (server, _) = setup(&upstream).await;
+
+    let response = server.get("/_api/v1/cache-config/test-cache").await;
+    response.assert_ok();
+
+    let config: CacheConfig = response.json();
+    assert_eq!(
+        Some(vec![upstream.substituter()]),
+        config.upstream_substituters
+    );
+}
+
+#[tokio::test]
+async fn test_upstream_invalid_config() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", true).await;
+
+    let token = server.build_token(server.token("admin").with_configure_cache("test-cache"));
+
+    for upstream in [
+        UpstreamSubstituter {
+            url: "file:///srv/cache".to_string(),
+            trusted_keys: vec![NixKeypair::generate("upstream-1")
+                .unwrap()
+                .export_public_key()],
+        },
+        UpstreamSubstituter {
+            url: "https://cache.example.com".to_string(),
+            trusted_keys: vec!["not-a-key".to_string()],
+        },
+        UpstreamSubstituter {
+            url: "https://cache.example.com".to_string(),
+            trusted_keys: vec![],
+        },
+    ] {
+        let config = CacheConfig {
+            upstream_substituters: Some(vec![upstream]),
+            ..CacheConfig::blank()
+        };
+
+        let response = server
+            .patch_json_with_token("/_api/v1/cache-config/test-cache", &config, &token)
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,237 @@
+//! Tests for database consistency checks.
+
+use std::time::Duration;
+
+use super::gc_tests::{count_rows, nar, wait_for_unlocked_chunks, PATH_A};
+use crate::database::check::{check_database, CheckOptions, CheckReport, Problem};
+use crate::scrub::DamagedPath;
+use crate::tests::helpers::TestServer;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn options(fix: bool) -> CheckOptions {
+    CheckOptions {
+        pending_age: DAY,
+        fix,
+        reset_holders: false,
+    }
+}
+
+fn problem<'a>(report: &'a CheckReport, check: &str) -> &'a Problem {
+    report
+        .problems
+        .iter()
+        .find(|problem| problem.check == check)
+        .unwrap_or_else(|| panic!("No {} check", check))
+}
+
+/// Returns a server with one path uploaded to `test-cache`.
+async fn server_with_path() -> TestServer {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    server
+        .upload_nar("test-cache", PATH_A, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    wait_for_unlocked_chunks(&server).await;
+
+    server
+}
+
+async fn execute(server: &TestServer, sql: &str) {
+    server.database().await.execute(sql, ()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_check_clean_database() {
+    let server = server_with_path().await;
+    let db = server.database().await;
+
+    let report = check_database(db, &options(false)).await.unwrap();
+    assert_eq!(9, report.problems.len());
+    assert!(report.problems.iter().all(|problem| problem.found == 0));
+    assert_eq!(0, report.unfixed());
+    assert!(report.damaged_paths.is_empty());
+}
+
+#[tokio::test]
+async fn test_check_fixes_dangling_rows() {
+    let server = server_with_path().await;
+    let db = server.database().await;
+
+    execute(&server, "PRAGMA foreign_keys = OFF").await;
+    execute(
+        &server,
+        r#"
+        INSERT INTO object (cache_id, nar_id, store_path_hash, store_path, "references", system, deriver, sigs, ca, created_at)
+        SELECT cache_id, 9999, 'zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz', store_path, "references", system, deriver, sigs, ca, created_at
+        FROM object
+        "#,
+    )
+    .await;
+    execute(
+        &server,
+        r#"
+        INSERT INTO chunkref (nar_id, seq, chunk_id, chunk_hash, compression)
+        SELECT 9999, seq, chunk_id, chunk_hash, compression FROM chunkref
+        "#,
+    )
+    .await;
+    execute(&server, "PRAGMA foreign_keys = ON").await;
+
+    let report = check_database(db, &options(false)).await.unwrap();
+    assert_eq!(1, problem(&report, "dangling-objects").found);
+    assert_eq!(1, problem(&report, "dangling-chunkrefs").found);
+    assert_eq!(2, report.unfixed());
+    assert_eq!(2, count_rows(&server, "object").await);
+
+    let report = check_database(db, &options(true)).await.unwrap();
+    assert_eq!(1, problem(&report, "dangling-objects").fixed);
+    assert_eq!(1, problem(&report, "dangling-chunkrefs").fixed);
+    assert_eq!(0, report.unfixed());
+    assert_eq!(1, count_rows(&server, "object").await);
+    assert_eq!(1, count_rows(&server, "chunkref").await);
+}
+
+#[tokio::test]
+async fn test_check_detaches_missing_chunks() {
+    let server = server_with_path().await;
+    let db = server.database().await;
+
+    execute(&server, "PRAGMA foreign_keys = OFF").await;
+    execute(&server, "UPDATE chunkref SET chunk_id = 9999").await;
+    execute(&server, "PRAGMA foreign_keys = ON").await;
+
+    let report = check_database(db, &options(false)).await.unwrap();
+    assert_eq!(vec![9999], problem(&report, "missing-chunks").examples);
+    assert_eq!(0, problem(&report, "incomplete-nars").found);
+
+    let report = check_database(db, &options(true)).await.unwrap();
+    assert_eq!(1, problem(&report, "missing-chunks").fixed);
+    assert_eq!(0, report.unfixed());
+
+    let mut rows = db
+        .query(
+            "SELECT n.completeness_hint, cr.chunk_id FROM nar n JOIN chunkref cr ON cr.nar_id = n.id",
+            (),
+        )
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    assert_eq!(0, row.get::<i64>(0).unwrap());
+    assert!(row.get::<Option<i64>>(1).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_check_flags_detached_chunks() {
+    let server = server_with_path().await;
+    let db = server.database().await;
+
+    execute(&server, "UPDATE chunkref SET chunk_id = NULL").await;
+
+    let report = check_database(db, &options(false)).await.unwrap();
+    assert_eq!(1, problem(&report, "incomplete-nars").found);
+
+    let report = check_database(db, &options(true)).await.unwrap();
+    assert_eq!(1, problem(&report, "incomplete-nars").fixed);
+
+    let report = check_database(db, &options(false)).await.unwrap();
+    assert_eq!(0, problem(&report, "incomplete-nars").found);
+}
+
+#[tokio::test]
+async fn test_check_deletes_broken_nars() {
+    let server = server_with_path().await;
+    let db = server.database().await;
+
+    execute(&server, "DELETE FROM chunkref").await;
+
+    let report = check_database(db, &options(false)).await.unwrap();
+    assert_eq!(1, problem(&report, "broken-chunk-sequences").found);
+    assert_eq!(
+        vec![DamagedPath {
+            cache: "test-cache".to_string(),
+            store_path: PATH_A.to_string(),
+        }],
+        report.damaged_paths
+    );
+    assert_eq!(1, count_rows(&server, "nar").await);
+
+    let report = check_database(db, &options(true)).await.unwrap();
+    assert_eq!(1, problem(&report, "broken-chunk-sequences").fixed);
+    assert_eq!(0, report.unfixed());
+    assert_eq!(0, count_rows(&server, "nar").await);
+    assert_eq!(0, count_rows(&server, "object").await);
+
+    // The chunk is left for garbage collection
+    assert_eq!(1, count_rows(&server, "chunk").await);
+}
+
+#[tokio::test]
+async fn test_check_deletes_stale_pending_rows() {
+    let server = server_with_path().await;
+    let db = server.database().await;
+
+    execute(&server, "UPDATE nar SET state = 'P'").await;
+    execute(&server, "UPDATE chunk SET state = 'P'").await;
+
+    // Too recent to be abandoned
+    let report = check_database(db, &options(true)).await.unwrap();
+    assert_eq!(0, problem(&report, "stale-pending-nars").found);
+    assert_eq!(0, problem(&report, "stale-pending-chunks").found);
+
+    execute(
+        &server,
+        "UPDATE nar SET created_at = '2000-01-01T00:00:00+00:00'",
+    )
+    .await;
+    execute(
+        &server,
+        "UPDATE chunk SET created_at = '2000-01-01T00:00:00+00:00'",
+    )
+    .await;
+
+    let report = check_database(db, &options(true)).await.unwrap();
+    assert_eq!(1, problem(&report, "stale-pending-nars").fixed);
+    assert_eq!(1, problem(&report, "stale-pending-chunks").fixed);
+    assert_eq!(0, report.unfixed());
+    assert_eq!(0, count_rows(&server, "nar").await);
+    assert_eq!(0, count_rows(&server, "object").await);
+    assert_eq!(0, count_rows(&server, "chunkref").await);
+    assert_eq!(0, count_rows(&server, "chunk").await);
+}
+
+#[tokio::test]
+async fn test_check_resets_holders() {
+    let server = server_with_path().await;
+    let db = server.database().await;
+
+    execute(&server, "UPDATE nar SET holders_count = -1").await;
+    execute(&server, "UPDATE chunk SET holders_count = 3").await;
+
+    let report = check_database(db, &options(true)).await.unwrap();
+    assert_eq!(1, problem(&report, "negative-nar-holders").fixed);
+    assert_eq!(0, problem(&report, "negative-chunk-holders").found);
+    assert!(report
+        .problems
+        .iter()
+        .all(|problem| !problem.check.starts_with("leaked-")));
+
+    let report = check_database(
+        db,
+        &CheckOptions {
+            reset_holders: true,
+            ..options(true)
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(0, problem(&report, "leaked-nar-holders").found);
+    assert_eq!(1, problem(&report, "leaked-chunk-holders").fixed);
+    assert_eq!(0, report.unfixed());
+
+    wait_for_unlocked_chunks(&server).await;
+}
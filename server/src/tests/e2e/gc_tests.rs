@@ -0,0 +1,811 @@
+//! Tests for garbage collection.
+
+use std::time::Duration;
+
+use crate::config::StorageConfig;
+use crate::database::queries;
+use crate::gc::{
+    find_deletion_backlog, plan_garbage_collection, retry_chunk_deletions,
+    run_garbage_collection_once,
+};
+use crate::storage::RemoteFile;
+use crate::tests::helpers::{nar_with_contents, TestServer};
+use attic::api::v1::cache_config::{CacheConfig, RetentionPeriodConfig, StorageQuotaConfig};
+use attic::api::v1::pin::CreatePinRequest;
+
+pub(super) const PATH_A: &str = "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-a";
+const PATH_B: &str = "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-b";
+const PATH_C: &str = "/nix/store/cccccccccccccccccccccccccccccccc-c";
+
+/// Returns a NAR of the same size for each distinct character.
+pub(super) fn nar(c: char) -> Vec<u8> {
+    nar_with_contents(&c.to_string().repeat(1000))
+}
+
+async fn set_storage_quota(server: &TestServer, cache: &str, quota: StorageQuotaConfig) {
+    let token = server.build_token(
+        server
+            .token("admin")
+            .with_configure_cache(cache)
+            .with_configure_cache_retention(cache),
+    );
+
+    let config = CacheConfig {
+        storage_quota: Some(quota),
+        ..CacheConfig::blank()
+    };
+
+    server
+        .patch_json_with_token(&format!("/_api/v1/cache-config/{}", cache), &config, &token)
+        .await
+        .assert_ok();
+}
+
+/// Makes a path look like it was uploaded and last accessed long ago.
+async fn backdate(server: &TestServer, store_path: &str) {
+    server
+        .database()
+        .await
+        .execute(
+            "UPDATE object SET created_at = ?1, last_accessed_at = NULL WHERE store_path = ?2",
+            ("2000-01-01T00:00:00+00:00", store_path),
+        )
+        .await
+        .unwrap();
+}
+
+async fn set_retention_period(server: &TestServer, cache: &str, seconds: u32) {
+    let token = server.build_token(
+        server
+            .token("admin")
+            .with_configure_cache(cache)
+            .with_configure_cache_retention(cache),
+    );
+
+    let config = CacheConfig {
+        retention_period: Some(RetentionPeriodConfig::Period(seconds)),
+        ..CacheConfig::blank()
+    };
+
+    server
+        .patch_json_with_token(&format!("/_api/v1/cache-config/{}", cache), &config, &token)
+        .await
+        .assert_ok();
+}
+
+/// Returns the path of the only chunk in the local storage.
+pub(super) async fn chunk_path(server: &TestServer) -> std::path::PathBuf {
+    let mut rows = server
+        .database()
+        .await
+        .query("SELECT remote_file FROM chunk", ())
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+
+    let RemoteFile::Local(file) = serde_json::from_str(&row.get::<String>(0).unwrap()).unwrap()
+    else {
+        panic!("Chunk isn't in local storage");
+    };
+    let StorageConfig::Local(storage) = &server.config.storage else {
+        panic!("Storage isn't local");
+    };
+
+    storage
+        .path()
+        .join(&file.name[..1])
+        .join(&file.name[..2])
+        .join(&file.name)
+}
+
+/// Waits for uploads to release their locks on chunks and NARs.
+///
+/// Locks are released in the background, and garbage collection
+/// leaves locked chunks and NARs alone.
+pub(super) async fn wait_for_unlocked_chunks(server: &TestServer) {
+    for _ in 0..100 {
+        let mut rows = server
+            .database()
+            .await
+            .query(
+                r#"
+                SELECT (SELECT COUNT(*) FROM chunk WHERE holders_count != 0)
+                     + (SELECT COUNT(*) FROM nar WHERE holders_count != 0)
+                "#,
+                (),
+            )
+            .await
+            .unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+
+        if row.get::<i64>(0).unwrap() == 0 {
+            return;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("Chunks are still locked");
+}
+
+fn base_name(store_path: &str) -> String {
+    store_path["/nix/store/".len()..].to_string()
+}
+
+async fn has_path(server: &TestServer, cache: &str, store_path: &str, token: &str) -> bool {
+    let hash = &store_path["/nix/store/".len()..][..32];
+    let response = server
+        .get_with_token(&format!("/{}/{}.narinfo", cache, hash), token)
+        .await;
+
+    response.status.is_success()
+}
+
+#[tokio::test]
+async fn test_quota_evicts_least_recently_accessed() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    for (path, c) in [(PATH_A, 'a'), (PATH_B, 'b'), (PATH_C, 'c')] {
+        server
+            .upload_nar("test-cache", path, nar(c), vec![], &token)
+            .await
+            .assert_ok();
+    }
+
+    // Accessing A makes B the least recently accessed
+    server
+        .get_with_token(
+            "/test-cache/nar/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.nar",
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    let quota = 2 * nar('a').len() as u64;
+    set_storage_quota(&server, "test-cache", StorageQuotaConfig::Quota(quota)).await;
+
+    run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+
+    assert!(has_path(&server, "test-cache", PATH_A, &token).await);
+    assert!(!has_path(&server, "test-cache", PATH_B, &token).await);
+    assert!(has_path(&server, "test-cache", PATH_C, &token).await);
+
+    let response = server
+        .get_with_token("/_api/v1/cache-config/test-cache", &token)
+        .await;
+    response.assert_ok();
+
+    let config: CacheConfig = response.json();
+    assert_eq!(Some(StorageQuotaConfig::Quota(quota)), config.storage_quota);
+}
+
+#[tokio::test]
+async fn test_quota_splits_shared_nars() {
+    let server = TestServer::new().await;
+    server.create_cache("cache-1", false).await;
+    server.create_cache("cache-2", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("cache-*")
+            .with_pull("cache-*"),
+    );
+
+    // A is shared between both caches, B is only in cache-1
+    for cache in ["cache-1", "cache-2"] {
+        server
+            .upload_nar(cache, PATH_A, nar('a'), vec![], &token)
+            .await
+            .assert_ok();
+    }
+    server
+        .upload_nar("cache-1", PATH_B, nar('b'), vec![], &token)
+        .await
+        .assert_ok();
+
+    // cache-1 is only responsible for half of A
+    let size = nar('a').len() as u64;
+    let quota = size + size / 2;
+    set_storage_quota(&server, "cache-1", StorageQuotaConfig::Quota(quota)).await;
+
+    run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+
+    assert!(has_path(&server, "cache-1", PATH_A, &token).await);
+    assert!(has_path(&server, "cache-1", PATH_B, &token).await);
+
+    // A is the least recently accessed, so it goes first
+    set_storage_quota(&server, "cache-1", StorageQuotaConfig::Quota(size)).await;
+
+    run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+
+    assert!(!has_path(&server, "cache-1", PATH_A, &token).await);
+    assert!(has_path(&server, "cache-1", PATH_B, &token).await);
+    assert!(has_path(&server, "cache-2", PATH_A, &token).await);
+}
+
+#[tokio::test]
+async fn test_quota_global_default_disabled() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    for (path, c) in [(PATH_A, 'a'), (PATH_B, 'b')] {
+        server
+            .upload_nar("test-cache", path, nar(c), vec![], &token)
+            .await
+            .assert_ok();
+    }
+
+    set_storage_quota(&server, "test-cache", StorageQuotaConfig::Global).await;
+
+    run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+
+    assert!(has_path(&server, "test-cache", PATH_A, &token).await);
+    assert!(has_path(&server, "test-cache", PATH_B, &token).await);
+}
+
+#[tokio::test]
+async fn test_retention_keeps_closures() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache")
+            .with_configure_cache("test-cache")
+            .with_configure_cache_retention("test-cache"),
+    );
+
+    // B depends on A, C is unrelated
+    for (path, c, references) in [
+        (PATH_A, 'a', vec![]),
+        (PATH_B, 'b', vec![base_name(PATH_A), base_name(PATH_B)]),
+        (PATH_C, 'c', vec![]),
+    ] {
+        server
+            .upload_nar("test-cache", path, nar(c), references, &token)
+            .await
+            .assert_ok();
+        backdate(&server, path).await;
+    }
+
+    // Only B was accessed recently
+    server
+        .get_with_token(
+            "/test-cache/nar/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb.nar",
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    let config = CacheConfig {
+        retention_period: Some(RetentionPeriodConfig::Period(86400)),
+        ..CacheConfig::blank()
+    };
+    server
+        .patch_json_with_token("/_api/v1/cache-config/test-cache", &config, &token)
+        .await
+        .assert_ok();
+
+    run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+
+    assert!(has_path(&server, "test-cache", PATH_A, &token).await);
+    assert!(has_path(&server, "test-cache", PATH_B, &token).await);
+    assert!(!has_path(&server, "test-cache", PATH_C, &token).await);
+}
+
+#[tokio::test]
+async fn test_quota_keeps_closures() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    // B depends on A, C is unrelated
+    for (path, c, references) in [
+        (PATH_A, 'a', vec![]),
+        (PATH_B, 'b', vec![base_name(PATH_A)]),
+        (PATH_C, 'c', vec![]),
+    ] {
+        server
+            .upload_nar("test-cache", path, nar(c), references, &token)
+            .await
+            .assert_ok();
+    }
+
+    // A is the oldest, but B still needs it
+    backdate(&server, PATH_A).await;
+
+    let quota = 2 * nar('a').len() as u64;
+    set_storage_quota(&server, "test-cache", StorageQuotaConfig::Quota(quota)).await;
+
+    run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+
+    assert!(has_path(&server, "test-cache", PATH_A, &token).await);
+    assert!(!has_path(&server, "test-cache", PATH_B, &token).await);
+    assert!(has_path(&server, "test-cache", PATH_C, &token).await);
+}
+
+#[tokio::test]
+async fn test_pins_survive_gc() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache")
+            .with_configure_cache("test-cache")
+            .with_configure_cache_retention("test-cache"),
+    );
+
+    // B depends on A, C is unrelated, and nothing was accessed recently
+    for (path, c, references) in [
+        (PATH_A, 'a', vec![]),
+        (PATH_B, 'b', vec![base_name(PATH_A)]),
+        (PATH_C, 'c', vec![]),
+    ] {
+        server
+            .upload_nar("test-cache", path, nar(c), references, &token)
+            .await
+            .assert_ok();
+        backdate(&server, path).await;
+    }
+
+    server
+        .put_json_with_token(
+            "/_api/v1/pins/test-cache/release",
+            &CreatePinRequest {
+                store_path: PATH_B.to_string(),
+            },
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    let config = CacheConfig {
+        retention_period: Some(RetentionPeriodConfig::Period(86400)),
+        storage_quota: Some(StorageQuotaConfig::Quota(1)),
+        ..CacheConfig::blank()
+    };
+    server
+        .patch_json_with_token("/_api/v1/cache-config/test-cache", &config, &token)
+        .await
+        .assert_ok();
+
+    run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+
+    assert!(has_path(&server, "test-cache", PATH_A, &token).await);
+    assert!(has_path(&server, "test-cache", PATH_B, &token).await);
+    assert!(!has_path(&server, "test-cache", PATH_C, &token).await);
+}
+
+#[tokio::test]
+async fn test_objects_used_after_selection_survive() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    for (path, c) in [(PATH_A, 'a'), (PATH_B, 'b'), (PATH_C, 'c')] {
+        server
+            .upload_nar("test-cache", path, nar(c), vec![], &token)
+            .await
+            .assert_ok();
+        backdate(&server, path).await;
+    }
+
+    // Select everything, like garbage collection would with a retention period of a day
+    let db = server.database().await;
+    let cache = queries::find_cache(db, &"test-cache".parse().unwrap())
+        .await
+        .unwrap();
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(1);
+    let selected: Vec<(i64, _)> = queries::find_gc_objects_by_cache(db, cache.id)
+        .await
+        .unwrap()
+        .iter()
+        .map(|object| (object.id, cutoff))
+        .collect();
+    assert_eq!(3, selected.len());
+
+    // A is pushed again and B is pinned before the objects are deleted
+    server
+        .upload_nar("test-cache", PATH_A, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    server
+        .put_json_with_token(
+            "/_api/v1/pins/test-cache/release",
+            &CreatePinRequest {
+                store_path: PATH_B.to_string(),
+            },
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    let deleted = queries::delete_unused_objects(db, &selected).await.unwrap();
+    assert_eq!(1, deleted);
+
+    assert!(has_path(&server, "test-cache", PATH_A, &token).await);
+    assert!(has_path(&server, "test-cache", PATH_B, &token).await);
+    assert!(!has_path(&server, "test-cache", PATH_C, &token).await);
+}
+
+#[tokio::test]
+async fn test_objects_accessed_after_eviction_survive() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    for (path, c) in [(PATH_A, 'a'), (PATH_B, 'b')] {
+        server
+            .upload_nar("test-cache", path, nar(c), vec![], &token)
+            .await
+            .assert_ok();
+    }
+
+    // Evict everything, like quota-based garbage collection would
+    let db = server.database().await;
+    let cache = queries::find_cache(db, &"test-cache".parse().unwrap())
+        .await
+        .unwrap();
+    let objects = queries::find_gc_objects_by_cache(db, cache.id)
+        .await
+        .unwrap();
+    let selected: Vec<(i64, _)> = objects
+        .iter()
+        .map(|object| {
+            let accessed_at = object.last_accessed_at.unwrap_or(object.created_at);
+            (object.id, accessed_at + chrono::Duration::nanoseconds(1))
+        })
+        .collect();
+
+    // A is accessed before the objects are deleted
+    let a = objects
+        .iter()
+        .find(|object| object.store_path == PATH_A)
+        .unwrap();
+    queries::bump_objects_last_accessed(db, &[(a.id, chrono::Utc::now())])
+        .await
+        .unwrap();
+
+    let deleted = queries::delete_unused_objects(db, &selected).await.unwrap();
+    assert_eq!(1, deleted);
+
+    assert!(has_path(&server, "test-cache", PATH_A, &token).await);
+    assert!(!has_path(&server, "test-cache", PATH_B, &token).await);
+}
+
+#[tokio::test]
+async fn test_dry_run_matches_real_run() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    for (path, c) in [(PATH_A, 'a'), (PATH_B, 'b')] {
+        server
+            .upload_nar("test-cache", path, nar(c), vec![], &token)
+            .await
+            .assert_ok();
+    }
+    wait_for_unlocked_chunks(&server).await;
+    backdate(&server, PATH_A).await;
+    set_retention_period(&server, "test-cache", 86400).await;
+
+    let plan = plan_garbage_collection(server.config.clone())
+        .await
+        .unwrap();
+
+    assert!(plan.dry_run);
+    assert_eq!(1, plan.objects);
+    assert_eq!(1, plan.nars);
+    assert_eq!(1, plan.caches.len());
+    assert_eq!("test-cache", plan.caches[0].name);
+    assert_eq!(1, plan.caches[0].expired);
+    assert_eq!(0, plan.caches[0].chunks_shared);
+    assert!(plan.bytes_freed > 0);
+    assert_eq!(plan.bytes_freed, plan.caches[0].bytes_freed);
+
+    // Nothing was deleted or recorded
+    assert!(has_path(&server, "test-cache", PATH_A, &token).await);
+    let runs = queries::find_recent_gc_runs(server.database().await, 10)
+        .await
+        .unwrap();
+    assert!(runs.is_empty());
+
+    let report = run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+
+    assert!(!has_path(&server, "test-cache", PATH_A, &token).await);
+    assert!(has_path(&server, "test-cache", PATH_B, &token).await);
+    assert_eq!(
+        crate::gc::GcReport {
+            dry_run: false,
+            ..plan
+        },
+        report
+    );
+}
+
+#[tokio::test]
+async fn test_dry_run_reports_shared_chunks() {
+    let server = TestServer::new().await;
+    server.create_cache("cache-1", false).await;
+    server.create_cache("cache-2", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("cache-*")
+            .with_pull("cache-*"),
+    );
+
+    for cache in ["cache-1", "cache-2"] {
+        server
+            .upload_nar(cache, PATH_A, nar('a'), vec![], &token)
+            .await
+            .assert_ok();
+    }
+    backdate(&server, PATH_A).await;
+
+    // Only cache-1 expires A, which cache-2 still uses
+    set_retention_period(&server, "cache-1", 86400).await;
+
+    let plan = plan_garbage_collection(server.config.clone())
+        .await
+        .unwrap();
+
+    assert_eq!(1, plan.objects);
+    assert_eq!(0, plan.nars);
+    assert_eq!(0, plan.chunks);
+    assert_eq!(0, plan.bytes_freed);
+
+    let cache = &plan.caches[0];
+    assert_eq!("cache-1", cache.name);
+    assert_eq!(1, cache.expired);
+    assert_eq!(0, cache.bytes_freed);
+    assert_eq!(1, cache.chunks_shared);
+    assert!(cache.bytes_shared > 0);
+}
+
+#[tokio::test]
+async fn test_gc_runs_are_recorded() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    server
+        .upload_nar("test-cache", PATH_A, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    wait_for_unlocked_chunks(&server).await;
+    backdate(&server, PATH_A).await;
+    set_retention_period(&server, "test-cache", 86400).await;
+
+    run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+    run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+
+    let runs = queries::find_recent_gc_runs(server.database().await, 10)
+        .await
+        .unwrap();
+
+    // Newest first
+    assert_eq!(2, runs.len());
+    assert!(runs[0].id > runs[1].id);
+    assert!(runs.iter().all(|run| run.error.is_none()));
+    assert_eq!(0, runs[0].report.0.objects);
+    assert_eq!(1, runs[1].report.0.objects);
+    assert_eq!(1, runs[1].report.0.chunks);
+}
+
+#[tokio::test]
+async fn test_failed_chunk_deletions_are_retried() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    server
+        .upload_nar("test-cache", PATH_A, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    wait_for_unlocked_chunks(&server).await;
+    backdate(&server, PATH_A).await;
+    set_retention_period(&server, "test-cache", 86400).await;
+
+    // Deleting a directory as a file fails
+    let path = chunk_path(&server).await;
+    std::fs::remove_file(&path).unwrap();
+    std::fs::create_dir(&path).unwrap();
+
+    let report = run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+    assert_eq!(1, report.nars);
+    assert_eq!(0, report.chunks);
+    assert_eq!(1, report.chunk_deletions_failed);
+
+    // Not retried until the backoff has passed
+    let report = run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+    assert_eq!(0, report.chunk_deletions_failed);
+
+    let backlog = find_deletion_backlog(server.config.clone()).await.unwrap();
+    assert_eq!(1, backlog.chunks);
+    assert_eq!(0, backlog.due);
+    assert_eq!(1, backlog.max_attempts);
+    assert_eq!(1, backlog.errors.len());
+
+    // Retrying by hand ignores the backoff
+    let deletions = retry_chunk_deletions(server.config.clone()).await.unwrap();
+    assert_eq!(0, deletions.deleted);
+    assert_eq!(1, deletions.failed);
+
+    let backlog = find_deletion_backlog(server.config.clone()).await.unwrap();
+    assert_eq!(2, backlog.max_attempts);
+
+    std::fs::remove_dir(&path).unwrap();
+    std::fs::write(&path, b"").unwrap();
+
+    let deletions = retry_chunk_deletions(server.config.clone()).await.unwrap();
+    assert_eq!(1, deletions.deleted);
+    assert_eq!(0, deletions.failed);
+    assert!(!path.exists());
+
+    let backlog = find_deletion_backlog(server.config.clone()).await.unwrap();
+    assert_eq!(0, backlog.chunks);
+}
+
+pub(super) async fn count_rows(server: &TestServer, table: &str) -> i64 {
+    let mut rows = server
+        .database()
+        .await
+        .query(&format!("SELECT COUNT(*) FROM {}", table), ())
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+
+    row.get::<i64>(0).unwrap()
+}
+
+#[tokio::test]
+async fn test_duplicate_nars_are_merged() {
+    let server = TestServer::new().await;
+    server.create_cache("cache-1", false).await;
+    server.create_cache("cache-2", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("cache-*")
+            .with_pull("cache-*"),
+    );
+
+    server
+        .upload_nar("cache-1", PATH_A, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    wait_for_unlocked_chunks(&server).await;
+
+    // Pretend that the first upload was still in progress when the
+    // second one started, so neither the NAR nor its chunk is reused
+    let db = server.database().await;
+    db.execute("UPDATE nar SET state = 'P'", ()).await.unwrap();
+    db.execute("UPDATE chunk SET state = 'P'", ())
+        .await
+        .unwrap();
+
+    server
+        .upload_nar("cache-2", PATH_B, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    wait_for_unlocked_chunks(&server).await;
+
+    db.execute("UPDATE nar SET state = 'V'", ()).await.unwrap();
+    db.execute("UPDATE chunk SET state = 'V'", ())
+        .await
+        .unwrap();
+    assert_eq!(2, count_rows(&server, "nar").await);
+    assert_eq!(2, count_rows(&server, "chunk").await);
+
+    let plan = plan_garbage_collection(server.config.clone())
+        .await
+        .unwrap();
+    assert_eq!(1, plan.duplicate_nars);
+    assert!(plan.duplicate_bytes_freed > 0);
+    assert_eq!(2, count_rows(&server, "nar").await);
+
+    let report = run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+    assert_eq!(1, report.duplicate_nars);
+    assert_eq!(plan.duplicate_bytes_freed, report.duplicate_bytes_freed);
+    assert_eq!(1, report.nars);
+    assert_eq!(1, report.chunks);
+    assert_eq!(report.duplicate_bytes_freed, report.bytes_freed);
+
+    assert_eq!(1, count_rows(&server, "nar").await);
+    assert_eq!(1, count_rows(&server, "chunk").await);
+    assert!(has_path(&server, "cache-1", PATH_A, &token).await);
+    assert!(has_path(&server, "cache-2", PATH_B, &token).await);
+
+    // Both objects use the NAR that was uploaded first
+    let mut rows = db
+        .query("SELECT DISTINCT nar_id FROM object", ())
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    assert_eq!(1, row.get::<i64>(0).unwrap());
+    assert!(rows.next().await.unwrap().is_none());
+
+    for (cache, path) in [("cache-1", PATH_A), ("cache-2", PATH_B)] {
+        let hash = &path["/nix/store/".len()..][..32];
+        let response = server
+            .get_with_token(&format!("/{}/nar/{}.nar", cache, hash), &token)
+            .await;
+        response.assert_ok();
+    }
+}
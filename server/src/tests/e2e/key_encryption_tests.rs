@@ -0,0 +1,209 @@
+//! Tests for encrypting cache signing keys at rest.
+
+use attic::api::v1::cache_config::{CacheConfig, KeyRotationStep, RotateKeyRequest};
+use attic::signing::NixPublicKey;
+
+use crate::config::SigningKeysConfig;
+use crate::narinfo::NarInfo;
+use crate::signing::envelope::{self, MasterKey};
+use crate::signing::{count_plaintext_keys, run_rewrap, seal_plaintext_keys};
+use crate::tests::helpers::{test_store_path, TestServer};
+
+const NARINFO: &str = "/test-cache/00000000000000000000000000000000.narinfo";
+
+/// Creates a public cache with one path uploaded.
+async fn upload_path(server: &TestServer) {
+    server.create_cache("test-cache", true).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+    server
+        .upload_minimal_nar("test-cache", &test_store_path(), &token)
+        .await
+        .assert_ok();
+}
+
+/// Returns the keypairs stored in the database.
+async fn stored_keypairs(server: &TestServer) -> Vec<String> {
+    let mut rows = server
+        .database()
+        .await
+        .query(
+            "SELECT keypair FROM cache UNION ALL SELECT keypair FROM cache_key",
+            (),
+        )
+        .await
+        .unwrap();
+
+    let mut keypairs = Vec::new();
+    while let Some(row) = rows.next().await.unwrap() {
+        keypairs.push(row.get::<String>(0).unwrap());
+    }
+    keypairs
+}
+
+/// Checks that the `.narinfo` is signed by the key of the cache.
+async fn assert_signed(server: &TestServer) {
+    let response = server.get("/_api/v1/cache-config/test-cache").await;
+    response.assert_ok();
+    let config: CacheConfig = response.json();
+    let public_key = NixPublicKey::from_str(&config.public_key.unwrap()).unwrap();
+
+    let response = server.get(NARINFO).await;
+    response.assert_ok();
+    let narinfo = NarInfo::from_str(&response.text()).unwrap();
+
+    let signature = narinfo.signatures().first().expect("Not signed");
+    public_key
+        .verify(&narinfo.fingerprint(), signature)
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_keys_encrypted_at_rest() {
+    let server = TestServer::with_master_key().await;
+    upload_path(&server).await;
+
+    let token = server.build_token(server.token("test-user").with_full_access("test-cache"));
+    server
+        .post_json_with_token(
+            "/_api/v1/cache-config/test-cache/rotate-key",
+            &RotateKeyRequest {
+                step: KeyRotationStep::Stage,
+            },
+            &token,
+        )
+        .await
+        .assert_ok();
+
+    let master_key = server.config.signing_keys.master_key.as_ref().unwrap();
+    let keypairs = stored_keypairs(&server).await;
+    assert_eq!(2, keypairs.len());
+    for keypair in keypairs.iter() {
+        assert_eq!(Some(master_key.id()), envelope::master_key_id(keypair));
+        assert!(!keypair.contains("test-cache"));
+    }
+
+    assert_signed(&server).await;
+}
+
+#[tokio::test]
+async fn test_plaintext_keys_encrypted() {
+    let server = TestServer::new().await;
+    upload_path(&server).await;
+
+    let keypairs = stored_keypairs(&server).await;
+    assert!(!envelope::is_sealed(&keypairs[0]));
+
+    // A master key is configured later
+    let mut config = server.config.clone();
+    config.signing_keys = SigningKeysConfig {
+        master_key: Some(MasterKey::generate()),
+        previous_master_keys: vec![],
+    };
+    let server = server.restart_with(config).await;
+
+    let db = server.database().await;
+    assert_eq!(1, count_plaintext_keys(db).await.unwrap());
+
+    let report = seal_plaintext_keys(db, &server.state.master_keys)
+        .await
+        .unwrap();
+    assert_eq!(1, report.sealed);
+    assert_eq!(0, count_plaintext_keys(db).await.unwrap());
+    assert!(envelope::is_sealed(&stored_keypairs(&server).await[0]));
+    assert_signed(&server).await;
+
+    // Already encrypted keys are left alone
+    let report = seal_plaintext_keys(db, &server.state.master_keys)
+        .await
+        .unwrap();
+    assert_eq!(0, report.sealed);
+    assert_eq!(1, report.unchanged);
+}
+
+#[tokio::test]
+async fn test_rewrap_with_new_master_key() {
+    let server = TestServer::with_master_key().await;
+    upload_path(&server).await;
+
+    let old_key = server.config.signing_keys.master_key.clone().unwrap();
+    let new_key = MasterKey::generate();
+
+    let mut config = server.config.clone();
+    config.signing_keys = SigningKeysConfig {
+        master_key: Some(new_key.clone()),
+        previous_master_keys: vec![old_key],
+    };
+
+    let report = run_rewrap(config.clone()).await.unwrap();
+    assert_eq!(1, report.rewrapped);
+    assert_eq!(0, report.failed);
+
+    let keypairs = stored_keypairs(&server).await;
+    assert_eq!(Some(new_key.id()), envelope::master_key_id(&keypairs[0]));
+
+    // Nothing left to rewrap
+    let report = run_rewrap(config).await.unwrap();
+    assert_eq!(0, report.rewrapped);
+    assert_eq!(1, report.unchanged);
+
+    // The previous master key is no longer needed
+    let mut config = server.config.clone();
+    config.signing_keys = SigningKeysConfig {
+        master_key: Some(new_key),
+        previous_master_keys: vec![],
+    };
+    let server = server.restart_with(config).await;
+    assert_signed(&server).await;
+}
+
+#[tokio::test]
+async fn test_rewrap_requires_master_key() {
+    let server = TestServer::new().await;
+
+    assert!(run_rewrap(server.config.clone()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_keys_bound_to_cache() {
+    let server = TestServer::with_master_key().await;
+    upload_path(&server).await;
+    server.create_cache("other-cache", true).await;
+
+    // The keypair of another cache is copied over
+    server
+        .database()
+        .await
+        .execute(
+            "UPDATE cache SET keypair = (SELECT keypair FROM cache WHERE name = 'other-cache') WHERE name = 'test-cache'",
+            (),
+        )
+        .await
+        .unwrap();
+
+    assert!(!server.get(NARINFO).await.status.is_success());
+}
+
+#[tokio::test]
+async fn test_rotation_with_encrypted_keys() {
+    let server = TestServer::with_master_key().await;
+    upload_path(&server).await;
+
+    let token = server.build_token(server.token("test-user").with_full_access("test-cache"));
+    for step in [KeyRotationStep::Stage, KeyRotationStep::Activate] {
+        server
+            .post_json_with_token(
+                "/_api/v1/cache-config/test-cache/rotate-key",
+                &RotateKeyRequest { step },
+                &token,
+            )
+            .await
+            .assert_ok();
+    }
+
+    assert_signed(&server).await;
+
+    let report = run_rewrap(server.config.clone()).await.unwrap();
+    assert_eq!(3, report.unchanged);
+    assert_eq!(0, report.failed);
+}
@@ -1,4 +1,10 @@
 //! End-to-end workflow tests.
 
+mod db_check_tests;
 mod deduplication_tests;
+mod gc_tests;
+mod key_encryption_tests;
+mod nix_copy_tests;
+mod reconcile_tests;
+mod scrub_tests;
 mod upload_download_tests;
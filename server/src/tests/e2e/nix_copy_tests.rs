@@ -0,0 +1,198 @@
+//! Tests for the Nix binary cache write protocol.
+//!
+//! These mimic what `nix copy --to https://...` does: upload the
+//! compressed NAR, then the `.narinfo` referencing it.
+
+use std::time::Duration;
+
+use async_compression::tokio::bufread::XzEncoder;
+use axum::body::Body;
+use axum::http::Request;
+use tokio::io::AsyncReadExt;
+
+use crate::gc::run_garbage_collection_once;
+use crate::tests::helpers::{minimal_nar, minimal_nar_hash, TestResponse, TestServer};
+
+const STORE_PATH: &str = "/nix/store/00000000000000000000000000000000-test";
+
+async fn put(server: &TestServer, uri: &str, body: Vec<u8>, token: &str) -> TestResponse {
+    let request = Request::builder()
+        .method("PUT")
+        .uri(uri)
+        .header("Host", "localhost")
+        .header("Authorization", format!("Bearer {}", token))
+        .body(Body::from(body))
+        .unwrap();
+    server.request(request).await
+}
+
+async fn xz_compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = XzEncoder::new(data);
+    let mut compressed = Vec::new();
+    encoder.read_to_end(&mut compressed).await.unwrap();
+    compressed
+}
+
+fn narinfo(url: &str) -> String {
+    format!(
+        "StorePath: {}\n\
+         URL: {}\n\
+         Compression: xz\n\
+         NarHash: {}\n\
+         NarSize: {}\n\
+         References: 00000000000000000000000000000000-test\n\
+         Sig: test-1:c2lnbmF0dXJlLTE=\n\
+         Sig: test-2:c2lnbmF0dXJlLTI=\n",
+        STORE_PATH,
+        url,
+        minimal_nar_hash().to_typed_base32(),
+        minimal_nar().len(),
+    )
+}
+
+async fn nix_copy_roundtrip(server: TestServer) {
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_push("test-cache")
+            .with_pull("test-cache"),
+    );
+
+    let compressed = xz_compress(&minimal_nar()).await;
+
+    put(
+        &server,
+        "/test-cache/nar/somefilehash.nar.xz",
+        compressed,
+        &token,
+    )
+    .await
+    .assert_ok();
+
+    put(
+        &server,
+        "/test-cache/00000000000000000000000000000000.narinfo",
+        narinfo("nar/somefilehash.nar.xz").into_bytes(),
+        &token,
+    )
+    .await
+    .assert_ok();
+
+    let narinfo_response = server
+        .get_with_token(
+            "/test-cache/00000000000000000000000000000000.narinfo",
+            &token,
+        )
+        .await;
+    narinfo_response.assert_ok();
+    assert!(narinfo_response
+        .text()
+        .contains("References: 00000000000000000000000000000000-test"));
+
+    let nar_response = server
+        .get_with_token(
+            "/test-cache/nar/00000000000000000000000000000000.nar",
+            &token,
+        )
+        .await;
+    nar_response.assert_ok();
+    assert_eq!(nar_response.body, minimal_nar());
+}
+
+#[tokio::test]
+async fn test_nix_copy_unchunked() {
+    nix_copy_roundtrip(TestServer::new().await).await;
+}
+
+#[tokio::test]
+async fn test_nix_copy_chunked() {
+    nix_copy_roundtrip(TestServer::with_chunking(1).await).await;
+}
+
+#[tokio::test]
+async fn test_nix_copy_narinfo_without_nar() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    let response = put(
+        &server,
+        "/test-cache/00000000000000000000000000000000.narinfo",
+        narinfo("nar/missing.nar.xz").into_bytes(),
+        &token,
+    )
+    .await;
+    assert!(response.status.is_client_error());
+}
+
+#[tokio::test]
+async fn test_nix_copy_nar_hash_mismatch() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    let mut other_nar = minimal_nar();
+    other_nar.extend_from_slice(b"garbage!");
+
+    put(&server, "/test-cache/nar/other.nar", other_nar, &token)
+        .await
+        .assert_ok();
+
+    let response = put(
+        &server,
+        "/test-cache/00000000000000000000000000000000.narinfo",
+        narinfo("nar/other.nar").into_bytes(),
+        &token,
+    )
+    .await;
+    assert!(response.status.is_client_error());
+}
+
+#[tokio::test]
+async fn test_nix_copy_no_permission() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_pull("test-cache"));
+
+    put(
+        &server,
+        "/test-cache/nar/somefilehash.nar",
+        minimal_nar(),
+        &token,
+    )
+    .await
+    .assert_forbidden();
+}
+
+#[tokio::test]
+async fn test_nix_copy_nar_without_narinfo_expires() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    put(
+        &server,
+        "/test-cache/nar/somefilehash.nar.xz",
+        xz_compress(&minimal_nar()).await,
+        &token,
+    )
+    .await
+    .assert_ok();
+
+    // Still waiting for its .narinfo
+    let report = run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+    assert_eq!(0, report.staged_nars);
+
+    let mut config = server.config.clone();
+    config.garbage_collection.staged_nar_expiry = Duration::ZERO;
+    let report = run_garbage_collection_once(config).await.unwrap();
+    assert_eq!(1, report.staged_nars);
+}
@@ -0,0 +1,156 @@
+//! Tests for storage reconciliation.
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use super::gc_tests::{chunk_path, nar, wait_for_unlocked_chunks, PATH_A};
+use crate::config::StorageConfig;
+use crate::reconcile::{run_reconcile, ReconcileOptions};
+use crate::tests::helpers::TestServer;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Writes a file into the local storage, last modified `age` ago.
+fn write_file(server: &TestServer, name: &str, age: Duration) -> PathBuf {
+    let StorageConfig::Local(storage) = &server.config.storage else {
+        panic!("Storage isn't local");
+    };
+
+    let dir = storage.path().join(&name[..1]).join(&name[..2]);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join(name);
+    std::fs::write(&path, b"leftover").unwrap();
+
+    let file = std::fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(SystemTime::now() - age).unwrap();
+
+    path
+}
+
+fn options(grace_period: Duration) -> ReconcileOptions {
+    ReconcileOptions {
+        grace_period,
+        delete_unreferenced: false,
+        mark_missing: false,
+    }
+}
+
+#[tokio::test]
+async fn test_reconcile_finds_unreferenced_files() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    server
+        .upload_nar("test-cache", PATH_A, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    wait_for_unlocked_chunks(&server).await;
+
+    let old = write_file(&server, "00000000-old.chunk", 2 * DAY);
+    let recent = write_file(&server, "00000000-new.chunk", Duration::ZERO);
+    let foreign = write_file(&server, "notes.txt", 2 * DAY);
+
+    let report = run_reconcile(server.config.clone(), options(DAY))
+        .await
+        .unwrap();
+    assert_eq!(4, report.files);
+    assert_eq!(1, report.foreign_files);
+    assert_eq!(1, report.recent);
+    assert_eq!(1, report.unreferenced.len());
+    assert_eq!("00000000-old.chunk", report.unreferenced[0].name);
+    assert_eq!(8, report.unreferenced[0].size);
+    assert!(report.missing.is_empty());
+    assert_eq!(0, report.deleted);
+    assert!(old.exists());
+
+    let report = run_reconcile(
+        server.config.clone(),
+        ReconcileOptions {
+            delete_unreferenced: true,
+            ..options(DAY)
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(1, report.deleted);
+    assert!(!old.exists());
+    assert!(recent.exists());
+    assert!(foreign.exists());
+    assert!(chunk_path(&server).await.exists());
+}
+
+#[tokio::test]
+async fn test_reconcile_keeps_build_logs() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    let request = axum::http::Request::builder()
+        .method("PUT")
+        .uri("/test-cache/log/00000000000000000000000000000000-test.drv")
+        .header("Host", "localhost")
+        .header("Authorization", format!("Bearer {}", token))
+        .body(axum::body::Body::from("build output"))
+        .unwrap();
+    server.request(request).await.assert_ok();
+
+    let report = run_reconcile(server.config.clone(), options(Duration::ZERO))
+        .await
+        .unwrap();
+    assert_eq!(1, report.files);
+    assert!(report.unreferenced.is_empty());
+}
+
+#[tokio::test]
+async fn test_reconcile_flags_missing_files() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    server
+        .upload_nar("test-cache", PATH_A, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    wait_for_unlocked_chunks(&server).await;
+
+    std::fs::remove_file(chunk_path(&server).await).unwrap();
+
+    // The chunk was just created
+    let report = run_reconcile(server.config.clone(), options(DAY))
+        .await
+        .unwrap();
+    assert!(report.missing.is_empty());
+    assert_eq!(1, report.recent);
+
+    let report = run_reconcile(server.config.clone(), options(Duration::ZERO))
+        .await
+        .unwrap();
+    assert_eq!(1, report.missing.len());
+    assert_eq!("V", report.missing[0].state);
+    assert_eq!(1, report.damaged_paths.len());
+    assert_eq!(PATH_A, report.damaged_paths[0].store_path);
+    assert_eq!(0, report.marked);
+
+    let report = run_reconcile(
+        server.config.clone(),
+        ReconcileOptions {
+            mark_missing: true,
+            ..options(Duration::ZERO)
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(1, report.marked);
+
+    // Marked chunks are still missing their files, but no longer in service
+    let report = run_reconcile(server.config.clone(), options(Duration::ZERO))
+        .await
+        .unwrap();
+    assert_eq!("X", report.missing[0].state);
+    assert!(report.damaged_paths.is_empty());
+}
@@ -0,0 +1,172 @@
+//! Tests for storage scrubbing.
+
+use super::gc_tests::{chunk_path, nar, wait_for_unlocked_chunks, PATH_A};
+use crate::gc::run_garbage_collection_once;
+use crate::scrub::run_scrub_once;
+use crate::tests::helpers::TestServer;
+use attic::api::v1::get_missing_paths::{GetMissingPathsRequest, GetMissingPathsResponse};
+use attic::nix_store::StorePathHash;
+
+fn hash(store_path: &str) -> &str {
+    &store_path["/nix/store/".len()..][..32]
+}
+
+async fn chunk_state(server: &TestServer) -> String {
+    let mut rows = server
+        .database()
+        .await
+        .query("SELECT state FROM chunk ORDER BY id LIMIT 1", ())
+        .await
+        .unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+
+    row.get::<String>(0).unwrap()
+}
+
+async fn is_missing(server: &TestServer, cache: &str, store_path: &str, token: &str) -> bool {
+    let request = GetMissingPathsRequest {
+        cache: cache.parse().unwrap(),
+        store_path_hashes: vec![StorePathHash::new(hash(store_path).to_string()).unwrap()],
+    };
+
+    let response = server
+        .post_json_with_token("/_api/v1/get-missing-paths", &request, token)
+        .await;
+    response.assert_ok();
+
+    let result: GetMissingPathsResponse = response.json();
+    !result.missing_paths.is_empty()
+}
+
+async fn can_download(server: &TestServer, cache: &str, store_path: &str, token: &str) -> bool {
+    let response = server
+        .get_with_token(&format!("/{}/nar/{}.nar", cache, hash(store_path)), token)
+        .await;
+
+    response.status.is_success()
+}
+
+#[tokio::test]
+async fn test_scrub_finds_no_damage() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    server
+        .upload_nar("test-cache", PATH_A, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    wait_for_unlocked_chunks(&server).await;
+
+    let report = run_scrub_once(server.config.clone(), false).await.unwrap();
+    assert_eq!(1, report.chunks);
+    assert!(report.bytes > 0);
+    assert!(report.corrupt.is_empty());
+    assert!(report.unverified.is_empty());
+    assert!(report.damaged_paths.is_empty());
+    assert_eq!("V", chunk_state(&server).await);
+}
+
+#[tokio::test]
+async fn test_scrub_marks_corrupt_chunks() {
+    let server = TestServer::new().await;
+    server.create_cache("cache-a", false).await;
+    server.create_cache("cache-b", false).await;
+
+    let token = server.build_token(
+        server
+            .token("test-user")
+            .with_pull("cache-*")
+            .with_push("cache-*"),
+    );
+
+    for cache in ["cache-a", "cache-b"] {
+        server
+            .upload_nar(cache, PATH_A, nar('a'), vec![], &token)
+            .await
+            .assert_ok();
+    }
+    wait_for_unlocked_chunks(&server).await;
+
+    // Flip a bit in the middle of the chunk
+    let path = chunk_path(&server).await;
+    let mut data = std::fs::read(&path).unwrap();
+    let middle = data.len() / 2;
+    data[middle] ^= 1;
+    std::fs::write(&path, data).unwrap();
+
+    // A dry run only reports the damage
+    let report = run_scrub_once(server.config.clone(), true).await.unwrap();
+    assert_eq!(1, report.corrupt.len());
+    assert!(report.corrupt[0].problem.starts_with("File hash is"));
+    assert_eq!(
+        vec!["cache-a", "cache-b"],
+        report
+            .damaged_paths
+            .iter()
+            .map(|path| path.cache.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert!(report.damaged_paths.iter().all(|p| p.store_path == PATH_A));
+    assert_eq!("V", chunk_state(&server).await);
+    assert!(!is_missing(&server, "cache-a", PATH_A, &token).await);
+
+    let report = run_scrub_once(server.config.clone(), false).await.unwrap();
+    assert_eq!(1, report.corrupt.len());
+    assert_eq!(2, report.damaged_paths.len());
+    assert_eq!("X", chunk_state(&server).await);
+
+    // Clients now see the path as missing and can't download it
+    for cache in ["cache-a", "cache-b"] {
+        assert!(is_missing(&server, cache, PATH_A, &token).await);
+        assert!(!can_download(&server, cache, PATH_A, &token).await);
+    }
+
+    // Marked chunks aren't checked again
+    let report = run_scrub_once(server.config.clone(), false).await.unwrap();
+    assert_eq!(0, report.chunks);
+    assert!(report.corrupt.is_empty());
+
+    // Pushing the path again repairs the NAR in both caches
+    server
+        .upload_nar("cache-a", PATH_A, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    wait_for_unlocked_chunks(&server).await;
+
+    assert!(!is_missing(&server, "cache-a", PATH_A, &token).await);
+    for cache in ["cache-a", "cache-b"] {
+        assert!(can_download(&server, cache, PATH_A, &token).await);
+    }
+
+    // The corrupt chunk is deleted by garbage collection
+    let report = run_garbage_collection_once(server.config.clone())
+        .await
+        .unwrap();
+    assert_eq!(1, report.chunks);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn test_scrub_leaves_unreadable_chunks_alone() {
+    let server = TestServer::new().await;
+    server.create_cache("test-cache", false).await;
+
+    let token = server.build_token(server.token("test-user").with_push("test-cache"));
+
+    server
+        .upload_nar("test-cache", PATH_A, nar('a'), vec![], &token)
+        .await
+        .assert_ok();
+    wait_for_unlocked_chunks(&server).await;
+
+    std::fs::remove_file(chunk_path(&server).await).unwrap();
+
+    let report = run_scrub_once(server.config.clone(), false).await.unwrap();
+    assert_eq!(0, report.chunks);
+    assert!(report.corrupt.is_empty());
+    assert_eq!(1, report.unverified.len());
+    assert!(report.damaged_paths.is_empty());
+    assert_eq!("V", chunk_state(&server).await);
+}
@@ -1,15 +1,19 @@
 //! Test configuration builder.
 
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
 
+use attic::cache::CacheName;
 use attic_token::HS256Key;
 
 use crate::config::{
-    ChunkingConfig, CompressionConfig, CompressionType, Config, DatabaseConfig,
-    GarbageCollectionConfig, JWTConfig, JWTSigningConfig, StorageConfig, WebUiConfig,
+    AccessTrackingConfig, ChunkingConfig, CompressionConfig, CompressionType, Config,
+    DatabaseConfig, GarbageCollectionConfig, JWTConfig, JWTSigningConfig, NarInfoCacheConfig,
+    ScrubConfig, SigningAgentConfig, SigningKeysConfig, StorageConfig, UpstreamConfig, WebUiConfig,
 };
+use crate::signing::envelope::MasterKey;
 use crate::storage::LocalStorageConfig;
 
 /// Builder for creating test configurations.
@@ -25,6 +29,11 @@ pub struct TestConfigBuilder {
     storage_path: PathBuf,
     jwt_secret: HS256Key,
     nar_size_threshold: usize,
+    access_flush_interval: Duration,
+    narinfo_cache_capacity: usize,
+    upstream_read_timeout: Duration,
+    master_key: Option<MasterKey>,
+    signing_agents: HashMap<CacheName, SigningAgentConfig>,
 }
 
 impl TestConfigBuilder {
@@ -42,6 +51,11 @@ impl TestConfigBuilder {
             storage_path,
             jwt_secret,
             nar_size_threshold: 0, // Disable chunking by default for simpler tests
+            access_flush_interval: Duration::ZERO, // Write access times right away
+            narinfo_cache_capacity: 0, // See changes made outside the API right away
+            upstream_read_timeout: UpstreamConfig::default().read_timeout,
+            master_key: None,
+            signing_agents: HashMap::new(),
         }
     }
 
@@ -63,6 +77,36 @@ impl TestConfigBuilder {
         self
     }
 
+    /// Set how often buffered access times are written.
+    pub fn with_access_flush_interval(mut self, interval: Duration) -> Self {
+        self.access_flush_interval = interval;
+        self
+    }
+
+    /// Set the number of rendered `.narinfo`s to cache.
+    pub fn with_narinfo_cache_capacity(mut self, capacity: usize) -> Self {
+        self.narinfo_cache_capacity = capacity;
+        self
+    }
+
+    /// Set how long to wait for upstreams to send more data.
+    pub fn with_upstream_read_timeout(mut self, timeout: Duration) -> Self {
+        self.upstream_read_timeout = timeout;
+        self
+    }
+
+    /// Set the master key that signing keys are encrypted with.
+    pub fn with_master_key(mut self, master_key: MasterKey) -> Self {
+        self.master_key = Some(master_key);
+        self
+    }
+
+    /// Have an external agent sign for a cache.
+    pub fn with_signing_agent(mut self, cache: &str, agent: SigningAgentConfig) -> Self {
+        self.signing_agents.insert(cache.parse().unwrap(), agent);
+        self
+    }
+
     /// Build the configuration.
     pub fn build(self) -> Config {
         Config {
@@ -79,6 +123,8 @@ impl TestConfigBuilder {
                 auth_token: None,
                 local_replica_path: None,
                 sync_interval: None,
+                pool_size: 8,
+                busy_timeout: Duration::from_secs(5),
             },
             storage: StorageConfig::Local(LocalStorageConfig::new_for_test(self.storage_path)),
             chunking: ChunkingConfig {
@@ -94,7 +140,26 @@ impl TestConfigBuilder {
             garbage_collection: GarbageCollectionConfig {
                 interval: Duration::from_secs(0),
                 default_retention_period: Duration::ZERO,
+                default_storage_quota: 0,
+                ..Default::default()
+            },
+            scrub: ScrubConfig::default(),
+            access_tracking: AccessTrackingConfig {
+                flush_interval: self.access_flush_interval,
+            },
+            narinfo_cache: NarInfoCacheConfig {
+                capacity: self.narinfo_cache_capacity,
+                ..Default::default()
+            },
+            upstream: UpstreamConfig {
+                read_timeout: self.upstream_read_timeout,
+                ..Default::default()
+            },
+            signing_keys: SigningKeysConfig {
+                master_key: self.master_key,
+                previous_master_keys: vec![],
             },
+            signing_agents: self.signing_agents,
             jwt: JWTConfig {
                 token_bound_issuer: None,
                 token_bound_audiences: None,
@@ -12,6 +12,16 @@ use attic::nix_store::StorePathHash;
 /// - nix-archive-1
 /// - (type regular contents "hello world")
 pub fn minimal_nar() -> Vec<u8> {
+    nar_with_contents("hello world")
+}
+
+/// Computes the hash of the minimal NAR.
+pub fn minimal_nar_hash() -> Hash {
+    nar_hash(&minimal_nar())
+}
+
+/// A NAR archive containing a single regular file with the given content.
+pub fn nar_with_contents(contents: &str) -> Vec<u8> {
     // NAR format: series of (tag, value) pairs
     // Strings are 8-byte length-prefixed and padded to 8-byte boundary
     let mut nar = Vec::new();
@@ -26,9 +36,9 @@ pub fn minimal_nar() -> Vec<u8> {
     write_string(&mut nar, "type");
     write_string(&mut nar, "regular");
 
-    // contents = "hello world"
+    // contents
     write_string(&mut nar, "contents");
-    write_string(&mut nar, "hello world");
+    write_string(&mut nar, contents);
 
     // End of entry: ")"
     write_string(&mut nar, ")");
@@ -36,11 +46,10 @@ pub fn minimal_nar() -> Vec<u8> {
     nar
 }
 
-/// Computes the hash of the minimal NAR.
-pub fn minimal_nar_hash() -> Hash {
-    let nar = minimal_nar();
+/// Computes the SHA-256 hash of a NAR.
+pub fn nar_hash(nar: &[u8]) -> Hash {
     let mut hasher = Sha256::new();
-    hasher.update(&nar);
+    hasher.update(nar);
     let hash = hasher.finalize();
     Hash::Sha256(hash.as_slice().try_into().unwrap())
 }
@@ -8,4 +8,4 @@ pub mod server;
 pub use config::TestConfigBuilder;
 pub use fixtures::*;
 pub use jwt::TestTokenBuilder;
-pub use server::TestServer;
+pub use server::{TestResponse, TestServer};
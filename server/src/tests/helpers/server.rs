@@ -1,6 +1,7 @@
 //! Test server infrastructure.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::body::Body;
 use axum::extract::Extension;
@@ -10,6 +11,8 @@ use http_body_util::BodyExt;
 use tempfile::TempDir;
 use tower::ServiceExt;
 
+use attic::api::v1::upload_path::{UploadPathNarInfo, ATTIC_NAR_INFO};
+use attic::nix_store::StorePathHash;
 use attic_token::HS256Key;
 
 use crate::access::http::apply_auth;
@@ -18,9 +21,11 @@ use crate::database::connection::{TursoConfig, TursoConnection};
 use crate::database::migrations::run_migrations;
 use crate::database::models::CacheModel;
 use crate::database::queries;
+use crate::signing::{self, envelope::MasterKey};
 use crate::{State, StateInner};
 
 use super::config::TestConfigBuilder;
+use super::fixtures::{minimal_nar, nar_hash};
 use super::jwt::TestTokenBuilder;
 
 /// A test server with all necessary infrastructure for integration testing.
@@ -53,6 +58,24 @@ impl TestServer {
         Self::with_config_builder(|builder| builder.with_proof_of_possession()).await
     }
 
+    /// Creates a new test server that buffers access times.
+    pub async fn with_access_tracking() -> Self {
+        Self::with_config_builder(|builder| {
+            builder.with_access_flush_interval(Duration::from_secs(30))
+        })
+        .await
+    }
+
+    /// Creates a new test server that caches rendered `.narinfo`s.
+    pub async fn with_narinfo_cache() -> Self {
+        Self::with_config_builder(|builder| builder.with_narinfo_cache_capacity(100)).await
+    }
+
+    /// Creates a new test server that encrypts signing keys.
+    pub async fn with_master_key() -> Self {
+        Self::with_config_builder(|builder| builder.with_master_key(MasterKey::generate())).await
+    }
+
     /// Creates a new test server with chunking enabled.
     pub async fn with_chunking(threshold: usize) -> Self {
         Self::with_config_builder(|builder| builder.with_chunking_threshold(threshold)).await
@@ -76,7 +99,7 @@ impl TestServer {
         let config = config_fn(builder).build();
 
         // Create state
-        let state = StateInner::new(config.clone()).await;
+        let state = StateInner::new(config.clone(), false).await;
 
         // Run migrations
         let turso_config = TursoConfig::from_database_config(&config.database);
@@ -100,6 +123,33 @@ impl TestServer {
         }
     }
 
+    /// Turns this server into a read-only substituter of the same
+    /// database and storage.
+    pub async fn into_substituter(self) -> Self {
+        let state = StateInner::new(self.config.clone(), true).await;
+        let router = crate::substituter::get_router(state.clone());
+
+        Self {
+            state,
+            router,
+            ..self
+        }
+    }
+
+    /// Restarts this server with another configuration, keeping
+    /// the same database and storage.
+    pub async fn restart_with(self, config: Config) -> Self {
+        let state = StateInner::new(config.clone(), false).await;
+        let router = create_test_router(state.clone());
+
+        Self {
+            config,
+            state,
+            router,
+            ..self
+        }
+    }
+
     /// Returns a clone of the router for making requests.
     pub fn router(&self) -> Router {
         self.router.clone()
@@ -123,17 +173,17 @@ impl TestServer {
         let keypair =
             attic::signing::NixKeypair::generate(name).expect("Failed to generate keypair");
 
-        queries::create_cache(
-            database,
-            name,
-            &keypair.export_keypair(),
-            is_public,
-            "/nix/store",
-            40,
-            &[],
-        )
-        .await
-        .expect("Failed to create cache")
+        let cache = queries::create_cache(database, name, "", is_public, "/nix/store", 40, &[])
+            .await
+            .expect("Failed to create cache");
+
+        signing::store_new_cache_keypair(database, &self.state.master_keys, cache.id, &keypair)
+            .await
+            .expect("Failed to store keypair");
+
+        queries::find_cache(database, &name.parse().unwrap())
+            .await
+            .expect("Failed to find cache")
     }
 
     /// Gets the database connection.
@@ -221,6 +271,25 @@ impl TestServer {
         self.request(request).await
     }
 
+    /// Makes a PUT request with JSON body and authorization.
+    pub async fn put_json_with_token(
+        &self,
+        uri: &str,
+        body: &impl serde::Serialize,
+        token: &str,
+    ) -> TestResponse {
+        let body_bytes = serde_json::to_vec(body).unwrap();
+        let request = Request::builder()
+            .method("PUT")
+            .uri(uri)
+            .header("Host", "localhost")
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::from(body_bytes))
+            .unwrap();
+        self.request(request).await
+    }
+
     /// Makes a PATCH request with JSON body and authorization.
     pub async fn patch_json_with_token(
         &self,
@@ -251,6 +320,85 @@ impl TestServer {
             .unwrap();
         self.request(request).await
     }
+
+    /// Makes a DELETE request with JSON body and authorization.
+    pub async fn delete_json_with_token(
+        &self,
+        uri: &str,
+        body: &impl serde::Serialize,
+        token: &str,
+    ) -> TestResponse {
+        let body_bytes = serde_json::to_vec(body).unwrap();
+        let request = Request::builder()
+            .method("DELETE")
+            .uri(uri)
+            .header("Host", "localhost")
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::from(body_bytes))
+            .unwrap();
+        self.request(request).await
+    }
+
+    /// Uploads the minimal NAR as the given store path via the Attic API.
+    pub async fn upload_minimal_nar(
+        &self,
+        cache: &str,
+        store_path: &str,
+        token: &str,
+    ) -> TestResponse {
+        self.upload_nar(cache, store_path, minimal_nar(), vec![], token)
+            .await
+    }
+
+    /// Uploads a NAR as the given store path via the Attic API.
+    ///
+    /// References are store path base names.
+    pub async fn upload_nar(
+        &self,
+        cache: &str,
+        store_path: &str,
+        nar_data: Vec<u8>,
+        references: Vec<String>,
+        token: &str,
+    ) -> TestResponse {
+        let base_name = store_path.rsplit('/').next().unwrap();
+        let store_path_hash = StorePathHash::new(base_name[..32].to_string()).unwrap();
+
+        let upload_info = UploadPathNarInfo {
+            cache: cache.parse().unwrap(),
+            store_path_hash,
+            store_path: store_path.to_string(),
+            references,
+            system: None,
+            deriver: None,
+            sigs: vec![],
+            ca: None,
+            nar_hash: nar_hash(&nar_data),
+            nar_size: nar_data.len(),
+        };
+
+        self.upload_nar_with_info(&upload_info, nar_data, token)
+            .await
+    }
+
+    /// Uploads a NAR with the given upload info via the Attic API.
+    pub async fn upload_nar_with_info(
+        &self,
+        upload_info: &UploadPathNarInfo,
+        nar_data: Vec<u8>,
+        token: &str,
+    ) -> TestResponse {
+        let request = Request::builder()
+            .method("PUT")
+            .uri("/_api/v1/upload-path")
+            .header("Host", "localhost")
+            .header("Authorization", format!("Bearer {}", token))
+            .header(ATTIC_NAR_INFO, serde_json::to_string(upload_info).unwrap())
+            .body(Body::from(nar_data))
+            .unwrap();
+        self.request(request).await
+    }
 }
 
 /// Creates a test router with all middleware configured.
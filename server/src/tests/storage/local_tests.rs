@@ -121,3 +121,39 @@ async fn test_local_storage_remote_file_id() {
     // Should start with "local:"
     assert!(file_id.starts_with("local:"));
 }
+
+// ==================== Listing ====================
+
+#[tokio::test]
+async fn test_local_storage_list_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LocalStorageConfig::new_for_test(temp_dir.path().to_path_buf());
+    let backend = LocalBackend::new(config).await.unwrap();
+
+    for name in ["b1.chunk", "ab.chunk", "a1.chunk", "a2.chunk"] {
+        let mut cursor = Cursor::new(name.as_bytes().to_vec());
+        backend
+            .upload_file(name.to_string(), &mut cursor)
+            .await
+            .unwrap();
+    }
+
+    // Sorted by name, and the VERSION file isn't listed
+    let files = backend.list_files(None, 10).await.unwrap();
+    let names: Vec<_> = files.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(vec!["a1.chunk", "a2.chunk", "ab.chunk", "b1.chunk"], names);
+    assert_eq!(8, files[0].size);
+    assert!(files[0].modified_at.is_some());
+
+    // Paging
+    let page = backend.list_files(None, 2).await.unwrap();
+    assert_eq!("a2.chunk", page[1].name);
+    let page = backend.list_files(Some("a2.chunk"), 2).await.unwrap();
+    let names: Vec<_> = page.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(vec!["ab.chunk", "b1.chunk"], names);
+    assert!(backend
+        .list_files(Some("b1.chunk"), 2)
+        .await
+        .unwrap()
+        .is_empty());
+}
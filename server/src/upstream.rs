@@ -0,0 +1,292 @@
+//! Pull-through fetching from upstream substituters.
+//!
+//! A cache may have a list of upstream substituters, each with its own
+//! set of trusted public keys. When a store path that isn't in the cache
+//! is requested, the upstreams are queried in order. The first `.narinfo`
+//! signed by a key trusted for its upstream wins, and the NAR it refers to
+//! is ingested through the same path as regular uploads. This verifies the
+//! NAR hash and size and chunks the NAR like any other.
+//!
+//! Errors from upstreams are logged and treated as misses, so a broken
+//! upstream never turns a 404 into a 500.
+
+use std::io::Error as IoError;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use dashmap::DashMap;
+use futures::TryStreamExt;
+use reqwest::{Client as HttpClient, StatusCode, Url};
+use tokio::io::BufReader;
+use tokio::sync::Mutex;
+use tokio_util::io::StreamReader;
+
+use crate::api::v1::upload_path::upload_path_with_info;
+use crate::compression::get_decompressor;
+use crate::config::UpstreamConfig;
+use crate::database::models::{CacheModel, CacheUpstreamModel};
+use crate::database::{queries, AtticDatabase};
+use crate::error::{ErrorKind, ServerResult};
+use crate::narinfo::NarInfo;
+use crate::State;
+use attic::api::v1::cache_config::UpstreamSubstituter;
+use attic::api::v1::upload_path::UploadPathNarInfo;
+use attic::cache::CacheName;
+use attic::nix_store::StorePathHash;
+use attic::signing::NixPublicKey;
+
+/// Fetches store paths from upstream substituters.
+#[derive(Debug)]
+pub struct UpstreamFetcher {
+    /// The HTTP client.
+    client: HttpClient,
+
+    /// Paths being fetched, keyed by cache ID and store path hash.
+    ///
+    /// Concurrent requests for the same path wait for the first
+    /// fetch instead of downloading the NAR again.
+    in_flight: DashMap<(i64, String), Arc<Mutex<()>>>,
+}
+
+impl UpstreamFetcher {
+    pub fn new(config: &UpstreamConfig) -> Self {
+        let client = HttpClient::builder()
+            .connect_timeout(config.connect_timeout)
+            .read_timeout(config.read_timeout)
+            .build()
+            .expect("Failed to build HTTP client");
+
+        Self {
+            client,
+            in_flight: DashMap::new(),
+        }
+    }
+
+    /// Fetches a store path from the upstreams of a cache.
+    ///
+    /// Returns whether the path is now in the cache.
+    pub async fn fetch_path(
+        &self,
+        state: &State,
+        cache: CacheModel,
+        cache_name: &CacheName,
+        store_path_hash: &StorePathHash,
+    ) -> ServerResult<bool> {
+        let database = state.database().await?;
+        let upstreams = queries::find_cache_upstreams(database, cache.id).await?;
+
+        if upstreams.is_empty() {
+            return Ok(false);
+        }
+
+        let key = (cache.id, store_path_hash.to_string());
+        let lock = self.in_flight.entry(key.clone()).or_default().clone();
+        let guard = lock.lock().await;
+
+        let found = self
+            .fetch_path_locked(state, &cache, cache_name, store_path_hash, &upstreams)
+            .await;
+
+        drop(guard);
+        self.in_flight.remove(&key);
+
+        found
+    }
+
+    async fn fetch_path_locked(
+        &self,
+        state: &State,
+        cache: &CacheModel,
+        cache_name: &CacheName,
+        store_path_hash: &StorePathHash,
+        upstreams: &[CacheUpstreamModel],
+    ) -> ServerResult<bool> {
+        // Someone else may have fetched it while we were waiting
+        let database = state.database().await?;
+        if database
+            .find_object_and_chunks_by_store_path_hash(cache_name, store_path_hash, false)
+            .await
+            .is_ok()
+        {
+            return Ok(true);
+        }
+
+        for upstream in upstreams {
+            match self
+                .fetch_from(state, cache, cache_name, store_path_hash, upstream)
+                .await
+            {
+                Ok(true) => return Ok(true),
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to fetch {} from {}: {:#}",
+                        store_path_hash.as_str(),
+                        upstream.url,
+                        e
+                    );
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Fetches a store path from a single upstream.
+    ///
+    /// Returns `false` if the upstream doesn't have a trusted copy of the path.
+    async fn fetch_from(
+        &self,
+        state: &State,
+        cache: &CacheModel,
+        cache_name: &CacheName,
+        store_path_hash: &StorePathHash,
+        upstream: &CacheUpstreamModel,
+    ) -> anyhow::Result<bool> {
+        let base = parse_base_url(&upstream.url)?;
+
+        let narinfo_url = base.join(&format!("{}.narinfo", store_path_hash.as_str()))?;
+        let response = self.client.get(narinfo_url).send().await?;
+
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::FORBIDDEN
+        ) {
+            return Ok(false);
+        }
+
+        let manifest = response.error_for_status()?.text().await?;
+
+        if manifest.len() > state.config.max_nar_info_size {
+            bail!("The .narinfo is too large");
+        }
+
+        let narinfo = NarInfo::from_str(&manifest)?;
+
+        let base_name = narinfo
+            .store_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        if !base_name.starts_with(&format!("{}-", store_path_hash.as_str()))
+            || narinfo.store_dir() != std::path::Path::new(&cache.store_dir)
+        {
+            bail!("The .narinfo is for a different store path");
+        }
+
+        let trusted_keys: Vec<NixPublicKey> = upstream
+            .trusted_keys
+            .0
+            .iter()
+            .filter_map(|key| NixPublicKey::from_str(key).ok())
+            .collect();
+
+        let fingerprint = narinfo.fingerprint();
+        let trusted = narinfo.signatures.iter().any(|sig| {
+            trusted_keys
+                .iter()
+                .any(|key| key.verify(&fingerprint, sig).is_ok())
+        });
+
+        if !trusted {
+            tracing::warn!(
+                "{} from {} isn't signed by a trusted key",
+                store_path_hash.as_str(),
+                upstream.url
+            );
+            return Ok(false);
+        }
+
+        let nar_url = nar_url(&base, &narinfo.url)?;
+        let response = self.client.get(nar_url).send().await?.error_for_status()?;
+
+        let stream = StreamReader::new(response.bytes_stream().map_err(IoError::other));
+        let stream = BufReader::new(get_decompressor(narinfo.compression, stream)?);
+
+        let upload_info = UploadPathNarInfo {
+            cache: cache_name.clone(),
+            store_path_hash: store_path_hash.clone(),
+            store_path: narinfo.store_path.to_string_lossy().into_owned(),
+            references: narinfo.references,
+            system: narinfo.system,
+            deriver: narinfo.deriver,
+            sigs: narinfo.signatures,
+            ca: narinfo.ca,
+            nar_hash: narinfo.nar_hash,
+            nar_size: narinfo.nar_size,
+        };
+
+        let database = state.database().await?;
+        let result =
+            upload_path_with_info(None, cache.clone(), upload_info, stream, database, state)
+                .await?;
+
+        tracing::info!(
+            "Fetched {} from {}: {:?}",
+            store_path_hash.as_str(),
+            upstream.url,
+            result.kind
+        );
+
+        Ok(true)
+    }
+}
+
+/// Checks that an upstream substituter can be used.
+pub fn validate_substituter(upstream: &UpstreamSubstituter) -> ServerResult<()> {
+    parse_base_url(&upstream.url)?;
+
+    if upstream.trusted_keys.is_empty() {
+        return Err(ErrorKind::RequestError(anyhow!(
+            "Upstream {} has no trusted keys",
+            upstream.url
+        ))
+        .into());
+    }
+
+    for key in upstream.trusted_keys.iter() {
+        NixPublicKey::from_str(key).map_err(|e| {
+            ErrorKind::RequestError(anyhow!("Invalid trusted key \"{}\": {}", key, e))
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Resolves the URL of a NAR in an upstream.
+///
+/// The URL comes from the `.narinfo` and must stay within the upstream,
+/// so an upstream can't make us fetch from anywhere else.
+fn nar_url(base: &Url, url: &str) -> anyhow::Result<Url> {
+    if Url::parse(url).is_ok() || url.starts_with('/') {
+        bail!("The NAR URL \"{}\" isn't relative", url);
+    }
+
+    let nar_url = base.join(url)?;
+    if nar_url.origin() != base.origin() || !nar_url.path().starts_with(base.path()) {
+        bail!("The NAR URL \"{}\" is outside of the upstream", url);
+    }
+
+    Ok(nar_url)
+}
+
+/// Parses the URL of an upstream so that paths can be joined onto it.
+fn parse_base_url(url: &str) -> ServerResult<Url> {
+    let mut url = Url::parse(url)
+        .map_err(|e| ErrorKind::RequestError(anyhow!("Invalid upstream URL \"{}\": {}", url, e)))?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ErrorKind::RequestError(anyhow!(
+            "Upstream URL \"{}\" must be HTTP or HTTPS",
+            url
+        ))
+        .into());
+    }
+
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+
+    Ok(url)
+}